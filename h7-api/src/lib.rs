@@ -1,6 +1,10 @@
 #![no_std]
 
 pub type AppEntryPoint = extern "C" fn(*const H7Api) -> i32;
+/// Called when a shell command registered by an app is run.
+/// `args` holds the command arguments separated by single spaces.
+pub type CommandCallback =
+    extern "C" fn(api: *const H7Api, args: *const u8, args_len: usize) -> i32;
 
 #[derive(Debug, Clone)]
 #[repr(C)]
@@ -13,6 +17,15 @@ pub struct H7Api {
     pub getc: extern "C" fn() -> u8,
     pub putc: extern "C" fn(c: u8) -> i32,
     pub puts: extern "C" fn(start: *const u8, len: usize) -> i32,
+    // Shell
+    pub register_command: extern "C" fn(
+        name: *const u8,
+        name_len: usize,
+        help: *const u8,
+        help_len: usize,
+        callback: CommandCallback,
+    ) -> i32,
+    pub unregister_command: extern "C" fn(name: *const u8, name_len: usize) -> i32,
     // GPU
     // pub screen_width_px: extern "C" fn() -> u32,
     // pub screen_height_px: extern "C" fn() -> u32,
@@ -1,4 +1,4 @@
-use crate::Host;
+use crate::{CommandCallback, Host};
 
 pub const MALLOC_DEFAULT_ALIGN: usize = 8;
 
@@ -48,6 +48,28 @@ pub unsafe extern "C" fn h7_puts(msg: *const u8) -> i32 {
     Host::puts(str_slice)
 }
 
+// Shell
+#[no_mangle]
+pub unsafe extern "C" fn h7_register_command(
+    name: *const u8,
+    help: *const u8,
+    callback: CommandCallback,
+) -> i32 {
+    let name = core::slice::from_raw_parts(name, cstd::strlen(name));
+    let help = core::slice::from_raw_parts(help, cstd::strlen(help));
+    Host::register_command(
+        core::str::from_utf8_unchecked(name),
+        core::str::from_utf8_unchecked(help),
+        callback,
+    )
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn h7_unregister_command(name: *const u8) -> i32 {
+    let slice = core::slice::from_raw_parts(name, cstd::strlen(name));
+    Host::unregister_command(core::str::from_utf8_unchecked(slice))
+}
+
 mod cstd {
     pub(crate) unsafe fn strlen(s: *const u8) -> usize {
         let mut result = 0;
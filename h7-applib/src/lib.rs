@@ -32,6 +32,8 @@ mod h7_alloc {
     }
 }
 
+pub use h7_api::CommandCallback;
+
 use {
     core::mem::MaybeUninit,
     h7_api::{AppEntryPoint, H7Api},
@@ -97,6 +99,24 @@ impl Host {
     pub fn puts(s: &str) -> i32 {
         (get_api().puts)(s.as_ptr(), s.len())
     }
+
+    /// Add a shell command that calls back into this app.
+    /// The command is available once the app has exited and until another app is loaded.
+    #[inline(always)]
+    pub fn register_command(name: &str, help: &str, callback: CommandCallback) -> i32 {
+        (get_api().register_command)(
+            name.as_ptr(),
+            name.len(),
+            help.as_ptr(),
+            help.len(),
+            callback,
+        )
+    }
+
+    #[inline(always)]
+    pub fn unregister_command(name: &str) -> i32 {
+        (get_api().unregister_command)(name.as_ptr(), name.len())
+    }
 }
 
 impl core::fmt::Write for Host {
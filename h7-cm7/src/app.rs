@@ -1,12 +1,21 @@
 use {
     crate::{
+        led::Led,
         mem,
-        terminal::{TerminalWriter, TERMINAL_INPUT_FIFO},
+        terminal::{
+            menu::{Menu, MenuResult},
+            Port, TerminalWriter,
+        },
         utils,
     },
-    core::{alloc::GlobalAlloc, cell::RefCell, fmt::Write},
+    alloc::{rc::Rc, string::String, vec::Vec},
+    core::{
+        alloc::GlobalAlloc,
+        cell::{Cell, RefCell},
+        fmt::Write,
+    },
     critical_section::Mutex,
-    h7_api::{AppEntryPoint, H7Api},
+    h7_api::{AppEntryPoint, CommandCallback, H7Api},
 };
 
 const ARM_ADDR_ALIGN: usize = 4;
@@ -27,6 +36,9 @@ pub static API: H7Api = H7Api {
     getc,
     putc,
     puts,
+    // Shell
+    register_command,
+    unregister_command,
 };
 
 pub fn get_address(data: &[u8]) -> AppEntryPoint {
@@ -91,6 +103,98 @@ pub fn print_info<W: core::fmt::Write>(w: &mut W, data: &[u8]) -> core::fmt::Res
     )
 }
 
+// Console of the session that is running the app, used for app I/O
+static APP_PORT: Mutex<Cell<Port>> = Mutex::new(Cell::new(Port::Uart));
+
+/// Run `f`, which calls into code loaded in [`app_slice`], with caches disabled.
+/// App I/O goes to `port`.
+pub unsafe fn run_in_app_context<R>(port: Port, f: impl FnOnce() -> R) -> R {
+    utils::interrupt_free(|cs| APP_PORT.borrow(cs).set(port));
+    Led::Green.on();
+    Led::Red.on();
+    // Disable cache
+    let mut cp = cortex_m::Peripherals::steal();
+    cp.SCB.disable_icache();
+    cp.SCB.invalidate_icache();
+    cp.SCB.disable_dcache(&mut cp.CPUID);
+    cp.SCB.clean_dcache(&mut cp.CPUID);
+
+    // Sync
+    cortex_m::asm::dmb();
+    cortex_m::asm::dsb();
+    cortex_m::asm::isb();
+
+    // Run
+    let ret = f();
+
+    // Enable cache
+    cp.SCB.enable_icache();
+    cp.SCB.enable_dcache(&mut cp.CPUID);
+
+    Led::Green.off();
+    Led::Red.off();
+
+    ret
+}
+
+enum Registration {
+    Register {
+        name: String,
+        help: String,
+        callback: CommandCallback,
+    },
+    Unregister {
+        name: String,
+    },
+}
+
+// Commands (un)registered by the running app, applied to the shell once it exits
+static PENDING_REGISTRATIONS: Mutex<RefCell<Vec<Registration>>> =
+    Mutex::new(RefCell::new(Vec::new()));
+
+/// Apply the command registrations made by the app that just exited
+pub fn apply_registrations(m: &mut Menu<'static, TerminalWriter>) -> core::fmt::Result {
+    let pending = utils::interrupt_free(|cs| {
+        core::mem::take(&mut *PENDING_REGISTRATIONS.borrow(cs).borrow_mut())
+    });
+    for registration in pending {
+        match registration {
+            Registration::Register {
+                name,
+                help,
+                callback,
+            } => {
+                let action = Rc::new(
+                    move |m: &mut Menu<'static, TerminalWriter>, args: &[&str]| -> MenuResult {
+                        let args = args.join(" ");
+                        let ret = unsafe {
+                            run_in_app_context(m.writer().port(), || {
+                                callback(&API, args.as_ptr(), args.len())
+                            })
+                        };
+                        match ret {
+                            0 => Ok(()),
+                            n => {
+                                writeln!(m.writer(), "Exit: {n} (error)")?;
+                                Ok(())
+                            }
+                        }
+                    },
+                );
+                if let Err(e) = m.register_command(&name, &help, &help, action) {
+                    writeln!(m.writer(), "Failed to register '{name}': {e}")?;
+                }
+            }
+            Registration::Unregister { name } => {
+                if let Err(e) = m.unregister(&name) {
+                    writeln!(m.writer(), "Failed to unregister '{name}': {e}")?;
+                }
+            }
+        }
+    }
+    Ok(())
+}
+
 // Keep track of app allocations so that we can free leaked application memory
 static APP_ALLOCATIONS: Mutex<RefCell<heapless::FnvIndexMap<usize, core::alloc::Layout, 128>>> =
     Mutex::new(RefCell::new(heapless::FnvIndexMap::new()));
@@ -157,12 +261,16 @@ extern "C" fn panic(start: *const u8, len: usize) -> ! {
 
 // IO
 
+fn app_port() -> Port {
+    utils::interrupt_free(|cs| APP_PORT.borrow(cs).get())
+}
+
 extern "C" fn getc() -> u8 {
-    TERMINAL_INPUT_FIFO.dequeue().unwrap_or(0)
+    app_port().read().unwrap_or(0)
 }
 
 extern "C" fn putc(c: u8) -> i32 {
-    match write!(TerminalWriter, "{}", c as char) {
+    match write!(TerminalWriter::new(app_port()), "{}", c as char) {
         Ok(_) => 0,
         _ => -1,
     }
@@ -171,8 +279,55 @@ extern "C" fn putc(c: u8) -> i32 {
 extern "C" fn puts(start: *const u8, len: usize) -> i32 {
     let s = unsafe { core::slice::from_raw_parts(start, len) };
 
-    match core::str::from_utf8(s).map(|s| write!(TerminalWriter, "{s}")) {
+    match core::str::from_utf8(s).map(|s| write!(TerminalWriter::new(app_port()), "{s}")) {
         Ok(Ok(_)) => 0,
         _ => -1,
     }
 }
+
+// Shell
+
+extern "C" fn register_command(
+    name: *const u8,
+    name_len: usize,
+    help: *const u8,
+    help_len: usize,
+    callback: CommandCallback,
+) -> i32 {
+    let name = unsafe { core::slice::from_raw_parts(name, name_len) };
+    let help = unsafe { core::slice::from_raw_parts(help, help_len) };
+
+    match (core::str::from_utf8(name), core::str::from_utf8(help)) {
+        (Ok(name), Ok(help)) if !name.is_empty() && !name.contains(char::is_whitespace) => {
+            utils::interrupt_free(|cs| {
+                PENDING_REGISTRATIONS
+                    .borrow(cs)
+                    .borrow_mut()
+                    .push(Registration::Register {
+                        name: name.into(),
+                        help: help.into(),
+                        callback,
+                    })
+            });
+            0
+        }
+        _ => -1,
+    }
+}
+
+extern "C" fn unregister_command(name: *const u8, name_len: usize) -> i32 {
+    let name = unsafe { core::slice::from_raw_parts(name, name_len) };
+
+    match core::str::from_utf8(name) {
+        Ok(name) => {
+            utils::interrupt_free(|cs| {
+                PENDING_REGISTRATIONS
+                    .borrow(cs)
+                    .borrow_mut()
+                    .push(Registration::Unregister { name: name.into() })
+            });
+            0
+        }
+        _ => -1,
+    }
+}
@@ -1,3 +1,6 @@
-pub mod path;
+pub mod nor_fs;
+pub use h7_fs::path;
 pub mod qspi_store;
+pub mod ram_disk;
 pub mod sdmmc_fs;
+pub mod vfs;
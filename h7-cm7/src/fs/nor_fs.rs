@@ -0,0 +1,132 @@
+use {
+    super::{
+        path::Path,
+        vfs::{FileSystem, Metadata, Stats, VfsError, Wear, WriteMode},
+    },
+    alloc::{string::String, string::ToString, vec::Vec},
+    embedded_storage::nor_flash::{NorFlash, NorFlashError},
+    h7_fs::norfs::{self, NorFs},
+};
+
+/// A [`NorFs`] mounted in the VFS
+pub struct NorFsVolume<F>(NorFs<F>);
+
+impl<F: NorFlash> NorFsVolume<F> {
+    pub fn new(fs: NorFs<F>) -> Self {
+        Self(fs)
+    }
+}
+
+impl<E: NorFlashError> From<norfs::Error<E>> for VfsError {
+    fn from(err: norfs::Error<E>) -> Self {
+        match err {
+            norfs::Error::NotFound => Self::NotFound,
+            norfs::Error::AlreadyExists => Self::AlreadyExists,
+            norfs::Error::NotADirectory => Self::NotADirectory,
+            norfs::Error::IsADirectory => Self::IsADirectory,
+            norfs::Error::DirectoryNotEmpty => Self::DirectoryNotEmpty,
+            err => Self::NorFs(err.to_string()),
+        }
+    }
+}
+
+impl From<WriteMode> for norfs::WriteMode {
+    fn from(mode: WriteMode) -> Self {
+        match mode {
+            WriteMode::Create => Self::Create,
+            WriteMode::Truncate => Self::Truncate,
+            WriteMode::Append => Self::Append,
+        }
+    }
+}
+
+/// The path within the filesystem, without the device
+fn local(path: Path) -> String {
+    path.parts().collect::<Vec<_>>().join("/")
+}
+
+fn to_metadata(meta: &norfs::Metadata) -> Metadata {
+    Metadata {
+        name: meta.name.clone(),
+        is_dir: meta.is_dir,
+        hidden: false,
+        size: meta.size,
+        mtime: None,
+    }
+}
+
+impl<F: NorFlash> FileSystem for NorFsVolume<F> {
+    fn kind(&self) -> &'static str {
+        "norfs"
+    }
+
+    fn stats(&mut self) -> Result<Stats, VfsError> {
+        let stats = self.0.stats();
+        Ok(Stats {
+            total_bytes: stats.total_bytes,
+            free_bytes: Some(stats.free_bytes),
+            wear: Some(Wear {
+                block_size: stats.block_size,
+                blocks: stats.blocks,
+                free_blocks: stats.free_blocks,
+                min_erases: stats.min_erases,
+                max_erases: stats.max_erases,
+            }),
+        })
+    }
+
+    fn metadata(&mut self, path: Path) -> Result<Metadata, VfsError> {
+        Ok(to_metadata(&self.0.metadata(&local(path))?))
+    }
+
+    fn ls(&mut self, path: Path, func: &mut dyn FnMut(&Metadata)) -> Result<(), VfsError> {
+        Ok(self
+            .0
+            .read_dir(&local(path), |meta| func(&to_metadata(meta)))?)
+    }
+
+    fn read_at(&mut self, path: Path, offset: u32, data: &mut [u8]) -> Result<usize, VfsError> {
+        Ok(self.0.read(&local(path), offset, data)?)
+    }
+
+    fn read_chunks(
+        &mut self,
+        path: Path,
+        buf: &mut [u8],
+        func: &mut dyn FnMut(&[u8]) -> bool,
+    ) -> Result<(), VfsError> {
+        let path = local(path);
+        let mut offset = 0;
+        loop {
+            let n = self.0.read(&path, offset, buf)?;
+            if n == 0 || !func(&buf[..n]) {
+                return Ok(());
+            }
+            offset += n as u32;
+        }
+    }
+
+    fn write(&mut self, path: Path, mode: WriteMode, data: &[u8]) -> Result<(), VfsError> {
+        Ok(self.0.write(&local(path), mode.into(), data)?)
+    }
+
+    fn touch(&mut self, path: Path) -> Result<(), VfsError> {
+        Ok(self.0.touch(&local(path))?)
+    }
+
+    fn delete(&mut self, path: Path) -> Result<(), VfsError> {
+        Ok(self.0.remove_file(&local(path))?)
+    }
+
+    fn create_dir(&mut self, path: Path, parents: bool) -> Result<(), VfsError> {
+        Ok(self.0.create_dir(&local(path), parents)?)
+    }
+
+    fn remove_dir(&mut self, path: Path) -> Result<(), VfsError> {
+        Ok(self.0.remove_dir(&local(path))?)
+    }
+
+    fn rename(&mut self, from: Path, to: Path, _buf: &mut [u8]) -> Result<(), VfsError> {
+        Ok(self.0.rename(&local(from), &local(to))?)
+    }
+}
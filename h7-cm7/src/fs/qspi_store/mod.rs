@@ -1,10 +1,15 @@
 use {
-    core::cell::RefCell,
+    crate::utils::interrupt_free,
+    core::{
+        cell::RefCell,
+        sync::atomic::{AtomicBool, Ordering},
+    },
     critical_section::Mutex,
-    mx25l::Mx25L,
+    embedded_storage::nor_flash::{ErrorType, NorFlashError, NorFlashErrorKind, ReadNorFlash},
+    mx25l::{ChipSelect, Mx25L},
     stm32h7xx_hal::{
         gpio::{gpiog::PG6, Output, PushPull},
-        pac::QUADSPI,
+        pac::{self, QUADSPI},
         xspi::{Qspi, QspiError},
     },
 };
@@ -12,8 +17,19 @@ use {
 #[allow(dead_code)]
 pub mod mx25l;
 
-pub const QSPI_FLASH_SIZE: usize = 16 * 1024 * 1024;
 pub static QSPI_STORE: Mutex<RefCell<Option<NorFlash>>> = Mutex::new(RefCell::new(None));
+/// The flash is taken out of [`QSPI_STORE`] by [`QspiFlash`]
+static QSPI_STORE_BUSY: AtomicBool = AtomicBool::new(false);
+
+/// Size of the external flash, as discovered through SFDP once the driver is initialized
+pub fn flash_size() -> usize {
+    interrupt_free(|cs| {
+        QSPI_STORE
+            .borrow_ref(cs)
+            .as_ref()
+            .map_or(mx25l::DEFAULT_CAPACITY, |store| store.capacity())
+    })
+}
 
 pub struct NorFlash {
     mx25l: Mx25L<PG6<Output<PushPull>>>,
@@ -41,6 +57,28 @@ impl NorFlash {
     // }
 }
 
+impl ChipSelect for PG6<Output<PushPull>> {
+    fn to_peripheral(&mut self) {
+        interrupt_free(|_| {
+            // Safety: only the bits of PG6, which `self` owns, change, with interrupts masked so
+            // other pins' read-modify-writes don't interleave
+            let gpiog = unsafe { &*pac::GPIOG::ptr() };
+            gpiog.afrl.modify(|_, w| w.afr6().af10()); // QUADSPI_BK1_NCS
+            gpiog.moder.modify(|_, w| w.moder6().alternate());
+        });
+    }
+
+    fn to_gpio(&mut self) {
+        // Takes over high, so CS doesn't glitch low
+        self.set_high();
+        interrupt_free(|_| {
+            // Safety: as above
+            let gpiog = unsafe { &*pac::GPIOG::ptr() };
+            gpiog.moder.modify(|_, w| w.moder6().output());
+        });
+    }
+}
+
 impl core::ops::Deref for NorFlash {
     type Target = Mx25L<PG6<Output<PushPull>>>;
 
@@ -54,3 +92,76 @@ impl core::ops::DerefMut for NorFlash {
         &mut self.mx25l
     }
 }
+
+#[derive(Debug, Clone, Copy)]
+pub enum QspiFlashError {
+    NotInitialized,
+    /// Used from within an operation on it
+    Busy,
+    Flash(mx25l::Error),
+}
+
+impl NorFlashError for QspiFlashError {
+    fn kind(&self) -> NorFlashErrorKind {
+        match self {
+            Self::NotInitialized | Self::Busy => NorFlashErrorKind::Other,
+            Self::Flash(e) => e.kind(),
+        }
+    }
+}
+
+/// [`QSPI_STORE`] as an `embedded-storage` NOR flash
+pub struct QspiFlash;
+
+impl QspiFlash {
+    /// The flash is taken out of [`QSPI_STORE`] while `f` runs, so sector erases don't mask
+    /// interrupts
+    fn with<T>(
+        f: impl FnOnce(&mut Mx25L<PG6<Output<PushPull>>>) -> Result<T, mx25l::Error>,
+    ) -> Result<T, QspiFlashError> {
+        let flash = interrupt_free(|cs| match QSPI_STORE.borrow(cs).take() {
+            Some(flash) => {
+                QSPI_STORE_BUSY.store(true, Ordering::Relaxed);
+                Ok(flash)
+            }
+            None if QSPI_STORE_BUSY.load(Ordering::Relaxed) => Err(QspiFlashError::Busy),
+            None => Err(QspiFlashError::NotInitialized),
+        });
+        let mut flash = flash?;
+        let result = f(&mut flash).map_err(QspiFlashError::Flash);
+        interrupt_free(|cs| {
+            QSPI_STORE.borrow(cs).replace(Some(flash));
+            QSPI_STORE_BUSY.store(false, Ordering::Relaxed);
+        });
+        result
+    }
+}
+
+impl ErrorType for QspiFlash {
+    type Error = QspiFlashError;
+}
+
+impl ReadNorFlash for QspiFlash {
+    const READ_SIZE: usize = 1;
+
+    fn read(&mut self, offset: u32, bytes: &mut [u8]) -> Result<(), Self::Error> {
+        Self::with(|flash| ReadNorFlash::read(flash, offset, bytes))
+    }
+
+    fn capacity(&self) -> usize {
+        Self::with(|flash| Ok(ReadNorFlash::capacity(flash))).unwrap_or(0)
+    }
+}
+
+impl embedded_storage::nor_flash::NorFlash for QspiFlash {
+    const WRITE_SIZE: usize = 1;
+    const ERASE_SIZE: usize = mx25l::SECTOR_SIZE;
+
+    fn erase(&mut self, from: u32, to: u32) -> Result<(), Self::Error> {
+        Self::with(|flash| embedded_storage::nor_flash::NorFlash::erase(flash, from, to))
+    }
+
+    fn write(&mut self, offset: u32, bytes: &[u8]) -> Result<(), Self::Error> {
+        Self::with(|flash| embedded_storage::nor_flash::NorFlash::write(flash, offset, bytes))
+    }
+}
@@ -1,5 +1,11 @@
 use {
+    alloc::{vec, vec::Vec},
     embedded_hal::digital::v2::OutputPin,
+    embedded_storage::nor_flash::{
+        check_erase, check_read, check_write, ErrorType, NorFlash, NorFlashError,
+        NorFlashErrorKind, ReadNorFlash,
+    },
+    h7_fs::sfdp::{self, AddressBytes, EraseType, FastRead, FlashParameters, ReadMode},
     stm32h7xx_hal::{
         pac::QUADSPI,
         rcc,
@@ -10,30 +16,175 @@ use {
 pub mod cmd;
 pub mod status;
 
-pub struct Mx25L<CS: OutputPin> {
+/// Largest transfer the QSPI FIFO takes in indirect mode
+const FIFO_SIZE: usize = 32;
+/// Dummy cycles of `RDSFDP`
+const SFDP_DUMMY_CYCLES: u8 = 8;
+/// Where the flash shows up in memory-mapped mode
+const MEMORY_MAPPED_BASE: usize = 0x9000_0000;
+
+/// Smallest erase, parts without a 4 KiB erase type are not supported
+pub const SECTOR_SIZE: usize = 4 * 1024;
+/// Density of the MX25L12833F on the GIGA R1
+pub const DEFAULT_CAPACITY: usize = 16 * 1024 * 1024;
+/// Commands are sent with 3 address bytes, which reach this far
+const MAX_CAPACITY: u64 = 1 << 24;
+
+/// The chip select line, driven as a GPIO for commands. In memory-mapped mode QUADSPI has to
+/// drive it: the flash only takes the address of a non-sequential read after a rising edge.
+pub trait ChipSelect: OutputPin {
+    /// Hand the pin to QUADSPI, its NCS alternate function
+    fn to_peripheral(&mut self);
+    /// Back to a GPIO output, deselected
+    fn to_gpio(&mut self);
+}
+
+/// Parameters of the MX25L12833F, for parts that can't be discovered through SFDP
+pub fn default_parameters() -> FlashParameters {
+    let read = |mode, opcode, dummy_cycles, mode_clocks| FastRead {
+        mode,
+        opcode,
+        dummy_cycles,
+        mode_clocks,
+    };
+    let erase = |size, opcode| EraseType { size, opcode };
+    FlashParameters {
+        major: 1,
+        minor: 0,
+        capacity: DEFAULT_CAPACITY as u64,
+        page_size: 256,
+        address_bytes: AddressBytes::Three,
+        erase_types: vec![
+            erase(4 * 1024, cmd::SE),
+            erase(32 * 1024, cmd::BE_32),
+            erase(64 * 1024, cmd::BE_64),
+        ],
+        fast_reads: vec![
+            read(ReadMode::Read112, cmd::DREAD, 8, 0),
+            read(ReadMode::Read122, cmd::READ_2, 4, 0),
+            read(ReadMode::Read114, cmd::QREAD, 8, 0),
+            read(ReadMode::Read144, cmd::READ_4, 4, 2),
+            read(ReadMode::Read444, cmd::READ_4, 4, 2),
+        ],
+    }
+}
+
+/// How many I/O lines every phase of a command uses
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BusMode {
+    /// SPI, 1-1-1
+    Single,
+    /// QPI, 4-4-4
+    Quad,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub enum Error {
+    NotAligned,
+    OutOfBounds,
+    Qspi(QspiError),
+}
+
+impl NorFlashError for Error {
+    fn kind(&self) -> NorFlashErrorKind {
+        match self {
+            Self::NotAligned => NorFlashErrorKind::NotAligned,
+            Self::OutOfBounds => NorFlashErrorKind::OutOfBounds,
+            Self::Qspi(_) => NorFlashErrorKind::Other,
+        }
+    }
+}
+
+impl From<NorFlashErrorKind> for Error {
+    fn from(kind: NorFlashErrorKind) -> Self {
+        match kind {
+            NorFlashErrorKind::NotAligned => Self::NotAligned,
+            _ => Self::OutOfBounds,
+        }
+    }
+}
+
+impl From<QspiError> for Error {
+    fn from(err: QspiError) -> Self {
+        Self::Qspi(err)
+    }
+}
+
+pub struct Mx25L<CS: ChipSelect> {
     qspi: Qspi<QUADSPI>,
     cs: CS,
+    mode: BusMode,
+    parameters: FlashParameters,
+    memory_mapped: bool,
 }
 
-impl<CS: OutputPin> Mx25L<CS> {
+impl<CS: ChipSelect> Mx25L<CS> {
     pub fn new(qspi: Qspi<QUADSPI>, cs: CS) -> Self {
-        Self { qspi, cs }
+        Self {
+            qspi,
+            cs,
+            mode: BusMode::Single,
+            parameters: default_parameters(),
+            memory_mapped: false,
+        }
     }
 
     pub fn init(&mut self) -> Result<(), QspiError> {
         self.qspi.configure_mode(QspiMode::OneBit)?;
+        self.mode = BusMode::Single;
         // self.write_extended(QspiWord::U8(0x41), QspiWord::None, QspiWord::None, &[])?;
         self.reset()?;
         self.exit_deep_sleep()?;
         self.write_status()?;
+        // Parts without SFDP keep the MX25L parameters
+        let _ = self.discover();
 
         Ok(())
     }
 
+    /// Read the SFDP tables and use what they say about the part from now on. Larger parts are
+    /// used up to [`MAX_CAPACITY`], 4-byte addressing isn't supported.
+    pub fn discover(&mut self) -> Result<&FlashParameters, sfdp::Error<QspiError>> {
+        let mut parameters = sfdp::read(|address, buf| self.read_sfdp(address, buf))?;
+        if parameters.capacity > MAX_CAPACITY {
+            log::warn!(
+                "QSPI flash has {} MiB, only the first {} MiB are used with {:?} address bytes",
+                parameters.capacity >> 20,
+                MAX_CAPACITY >> 20,
+                parameters.address_bytes
+            );
+            parameters.capacity = MAX_CAPACITY;
+        }
+        self.parameters = parameters;
+        Ok(&self.parameters)
+    }
+
+    pub fn parameters(&self) -> &FlashParameters {
+        &self.parameters
+    }
+
+    /// Read the SFDP space, see [`Mx25L::discover`]
+    pub fn read_sfdp(&mut self, address: u32, data: &mut [u8]) -> Result<(), QspiError> {
+        for (i, chunk) in data.chunks_mut(FIFO_SIZE).enumerate() {
+            self.read_extended(
+                QspiWord::U8(cmd::RDSFDP),
+                QspiWord::U24(address + (i * FIFO_SIZE) as u32),
+                QspiWord::None,
+                SFDP_DUMMY_CYCLES,
+                chunk,
+            )?;
+        }
+        Ok(())
+    }
+
     pub fn reaq_identification(&mut self) -> Result<[u8; 3], QspiError> {
         let mut id = [0u8; 3];
+        let instruction = match self.mode {
+            BusMode::Single => cmd::RDID,
+            BusMode::Quad => cmd::QPIID,
+        };
         self.read_extended(
-            QspiWord::U8(0x9f),
+            QspiWord::U8(instruction),
             QspiWord::None,
             QspiWord::None,
             0,
@@ -82,37 +233,103 @@ impl<CS: OutputPin> Mx25L<CS> {
         Ok(())
     }
 
+    pub fn mode(&self) -> BusMode {
+        self.mode
+    }
+
+    /// Size of the flash in bytes
+    pub fn capacity(&self) -> usize {
+        self.parameters.capacity as usize
+    }
+
+    /// Instruction and wait cycles of the read used in QPI mode
+    fn quad_read(&self) -> (u8, u8) {
+        match self.parameters.fast_read(ReadMode::Read444) {
+            Some(read) => (read.opcode, read.wait_cycles()),
+            None => (cmd::READ_4, 6),
+        }
+    }
+
     pub fn read(&mut self, address: u32, data: &mut [u8]) -> Result<(), QspiError> {
-        self.read_extended(
-            QspiWord::U8(cmd::READ),
-            QspiWord::U24(address),
-            QspiWord::None,
-            0,
-            data,
-        )?;
+        let (instruction, dummy_cycles) = match self.mode {
+            BusMode::Single => (cmd::READ, 0),
+            BusMode::Quad => self.quad_read(),
+        };
+        for (i, chunk) in data.chunks_mut(FIFO_SIZE).enumerate() {
+            self.read_extended(
+                QspiWord::U8(instruction),
+                QspiWord::U24(address + (i * FIFO_SIZE) as u32),
+                QspiWord::None,
+                dummy_cycles,
+                chunk,
+            )?;
+        }
         Ok(())
     }
 
+    /// Program `data` at `address`, split at page boundaries. The bytes must have been erased.
     pub fn write(&mut self, address: u32, data: &[u8]) -> Result<(), QspiError> {
-        // self.enable_write()?;
-        // self.write_extended(
-        //     QspiWord::U8(cmd::BE_32),
-        //     QspiWord::U24(address),
-        //     QspiWord::None,
-        //     &[],
-        // )?;
-
-        // the internal write buffer is 32 bytes
-        for chunk in data.chunks(32) {
+        let page_size = self.parameters.page_size as usize;
+        let mut address = address;
+        let mut data = data;
+        while !data.is_empty() {
+            let len = data.len().min(page_size - address as usize % page_size);
+            let (page, rest) = data.split_at(len);
+            self.program_page(address, page)?;
+            address += len as u32;
+            data = rest;
+        }
+        Ok(())
+    }
+
+    /// Program bytes within one page, a program that runs past the end of a page wraps around to
+    /// its start
+    pub fn program_page(&mut self, address: u32, data: &[u8]) -> Result<(), QspiError> {
+        let page_size = self.parameters.page_size as usize;
+        debug_assert!(address as usize % page_size + data.len() <= page_size);
+        // In QPI mode PP takes address and data on four lines, 4PP is for SPI mode only
+        for (i, chunk) in data.chunks(FIFO_SIZE).enumerate() {
             self.enable_write()?;
             self.write_extended(
                 QspiWord::U8(cmd::PP),
-                QspiWord::U24(address),
+                QspiWord::U24(address + (i * FIFO_SIZE) as u32),
                 QspiWord::None,
                 chunk,
             )?;
         }
+        Ok(())
+    }
 
+    /// Erase the 4 KiB sector `address` is in
+    pub fn sector_erase(&mut self, address: u32) -> Result<(), QspiError> {
+        let opcode = self
+            .parameters
+            .erase_type(SECTOR_SIZE as u32)
+            .map_or(cmd::SE, |erase| erase.opcode);
+        self.erase_command(opcode, address)
+    }
+
+    /// Erase `from..to`, with the largest erase type the alignment allows
+    pub fn erase_range(&mut self, from: u32, to: u32) -> Result<(), QspiError> {
+        let erase_types: Vec<EraseType> =
+            self.parameters.erase_types.iter().rev().copied().collect();
+        let mut address = from;
+        while address < to {
+            let left = to - address;
+            match erase_types
+                .iter()
+                .find(|erase| address % erase.size == 0 && left >= erase.size)
+            {
+                Some(erase) => {
+                    self.erase_command(erase.opcode, address)?;
+                    address += erase.size;
+                }
+                None => {
+                    self.sector_erase(address)?;
+                    address += SECTOR_SIZE as u32;
+                }
+            }
+        }
         Ok(())
     }
 
@@ -126,6 +343,91 @@ impl<CS: OutputPin> Mx25L<CS> {
         )
     }
 
+    /// Switch to QPI, every phase of a command on four lines
+    pub fn enter_quad_mode(&mut self) -> Result<(), QspiError> {
+        if self.mode == BusMode::Quad {
+            return Ok(());
+        }
+        let status = self.read_status()?;
+        if status & status::QE == 0 {
+            self.enable_write()?;
+            self.write_extended(
+                QspiWord::U8(cmd::WRSR),
+                QspiWord::None,
+                QspiWord::None,
+                &[status | status::QE],
+            )?;
+        }
+        self.write_extended(QspiWord::U8(cmd::EQIO), QspiWord::None, QspiWord::None, &[])?;
+        self.qspi.configure_mode(QspiMode::FourBit)?;
+        self.mode = BusMode::Quad;
+        Ok(())
+    }
+
+    /// Back to SPI, one line for every phase
+    pub fn exit_quad_mode(&mut self) -> Result<(), QspiError> {
+        if self.mode == BusMode::Single {
+            return Ok(());
+        }
+        self.write_extended(
+            QspiWord::U8(cmd::RSTQIO),
+            QspiWord::None,
+            QspiWord::None,
+            &[],
+        )?;
+        self.qspi.configure_mode(QspiMode::OneBit)?;
+        self.mode = BusMode::Single;
+        Ok(())
+    }
+
+    /// Map the flash at `0x9000_0000`, the QSPI peripheral then issues reads on its own.
+    ///
+    /// Any other command leaves memory-mapped mode first, the returned slice must not be used
+    /// after that. Lines cached by the D-cache are not invalidated after a program or erase.
+    pub fn enter_memory_mapped(&mut self) -> Result<&'static [u8], QspiError> {
+        let (instruction, lines, dummy_cycles) = match self.mode {
+            BusMode::Single => (cmd::READ, 0b01, 0),
+            BusMode::Quad => {
+                let (instruction, dummy_cycles) = self.quad_read();
+                (instruction, 0b11, dummy_cycles as u32)
+            }
+        };
+        self.abort();
+        // Safety: the registers belong to `self.qspi`, which is borrowed mutably
+        let regs = unsafe { &*QUADSPI::ptr() };
+        let size_bits = self.capacity().trailing_zeros() - 1;
+        regs.dcr
+            .modify(|r, w| unsafe { w.bits((r.bits() & !(0x1f << 16)) | (size_bits << 16)) });
+        let ccr = (0b11 << 26) // functional mode: memory-mapped
+            | (lines << 24) // data
+            | (dummy_cycles << 18)
+            | (0b10 << 12) // 24 bit address
+            | (lines << 10) // address
+            | (lines << 8) // instruction
+            | instruction as u32;
+        self.cs.to_peripheral();
+        regs.ccr.write(|w| unsafe { w.bits(ccr) });
+        self.memory_mapped = true;
+        // Safety: the region is mapped read-only for as long as memory-mapped mode lasts
+        Ok(
+            unsafe {
+                core::slice::from_raw_parts(MEMORY_MAPPED_BASE as *const u8, self.capacity())
+            },
+        )
+    }
+
+    pub fn exit_memory_mapped(&mut self) {
+        if self.memory_mapped {
+            self.abort();
+            self.cs.to_gpio();
+            self.memory_mapped = false;
+        }
+    }
+
+    pub fn is_memory_mapped(&self) -> bool {
+        self.memory_mapped
+    }
+
     pub fn enter_deep_sleep(&mut self) -> Result<(), QspiError> {
         self.write_extended(QspiWord::U8(cmd::DP), QspiWord::None, QspiWord::None, &[])?;
         Ok(())
@@ -162,6 +464,27 @@ impl<CS: OutputPin> Mx25L<CS> {
 
     // --------------------------------------------------------
 
+    fn erase_command(&mut self, instruction: u8, address: u32) -> Result<(), QspiError> {
+        self.enable_write()?;
+        self.write_extended(
+            QspiWord::U8(instruction),
+            QspiWord::U24(address),
+            QspiWord::None,
+            &[],
+        )
+    }
+
+    /// Stop the ongoing command, or memory-mapped mode
+    fn abort(&mut self) {
+        // Safety: the registers belong to `self.qspi`, which is borrowed mutably
+        let regs = unsafe { &*QUADSPI::ptr() };
+        regs.cr
+            .modify(|r, w| unsafe { w.bits(r.bits() | (1 << 1)) });
+        while regs.cr.read().bits() & (1 << 1) != 0 {
+            cortex_m::asm::nop();
+        }
+    }
+
     #[allow(clippy::wrong_self_convention)]
     fn is_busy(&mut self) -> Result<bool, QspiError> {
         Ok((self.read_status()? & status::WIP) != 0)
@@ -196,6 +519,7 @@ impl<CS: OutputPin> Mx25L<CS> {
         alternate_bytes: QspiWord,
         data: &[u8],
     ) -> Result<(), QspiError> {
+        self.exit_memory_mapped();
         self.chip_select();
         self.qspi
             .write_extended(instruction, address, alternate_bytes, data)?;
@@ -214,6 +538,7 @@ impl<CS: OutputPin> Mx25L<CS> {
         dummy_cycles: u8,
         dest: &mut [u8],
     ) -> Result<(), QspiError> {
+        self.exit_memory_mapped();
         self.chip_select();
         self.qspi
             .read_extended(instruction, address, alternate_bytes, dummy_cycles, dest)?;
@@ -221,3 +546,35 @@ impl<CS: OutputPin> Mx25L<CS> {
         Ok(())
     }
 }
+
+impl<CS: ChipSelect> ErrorType for Mx25L<CS> {
+    type Error = Error;
+}
+
+impl<CS: ChipSelect> ReadNorFlash for Mx25L<CS> {
+    const READ_SIZE: usize = 1;
+
+    fn read(&mut self, offset: u32, bytes: &mut [u8]) -> Result<(), Self::Error> {
+        check_read(self, offset, bytes.len())?;
+        Ok(Mx25L::read(self, offset, bytes)?)
+    }
+
+    fn capacity(&self) -> usize {
+        Mx25L::capacity(self)
+    }
+}
+
+impl<CS: ChipSelect> NorFlash for Mx25L<CS> {
+    const WRITE_SIZE: usize = 1;
+    const ERASE_SIZE: usize = SECTOR_SIZE;
+
+    fn erase(&mut self, from: u32, to: u32) -> Result<(), Self::Error> {
+        check_erase(self, from, to)?;
+        Ok(self.erase_range(from, to)?)
+    }
+
+    fn write(&mut self, offset: u32, bytes: &[u8]) -> Result<(), Self::Error> {
+        check_write(self, offset, bytes.len())?;
+        Ok(Mx25L::write(self, offset, bytes)?)
+    }
+}
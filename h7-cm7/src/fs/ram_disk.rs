@@ -0,0 +1,35 @@
+use {
+    super::{nor_fs::NorFsVolume, vfs, vfs::VfsError},
+    crate::mem,
+    alloc::boxed::Box,
+    h7_fs::norfs::{MemFlash, NorFs},
+};
+
+/// Device the RAM disk is mounted on
+pub const DEVICE: &str = "ram";
+/// Size used when `ram mount` isn't given one
+pub const DEFAULT_SIZE: usize = 4 * 1024 * 1024;
+/// Heap left to everything else, a failed allocation panics
+pub const HEAP_RESERVE: usize = 1024 * 1024;
+
+const BLOCK_SIZE: usize = 4096;
+
+/// Heap available to a RAM disk
+pub fn available() -> usize {
+    (mem::HEAP_SIZE - mem::ALLOCATOR.used()).saturating_sub(HEAP_RESERVE)
+}
+
+/// Carve a RAM disk of `size` bytes, rounded up to whole blocks, out of the SDRAM heap and mount
+/// it on `ram:`. Its content is lost when it is unmounted.
+pub fn mount(size: usize) -> Result<(), VfsError> {
+    if vfs::is_mounted(DEVICE) {
+        return Err(VfsError::AlreadyMounted(DEVICE.into()));
+    }
+    let size = match size.div_ceil(BLOCK_SIZE).checked_mul(BLOCK_SIZE) {
+        Some(size) if size <= available() => size,
+        _ => return Err(VfsError::OutOfMemory(size)),
+    };
+    let flash = MemFlash::<BLOCK_SIZE>::new(size / BLOCK_SIZE);
+    let fs = NorFs::format(flash)?;
+    vfs::mount(DEVICE, Box::new(NorFsVolume::new(fs)))
+}
@@ -0,0 +1,71 @@
+use {
+    super::H7Sdmmc,
+    alloc::string::{String, ToString},
+    h7_fs::sd::{self, CapacityClass},
+    stm32h7xx_hal::{pac::SDMMC2, time::Hertz},
+};
+
+/// What the card says about itself in its CID, CSD, SCR and SD status registers, and the bus it
+/// was brought up on. Read when the card is mounted.
+#[derive(Debug, Clone)]
+pub struct CardInfo {
+    pub manufacturer_id: u8,
+    pub oem_id: String,
+    pub product_name: String,
+    pub product_revision: u8,
+    pub serial: u32,
+    /// Year and month
+    pub manufactured: (u16, u8),
+    pub capacity_class: CapacityClass,
+    pub capacity: u64,
+    /// Maximum transfer rate in kbit/s
+    pub max_transfer_rate: u32,
+    pub spec_version: &'static str,
+    pub speed_class: Option<u8>,
+    pub uhs_grade: u8,
+    pub video_speed_class: u8,
+    /// Allocation unit size in bytes
+    pub au_size: Option<u32>,
+    /// Data lines in use
+    pub bus_width: u8,
+    pub clock: Hertz,
+}
+
+impl CardInfo {
+    pub fn read(sdmmc: &H7Sdmmc) -> Option<Self> {
+        let card = sdmmc.card().ok()?;
+        let (month, year) = card.cid.manufacturing_date();
+        let bus_width = match unsafe { (*SDMMC2::ptr()).clkcr.read().widbus().bits() } {
+            0 => 1,
+            1 => 4,
+            _ => 8,
+        };
+        Some(Self {
+            manufacturer_id: card.cid.manufacturer_id(),
+            oem_id: card.cid.oem_id().to_string(),
+            product_name: card.cid.product_name().to_string(),
+            product_revision: card.cid.product_revision(),
+            serial: card.cid.serial(),
+            manufactured: (year, month),
+            capacity_class: CapacityClass::new(card.csd.version(), card.csd.card_size()),
+            capacity: card.csd.card_size(),
+            max_transfer_rate: sd::transfer_rate_kbit(card.csd.transfer_rate()),
+            spec_version: sd::spec_version(
+                card.scr.version(),
+                card.scr.sd_spec3(),
+                card.scr.sd_spec4(),
+                card.scr.sd_specx(),
+            ),
+            speed_class: sd::speed_class(card.status.speed_class()),
+            uhs_grade: card.status.uhs_speed_grade(),
+            video_speed_class: card.status.video_speed_class(),
+            au_size: sd::au_size(card.status.allocation_unit_size()),
+            bus_width,
+            clock: sdmmc.clock(),
+        })
+    }
+
+    pub fn manufacturer(&self) -> &'static str {
+        sd::manufacturer(self.manufacturer_id).unwrap_or("Unknown")
+    }
+}
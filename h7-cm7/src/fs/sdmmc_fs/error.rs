@@ -1,14 +1,36 @@
-use stm32h7xx_hal::sdmmc::Error;
+use {
+    h7_fs::fat::{self, FormatError},
+    stm32h7xx_hal::sdmmc::Error,
+};
 
 pub enum SdmmcFsError {
     NotFound,
+    AlreadyExists,
+    InvalidOffset,
+    NotADirectory,
+    IsADirectory,
+    DirectoryNotEmpty,
     // BufferTooSmall,
     AlreadyMounted,
     NotMounted,
+    NotInitialized,
+    /// In use by the operation this one was started from
+    Busy,
+    NoSuchPartition(usize),
+    Format(FormatError),
     Sdmmc(embedded_sdmmc::Error<Error>),
     HalSdmmc(Error),
 }
 
+impl From<fat::Error<Error>> for SdmmcFsError {
+    fn from(err: fat::Error<Error>) -> Self {
+        match err {
+            fat::Error::Io(e) => Self::HalSdmmc(e),
+            fat::Error::Format(e) => Self::Format(e),
+        }
+    }
+}
+
 impl From<Error> for SdmmcFsError {
     fn from(err: Error) -> Self {
         Self::HalSdmmc(err)
@@ -19,9 +41,18 @@ impl core::fmt::Display for SdmmcFsError {
     fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
         match self {
             Self::NotFound => write!(f, "Not Found"),
+            Self::AlreadyExists => write!(f, "Already exists"),
+            Self::InvalidOffset => write!(f, "Invalid offset"),
+            Self::NotADirectory => write!(f, "Not a directory"),
+            Self::IsADirectory => write!(f, "Is a directory"),
+            Self::DirectoryNotEmpty => write!(f, "Directory not empty"),
             // Self::BufferTooSmall => write!(f, "Buffer Too Small"),
             Self::AlreadyMounted => write!(f, "Already mounted"),
             Self::NotMounted => write!(f, "Not Mounted"),
+            Self::NotInitialized => write!(f, "SD Card controller not initialized"),
+            Self::Busy => write!(f, "SD Card busy"),
+            Self::NoSuchPartition(n) => write!(f, "No usable partition {n}"),
+            Self::Format(e) => write!(f, "{e}"),
             Self::Sdmmc(e) => write!(f, "Sdmmc: {e:?}"),
             Self::HalSdmmc(e) => write!(f, "HalSdmmc: {e:?}"),
         }
@@ -30,7 +61,14 @@ impl core::fmt::Display for SdmmcFsError {
 
 impl From<embedded_sdmmc::Error<Error>> for SdmmcFsError {
     fn from(err: embedded_sdmmc::Error<Error>) -> Self {
-        Self::Sdmmc(err)
+        match err {
+            embedded_sdmmc::Error::FileNotFound => Self::NotFound,
+            embedded_sdmmc::Error::FileAlreadyExists => Self::AlreadyExists,
+            embedded_sdmmc::Error::OpenedDirAsFile | embedded_sdmmc::Error::DeleteDirAsFile => {
+                Self::IsADirectory
+            }
+            err => Self::Sdmmc(err),
+        }
     }
 }
 
@@ -0,0 +1,168 @@
+//! Raw FAT directory entry edits for what `embedded-sdmmc` can't do itself
+
+use embedded_sdmmc::{Block, BlockDevice, BlockIdx, DirEntry};
+
+pub const ATTR_DIRECTORY: u8 = 0x10;
+pub const ATTR_ARCHIVE: u8 = 0x20;
+
+const ENTRY_LEN: usize = 32;
+const OFFSET_ATTRIBUTES: usize = 11;
+const OFFSET_CLUSTER_HI: usize = 20;
+const OFFSET_CLUSTER_LO: usize = 26;
+const OFFSET_SIZE: usize = 28;
+
+// MBR partition table, first entry
+const MBR_PARTITION_LBA: usize = 0x1C6;
+// BIOS parameter block
+const BPB_SECTORS_PER_CLUSTER: usize = 13;
+const BPB_RESERVED_SECTORS: usize = 14;
+const BPB_NUM_FATS: usize = 16;
+const BPB_ROOT_ENTRIES: usize = 17;
+const BPB_TOTAL_SECTORS_16: usize = 19;
+const BPB_FAT_SIZE_16: usize = 22;
+const BPB_TOTAL_SECTORS_32: usize = 32;
+const BPB_FAT_SIZE_32: usize = 36;
+const BPB_FS_INFO: usize = 48;
+/// Volumes with fewer clusters are FAT12
+const FAT16_MIN_CLUSTERS: u32 = 4085;
+// FAT32 FSInfo sector
+const FS_INFO_LEAD_SIGNATURE: u32 = 0x4161_5252;
+const FS_INFO_FREE_COUNT: usize = 488;
+const FS_INFO_UNKNOWN: u32 = 0xFFFF_FFFF;
+
+/// The `.` and `..` entries every directory but the root starts with.
+/// `parent` is 0 when the parent is the root directory.
+pub fn dot_entries(cluster: u32, parent: u32) -> [u8; 2 * ENTRY_LEN] {
+    let mut entries = [0u8; 2 * ENTRY_LEN];
+    for (entry, name, cluster) in [
+        (&mut entries[..ENTRY_LEN], b".          ", cluster),
+        (&mut entries[ENTRY_LEN..], b"..         ", parent),
+    ] {
+        entry[..11].copy_from_slice(name);
+        entry[OFFSET_ATTRIBUTES] = ATTR_DIRECTORY;
+        entry[OFFSET_CLUSTER_HI..OFFSET_CLUSTER_HI + 2]
+            .copy_from_slice(&((cluster >> 16) as u16).to_le_bytes());
+        entry[OFFSET_CLUSTER_LO..OFFSET_CLUSTER_LO + 2]
+            .copy_from_slice(&(cluster as u16).to_le_bytes());
+    }
+    entries
+}
+
+/// Rewrite the attributes of `entry` on disk, directories get their size cleared
+pub fn set_attributes<D: BlockDevice>(
+    device: &D,
+    entry: &DirEntry,
+    attributes: u8,
+) -> Result<(), D::Error> {
+    let mut blocks = [Block::new()];
+    device.read(&mut blocks, entry.entry_block, "dir_entry")?;
+    let raw = &mut blocks[0].contents[entry.entry_offset as usize..][..ENTRY_LEN];
+    raw[OFFSET_ATTRIBUTES] = attributes;
+    if attributes & ATTR_DIRECTORY != 0 {
+        raw[OFFSET_SIZE..].fill(0);
+    }
+    device.write(&blocks, entry.entry_block)
+}
+
+/// Cluster size of the first partition, the one `get_volume(VolumeIdx(0))` opens
+pub fn cluster_len<D: BlockDevice>(device: &D) -> Result<usize, D::Error> {
+    let mut blocks = [Block::new()];
+    read_bpb(device, &mut blocks[0])?;
+    Ok(blocks[0].contents[BPB_SECTORS_PER_CLUSTER] as usize * Block::LEN)
+}
+
+/// Size of the data area of the first partition and its free space, from cluster counts.
+///
+/// FAT32 keeps the free count in its FSInfo sector, otherwise the FAT is scanned. The free space of
+/// FAT12 volumes isn't known.
+pub fn volume_stats<D: BlockDevice>(device: &D) -> Result<(u64, Option<u64>), D::Error> {
+    let mut blocks = [Block::new()];
+    device.read(&mut blocks, BlockIdx(0), "mbr")?;
+    let start = le_u32(&blocks[0].contents, MBR_PARTITION_LBA);
+
+    device.read(&mut blocks, BlockIdx(start), "bpb")?;
+    let bpb = &blocks[0].contents;
+    let sectors_per_cluster = bpb[BPB_SECTORS_PER_CLUSTER] as u32;
+    let reserved = le_u16(bpb, BPB_RESERVED_SECTORS);
+    let root_sectors =
+        (le_u16(bpb, BPB_ROOT_ENTRIES) * ENTRY_LEN as u32).div_ceil(Block::LEN as u32);
+    let fat32 = le_u16(bpb, BPB_FAT_SIZE_16) == 0;
+    let fat_size = match fat32 {
+        true => le_u32(bpb, BPB_FAT_SIZE_32),
+        false => le_u16(bpb, BPB_FAT_SIZE_16),
+    };
+    let total_sectors = match le_u16(bpb, BPB_TOTAL_SECTORS_16) {
+        0 => le_u32(bpb, BPB_TOTAL_SECTORS_32),
+        n => n,
+    };
+    let data_sectors =
+        total_sectors.saturating_sub(reserved + bpb[BPB_NUM_FATS] as u32 * fat_size + root_sectors);
+    let clusters = data_sectors / sectors_per_cluster.max(1);
+    let cluster_len = sectors_per_cluster as u64 * Block::LEN as u64;
+    let total = clusters as u64 * cluster_len;
+    let fs_info = le_u16(bpb, BPB_FS_INFO);
+
+    let free = match (fat32, clusters < FAT16_MIN_CLUSTERS) {
+        (true, _) => {
+            device.read(&mut blocks, BlockIdx(start + fs_info), "fs_info")?;
+            let info = &blocks[0].contents;
+            let known = le_u32(info, 0) == FS_INFO_LEAD_SIGNATURE
+                && le_u32(info, FS_INFO_FREE_COUNT) != FS_INFO_UNKNOWN;
+            match known {
+                true => Some(le_u32(info, FS_INFO_FREE_COUNT)),
+                false => Some(free_clusters(device, start + reserved, clusters, 4)?),
+            }
+        }
+        (false, true) => None,
+        (false, false) => Some(free_clusters(device, start + reserved, clusters, 2)?),
+    };
+    Ok((total, free.map(|clusters| clusters as u64 * cluster_len)))
+}
+
+/// Count the free entries of the FAT starting at block `fat`, `entry_len` is 2 for FAT16 and 4
+/// for FAT32
+fn free_clusters<D: BlockDevice>(
+    device: &D,
+    fat: u32,
+    clusters: u32,
+    entry_len: usize,
+) -> Result<u32, D::Error> {
+    let mut blocks = [Block::new()];
+    let mut loaded = None;
+    let mut free = 0;
+    // The first two entries are reserved
+    for cluster in 2..clusters + 2 {
+        let offset = cluster as usize * entry_len;
+        let block = fat + (offset / Block::LEN) as u32;
+        if loaded != Some(block) {
+            device.read(&mut blocks, BlockIdx(block), "fat")?;
+            loaded = Some(block);
+        }
+        let offset = offset % Block::LEN;
+        let entry = match entry_len {
+            4 => le_u32(&blocks[0].contents, offset) & 0x0FFF_FFFF,
+            _ => le_u16(&blocks[0].contents, offset),
+        };
+        if entry == 0 {
+            free += 1;
+        }
+    }
+    Ok(free)
+}
+
+fn read_bpb<D: BlockDevice>(device: &D, block: &mut Block) -> Result<(), D::Error> {
+    let blocks = core::slice::from_mut(block);
+    device.read(blocks, BlockIdx(0), "mbr")?;
+    let lba = le_u32(&blocks[0].contents, MBR_PARTITION_LBA);
+    device.read(blocks, BlockIdx(lba), "bpb")
+}
+
+fn le_u16(bytes: &[u8], offset: usize) -> u32 {
+    u16::from_le_bytes([bytes[offset], bytes[offset + 1]]) as u32
+}
+
+fn le_u32(bytes: &[u8], offset: usize) -> u32 {
+    let mut buf = [0u8; 4];
+    buf.copy_from_slice(&bytes[offset..][..4]);
+    u32::from_le_bytes(buf)
+}
@@ -0,0 +1,86 @@
+//! Mounting cards when they are inserted and unmounting them when they are pulled.
+//!
+//! The slot has no card detect line, so it is probed from the main loop once a second: a
+//! mounted card has to answer a block read, an empty slot is checked by trying to bring a card up.
+
+use {
+    super::{mount_volumes, unmount_volumes, with_sd_card, DEVICE},
+    crate::{settings, system::Timeout, utils::interrupt_free},
+    core::{
+        cell::RefCell,
+        sync::atomic::{AtomicBool, Ordering},
+    },
+    critical_section::Mutex,
+    fugit::RateExtU32,
+    stm32h7xx_hal::delay::Delay,
+};
+
+/// Between checks of the slot
+const POLL_INTERVAL_MS: u64 = 1000;
+
+/// Set by `sdcard unmount`, so the card isn't mounted again right away
+static SUSPENDED: AtomicBool = AtomicBool::new(false);
+
+/// Until the next check, `None` before the first one
+static NEXT_POLL: Mutex<RefCell<Option<Timeout>>> = Mutex::new(RefCell::new(None));
+
+enum Event {
+    Inserted,
+    Removed,
+}
+
+/// Stop mounting cards until [`resume`]
+pub fn suspend() {
+    SUSPENDED.store(true, Ordering::Relaxed);
+}
+
+pub fn resume() {
+    SUSPENDED.store(false, Ordering::Relaxed);
+}
+
+/// Check the slot if [`POLL_INTERVAL_MS`] passed since the last check, called from the main loop
+pub fn poll() {
+    let due = interrupt_free(|cs| {
+        let mut next = NEXT_POLL.borrow_ref_mut(cs);
+        match next.as_mut() {
+            Some(timeout) if !timeout.expired() => false,
+            _ => {
+                *next = Some(Timeout::new(POLL_INTERVAL_MS));
+                true
+            }
+        }
+    });
+    if !due {
+        return;
+    }
+
+    let settings = settings::get();
+    let automount = settings.sd_automount && !SUSPENDED.load(Ordering::Relaxed);
+    // Card init runs with interrupts enabled, the card is taken out of its lock meanwhile
+    let event = with_sd_card(|sdfs| {
+        Ok(match sdfs.is_mounted() {
+            true if !sdfs.probe() => {
+                let _ = sdfs.unmount();
+                Some(Event::Removed)
+            }
+            false if automount => sdfs
+                .mount::<Delay, _>(settings.sd_mount_khz.kHz(), 1, None)
+                .ok()
+                .map(|_| Event::Inserted),
+            _ => None,
+        })
+    })
+    .ok()
+    .flatten();
+    match event {
+        Some(Event::Inserted) => match mount_volumes() {
+            Ok(()) => log::info!("SD card inserted, mounted on '{DEVICE}:'"),
+            Err(e) => log::warn!("SD card inserted, but not mounted: {e}"),
+        },
+        Some(Event::Removed) => {
+            unmount_volumes();
+            log::warn!("SD card removed, '{DEVICE}:' unmounted");
+        }
+        None => {}
+    }
+}
@@ -1,13 +1,27 @@
 use {
-    super::path::Path,
-    crate::time::TimeSource,
-    core::{cell::RefCell, fmt},
+    super::{
+        path::Path,
+        vfs::{FileSystem, Metadata, Stats, VfsError, WriteMode},
+    },
+    crate::{fs::vfs, time::TimeSource, utils::interrupt_free},
+    alloc::{boxed::Box, format, string::String, vec::Vec},
+    chrono::NaiveDate,
+    core::{
+        cell::RefCell,
+        fmt::Write,
+        sync::atomic::{AtomicBool, Ordering},
+    },
     critical_section::Mutex,
     embedded_hal::blocking::delay::DelayMs,
     embedded_sdmmc::{
-        BlockDevice, Controller, DirEntry, Directory, File, Mode as FileOpenMode, Volume, VolumeIdx,
+        Block, BlockDevice, BlockIdx, Controller, DirEntry, Directory, File, Mode as FileOpenMode,
+        Volume, VolumeIdx,
     },
     error::*,
+    h7_fs::{
+        fat::{FormatOptions, Layout},
+        partition::PartitionTable,
+    },
     stm32h7xx_hal::{
         pac::SDMMC2,
         sdmmc::{SdCard, Sdmmc, SdmmcBlockDevice},
@@ -15,16 +29,28 @@ use {
     },
 };
 
-mod error;
+mod card;
+pub mod error;
+mod fat;
+pub mod hotplug;
+mod volume;
+
+pub use card::CardInfo;
+use volume::{FormatDisk, PartitionDevice};
+
+/// Device the first FAT partition of the card is mounted on, partition N is `sdcardN:`
+pub const DEVICE: &str = "sdcard";
 
 const H7_MAX_OPEN_DIRS: usize = 4;
 const H7_MAX_OPEN_FILES: usize = 4;
 
 pub static SD_CARD: Mutex<RefCell<Option<SdmmcFs<H7_MAX_OPEN_DIRS, H7_MAX_OPEN_FILES>>>> =
     Mutex::new(RefCell::new(None));
+/// The card is taken out of [`SD_CARD`] by [`with_sd_card`]
+static SD_CARD_BUSY: AtomicBool = AtomicBool::new(false);
 
 type H7Sdmmc = Sdmmc<SDMMC2, SdCard>;
-type H7SdmmcBlockDev = SdmmcBlockDevice<H7Sdmmc>;
+type H7SdmmcBlockDev = PartitionDevice<SdmmcBlockDevice<H7Sdmmc>>;
 
 enum SdmmcState<const MAX_OPEN_DIRS: usize, const MAX_OPEN_FILES: usize> {
     Controller(Controller<H7SdmmcBlockDev, TimeSource, MAX_OPEN_DIRS, MAX_OPEN_FILES>),
@@ -34,6 +60,8 @@ enum SdmmcState<const MAX_OPEN_DIRS: usize, const MAX_OPEN_FILES: usize> {
 
 pub struct SdmmcFs<const MAX_OPEN_DIRS: usize, const MAX_OPEN_FILES: usize> {
     state: SdmmcState<MAX_OPEN_DIRS, MAX_OPEN_FILES>,
+    card: Option<CardInfo>,
+    partitions: Option<PartitionTable>,
 }
 
 impl<const MAX_OPEN_DIRS: usize, const MAX_OPEN_FILES: usize>
@@ -43,6 +71,8 @@ impl<const MAX_OPEN_DIRS: usize, const MAX_OPEN_FILES: usize>
     pub fn new(sdmmc: H7Sdmmc) -> Self {
         Self {
             state: SdmmcState::Sdmmc(sdmmc),
+            card: None,
+            partitions: None,
         }
     }
 
@@ -54,6 +84,49 @@ impl<const MAX_OPEN_DIRS: usize, const MAX_OPEN_FILES: usize>
         }
     }
 
+    /// Registers of the mounted card
+    pub fn card_info(&self) -> Option<&CardInfo> {
+        self.card.as_ref()
+    }
+
+    /// Partition table of the mounted card, `None` if it has none
+    pub fn partition_table(&self) -> Option<&PartitionTable> {
+        self.partitions.as_ref()
+    }
+
+    /// Direct the following operations to partition `number`, or to the first FAT partition
+    pub fn select(&mut self, number: Option<usize>) -> Result<(), SdmmcFsError> {
+        let controller = match &mut self.state {
+            SdmmcState::Controller(c) => c,
+            SdmmcState::Sdmmc(_) => return Err(SdmmcFsError::NotMounted),
+            SdmmcState::MidSwap => unreachable!(),
+        };
+        let partition = match (&self.partitions, number) {
+            (Some(table), Some(n)) => Some(table.get(n).ok_or(SdmmcFsError::NoSuchPartition(n))?),
+            (Some(table), None) => table.first_fat(),
+            (None, Some(n)) => return Err(SdmmcFsError::NoSuchPartition(n)),
+            (None, None) => None,
+        };
+        match controller.device().select(partition) {
+            true => Ok(()),
+            false => Err(SdmmcFsError::NoSuchPartition(number.unwrap_or_default())),
+        }
+    }
+
+    /// Write a new MBR and an empty FAT filesystem to the card, everything on it is lost
+    pub fn format(&mut self, options: &FormatOptions) -> Result<Layout, SdmmcFsError> {
+        let device = match &mut self.state {
+            SdmmcState::Controller(c) => c.device(),
+            SdmmcState::Sdmmc(_) => return Err(SdmmcFsError::NotMounted),
+            SdmmcState::MidSwap => unreachable!(),
+        };
+        device.select(None);
+        let layout = h7_fs::fat::format(&mut FormatDisk(&*device), options);
+        // Even a failed format may have changed the table
+        self.partitions = device.partition_table().ok().flatten();
+        Ok(layout?)
+    }
+
     pub fn card_size(&mut self) -> Result<u64, SdmmcFsError> {
         match self.state {
             SdmmcState::Controller(ref mut c) => {
@@ -78,6 +151,7 @@ impl<const MAX_OPEN_DIRS: usize, const MAX_OPEN_FILES: usize>
                 for i in (0..n_retry).rev() {
                     match sdmmc.init(freq) {
                         Ok(_) => {
+                            self.card = CardInfo::read(sdmmc);
                             // We just got here because the state is SdmmcState::Sdmmc so this should never fail
                             if let SdmmcState::Sdmmc(sd) =
                                 core::mem::replace(&mut self.state, SdmmcState::MidSwap)
@@ -88,9 +162,18 @@ impl<const MAX_OPEN_DIRS: usize, const MAX_OPEN_FILES: usize>
                                     MAX_OPEN_DIRS,
                                     MAX_OPEN_FILES,
                                 >::new_with_limits(
-                                    sd.sdmmc_block_device(),
+                                    PartitionDevice::new(sd.sdmmc_block_device()),
                                     TimeSource,
                                 ));
+                                self.partitions = match self.state {
+                                    SdmmcState::Controller(ref mut c) => {
+                                        c.device().partition_table().unwrap_or_else(|e| {
+                                            log::warn!("SD card partition table: {e}");
+                                            None
+                                        })
+                                    }
+                                    _ => unreachable!(),
+                                };
                             } else {
                                 unreachable!()
                             }
@@ -115,14 +198,29 @@ impl<const MAX_OPEN_DIRS: usize, const MAX_OPEN_FILES: usize>
         unreachable!()
     }
 
-    /// Useless until https://github.com/stm32-rs/stm32h7xx-hal/issues/145 is fixed
+    /// Whether the mounted card still answers, `false` once it was pulled
+    pub fn probe(&mut self) -> bool {
+        match &mut self.state {
+            SdmmcState::Controller(c) => c
+                .device()
+                .read(&mut [Block::new()], BlockIdx(0), "probe")
+                .is_ok(),
+            SdmmcState::Sdmmc(_) => false,
+            SdmmcState::MidSwap => unreachable!(),
+        }
+    }
+
+    /// Release the card. Nothing stays open between operations, so this can't leave handles
+    /// behind, and the next [`SdmmcFs::mount`] brings a swapped card up from scratch.
     pub fn unmount(&mut self) -> Result<(), SdmmcFsError> {
         match &mut self.state {
             SdmmcState::Controller(_) => {
                 if let SdmmcState::Controller(c) =
                     core::mem::replace(&mut self.state, SdmmcState::MidSwap)
                 {
-                    self.state = SdmmcState::Sdmmc(c.free().0.free());
+                    self.state = SdmmcState::Sdmmc(c.free().0.into_inner().free());
+                    self.card = None;
+                    self.partitions = None;
                     Ok(())
                 } else {
                     unreachable!()
@@ -137,37 +235,218 @@ impl<const MAX_OPEN_DIRS: usize, const MAX_OPEN_FILES: usize>
         &mut self,
         path: P,
         data: &mut [u8],
+    ) -> Result<usize, SdmmcFsError> {
+        self.read_at(path, 0, data)
+    }
+
+    /// Read from `offset` until `data` is full or the end of the file is reached
+    pub fn read_at<'p, P: Into<Path<'p>>>(
+        &mut self,
+        path: P,
+        offset: u32,
+        data: &mut [u8],
     ) -> Result<usize, SdmmcFsError> {
         self.find_file(path, FileOpenMode::ReadOnly, |controller, volume, file| {
-            controller.read(volume, file, data)
+            file.seek_from_start(offset)
+                .map_err(|_| SdmmcFsError::InvalidOffset)?;
+            let mut n = 0;
+            while n < data.len() && !file.eof() {
+                n += controller.read(volume, file, &mut data[n..])?;
+            }
+            Ok(n)
+        })?
+    }
+
+    pub fn write<'p, P: Into<Path<'p>>>(
+        &mut self,
+        path: P,
+        mode: WriteMode,
+        data: &[u8],
+    ) -> Result<(), SdmmcFsError> {
+        self.find_file(path, mode.into(), |controller, volume, file| {
+            controller.write(volume, file, data)?;
+            Ok(())
+        })?
+    }
+
+    /// Create an empty file, fails if it exists
+    pub fn create<'p, P: Into<Path<'p>>>(&mut self, path: P) -> Result<(), SdmmcFsError> {
+        self.write(path, WriteMode::Create, &[])
+    }
+
+    /// Cut an existing file to zero length
+    pub fn truncate<'p, P: Into<Path<'p>>>(&mut self, path: P) -> Result<(), SdmmcFsError> {
+        self.find_file(path, FileOpenMode::ReadWriteTruncate, |_, _, _| ())
+    }
+
+    pub fn delete<'p, P: Into<Path<'p>>>(&mut self, path: P) -> Result<(), SdmmcFsError> {
+        self.find_parent(path, |controller, volume, dir, name| {
+            controller.delete_file_in_dir(volume, dir, name)
+        })?
+        .map_err(SdmmcFsError::from)
+    }
+
+    pub fn metadata<'p, P: Into<Path<'p>>>(&mut self, path: P) -> Result<DirEntry, SdmmcFsError> {
+        self.find_parent(path, |controller, volume, dir, name| {
+            controller.find_directory_entry(volume, dir, name)
         })?
         .map_err(SdmmcFsError::from)
     }
 
-    // pub fn write_file<P: AsRef<str>>(
-    //     &mut self,
-    //     path: P,
-    //     create: bool,
-    //     data: &[u8],
-    // ) -> Result<(), SdmmcFsError> {
-    //     match &mut self.state {
-    //         SdmmcState::Controller(controller) => {
-    //             todo!()
-    //         }
-    //         SdmmcState::Sdmmc(_) => Err(SdmmcFsError::NotMounted),
-    //         SdmmcState::MidSwap => unreachable!(),
-    //     }
-    // }
-
-    // pub fn exists<P: AsRef<str>>(&mut self, path: P) -> Result<bool, SdmmcFsError> {
-    //     match &mut self.state {
-    //         SdmmcState::Controller(controller) => {
-    //             todo!()
-    //         }
-    //         SdmmcState::Sdmmc(_) => Err(SdmmcFsError::NotMounted),
-    //         SdmmcState::MidSwap => unreachable!(),
-    //     }
-    // }
+    pub fn exists<'p, P: Into<Path<'p>>>(&mut self, path: P) -> Result<bool, SdmmcFsError> {
+        let path = path.into();
+        if path.file_name().is_none() {
+            // Root
+            return self.find_dir(path, |_, _, _| true);
+        }
+        match self.metadata(path) {
+            Ok(_) => Ok(true),
+            Err(SdmmcFsError::NotFound) => Ok(false),
+            Err(e) => Err(e),
+        }
+    }
+
+    /// Size of the partition and free space as recorded by FAT32's FSInfo sector
+    pub fn stats(&mut self) -> Result<(u64, Option<u64>), SdmmcFsError> {
+        self.with_volume(|controller, _| Ok(fat::volume_stats(controller.device())?))
+    }
+
+    /// Copy a file, streaming through `buf`. Both files are open at the same time.
+    pub fn copy<'a, 'b, A: Into<Path<'a>>, B: Into<Path<'b>>>(
+        &mut self,
+        from: A,
+        to: B,
+        mode: WriteMode,
+        buf: &mut [u8],
+    ) -> Result<u32, SdmmcFsError> {
+        let (from, to) = (from.into(), to.into());
+        self.with_volume(|controller, volume| {
+            let mut src = open_file(controller, volume, from, FileOpenMode::ReadOnly)?;
+            let mut dst = match open_file(controller, volume, to, mode.into()) {
+                Ok(dst) => dst,
+                Err(e) => {
+                    controller.close_file(volume, src)?;
+                    return Err(e);
+                }
+            };
+
+            let mut copied = 0;
+            let mut res = Ok(());
+            while !src.eof() {
+                match controller
+                    .read(volume, &mut src, buf)
+                    .and_then(|n| controller.write(volume, &mut dst, &buf[..n]))
+                {
+                    Ok(n) => copied += n as u32,
+                    Err(e) => {
+                        res = Err(e);
+                        break;
+                    }
+                }
+            }
+
+            let closed = controller
+                .close_file(volume, src)
+                .and(controller.close_file(volume, dst));
+            res.and(closed)?;
+            Ok(copied)
+        })
+    }
+
+    /// Create an empty file, an existing one is left as is
+    pub fn touch<'p, P: Into<Path<'p>>>(&mut self, path: P) -> Result<(), SdmmcFsError> {
+        self.find_file(path, FileOpenMode::ReadWriteCreateOrAppend, |_, _, _| ())
+    }
+
+    /// Create a directory, with `parents` missing directories above it are created too.
+    ///
+    /// The FAT driver can only create files, so the directory starts out as a file holding one
+    /// zeroed cluster with the `.` and `..` entries, which is then marked as a directory.
+    pub fn create_dir<'p, P: Into<Path<'p>>>(
+        &mut self,
+        path: P,
+        parents: bool,
+    ) -> Result<(), SdmmcFsError> {
+        let path = path.into();
+        let parent = path.parent().ok_or(SdmmcFsError::AlreadyExists)?;
+        let parent_cluster = match parent.file_name() {
+            // Root
+            None => 0,
+            Some(_) => match self.metadata(parent) {
+                Ok(entry) if entry.attributes.is_directory() => entry.cluster.0,
+                Ok(_) => return Err(SdmmcFsError::NotADirectory),
+                Err(SdmmcFsError::NotFound) if parents => {
+                    self.create_dir(parent, true)?;
+                    self.metadata(parent)?.cluster.0
+                }
+                Err(e) => return Err(e),
+            },
+        };
+
+        let cluster_len =
+            self.with_volume(|controller, _| Ok(fat::cluster_len(controller.device())?))?;
+        self.find_file(
+            path,
+            FileOpenMode::ReadWriteCreate,
+            |controller, volume, file| {
+                let zeros = [0u8; Block::LEN];
+                for _ in 0..cluster_len / Block::LEN {
+                    controller.write(volume, file, &zeros)?;
+                }
+                Ok::<_, SdmmcFsError>(())
+            },
+        )??;
+
+        let res = self.metadata(path).and_then(|entry| {
+            let dots = fat::dot_entries(entry.cluster.0, parent_cluster);
+            self.find_file(
+                path,
+                FileOpenMode::ReadWriteAppend,
+                |controller, volume, file| {
+                    file.seek_from_start(0)
+                        .map_err(|_| SdmmcFsError::InvalidOffset)?;
+                    controller.write(volume, file, &dots)?;
+                    Ok::<_, SdmmcFsError>(())
+                },
+            )??;
+            // Closing the file rewrote its entry, so look it up again
+            let entry = self.metadata(path)?;
+            self.with_volume(|controller, _| {
+                Ok(fat::set_attributes(
+                    controller.device(),
+                    &entry,
+                    fat::ATTR_DIRECTORY,
+                )?)
+            })
+        });
+        if res.is_err() {
+            let _ = self.delete(path);
+        }
+        res
+    }
+
+    /// Remove an empty directory
+    pub fn remove_dir<'p, P: Into<Path<'p>>>(&mut self, path: P) -> Result<(), SdmmcFsError> {
+        let path = path.into();
+        let entry = self.metadata(path)?;
+        if !entry.attributes.is_directory() {
+            return Err(SdmmcFsError::NotADirectory);
+        }
+        let mut empty = true;
+        self.ls(path, |e| empty &= is_dot_entry(e))?;
+        if !empty {
+            return Err(SdmmcFsError::DirectoryNotEmpty);
+        }
+        // The driver refuses to delete directories, turn it back into a file first
+        self.with_volume(|controller, _| {
+            Ok(fat::set_attributes(
+                controller.device(),
+                &entry,
+                fat::ATTR_ARCHIVE,
+            )?)
+        })?;
+        self.delete(path)
+    }
 
     pub fn ls<'p, P: Into<Path<'p>>>(
         &mut self,
@@ -176,121 +455,155 @@ impl<const MAX_OPEN_DIRS: usize, const MAX_OPEN_FILES: usize>
     ) -> Result<(), SdmmcFsError> {
         self.find_dir(path, |controller, volume, dir| {
             controller.iterate_dir(volume, dir, &mut func)
-        })
-        .map_err(SdmmcFsError::from)?
+        })?
         .map_err(SdmmcFsError::from)
     }
 
-    fn find_dir<'p, R, P: Into<Path<'p>>>(
+    fn with_volume<R>(
         &mut self,
-        path: P,
-        func: impl FnMut(
+        func: impl FnOnce(
             &mut Controller<H7SdmmcBlockDev, TimeSource, MAX_OPEN_DIRS, MAX_OPEN_FILES>,
-            &Volume,
-            &Directory,
-        ) -> R,
+            &mut Volume,
+        ) -> Result<R, SdmmcFsError>,
     ) -> Result<R, SdmmcFsError> {
         match self.state {
             SdmmcState::Controller(ref mut controller) => {
-                let path = path.into();
                 let mut volume = controller.get_volume(VolumeIdx(0))?;
-                let root_dir = controller.open_root_dir(&volume)?;
-
-                let res = find_dir(controller, &mut volume, &root_dir, &mut path.parts(), func);
-
-                controller.close_dir(&volume, root_dir);
-                res.ok_or(SdmmcFsError::NotFound)?
+                func(controller, &mut volume)
             }
             SdmmcState::Sdmmc(_) => Err(SdmmcFsError::NotMounted),
             SdmmcState::MidSwap => unreachable!(),
         }
     }
 
+    fn find_dir<'p, R, P: Into<Path<'p>>>(
+        &mut self,
+        path: P,
+        func: impl FnOnce(
+            &mut Controller<H7SdmmcBlockDev, TimeSource, MAX_OPEN_DIRS, MAX_OPEN_FILES>,
+            &mut Volume,
+            &Directory,
+        ) -> R,
+    ) -> Result<R, SdmmcFsError> {
+        let path = path.into();
+        self.with_volume(|controller, volume| {
+            let dir = open_dir(controller, volume, path.parts())?;
+            let res = func(controller, volume, &dir);
+            controller.close_dir(volume, dir);
+            Ok(res)
+        })
+    }
+
+    /// Open the directory containing the last component of `path`
+    fn find_parent<'p, R, P: Into<Path<'p>>>(
+        &mut self,
+        path: P,
+        func: impl FnOnce(
+            &mut Controller<H7SdmmcBlockDev, TimeSource, MAX_OPEN_DIRS, MAX_OPEN_FILES>,
+            &mut Volume,
+            &Directory,
+            &'p str,
+        ) -> R,
+    ) -> Result<R, SdmmcFsError> {
+        let path = path.into();
+        match (path.parent(), path.file_name()) {
+            (Some(parent), Some(name)) => self.find_dir(parent, |controller, volume, dir| {
+                func(controller, volume, dir, name)
+            }),
+            _ => Err(SdmmcFsError::NotFound),
+        }
+    }
+
     fn find_file<'p, R, P: Into<Path<'p>>>(
         &mut self,
         path: P,
         mode: FileOpenMode,
-        func: impl FnMut(
+        func: impl FnOnce(
             &mut Controller<H7SdmmcBlockDev, TimeSource, MAX_OPEN_DIRS, MAX_OPEN_FILES>,
-            &Volume,
+            &mut Volume,
             &mut File,
         ) -> R,
     ) -> Result<R, SdmmcFsError> {
-        match self.state {
-            SdmmcState::Controller(ref mut controller) => {
-                let path = path.into();
-                let mut volume = controller.get_volume(VolumeIdx(0))?;
-                let root_dir = controller.open_root_dir(&volume)?;
-
-                let res = find_file(
-                    controller,
-                    &mut volume,
-                    &root_dir,
-                    mode,
-                    &mut path.parts(),
-                    func,
-                );
-
-                controller.close_dir(&volume, root_dir);
-                res.ok_or(SdmmcFsError::NotFound)?
-            }
-            SdmmcState::Sdmmc(_) => Err(SdmmcFsError::NotMounted),
-            SdmmcState::MidSwap => unreachable!(),
-        }
+        let path = path.into();
+        self.with_volume(|controller, volume| {
+            let mut file = open_file(controller, volume, path, mode)?;
+            let res = func(controller, volume, &mut file);
+            controller.close_file(volume, file)?;
+            log::trace!("CLOSED FILE: {}", path);
+            Ok(res)
+        })
     }
 }
 
-pub fn print_dir_entry<W: fmt::Write>(writer: &mut W, dir_entry: &DirEntry) -> fmt::Result {
-    if !dir_entry.attributes.is_volume() && !dir_entry.attributes.is_hidden() {
-        if dir_entry.attributes.is_directory() {
-            writeln!(writer, "{:13} {}  <DIR>", dir_entry.name, dir_entry.mtime)?;
-        } else {
-            writeln!(
-                writer,
-                "{:13} {}  {} bytes",
-                dir_entry.name, dir_entry.mtime, dir_entry.size
-            )?;
+/// The `.` and `..` entries of a directory
+fn is_dot_entry(entry: &DirEntry) -> bool {
+    let mut name = heapless::String::<12>::new();
+    let _ = write!(name, "{}", entry.name);
+    matches!(name.as_str(), "." | "..")
+}
+
+impl From<WriteMode> for FileOpenMode {
+    fn from(mode: WriteMode) -> Self {
+        match mode {
+            WriteMode::Create => FileOpenMode::ReadWriteCreate,
+            WriteMode::Truncate => FileOpenMode::ReadWriteCreateOrTruncate,
+            WriteMode::Append => FileOpenMode::ReadWriteCreateOrAppend,
         }
     }
-    Ok(())
 }
 
-fn find_dir<
+/// Run `func` with the SD card filesystem. The card is taken out of [`SD_CARD`] meanwhile, so
+/// long transfers and formats don't mask interrupts; calls made from within `func` get
+/// [`SdmmcFsError::Busy`].
+pub fn with_sd_card<R>(
+    func: impl FnOnce(&mut SdmmcFs<H7_MAX_OPEN_DIRS, H7_MAX_OPEN_FILES>) -> Result<R, SdmmcFsError>,
+) -> Result<R, SdmmcFsError> {
+    let sdfs = interrupt_free(|cs| match SD_CARD.borrow(cs).take() {
+        Some(sdfs) => {
+            SD_CARD_BUSY.store(true, Ordering::Relaxed);
+            Ok(sdfs)
+        }
+        None if SD_CARD_BUSY.load(Ordering::Relaxed) => Err(SdmmcFsError::Busy),
+        None => Err(SdmmcFsError::NotInitialized),
+    });
+    let mut sdfs = sdfs?;
+    let result = func(&mut sdfs);
+    interrupt_free(|cs| {
+        SD_CARD.borrow(cs).replace(Some(sdfs));
+        SD_CARD_BUSY.store(false, Ordering::Relaxed);
+    });
+    result
+}
+
+/// Walk down `parts` from the root, only the directory being entered and its parent are
+/// open at any time
+fn open_dir<
     'p,
-    R,
     D: BlockDevice,
     T: embedded_sdmmc::TimeSource,
     const MAX_OPEN_DIRS: usize,
     const MAX_OPEN_FILES: usize,
 >(
     controller: &mut Controller<D, T, MAX_OPEN_DIRS, MAX_OPEN_FILES>,
-    volume: &mut Volume,
-    dir: &Directory,
-    path_iter: &mut core::iter::Peekable<impl Iterator<Item = &'p str>>,
-    mut func: impl FnMut(&mut Controller<D, T, MAX_OPEN_DIRS, MAX_OPEN_FILES>, &Volume, &Directory) -> R,
-) -> Option<Result<R, SdmmcFsError>>
+    volume: &Volume,
+    parts: impl Iterator<Item = &'p str>,
+) -> Result<Directory, SdmmcFsError>
 where
     SdmmcFsError: From<embedded_sdmmc::Error<<D as BlockDevice>::Error>>,
 {
-    if let Some(name) = path_iter.next() {
-        match controller.open_dir(volume, dir, name) {
-            Ok(new_dir) => {
-                log::trace!("OPENED DIR: {}", name);
-                let res = find_dir(controller, volume, &new_dir, path_iter, func);
-                controller.close_dir(volume, new_dir);
-                log::trace!("CLOSED DIR: {}", name);
-                res
-            }
-            Err(e) => Some(Err(SdmmcFsError::from(e))),
-        }
-    } else {
-        Some(Ok(func(controller, volume, dir)))
+    let mut dir = controller.open_root_dir(volume)?;
+    for name in parts {
+        let next = controller.open_dir(volume, &dir, name);
+        controller.close_dir(volume, dir);
+        log::trace!("OPENED DIR: {}", name);
+        dir = next?;
     }
+    Ok(dir)
 }
 
-fn find_file<
+/// Open the file at `path`, its directory is closed again before returning
+fn open_file<
     'p,
-    R,
     D: BlockDevice,
     T: embedded_sdmmc::TimeSource,
     const MAX_OPEN_DIRS: usize,
@@ -298,41 +611,179 @@ fn find_file<
 >(
     controller: &mut Controller<D, T, MAX_OPEN_DIRS, MAX_OPEN_FILES>,
     volume: &mut Volume,
-    dir: &Directory,
+    path: Path<'p>,
     mode: FileOpenMode,
-    path_iter: &mut core::iter::Peekable<impl Iterator<Item = &'p str>>,
-    mut func: impl FnMut(&mut Controller<D, T, MAX_OPEN_DIRS, MAX_OPEN_FILES>, &Volume, &mut File) -> R,
-) -> Option<Result<R, SdmmcFsError>>
+) -> Result<File, SdmmcFsError>
 where
     SdmmcFsError: From<embedded_sdmmc::Error<<D as BlockDevice>::Error>>,
 {
-    if let Some(name) = path_iter.next() {
-        if path_iter.peek().is_some() {
-            match controller.open_dir(volume, dir, name) {
-                Ok(new_dir) => {
-                    log::trace!("OPENED DIR: {}", name);
-                    let res = find_file(controller, volume, &new_dir, mode, path_iter, func);
-                    controller.close_dir(volume, new_dir);
-                    log::trace!("CLOSED DIR: {}", name);
-                    res
-                }
-                Err(e) => Some(Err(SdmmcFsError::from(e))),
+    let (parent, name) = match (path.parent(), path.file_name()) {
+        (Some(parent), Some(name)) => (parent, name),
+        _ => return Err(SdmmcFsError::NotFound),
+    };
+    let dir = open_dir(controller, volume, parent.parts())?;
+    let file = controller.open_file_in_dir(volume, &dir, name, mode);
+    controller.close_dir(volume, dir);
+    log::trace!("OPENED FILE: {}", path);
+    Ok(file?)
+}
+
+/// Mount the card's first FAT partition as `sdcard:` and each FAT partition N as `sdcardN:`
+pub fn mount_volumes() -> Result<(), VfsError> {
+    let numbers = with_sd_card(|sdfs| {
+        Ok(match sdfs.partition_table() {
+            Some(table) => table
+                .partitions
+                .iter()
+                .filter(|p| p.kind.is_fat())
+                .map(|p| p.number)
+                .collect(),
+            None => Vec::new(),
+        })
+    })?;
+    vfs::mount(DEVICE, Box::new(SdCardFs(None)))?;
+    for n in numbers {
+        vfs::mount(&format!("{DEVICE}{n}"), Box::new(SdCardFs(Some(n))))?;
+    }
+    Ok(())
+}
+
+/// Remove everything [`mount_volumes`] mounted from the VFS
+pub fn unmount_volumes() {
+    let mut devices = Vec::new();
+    vfs::for_each_mount(|device, _| {
+        if let Some(n) = device.strip_prefix(DEVICE) {
+            if n.is_empty() || n.parse::<usize>().is_ok() {
+                devices.push(String::from(device));
             }
-        } else {
-            match controller.open_file_in_dir(volume, dir, name, mode) {
-                Ok(mut file) => {
-                    log::trace!("OPENED FILE: {}", name);
-                    let ret = func(controller, volume, &mut file);
-                    if let Err(e) = controller.close_file(volume, file) {
-                        return Some(Err(SdmmcFsError::from(e)));
-                    };
-                    log::trace!("CLOSED FILE: {}", name);
-                    Some(Ok(ret))
+        }
+    });
+    for device in devices {
+        let _ = vfs::unmount(&device);
+    }
+}
+
+/// A partition of the SD card as seen through the VFS, `None` being the one mounted as `sdcard:`
+pub struct SdCardFs(Option<usize>);
+
+impl SdCardFs {
+    fn with<R>(
+        &self,
+        func: impl FnOnce(&mut SdmmcFs<H7_MAX_OPEN_DIRS, H7_MAX_OPEN_FILES>) -> Result<R, SdmmcFsError>,
+    ) -> Result<R, VfsError> {
+        with_sd_card(|sdfs| {
+            sdfs.select(self.0)?;
+            func(sdfs)
+        })
+        .map_err(VfsError::from)
+    }
+}
+
+impl FileSystem for SdCardFs {
+    fn kind(&self) -> &'static str {
+        "fat"
+    }
+
+    fn case_sensitive(&self) -> bool {
+        false
+    }
+
+    fn stats(&mut self) -> Result<Stats, VfsError> {
+        let (total_bytes, free_bytes) = self.with(|sdfs| sdfs.stats())?;
+        Ok(Stats {
+            total_bytes,
+            free_bytes,
+            wear: None,
+        })
+    }
+
+    fn metadata(&mut self, path: Path) -> Result<Metadata, VfsError> {
+        self.with(|sdfs| sdfs.metadata(path))
+            .map(|entry| to_metadata(&entry))
+    }
+
+    fn ls(&mut self, path: Path, func: &mut dyn FnMut(&Metadata)) -> Result<(), VfsError> {
+        self.with(|sdfs| {
+            sdfs.ls(path, |entry| {
+                if !entry.attributes.is_volume() && !is_dot_entry(entry) {
+                    func(&to_metadata(entry))
                 }
-                Err(e) => Some(Err(SdmmcFsError::from(e))),
+            })
+        })
+    }
+
+    fn read_at(&mut self, path: Path, offset: u32, data: &mut [u8]) -> Result<usize, VfsError> {
+        self.with(|sdfs| sdfs.read_at(path, offset, data))
+    }
+
+    fn read_chunks(
+        &mut self,
+        path: Path,
+        buf: &mut [u8],
+        func: &mut dyn FnMut(&[u8]) -> bool,
+    ) -> Result<(), VfsError> {
+        // The card is released between chunks, `func` may be writing to another partition
+        let mut offset = 0;
+        loop {
+            let n = self.with(|sdfs| sdfs.read_at(path, offset, buf))?;
+            if n == 0 || !func(&buf[..n]) {
+                return Ok(());
             }
+            offset += n as u32;
         }
-    } else {
-        None
+    }
+
+    fn write(&mut self, path: Path, mode: WriteMode, data: &[u8]) -> Result<(), VfsError> {
+        self.with(|sdfs| sdfs.write(path, mode, data))
+    }
+
+    fn touch(&mut self, path: Path) -> Result<(), VfsError> {
+        self.with(|sdfs| sdfs.touch(path))
+    }
+
+    fn delete(&mut self, path: Path) -> Result<(), VfsError> {
+        self.with(|sdfs| sdfs.delete(path))
+    }
+
+    fn create_dir(&mut self, path: Path, parents: bool) -> Result<(), VfsError> {
+        self.with(|sdfs| sdfs.create_dir(path, parents))
+    }
+
+    fn remove_dir(&mut self, path: Path) -> Result<(), VfsError> {
+        self.with(|sdfs| sdfs.remove_dir(path))
+    }
+
+    fn copy(
+        &mut self,
+        from: Path,
+        to: Path,
+        mode: WriteMode,
+        buf: &mut [u8],
+    ) -> Result<u32, VfsError> {
+        self.with(|sdfs| sdfs.copy(from, to, mode, buf))
+    }
+}
+
+fn to_metadata(entry: &DirEntry) -> Metadata {
+    let mut name = String::new();
+    let _ = write!(name, "{}", entry.name);
+    let mtime = &entry.mtime;
+    Metadata {
+        name,
+        is_dir: entry.attributes.is_directory(),
+        hidden: entry.attributes.is_hidden(),
+        size: entry.size,
+        mtime: NaiveDate::from_ymd_opt(
+            1970 + mtime.year_since_1970 as i32,
+            mtime.zero_indexed_month as u32 + 1,
+            mtime.zero_indexed_day as u32 + 1,
+        )
+        .and_then(|date| {
+            date.and_hms_opt(
+                mtime.hours as u32,
+                mtime.minutes as u32,
+                mtime.seconds as u32,
+            )
+        }),
     }
 }
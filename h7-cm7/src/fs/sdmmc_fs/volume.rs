@@ -0,0 +1,109 @@
+//! Opening partitions `embedded-sdmmc` can't find on its own
+
+use {
+    core::cell::Cell,
+    embedded_sdmmc::{Block, BlockCount, BlockDevice, BlockIdx},
+    h7_fs::{
+        fat,
+        partition::{self, Partition, PartitionTable},
+    },
+};
+
+/// Block device that shows the selected partition as the only one on an MBR disk, so
+/// `VolumeIdx(0)` opens it whatever the real partition table looks like, GPT included
+pub struct PartitionDevice<D> {
+    inner: D,
+    /// First block and length of the selected partition, block 0 is passed through without one
+    selected: Cell<Option<(u32, u32)>>,
+}
+
+impl<D: BlockDevice> PartitionDevice<D> {
+    pub fn new(inner: D) -> Self {
+        Self {
+            inner,
+            selected: Cell::new(None),
+        }
+    }
+
+    pub fn into_inner(self) -> D {
+        self.inner
+    }
+
+    /// Direct the following operations to `partition`, `None` shows the disk as it is.
+    /// Returns `false` for partitions beyond what an MBR can address.
+    pub fn select(&self, partition: Option<&Partition>) -> bool {
+        let window = match partition {
+            Some(p) => match (u32::try_from(p.first_lba), u32::try_from(p.sectors)) {
+                (Ok(first), Ok(sectors)) => Some((first, sectors)),
+                _ => return false,
+            },
+            None => None,
+        };
+        self.selected.set(window);
+        true
+    }
+
+    /// The real partition table
+    pub fn partition_table(&self) -> Result<Option<PartitionTable>, partition::Error<D::Error>> {
+        let mut blocks = [Block::new()];
+        partition::read(|lba, data| {
+            self.inner
+                .read(&mut blocks, BlockIdx(lba as u32), "partition_table")?;
+            data.copy_from_slice(&blocks[0].contents);
+            Ok(())
+        })
+    }
+}
+
+impl<D: BlockDevice> BlockDevice for PartitionDevice<D> {
+    type Error = D::Error;
+
+    fn read(&self, blocks: &mut [Block], start: BlockIdx, reason: &str) -> Result<(), D::Error> {
+        self.inner.read(blocks, start, reason)?;
+        if let (Some((first, sectors)), BlockIdx(0), Some(mbr)) =
+            (self.selected.get(), start, blocks.first_mut())
+        {
+            mbr.contents = partition::single_partition_mbr(first, sectors);
+        }
+        Ok(())
+    }
+
+    fn write(&self, blocks: &[Block], start: BlockIdx) -> Result<(), D::Error> {
+        match (self.selected.get(), start) {
+            // The MBR seen through the device is made up, the real one is kept
+            (Some(_), BlockIdx(0)) => match blocks.get(1..) {
+                Some(rest) if !rest.is_empty() => self.inner.write(rest, BlockIdx(1)),
+                _ => Ok(()),
+            },
+            _ => self.inner.write(blocks, start),
+        }
+    }
+
+    fn num_blocks(&self) -> Result<BlockCount, D::Error> {
+        self.inner.num_blocks()
+    }
+}
+
+/// A block device as [`fat::format`] writes to it
+pub struct FormatDisk<'a, D>(pub &'a D);
+
+impl<D: BlockDevice> fat::Disk for FormatDisk<'_, D> {
+    type Error = D::Error;
+
+    fn num_blocks(&self) -> Result<u64, D::Error> {
+        Ok(self.0.num_blocks()?.0 as u64)
+    }
+
+    fn write_blocks(&mut self, lba: u64, data: &[u8]) -> Result<(), D::Error> {
+        let mut blocks = [Block::new(), Block::new(), Block::new(), Block::new()];
+        for (i, chunk) in data.chunks(blocks.len() * Block::LEN).enumerate() {
+            let n = chunk.len() / Block::LEN;
+            for (block, data) in blocks.iter_mut().zip(chunk.chunks_exact(Block::LEN)) {
+                block.contents.copy_from_slice(data);
+            }
+            let start = lba as u32 + (i * blocks.len()) as u32;
+            self.0.write(&blocks[..n], BlockIdx(start))?;
+        }
+        Ok(())
+    }
+}
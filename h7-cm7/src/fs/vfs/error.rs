@@ -0,0 +1,59 @@
+use {crate::fs::sdmmc_fs::error::SdmmcFsError, alloc::string::String};
+
+pub enum VfsError {
+    NoDevice,
+    NotMounted(String),
+    AlreadyMounted(String),
+    /// The filesystem is in use by the operation this one was started from
+    Busy(String),
+    NotFound,
+    AlreadyExists,
+    NotADirectory,
+    IsADirectory,
+    DirectoryNotEmpty,
+    InvalidDestination,
+    InvalidOffset,
+    /// A wildcard pattern matched nothing
+    NoMatch(String),
+    /// The heap can't spare the requested number of bytes
+    OutOfMemory(usize),
+    Sdmmc(SdmmcFsError),
+    /// Any other error of a NOR flash filesystem
+    NorFs(String),
+}
+
+impl core::fmt::Display for VfsError {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        match self {
+            Self::NoDevice => write!(f, "No device selected"),
+            Self::NotMounted(device) => write!(f, "Nothing mounted on '{device}:'"),
+            Self::AlreadyMounted(device) => write!(f, "Already mounted on '{device}:'"),
+            Self::Busy(device) => write!(f, "'{device}:' is busy"),
+            Self::NotFound => write!(f, "Not Found"),
+            Self::AlreadyExists => write!(f, "Already exists"),
+            Self::NotADirectory => write!(f, "Not a directory"),
+            Self::IsADirectory => write!(f, "Is a directory"),
+            Self::DirectoryNotEmpty => write!(f, "Directory not empty"),
+            Self::InvalidDestination => write!(f, "Destination is inside the source"),
+            Self::InvalidOffset => write!(f, "Invalid offset"),
+            Self::NoMatch(pattern) => write!(f, "No match for '{pattern}'"),
+            Self::OutOfMemory(size) => write!(f, "Not enough free heap for {size} bytes"),
+            Self::Sdmmc(e) => write!(f, "{e}"),
+            Self::NorFs(e) => write!(f, "{e}"),
+        }
+    }
+}
+
+impl From<SdmmcFsError> for VfsError {
+    fn from(err: SdmmcFsError) -> Self {
+        match err {
+            SdmmcFsError::NotFound => Self::NotFound,
+            SdmmcFsError::AlreadyExists => Self::AlreadyExists,
+            SdmmcFsError::NotADirectory => Self::NotADirectory,
+            SdmmcFsError::IsADirectory => Self::IsADirectory,
+            SdmmcFsError::DirectoryNotEmpty => Self::DirectoryNotEmpty,
+            SdmmcFsError::InvalidOffset => Self::InvalidOffset,
+            err => Self::Sdmmc(err),
+        }
+    }
+}
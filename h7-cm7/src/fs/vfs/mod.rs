@@ -0,0 +1,607 @@
+use {
+    super::path::Path,
+    crate::utils::interrupt_free,
+    alloc::{
+        boxed::Box,
+        string::{String, ToString},
+        vec,
+        vec::Vec,
+    },
+    chrono::NaiveDateTime,
+    core::{cell::RefCell, fmt},
+    critical_section::Mutex,
+    h7_fs::{
+        glob,
+        mv::{self, CopyDelete},
+    },
+};
+
+pub use error::VfsError;
+
+mod error;
+
+/// Filesystems mounted under a device name, see [`mount`]
+static MOUNTS: Mutex<RefCell<Vec<Mount>>> = Mutex::new(RefCell::new(Vec::new()));
+
+type BoxedFs = Box<dyn FileSystem + Send>;
+
+struct Mount {
+    device: String,
+    /// Taken out while an operation runs, so it runs with interrupts enabled, see [`with_fs`]
+    fs: Option<BoxedFs>,
+}
+
+/// How [`FileSystem::write`] treats an existing file
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WriteMode {
+    /// Fail if the file exists
+    Create,
+    /// Create or truncate
+    Truncate,
+    /// Create or append
+    Append,
+}
+
+/// A file or directory as seen through the VFS
+#[derive(Debug, Clone)]
+pub struct Metadata {
+    pub name: String,
+    pub is_dir: bool,
+    pub hidden: bool,
+    pub size: u32,
+    pub mtime: Option<NaiveDateTime>,
+}
+
+/// Size and free space of a mounted filesystem
+#[derive(Debug, Clone, Copy)]
+pub struct Stats {
+    pub total_bytes: u64,
+    /// `None` if the filesystem doesn't keep track of it
+    pub free_bytes: Option<u64>,
+    /// Erase block usage, for filesystems on flash
+    pub wear: Option<Wear>,
+}
+
+/// Erase blocks of a flash filesystem and how worn they are
+#[derive(Debug, Clone, Copy)]
+pub struct Wear {
+    pub block_size: usize,
+    pub blocks: usize,
+    pub free_blocks: usize,
+    pub min_erases: u32,
+    pub max_erases: u32,
+}
+
+/// A filesystem that can be mounted under a device name.
+///
+/// Paths are passed on with their device prefix, implementations only look at the parts.
+pub trait FileSystem {
+    /// Type of the filesystem shown by `mount`, e.g. `fat`
+    fn kind(&self) -> &'static str;
+
+    /// Whether names that only differ in case are different files, for wildcards
+    fn case_sensitive(&self) -> bool {
+        true
+    }
+
+    fn stats(&mut self) -> Result<Stats, VfsError>;
+
+    fn metadata(&mut self, path: Path) -> Result<Metadata, VfsError>;
+
+    /// Call `func` for each entry of a directory, `.` and `..` are left out
+    fn ls(&mut self, path: Path, func: &mut dyn FnMut(&Metadata)) -> Result<(), VfsError>;
+
+    /// Read from `offset` until `data` is full or the end of the file is reached
+    fn read_at(&mut self, path: Path, offset: u32, data: &mut [u8]) -> Result<usize, VfsError>;
+
+    /// Stream a file through `buf`, `func` gets each chunk and returns `false` to stop early
+    fn read_chunks(
+        &mut self,
+        path: Path,
+        buf: &mut [u8],
+        func: &mut dyn FnMut(&[u8]) -> bool,
+    ) -> Result<(), VfsError>;
+
+    fn write(&mut self, path: Path, mode: WriteMode, data: &[u8]) -> Result<(), VfsError>;
+
+    /// Create an empty file, an existing one is left as is
+    fn touch(&mut self, path: Path) -> Result<(), VfsError>;
+
+    fn delete(&mut self, path: Path) -> Result<(), VfsError>;
+
+    /// Create a directory, with `parents` missing directories above it are created too
+    fn create_dir(&mut self, path: Path, parents: bool) -> Result<(), VfsError>;
+
+    /// Remove an empty directory
+    fn remove_dir(&mut self, path: Path) -> Result<(), VfsError>;
+
+    /// Copy a file within this filesystem, returns the number of bytes copied
+    fn copy(
+        &mut self,
+        from: Path,
+        to: Path,
+        mode: WriteMode,
+        buf: &mut [u8],
+    ) -> Result<u32, VfsError> {
+        // Create or truncate up front so empty files are copied too
+        self.write(to, mode, &[])?;
+        let mut offset = 0;
+        loop {
+            let n = self.read_at(from, offset, buf)?;
+            if n > 0 {
+                self.write(to, WriteMode::Append, &buf[..n])?;
+            }
+            offset += n as u32;
+            if n < buf.len() {
+                return Ok(offset);
+            }
+        }
+    }
+
+    /// Move a file within this filesystem
+    fn rename(&mut self, from: Path, to: Path, buf: &mut [u8]) -> Result<(), VfsError> {
+        mv::move_file(&mut Within { fs: self, buf }, from, to)
+    }
+}
+
+fn exists<F: FileSystem + ?Sized>(fs: &mut F, path: Path) -> Result<bool, VfsError> {
+    match fs.metadata(path) {
+        Ok(_) => Ok(true),
+        Err(VfsError::NotFound) => Ok(false),
+        Err(e) => Err(e),
+    }
+}
+
+/// A move by copy and delete within one filesystem
+struct Within<'a, F: ?Sized> {
+    fs: &'a mut F,
+    buf: &'a mut [u8],
+}
+
+impl<F: FileSystem + ?Sized> CopyDelete for Within<'_, F> {
+    type Error = VfsError;
+
+    fn source_is_dir(&mut self, path: Path) -> Result<bool, VfsError> {
+        Ok(self.fs.metadata(path)?.is_dir)
+    }
+
+    fn target_exists(&mut self, path: Path) -> Result<bool, VfsError> {
+        exists(self.fs, path)
+    }
+
+    fn copy_new(&mut self, from: Path, to: Path) -> Result<(), VfsError> {
+        self.fs
+            .copy(from, to, WriteMode::Create, self.buf)
+            .map(|_| ())
+    }
+
+    fn delete_source(&mut self, path: Path) -> Result<(), VfsError> {
+        self.fs.delete(path)
+    }
+
+    fn delete_target(&mut self, path: Path) -> Result<(), VfsError> {
+        self.fs.delete(path)
+    }
+
+    fn is_a_directory(&self) -> VfsError {
+        VfsError::IsADirectory
+    }
+}
+
+/// Attach `fs` as `<device>:`
+pub fn mount(device: &str, fs: BoxedFs) -> Result<(), VfsError> {
+    interrupt_free(|cs| {
+        let mut mounts = MOUNTS.borrow(cs).borrow_mut();
+        if mounts.iter().any(|m| m.device == device) {
+            return Err(VfsError::AlreadyMounted(device.to_string()));
+        }
+        mounts.push(Mount {
+            device: device.to_string(),
+            fs: Some(fs),
+        });
+        Ok(())
+    })
+}
+
+pub fn unmount(device: &str) -> Result<BoxedFs, VfsError> {
+    interrupt_free(|cs| {
+        let mut mounts = MOUNTS.borrow(cs).borrow_mut();
+        let Some(i) = mounts.iter().position(|m| m.device == device) else {
+            return Err(VfsError::NotMounted(device.to_string()));
+        };
+        match mounts[i].fs.is_some() {
+            true => Ok(mounts.remove(i).fs.unwrap()),
+            false => Err(VfsError::Busy(device.to_string())),
+        }
+    })
+}
+
+pub fn is_mounted(device: &str) -> bool {
+    interrupt_free(|cs| {
+        MOUNTS
+            .borrow(cs)
+            .borrow()
+            .iter()
+            .any(|m| m.device == device)
+    })
+}
+
+/// Call `func` for every mounted filesystem, in mount order. Filesystems in use by an
+/// operation `func` is called from are skipped.
+pub fn for_each_mount(mut func: impl FnMut(&str, &mut dyn FileSystem)) {
+    let devices: Vec<String> = interrupt_free(|cs| {
+        MOUNTS
+            .borrow(cs)
+            .borrow()
+            .iter()
+            .map(|m| m.device.clone())
+            .collect()
+    });
+    for device in devices {
+        if let Ok(mut fs) = take(&device) {
+            func(&device, fs.as_mut());
+            put_back(&device, fs);
+        }
+    }
+}
+
+/// Take the filesystem mounted on `device` out of the table for an operation. Only the lookup
+/// masks interrupts, the operation itself doesn't.
+fn take(device: &str) -> Result<BoxedFs, VfsError> {
+    interrupt_free(|cs| {
+        let mut mounts = MOUNTS.borrow(cs).borrow_mut();
+        match mounts.iter_mut().find(|m| m.device == device) {
+            Some(m) => {
+                m.fs.take()
+                    .ok_or_else(|| VfsError::Busy(device.to_string()))
+            }
+            None => Err(VfsError::NotMounted(device.to_string())),
+        }
+    })
+}
+
+fn put_back(device: &str, fs: BoxedFs) {
+    interrupt_free(|cs| {
+        let mut mounts = MOUNTS.borrow(cs).borrow_mut();
+        if let Some(m) = mounts.iter_mut().find(|m| m.device == device) {
+            m.fs = Some(fs);
+        }
+    })
+}
+
+/// Run `func` with the filesystem `path` is on. Interrupts stay enabled, the filesystem is
+/// busy for other operations meanwhile.
+pub fn with_fs<R>(
+    path: Path,
+    func: impl FnOnce(&mut dyn FileSystem) -> Result<R, VfsError>,
+) -> Result<R, VfsError> {
+    let device = path.device().ok_or(VfsError::NoDevice)?;
+    let mut fs = take(device)?;
+    let result = func(fs.as_mut());
+    put_back(device, fs);
+    result
+}
+
+/// Whether `path` is a directory, the root of a mounted device is one
+pub fn is_dir(path: Path) -> Result<bool, VfsError> {
+    with_fs(path, |fs| match path.is_empty() {
+        true => Ok(true),
+        false => Ok(fs.metadata(path)?.is_dir),
+    })
+}
+
+/// Expand wildcards in the last component of `path` to the matching entries of its directory,
+/// sorted by name. Hidden entries only match patterns starting with a `.`. A path without
+/// wildcards is returned as is, whether it exists or not.
+pub fn glob(path: Path) -> Result<Vec<String>, VfsError> {
+    let (Some(pattern), Some(dir)) = (path.file_name(), path.parent()) else {
+        return Ok(vec![path.to_string()]);
+    };
+    if !glob::is_pattern(pattern) {
+        return Ok(vec![path.to_string()]);
+    }
+    let mut paths = Vec::new();
+    with_fs(path, |fs| {
+        let case_sensitive = fs.case_sensitive();
+        fs.ls(dir, &mut |entry| {
+            if (!entry.hidden || pattern.starts_with('.'))
+                && glob::matches(pattern, &entry.name, case_sensitive)
+            {
+                paths.push(dir.join(&entry.name));
+            }
+        })
+    })?;
+    match paths.is_empty() {
+        true => Err(VfsError::NoMatch(pattern.to_string())),
+        false => {
+            paths.sort();
+            Ok(paths)
+        }
+    }
+}
+
+/// Source and destination filesystem of a copy, which might be the same one
+enum Pair<'a> {
+    Same(&'a mut dyn FileSystem),
+    Split(&'a mut dyn FileSystem, &'a mut dyn FileSystem),
+}
+
+impl<'a> Pair<'a> {
+    fn src(&mut self) -> &mut dyn FileSystem {
+        match self {
+            Pair::Same(fs) | Pair::Split(fs, _) => &mut **fs,
+        }
+    }
+
+    fn dst(&mut self) -> &mut dyn FileSystem {
+        match self {
+            Pair::Same(fs) | Pair::Split(_, fs) => &mut **fs,
+        }
+    }
+
+    fn copy_file(
+        &mut self,
+        from: Path,
+        to: Path,
+        mode: WriteMode,
+        buf: &mut [u8],
+    ) -> Result<u32, VfsError> {
+        match self {
+            Pair::Same(fs) => fs.copy(from, to, mode, buf),
+            Pair::Split(src, dst) => {
+                dst.write(to, mode, &[])?;
+                let mut copied = 0;
+                let mut res = Ok(());
+                src.read_chunks(from, buf, &mut |chunk| match dst.write(
+                    to,
+                    WriteMode::Append,
+                    chunk,
+                ) {
+                    Ok(()) => {
+                        copied += chunk.len() as u32;
+                        true
+                    }
+                    Err(e) => {
+                        res = Err(e);
+                        false
+                    }
+                })?;
+                res.map(|_| copied)
+            }
+        }
+    }
+}
+
+fn with_pair<R>(
+    from: Path,
+    to: Path,
+    func: impl FnOnce(Pair) -> Result<R, VfsError>,
+) -> Result<R, VfsError> {
+    let from_device = from.device().ok_or(VfsError::NoDevice)?;
+    let to_device = to.device().ok_or(VfsError::NoDevice)?;
+    if from_device == to_device {
+        return with_fs(from, |fs| func(Pair::Same(fs)));
+    }
+    let mut src = take(from_device)?;
+    let result = match take(to_device) {
+        Ok(mut dst) => {
+            let result = func(Pair::Split(src.as_mut(), dst.as_mut()));
+            put_back(to_device, dst);
+            result
+        }
+        Err(e) => Err(e),
+    };
+    put_back(from_device, src);
+    result
+}
+
+/// Copying into a directory keeps the file name of the source
+fn destination(fs: &mut dyn FileSystem, from: Path, to: Path) -> Result<String, VfsError> {
+    let is_dir = match to.file_name() {
+        Some(_) => match fs.metadata(to) {
+            Ok(entry) => entry.is_dir,
+            Err(VfsError::NotFound) => false,
+            Err(e) => return Err(e),
+        },
+        // Root
+        None => true,
+    };
+    match (is_dir, from.file_name()) {
+        (true, Some(name)) => Ok(to.join(name)),
+        _ => Ok(to.to_string()),
+    }
+}
+
+/// Copy a file, or with `recursive` a directory with everything in it, across devices if
+/// needed. Returns the number of bytes copied.
+pub fn copy(from: Path, to: Path, recursive: bool, buf: &mut [u8]) -> Result<u32, VfsError> {
+    with_pair(from, to, |mut pair| {
+        let to = destination(pair.dst(), from, to)?;
+        let to = Path::new(&to);
+        if !recursive || !pair.src().metadata(from)?.is_dir {
+            return pair.copy_file(from, to, WriteMode::Truncate, buf);
+        }
+        // The walk would find the copy and keep going
+        if let Pair::Same(_) = pair {
+            let (from, to) = (from.to_string(), to.to_string());
+            if to == from || to.starts_with(&Path::new(&from).join("")) {
+                return Err(VfsError::InvalidDestination);
+            }
+        }
+
+        let mut entries = Vec::new();
+        walk(pair.src(), from, &mut |child, entry| {
+            entries.push((child.to_string(), entry.is_dir));
+            Ok(())
+        })?;
+        create_dir_if_missing(pair.dst(), to)?;
+        let prefix = from.to_string().len();
+        let mut copied = 0;
+        for (child, is_dir) in entries {
+            let dest = to.join(child[prefix..].trim_start_matches('/'));
+            match is_dir {
+                true => create_dir_if_missing(pair.dst(), Path::new(&dest))?,
+                false => {
+                    copied += pair.copy_file(
+                        Path::new(&child),
+                        Path::new(&dest),
+                        WriteMode::Truncate,
+                        buf,
+                    )?
+                }
+            }
+        }
+        Ok(copied)
+    })
+}
+
+/// Move a file, across devices if needed
+pub fn rename(from: Path, to: Path, buf: &mut [u8]) -> Result<(), VfsError> {
+    with_pair(from, to, |mut pair| {
+        let to = destination(pair.dst(), from, to)?;
+        let to = Path::new(&to);
+        if let Pair::Same(fs) = &mut pair {
+            return fs.rename(from, to, buf);
+        }
+        mv::move_file(&mut Across { pair, buf }, from, to)
+    })
+}
+
+/// A move by copy and delete from one filesystem to another
+struct Across<'a, 'b> {
+    pair: Pair<'a>,
+    buf: &'b mut [u8],
+}
+
+impl CopyDelete for Across<'_, '_> {
+    type Error = VfsError;
+
+    fn source_is_dir(&mut self, path: Path) -> Result<bool, VfsError> {
+        Ok(self.pair.src().metadata(path)?.is_dir)
+    }
+
+    fn target_exists(&mut self, path: Path) -> Result<bool, VfsError> {
+        exists(self.pair.dst(), path)
+    }
+
+    fn copy_new(&mut self, from: Path, to: Path) -> Result<(), VfsError> {
+        self.pair
+            .copy_file(from, to, WriteMode::Create, self.buf)
+            .map(|_| ())
+    }
+
+    fn delete_source(&mut self, path: Path) -> Result<(), VfsError> {
+        self.pair.src().delete(path)
+    }
+
+    fn delete_target(&mut self, path: Path) -> Result<(), VfsError> {
+        self.pair.dst().delete(path)
+    }
+
+    fn is_a_directory(&self) -> VfsError {
+        VfsError::IsADirectory
+    }
+}
+
+/// Remove a file, or with `recursive` a directory with everything in it
+pub fn remove(path: Path, recursive: bool) -> Result<(), VfsError> {
+    with_fs(path, |fs| {
+        if !recursive || !fs.metadata(path)?.is_dir {
+            return fs.delete(path);
+        }
+        let mut entries = Vec::new();
+        walk(fs, path, &mut |child, entry| {
+            entries.push((child.to_string(), entry.is_dir));
+            Ok(())
+        })?;
+        // Files first, then directories innermost first
+        for (child, _) in entries.iter().filter(|(_, is_dir)| !is_dir) {
+            fs.delete(Path::new(child))?;
+        }
+        for (child, _) in entries.iter().rev().filter(|(_, is_dir)| *is_dir) {
+            fs.remove_dir(Path::new(child))?;
+        }
+        fs.remove_dir(path)
+    })
+}
+
+/// Visit every entry below `path`, a directory is visited before its contents.
+///
+/// The entries of one directory are collected before `func` sees them, so no directory stays
+/// open between calls and the depth of the tree isn't limited by open handles.
+pub fn walk(
+    fs: &mut dyn FileSystem,
+    path: Path,
+    func: &mut dyn FnMut(&str, &Metadata) -> Result<(), VfsError>,
+) -> Result<(), VfsError> {
+    let mut dirs = vec![path.to_string()];
+    while let Some(dir) = dirs.pop() {
+        let mut entries = Vec::new();
+        fs.ls(Path::new(&dir), &mut |entry| entries.push(entry.clone()))?;
+        for entry in entries {
+            let child = Path::new(&dir).join(&entry.name);
+            func(&child, &entry)?;
+            if entry.is_dir {
+                dirs.push(child);
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Size of the files below `path`, for `path` itself and every directory in it, sorted by path.
+/// A file is its own total.
+pub fn disk_usage(path: Path) -> Result<Vec<(String, u64)>, VfsError> {
+    with_fs(path, |fs| {
+        if !path.is_empty() {
+            let entry = fs.metadata(path)?;
+            if !entry.is_dir {
+                return Ok(vec![(path.to_string(), entry.size as u64)]);
+            }
+        }
+        // Directory, its path with a trailing `/` to match children against and the total
+        let mut dirs = vec![(path.to_string(), path.join(""), 0)];
+        walk(fs, path, &mut |child, entry| {
+            match entry.is_dir {
+                true => dirs.push((child.to_string(), Path::new(child).join(""), 0)),
+                false => {
+                    for (_, prefix, total) in dirs.iter_mut() {
+                        if child.starts_with(prefix.as_str()) {
+                            *total += entry.size as u64;
+                        }
+                    }
+                }
+            }
+            Ok(())
+        })?;
+        let mut totals: Vec<_> = dirs
+            .into_iter()
+            .map(|(dir, _, total)| (dir, total))
+            .collect();
+        totals.sort();
+        Ok(totals)
+    })
+}
+
+fn create_dir_if_missing(fs: &mut dyn FileSystem, path: Path) -> Result<(), VfsError> {
+    match fs.create_dir(path, false) {
+        Ok(()) | Err(VfsError::AlreadyExists) => Ok(()),
+        Err(e) => Err(e),
+    }
+}
+
+pub fn print_entry<W: fmt::Write>(writer: &mut W, entry: &Metadata) -> fmt::Result {
+    if entry.hidden {
+        return Ok(());
+    }
+    write!(writer, "{:13} ", entry.name)?;
+    match entry.mtime {
+        Some(mtime) => write!(writer, "{mtime}")?,
+        None => write!(writer, "{:19}", "-")?,
+    }
+    if entry.is_dir {
+        writeln!(writer, "  <DIR>")
+    } else {
+        writeln!(writer, "  {} bytes", entry.size)
+    }
+}
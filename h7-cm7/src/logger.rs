@@ -38,30 +38,20 @@ mod semihosting {
 pub use semihosting::{init, set_log_level};
 
 #[cfg(feature = "usb_logging")]
-mod uart {
+mod terminal {
 
     use {
-        crate::{terminal::UART_TERMINAL_TX, utils::interrupt_free},
+        crate::terminal::BroadcastWriter,
         core::fmt::Write,
         log::{Log, Metadata, Record},
     };
 
-    static LOGGER: UartLogger = UartLogger;
+    static LOGGER: TerminalLogger = TerminalLogger;
 
-    pub struct UartLogger;
+    /// Logs to every console, UART and USB
+    pub struct TerminalLogger;
 
-    impl Write for UartLogger {
-        fn write_str(&mut self, s: &str) -> core::fmt::Result {
-            interrupt_free(|cs| {
-                if let Some(tx) = &mut *UART_TERMINAL_TX.borrow(cs).borrow_mut() {
-                    tx.write_str(s)?;
-                }
-                Ok(())
-            })
-        }
-    }
-
-    impl Log for UartLogger {
+    impl Log for TerminalLogger {
         fn enabled(&self, metadata: &Metadata) -> bool {
             metadata.level() <= unsafe { super::LOG_LEVEL }
         }
@@ -70,7 +60,7 @@ mod uart {
             // haha rust go brrr
             // let this = self as *const Self as *mut Self;
             // let this = unsafe { &mut *this };
-            let this = &mut UartLogger;
+            let this = &mut BroadcastWriter;
             let _ = write!(
                 this,
                 "{}",
@@ -99,7 +89,7 @@ mod uart {
 }
 
 #[cfg(feature = "usb_logging")]
-pub use uart::{init, set_log_level};
+pub use terminal::{init, set_log_level};
 
 // #[cfg(feature = "defmt")]
 // mod defmt_logger {
@@ -22,7 +22,7 @@ use {
         utils::interrupt_free,
     },
     chrono::Timelike,
-    core::{cell::RefCell, fmt::Write},
+    core::cell::RefCell,
     defmt_rtt as _, // global logger
     fugit::RateExtU32,
     hal::gpio::{ErasedPin, Output},
@@ -43,6 +43,7 @@ mod mem;
 #[cfg(not(feature = "semihosting"))]
 mod panic;
 mod pmic;
+mod settings;
 mod system;
 mod terminal;
 mod time;
@@ -147,7 +148,7 @@ unsafe fn main() -> ! {
     });
 
     // GPIO
-    let (_gpioa, _gpiob, _gpioc, gpiod, gpioe, gpiof, gpiog, gpioh, gpioi, gpioj, _gpiok) = {
+    let (gpioa, _gpiob, _gpioc, gpiod, gpioe, gpiof, gpiog, gpioh, gpioi, gpioj, _gpiok) = {
         (
             dp.GPIOA.split(ccdr.peripheral.GPIOA),
             dp.GPIOB.split(ccdr.peripheral.GPIOB),
@@ -196,6 +197,7 @@ unsafe fn main() -> ! {
 
     // // UART1 terminal
     // {
+    //     let baud = settings::get().uart_baud;
     //     let mut uart = dp
     //         .USART1
     //         .serial(
@@ -203,12 +205,15 @@ unsafe fn main() -> ! {
     //                 gpioa.pa9.into_alternate::<7>(), // tx
     //                 gpiob.pb7.into_alternate::<7>(), // rx
     //             ),
-    //             terminal::UART_TERMINAL_BAUD.bps(),
+    //             baud.bps(),
     //             ccdr.peripheral.USART1,
     //             &ccdr.clocks,
     //         )
     //         .unwrap();
 
+    //     // USART1 runs off PCLK2 unless D2CCIP2R says otherwise
+    //     terminal::uart::init(ccdr.clocks.pclk2(), baud);
+
     //     // UART interrupt
     //     uart.listen(hal::serial::Event::Rxne);
     //     cortex_m::peripheral::NVIC::unmask(pac::Interrupt::USART1);
@@ -226,6 +231,20 @@ unsafe fn main() -> ! {
     //     });
     // };
 
+    // USB terminal
+    {
+        let usb = hal::usb_hs::USB2::new(
+            dp.OTG2_HS_GLOBAL,
+            dp.OTG2_HS_DEVICE,
+            dp.OTG2_HS_PWRCLK,
+            gpioa.pa11.into_alternate(),
+            gpioa.pa12.into_alternate(),
+            ccdr.peripheral.USB2OTG,
+            &ccdr.clocks,
+        );
+        terminal::usb::init(usb);
+    }
+
     // gpiok.pk3.into_push_pull_output().set_high().unwrap(); // cable
     // gpiok.pk4.into_push_pull_output().set_low().unwrap(); // alt
 
@@ -419,6 +438,29 @@ unsafe fn main() -> ! {
     //     });
     // }
 
+    // Settings are kept on the NOR flash, the defaults stay if it holds no filesystem
+    {
+        let loaded = h7_fs::norfs::NorFs::mount(fs::qspi_store::QspiFlash)
+            .map_err(fs::vfs::VfsError::from)
+            .and_then(|nor| {
+                fs::vfs::mount(
+                    "nor",
+                    alloc::boxed::Box::new(fs::nor_fs::NorFsVolume::new(nor)),
+                )
+            })
+            .and_then(|()| settings::load());
+        if let Err(e) = loaded {
+            log::info!("Using default settings: {e}");
+        }
+
+        // The UART came up before the settings could be read
+        let settings = settings::get();
+        terminal::uart::configure(&settings);
+        if settings.uart_baud != terminal::uart::baud() {
+            let _ = terminal::uart::set_baud(settings.uart_baud);
+        }
+    }
+
     // FIXME
     // // Display config
     // {
@@ -486,63 +528,27 @@ unsafe fn main() -> ! {
     //     );
     // }
 
-    let mut menu = terminal::menu::Menu::new(terminal::TerminalWriter, terminal::MENU);
-
-    let mut cmd_buf = [0u8; 1024];
-    let mut cmd_buf_len: usize = 0;
+    // One session per console
+    let mut sessions = terminal::Port::ALL.map(terminal::session::Session::new);
 
     // Main loop
     set_red_led(LedState::Off);
     set_green_led(LedState::Off);
     set_blue_led(LedState::Off);
-    let _ = write!(menu.writer(), "> ");
+    for session in sessions.iter_mut() {
+        session.finish_line();
+    }
 
     loop {
-        match terminal::TERMINAL_INPUT_FIFO.dequeue() {
-            Some(10) => match core::str::from_utf8(&cmd_buf[0..cmd_buf_len]) {
-                Ok(s) => {
-                    let mut parts = s.split_whitespace().filter(|l| !l.trim().is_empty());
-
-                    if let Some(cmd) = parts.next() {
-                        // Collect up to 16 arguments
-                        let mut args = [""; 16];
-                        let mut args_len = 0;
-                        for arg in args.iter_mut() {
-                            match parts.next() {
-                                Some(s) => {
-                                    *arg = s;
-                                    args_len += 1;
-                                }
-                                None => break,
-                            }
-                        }
-                        // Run command
-                        if let Err(e) = menu.run(cmd, &args[0..args_len]) {
-                            let _ = writeln!(menu.writer(), "Error: {e}");
-                        }
-                        // Clear input
-                        delay.delay_ms(10u8); // Wait for interrupts
-                        while terminal::TERMINAL_INPUT_FIFO.dequeue().is_some() {}
-                        cmd_buf_len = 0;
-                    }
-                    let _ = write!(menu.writer(), "> ");
-                }
-                Err(e) => {
-                    let _ = writeln!(menu, "Error: {e}");
-                }
-            },
-            Some(c) => {
-                if cmd_buf_len < cmd_buf.len() {
-                    cmd_buf[cmd_buf_len] = c;
-                    cmd_buf_len += 1;
-                } else {
-                    let _ = writeln!(menu, "Error: Buffer full");
-                }
+        for session in sessions.iter_mut() {
+            if session.poll() {
+                delay.delay_ms(10u8); // Wait for interrupts
+                session.finish_line();
             }
-            None => {
-                // defmt::println!("No UART data");
-            } // FIFO empty
-        };
+        }
+
+        // Mount or drop the SD card as it is inserted and pulled
+        fs::sdmmc_fs::hotplug::poll();
 
         // Blink
         if let Some(dt) = TimeSource::get_date_time() {
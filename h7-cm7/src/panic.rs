@@ -11,12 +11,7 @@ struct PanicLogger;
 #[cfg(feature = "usb_logging")]
 impl Write for PanicLogger {
     fn write_str(&mut self, s: &str) -> core::fmt::Result {
-        interrupt_free(|cs| {
-            if let Some(tx) = &mut *terminal::UART_TERMINAL_TX.borrow(cs).borrow_mut() {
-                write!(tx, "{s}")?
-            }
-            Ok(())
-        })
+        crate::terminal::BroadcastWriter.write_str(s)
     }
 }
 
@@ -35,6 +30,8 @@ impl Write for PanicLogger {
 fn panic_handler(panic_info: &PanicInfo) -> ! {
     // TODO: Render panic info to display
 
+    // Interrupts might never run again, flush pending output and write directly from now on
+    crate::terminal::uart::enter_blocking_mode();
     let _ = writeln!(PanicLogger, "{panic_info}");
     const LIMIT: usize = 10_000_000;
     const LIMIT_DC: usize = LIMIT / 2;
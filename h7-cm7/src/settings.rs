@@ -0,0 +1,171 @@
+//! System-wide settings, see `sys config`, kept in a text file on the NOR flash
+
+use {
+    crate::{
+        fs::{
+            path::Path,
+            vfs::{self, VfsError, WriteMode},
+        },
+        terminal::UART_TERMINAL_BAUD,
+        utils::interrupt_free,
+    },
+    alloc::{string::String, vec::Vec},
+    core::{cell::RefCell, fmt, fmt::Write},
+    critical_section::Mutex,
+    h7_shell::input::Flow,
+};
+
+/// Where [`save`] keeps the settings, one `key value` line each
+pub const PATH: &str = "nor:/settings";
+
+static SETTINGS: Mutex<RefCell<Settings>> = Mutex::new(RefCell::new(Settings::DEFAULT));
+
+/// Bounds of `uart.rx_buffer`, the buffer lives on the heap
+const UART_RX_BUFFER_MIN: usize = 64;
+const UART_RX_BUFFER_MAX: usize = 64 * 1024;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Settings {
+    /// Bus clock `sdcard mount` brings cards up with, in kHz
+    pub sd_mount_khz: u32,
+    /// Mount cards when they are inserted
+    pub sd_automount: bool,
+    /// Size of the UART console's input buffer in bytes
+    pub uart_rx_buffer: usize,
+    /// Flow control on the UART console
+    pub uart_flow: Flow,
+    /// Baud rate the UART console starts with
+    pub uart_baud: u32,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SettingsError {
+    UnknownKey,
+    InvalidValue,
+}
+
+impl fmt::Display for SettingsError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::UnknownKey => write!(f, "Unknown setting"),
+            Self::InvalidValue => write!(f, "Invalid value"),
+        }
+    }
+}
+
+impl Settings {
+    pub const DEFAULT: Self = Self {
+        sd_mount_khz: 400,
+        sd_automount: true,
+        uart_rx_buffer: 4096,
+        uart_flow: Flow::None,
+        uart_baud: UART_TERMINAL_BAUD,
+    };
+
+    pub const KEYS: &'static [&'static str] = &[
+        "sdcard.freq",
+        "sdcard.automount",
+        "uart.rx_buffer",
+        "uart.flow",
+        "uart.baud",
+    ];
+
+    /// Write the value of `key` as `sys config` shows it
+    pub fn write_value(&self, key: &str, w: &mut impl fmt::Write) -> Result<(), SettingsError> {
+        match key {
+            "sdcard.freq" => write!(w, "{}", self.sd_mount_khz),
+            "sdcard.automount" => write!(w, "{}", on_off(self.sd_automount)),
+            "uart.rx_buffer" => write!(w, "{}", self.uart_rx_buffer),
+            "uart.flow" => write!(w, "{}", self.uart_flow),
+            "uart.baud" => write!(w, "{}", self.uart_baud),
+            _ => return Err(SettingsError::UnknownKey),
+        }
+        .map_err(|_| SettingsError::InvalidValue)
+    }
+
+    pub fn set(&mut self, key: &str, value: &str) -> Result<(), SettingsError> {
+        match key {
+            "sdcard.freq" => self.sd_mount_khz = parse(value)?,
+            "sdcard.automount" => {
+                self.sd_automount = match value {
+                    "on" | "true" | "1" => true,
+                    "off" | "false" | "0" => false,
+                    _ => return Err(SettingsError::InvalidValue),
+                }
+            }
+            "uart.rx_buffer" => {
+                let size = parse(value)? as usize;
+                if !(UART_RX_BUFFER_MIN..=UART_RX_BUFFER_MAX).contains(&size) {
+                    return Err(SettingsError::InvalidValue);
+                }
+                self.uart_rx_buffer = size;
+            }
+            "uart.flow" => {
+                self.uart_flow = Flow::parse(value).ok_or(SettingsError::InvalidValue)?
+            }
+            "uart.baud" => self.uart_baud = parse(value)?,
+            _ => return Err(SettingsError::UnknownKey),
+        }
+        Ok(())
+    }
+}
+
+pub fn get() -> Settings {
+    interrupt_free(|cs| *SETTINGS.borrow_ref(cs))
+}
+
+pub fn update<R>(f: impl FnOnce(&mut Settings) -> R) -> R {
+    interrupt_free(|cs| f(&mut SETTINGS.borrow_ref_mut(cs)))
+}
+
+/// Read the settings saved with [`save`]. Keys missing from the file keep their value, unknown
+/// keys and invalid values are skipped.
+pub fn load() -> Result<(), VfsError> {
+    let path = Path::new(PATH);
+    let mut data = Vec::new();
+    let mut buf = [0u8; 256];
+    vfs::with_fs(path, |fs| {
+        fs.read_chunks(path, &mut buf, &mut |chunk| {
+            data.extend_from_slice(chunk);
+            true
+        })
+    })?;
+    let text = String::from_utf8_lossy(&data);
+    update(|settings| {
+        for line in text.lines() {
+            if let Some((key, value)) = line.trim().split_once(' ') {
+                let _ = settings.set(key, value.trim());
+            }
+        }
+    });
+    Ok(())
+}
+
+/// Write all settings to [`PATH`]
+pub fn save() -> Result<(), VfsError> {
+    let settings = get();
+    let mut text = String::new();
+    for key in Settings::KEYS {
+        let _ = write!(text, "{key} ");
+        let _ = settings.write_value(key, &mut text);
+        text.push('\n');
+    }
+    let path = Path::new(PATH);
+    vfs::with_fs(path, |fs| {
+        fs.write(path, WriteMode::Truncate, text.as_bytes())
+    })
+}
+
+fn parse(value: &str) -> Result<u32, SettingsError> {
+    match value.parse() {
+        Ok(n) if n > 0 => Ok(n),
+        _ => Err(SettingsError::InvalidValue),
+    }
+}
+
+fn on_off(b: bool) -> &'static str {
+    match b {
+        true => "on",
+        false => "off",
+    }
+}
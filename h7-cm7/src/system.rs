@@ -1,5 +1,7 @@
 use {
+    crate::utils::interrupt_free,
     core::cell::RefCell,
+    cortex_m::peripheral::DWT,
     critical_section::{CriticalSection, Mutex},
     stm32h7xx_hal::{
         self as hal,
@@ -12,6 +14,8 @@ use {
 
 // As per schematic
 const VDDA: f64 = 3.100;
+/// Used to time out if the clock frequency is unknown
+const DEFAULT_CPU_HZ: u32 = 400_000_000;
 
 extern "C" {
     static _ram_start: u32;
@@ -48,3 +52,28 @@ pub fn ram_size() -> usize {
 pub fn flash_size() -> usize {
     hal::signature::FlashSize::bytes()
 }
+
+/// Counts down busy-waiting time with the cycle counter
+pub struct Timeout {
+    remaining: u64,
+    last: u32,
+}
+
+impl Timeout {
+    pub fn new(ms: u64) -> Self {
+        let hz = interrupt_free(cpu_freq).map_or(DEFAULT_CPU_HZ, |f| f.raw());
+        Self {
+            remaining: ms * (hz as u64 / 1000),
+            last: DWT::cycle_count(),
+        }
+    }
+
+    pub fn expired(&mut self) -> bool {
+        // The cycle counter wraps within seconds, count down the steps instead
+        let now = DWT::cycle_count();
+        let step = now.wrapping_sub(self.last) as u64;
+        self.last = now;
+        self.remaining = self.remaining.saturating_sub(step);
+        self.remaining == 0
+    }
+}
@@ -1,115 +1,588 @@
 use {
-    super::utils::from_hex,
+    super::{
+        confirm, resolve,
+        utils::{check_args_len, from_hex, parse_size, strip_flag, Utf8Lossy},
+        CONFIRM_MATCHES,
+    },
     crate::{
         fs::{
+            nor_fs::NorFsVolume,
             path::Path,
-            qspi_store::{mx25l::status as mx25l_status, QSPI_STORE},
-            sdmmc_fs,
+            qspi_store::{flash_size, mx25l::status as mx25l_status, QspiFlash, QSPI_STORE},
+            ram_disk,
+            sdmmc_fs::{self, error::SdmmcFsError, hotplug, CardInfo},
+            vfs::{self, Stats, VfsError, Wear},
         },
+        settings,
         terminal::{
             commands::LABEL_WIDTH,
-            menu::{MenuError, MenuItem},
+            menu::{Menu, MenuError, MenuItem, MenuResult},
             TerminalWriter,
         },
+        time::TimeSource,
         utils::interrupt_free,
     },
+    alloc::{
+        boxed::Box,
+        format,
+        string::{String, ToString},
+        vec,
+        vec::Vec,
+    },
     core::fmt::Write,
+    embedded_sdmmc::Block,
     fugit::RateExtU32,
+    h7_fs::{
+        fat::{self, FatType, FormatOptions, Layout},
+        glob,
+        norfs::NorFs,
+        partition::PartitionTable,
+        sfdp::FlashParameters,
+    },
+    h7_shell::json::{self, JsonObject, OutputMode},
     stm32h7xx_hal as hal,
 };
 
 pub const LS: MenuItem<'static, TerminalWriter> = MenuItem::Command {
     name: "ls",
-    help: "ls <device>:<dir> - List files on a filesystem",
+    help:
+        "ls [dir|pattern] - List files, in the working directory if no dir is given, e.g. ls *.h7",
     description: "List files",
     action: |m, args| {
-        let path = Path::new(args.first().unwrap_or(&""));
-        match path.device() {
-            Some("sdcard") => interrupt_free(|cs| {
-                match crate::fs::sdmmc_fs::SD_CARD
-                    .borrow(cs)
-                    .borrow_mut()
-                    .as_mut()
-                    .map(|sdfs| {
-                        sdfs.ls(path, |e| {
-                            let _ = sdmmc_fs::print_dir_entry(m.writer(), e);
-                        })
-                    }) {
-                    Some(Ok(_)) => Ok(()),
-                    Some(Err(e)) => {
-                        writeln!(m.writer(), "Error: {e}")?;
-                        Ok(())
-                    }
-                    None => {
-                        writeln!(m.writer(), "Error: SD Card controller not initialized")?;
-                        Ok(())
-                    }
+        let path = resolve(m, args.first().unwrap_or(&""));
+        let path = Path::new(&path);
+        // Wildcards filter the entries of the parent directory
+        let (dir, pattern) = match path.file_name() {
+            Some(name) if glob::is_pattern(name) => (path.parent().unwrap_or(path), Some(name)),
+            _ => (path, None),
+        };
+        let res = vfs::with_fs(dir, |fs| {
+            let case_sensitive = fs.case_sensitive();
+            fs.ls(dir, &mut |entry| {
+                let shown = match pattern {
+                    Some(pattern) => glob::matches(pattern, &entry.name, case_sensitive),
+                    None => true,
+                };
+                if shown {
+                    let _ = vfs::print_entry(m.writer(), entry);
                 }
-            }),
-            Some("nor") => {
-                writeln!(m.writer(), "Not implemented")?;
-                Ok(())
-            }
-            Some(device) => {
-                writeln!(m.writer(), "Unknown device '{device}'")?;
-                Ok(())
-            }
-            None => {
-                writeln!(m.writer(), "No device selected")?;
-                Ok(())
-            }
+            })
+        });
+        if let Err(e) = res {
+            writeln!(m.writer(), "Error: {e}")?;
         }
+        Ok(())
     },
 };
 
 pub const MV: MenuItem<'static, TerminalWriter> = MenuItem::Command {
     name: "mv",
-    help: "mv <source> <destination> - Move a file or directory from source to destination",
-    description: "(TODO) Move a file or directory",
-    action: |m, _| {
-        writeln!(m.writer(), "todo")?;
+    help: "mv <source> <destination> - Move files from source to destination, across devices too",
+    description: "Move a file",
+    action: |m, args| {
+        check_args_len(2, args.len())?;
+        let mut buf = vec![0u8; COPY_BUFFER_SIZE];
+        let Some(sources) = expand_sources(m, args[0], Some(args[1]))? else {
+            return Ok(());
+        };
+        if sources.len() > CONFIRM_MATCHES
+            && !confirm(m, &format!("Move {} entries?", sources.len()))?
+        {
+            return Ok(());
+        }
+        let to = resolve(m, args[1]);
+        for from in &sources {
+            if let Err(e) = vfs::rename(Path::new(from), Path::new(&to), &mut buf) {
+                print_error(m, &sources, from, e)?;
+            }
+        }
         Ok(())
     },
 };
 
 pub const RM: MenuItem<'static, TerminalWriter> = MenuItem::Command {
     name: "rm",
-    help: "rm <file> - Remove a file from a filesystem",
-    description: "(TODO) Remove a file from a filesystem",
-    action: |m, _| {
-        writeln!(m.writer(), "todo")?;
+    help: "rm [-r] <file> - Remove files, or with -r directories and everything in them",
+    description: "Remove a file from a filesystem",
+    action: |m, args| {
+        let (recursive, args) = strip_flag(args, "-r");
+        check_args_len(1, args.len())?;
+        let Some(paths) = expand_sources(m, args[0], None)? else {
+            return Ok(());
+        };
+        if paths.len() > CONFIRM_MATCHES
+            && !confirm(m, &format!("Remove {} entries?", paths.len()))?
+        {
+            return Ok(());
+        }
+        for path in &paths {
+            if let Err(e) = vfs::remove(Path::new(path), recursive) {
+                print_error(m, &paths, path, e)?;
+            }
+        }
         Ok(())
     },
 };
 
 pub const CP: MenuItem<'static, TerminalWriter> = MenuItem::Command {
     name: "cp",
-    help: "cp <source> <destination> - Copy a file or directory from source to destination",
-    description: "(TODO) Copy a file or directory",
-    action: |m, _| {
-        writeln!(m.writer(), "todo")?;
+    help: "cp [-r] <source> <destination> - Copy files, or with -r directories, across devices too",
+    description: "Copy a file",
+    action: |m, args| {
+        let (recursive, args) = strip_flag(args, "-r");
+        check_args_len(2, args.len())?;
+        let mut buf = vec![0u8; COPY_BUFFER_SIZE];
+        let Some(sources) = expand_sources(m, args[0], Some(args[1]))? else {
+            return Ok(());
+        };
+        let to = resolve(m, args[1]);
+        let (mut copied, mut failed) = (0, 0);
+        for from in &sources {
+            match vfs::copy(Path::new(from), Path::new(&to), recursive, &mut buf) {
+                Ok(bytes) => copied += bytes,
+                Err(e) => {
+                    failed += 1;
+                    print_error(m, &sources, from, e)?;
+                }
+            }
+        }
+        if failed < sources.len() {
+            writeln!(m.writer(), "Copied {copied} bytes")?;
+        }
         Ok(())
     },
 };
 
 pub const CAT: MenuItem<'static, TerminalWriter> = MenuItem::Command {
     name: "cat",
-    help: "cat - Read and print a file to stdout",
-    description: "(TODO) Read and print a file to stdout",
-    action: |m, _| {
-        writeln!(m.writer(), "todo")?;
+    help: "cat <file> - Read and print a file to stdout",
+    description: "Read and print a file to stdout",
+    action: |m, args| {
+        check_args_len(1, args.len())?;
+        let path = resolve(m, args[0]);
+        let path = Path::new(&path);
+        let mut buf = vec![0u8; COPY_BUFFER_SIZE];
+        let mut out = Utf8Lossy::new(m.writer());
+        let res = vfs::with_fs(path, |fs| {
+            fs.read_chunks(path, &mut buf, &mut |chunk| out.write_bytes(chunk).is_ok())
+        });
+        out.finish()?;
+        if let Err(e) = res {
+            writeln!(m.writer(), "Error: {e}")?;
+        }
+        Ok(())
+    },
+};
+
+pub const MKDIR: MenuItem<'static, TerminalWriter> = MenuItem::Command {
+    name: "mkdir",
+    help: "mkdir [-p] <dir> - Create a directory, with -p missing parents are created too",
+    description: "Create a directory",
+    action: |m, args| {
+        let (parents, args) = strip_flag(args, "-p");
+        check_args_len(1, args.len())?;
+        let path = resolve(m, args[0]);
+        let path = Path::new(&path);
+        if let Err(e) = vfs::with_fs(path, |fs| fs.create_dir(path, parents)) {
+            writeln!(m.writer(), "Error: {e}")?;
+        }
+        Ok(())
+    },
+};
+
+pub const RMDIR: MenuItem<'static, TerminalWriter> = MenuItem::Command {
+    name: "rmdir",
+    help: "rmdir <dir> - Remove an empty directory",
+    description: "Remove an empty directory",
+    action: |m, args| {
+        check_args_len(1, args.len())?;
+        let path = resolve(m, args[0]);
+        let path = Path::new(&path);
+        if let Err(e) = vfs::with_fs(path, |fs| fs.remove_dir(path)) {
+            writeln!(m.writer(), "Error: {e}")?;
+        }
+        Ok(())
+    },
+};
+
+pub const TOUCH: MenuItem<'static, TerminalWriter> = MenuItem::Command {
+    name: "touch",
+    help: "touch <file> - Create an empty file if it doesn't exist",
+    description: "Create an empty file",
+    action: |m, args| {
+        check_args_len(1, args.len())?;
+        let path = resolve(m, args[0]);
+        let path = Path::new(&path);
+        if let Err(e) = vfs::with_fs(path, |fs| fs.touch(path)) {
+            writeln!(m.writer(), "Error: {e}")?;
+        }
         Ok(())
     },
 };
 
+pub const CD: MenuItem<'static, TerminalWriter> = MenuItem::Command {
+    name: "cd",
+    help: "cd <dir> - Change the working directory relative paths start at, e.g. cd sdcard:/logs",
+    description: "Change the working directory",
+    action: |m, args| {
+        check_args_len(1, args.len())?;
+        let dir = resolve(m, args[0]);
+        let path = Path::new(&dir);
+        let res = vfs::is_dir(path).and_then(|is_dir| match is_dir {
+            true => Ok(()),
+            false => Err(VfsError::NotADirectory),
+        });
+        match res {
+            Ok(()) => m.set_cwd(dir),
+            Err(e) => writeln!(m.writer(), "Error: {e}")?,
+        }
+        Ok(())
+    },
+};
+
+pub const PWD: MenuItem<'static, TerminalWriter> = MenuItem::Command {
+    name: "pwd",
+    help: "pwd - Print the working directory",
+    description: "Print the working directory",
+    action: |m, args| {
+        check_args_len(0, args.len())?;
+        match m.cwd() {
+            "" => writeln!(m.writer(), "Error: {}", VfsError::NoDevice)?,
+            cwd => {
+                let cwd = cwd.to_string();
+                writeln!(m.writer(), "{cwd}")?;
+            }
+        }
+        Ok(())
+    },
+};
+
+pub const MOUNT: MenuItem<'static, TerminalWriter> = MenuItem::Command {
+    name: "mount",
+    help: "mount - List mounted filesystems",
+    description: "List mounted filesystems",
+    action: |m, args| {
+        check_args_len(0, args.len())?;
+        let mut res = Ok(());
+        vfs::for_each_mount(|device, fs| {
+            if res.is_ok() {
+                let device = format!("{device}:");
+                res = writeln!(m.writer(), "{device:LABEL_WIDTH$} type {}", fs.kind());
+            }
+        });
+        Ok(res?)
+    },
+};
+
+pub const DF: MenuItem<'static, TerminalWriter> = MenuItem::Command {
+    name: "df",
+    help: "df - Show size, used and free space of mounted filesystems",
+    description: "Show free space",
+    action: |m, args| {
+        check_args_len(0, args.len())?;
+        writeln!(
+            m.writer(),
+            "{:10} {:6} {:>14} {:>14} {:>14}",
+            "Device",
+            "Type",
+            "Size",
+            "Used",
+            "Free"
+        )?;
+        let mut res = Ok(());
+        vfs::for_each_mount(|device, fs| {
+            if res.is_err() {
+                return;
+            }
+            let (device, kind) = (format!("{device}:"), fs.kind());
+            res = match fs.stats() {
+                Ok(Stats {
+                    total_bytes,
+                    free_bytes,
+                    ..
+                }) => {
+                    let used = free_bytes.map(|free| total_bytes.saturating_sub(free).to_string());
+                    let free = free_bytes.map(|n| n.to_string());
+                    writeln!(
+                        m.writer(),
+                        "{device:10} {kind:6} {total_bytes:>14} {:>14} {:>14}",
+                        used.as_deref().unwrap_or("-"),
+                        free.as_deref().unwrap_or("-")
+                    )
+                }
+                Err(e) => writeln!(m.writer(), "{device:10} {kind:6} Error: {e}"),
+            };
+        });
+        Ok(res?)
+    },
+};
+
+pub const DU: MenuItem<'static, TerminalWriter> = MenuItem::Command {
+    name: "du",
+    help: "du [-s] [path] - Show the size of a directory and everything in it, -s only the total",
+    description: "Show disk usage",
+    action: |m, args| {
+        let (summary, args) = strip_flag(args, "-s");
+        if args.len() > 1 {
+            check_args_len(1, args.len())?;
+        }
+        let path = resolve(m, args.first().unwrap_or(&""));
+        let path = Path::new(&path);
+        match vfs::disk_usage(path) {
+            Ok(totals) => {
+                for (dir, total) in totals {
+                    if !summary || Path::new(&dir) == path {
+                        writeln!(m.writer(), "{total:>14} {dir}")?;
+                    }
+                }
+            }
+            Err(e) => writeln!(m.writer(), "Error: {e}")?,
+        }
+        Ok(())
+    },
+};
+
+pub const FIND: MenuItem<'static, TerminalWriter> = MenuItem::Command {
+    name: "find",
+    help: "find [path] [-name <pattern>] [-size [+|-]<size>] - Find files below a directory",
+    description: "Find files",
+    action: |m, args| {
+        let (dir, mut opts) = match args.split_first() {
+            Some((first, rest)) if !first.starts_with('-') => (*first, rest),
+            _ => ("", args),
+        };
+        let (mut name, mut size) = (None, None);
+        while let Some(opt) = opts.first() {
+            match (*opt, opts.get(1)) {
+                ("-name", Some(pattern)) => name = Some(*pattern),
+                ("-size", Some(filter)) => match SizeFilter::parse(filter) {
+                    Some(filter) => size = Some(filter),
+                    None => return Err(MenuError::InvalidArgument),
+                },
+                _ => {
+                    writeln!(m.writer(), "Expected:")?;
+                    writeln!(m.writer(), "\t-name <pattern> - Name matches, e.g. *.txt")?;
+                    writeln!(
+                        m.writer(),
+                        "\t-size [+|-]<size> - Files of exactly, more or less than size, e.g. +64K"
+                    )?;
+                    return Err(MenuError::InvalidArgument);
+                }
+            }
+            opts = &opts[2..];
+        }
+
+        let dir = resolve(m, dir);
+        let dir = Path::new(&dir);
+        let res = vfs::with_fs(dir, |fs| {
+            let case_sensitive = fs.case_sensitive();
+            vfs::walk(fs, dir, &mut |path, entry| {
+                let name_matches = match name {
+                    Some(pattern) => glob::matches(pattern, &entry.name, case_sensitive),
+                    None => true,
+                };
+                let size_matches = match size {
+                    Some(size) => !entry.is_dir && size.matches(entry.size),
+                    None => true,
+                };
+                if name_matches && size_matches {
+                    let _ = writeln!(m.writer(), "{path}");
+                }
+                Ok(())
+            })
+        });
+        if let Err(e) = res {
+            writeln!(m.writer(), "Error: {e}")?;
+        }
+        Ok(())
+    },
+};
+
+/// The `-size` test of `find`
+#[derive(Clone, Copy)]
+enum SizeFilter {
+    Exactly(usize),
+    MoreThan(usize),
+    LessThan(usize),
+}
+
+impl SizeFilter {
+    fn parse(s: &str) -> Option<Self> {
+        match s.as_bytes().first()? {
+            b'+' => parse_size(&s[1..]).map(Self::MoreThan),
+            b'-' => parse_size(&s[1..]).map(Self::LessThan),
+            _ => parse_size(s).map(Self::Exactly),
+        }
+    }
+
+    fn matches(self, size: u32) -> bool {
+        let size = size as usize;
+        match self {
+            Self::Exactly(n) => size == n,
+            Self::MoreThan(n) => size > n,
+            Self::LessThan(n) => size < n,
+        }
+    }
+}
+
+/// Size of the buffer files are streamed through
+const COPY_BUFFER_SIZE: usize = 4096;
+
+/// Resolve and expand the wildcards of `arg`. With more than one match, `destination` must be a
+/// directory. `None` if the command can't go ahead, the error was printed already.
+fn expand_sources(
+    m: &mut Menu<'_, TerminalWriter>,
+    arg: &str,
+    destination: Option<&str>,
+) -> Result<Option<Vec<String>>, MenuError> {
+    let res = vfs::glob(Path::new(&resolve(m, arg))).and_then(|paths| match destination {
+        Some(to) if paths.len() > 1 => {
+            let to = resolve(m, to);
+            match vfs::is_dir(Path::new(&to)) {
+                Ok(true) => Ok(paths),
+                Ok(false) | Err(VfsError::NotFound) => Err(VfsError::NotADirectory),
+                Err(e) => Err(e),
+            }
+        }
+        _ => Ok(paths),
+    });
+    match res {
+        Ok(paths) => Ok(Some(paths)),
+        Err(e) => {
+            writeln!(m.writer(), "Error: {e}")?;
+            Ok(None)
+        }
+    }
+}
+
+/// Print the error of one of `paths`, naming it if there are several
+fn print_error(
+    m: &mut Menu<'_, TerminalWriter>,
+    paths: &[String],
+    path: &str,
+    e: VfsError,
+) -> core::fmt::Result {
+    match paths.len() {
+        1 => writeln!(m.writer(), "Error: {e}"),
+        _ => writeln!(m.writer(), "Error: {path}: {e}"),
+    }
+}
+
+fn write_flash_parameters(w: &mut impl Write, parameters: &FlashParameters) -> core::fmt::Result {
+    writeln!(
+        w,
+        "{:LABEL_WIDTH$} {}.{}",
+        "SFDP revision", parameters.major, parameters.minor
+    )?;
+    writeln!(w, "{:LABEL_WIDTH$} {}", "Capacity", parameters.capacity)?;
+    writeln!(w, "{:LABEL_WIDTH$} {}", "Page size", parameters.page_size)?;
+    writeln!(
+        w,
+        "{:LABEL_WIDTH$} {:?}",
+        "Address bytes", parameters.address_bytes
+    )?;
+    for erase in &parameters.erase_types {
+        writeln!(
+            w,
+            "{:LABEL_WIDTH$} {} KiB, opcode {:02x}",
+            "Erase",
+            erase.size / 1024,
+            erase.opcode
+        )?;
+    }
+    for read in &parameters.fast_reads {
+        writeln!(
+            w,
+            "{:LABEL_WIDTH$} {}, opcode {:02x}, {} dummy + {} mode clocks",
+            "Fast read", read.mode, read.opcode, read.dummy_cycles, read.mode_clocks
+        )?;
+    }
+    Ok(())
+}
+
 pub const NOR: MenuItem<'static, TerminalWriter> = MenuItem::Command {
     name: "nor",
     help: "nor <(i|info)|(m|mount)|(u|unmount)|(f|format)> - Info/Mount/Unmount/Format NOR-Flash filesystem",
     description: "Info/Mount/Unmount/Format NOR-Flash filesystem",
     action: |m, args| {
-        // writeln!(m.writer(), "todo")?;
-
         match args {
+            ["i" | "info"] => {
+                let mounted = vfs::is_mounted("nor");
+                writeln!(m.writer(), "{:LABEL_WIDTH$} {}", "NOR mounted", mounted)?;
+                writeln!(
+                    m.writer(),
+                    "{:LABEL_WIDTH$} {}MiB",
+                    "Flash size",
+                    flash_size() / (1024 * 1024)
+                )?;
+                let parameters = interrupt_free(|cs| {
+                    QSPI_STORE.borrow_ref(cs).as_ref().map(|store| store.parameters().clone())
+                });
+                if let Some(parameters) = parameters {
+                    write_flash_parameters(m.writer(), &parameters)?;
+                }
+                if mounted {
+                    match vfs::with_fs(Path::new("nor:"), |fs| fs.stats()) {
+                        Ok(Stats {
+                            total_bytes,
+                            free_bytes,
+                            wear,
+                        }) => {
+                            writeln!(m.writer(), "{:LABEL_WIDTH$} {total_bytes}", "Size")?;
+                            if let Some(free_bytes) = free_bytes {
+                                writeln!(m.writer(), "{:LABEL_WIDTH$} {free_bytes}", "Free")?;
+                            }
+                            if let Some(Wear {
+                                block_size,
+                                blocks,
+                                free_blocks,
+                                min_erases,
+                                max_erases,
+                            }) = wear
+                            {
+                                writeln!(
+                                    m.writer(),
+                                    "{:LABEL_WIDTH$} {free_blocks}/{blocks} free, {block_size} bytes each",
+                                    "Blocks"
+                                )?;
+                                writeln!(
+                                    m.writer(),
+                                    "{:LABEL_WIDTH$} {min_erases} - {max_erases}",
+                                    "Erase count"
+                                )?;
+                            }
+                        }
+                        Err(e) => writeln!(m.writer(), "Error: {e}")?,
+                    }
+                }
+            }
+            ["m" | "mount"] => {
+                let res = match vfs::is_mounted("nor") {
+                    true => Err(VfsError::AlreadyMounted("nor".to_string())),
+                    false => NorFs::mount(QspiFlash)
+                        .map_err(VfsError::from)
+                        .and_then(|fs| vfs::mount("nor", Box::new(NorFsVolume::new(fs)))),
+                };
+                match res {
+                    Ok(()) => writeln!(m.writer(), "NOR flash mounted")?,
+                    Err(e) => writeln!(m.writer(), "Error: {e}")?,
+                }
+            }
+            ["u" | "unmount"] => match vfs::unmount("nor") {
+                Ok(_) => writeln!(m.writer(), "NOR flash unmounted")?,
+                Err(e) => writeln!(m.writer(), "Error: {e}")?,
+            },
+            ["f" | "format"] => {
+                // Erase counts are kept, so a mounted filesystem can be formatted in place
+                let _ = vfs::unmount("nor");
+                let res = NorFs::format(QspiFlash)
+                    .map_err(VfsError::from)
+                    .and_then(|fs| vfs::mount("nor", Box::new(NorFsVolume::new(fs))));
+                match res {
+                    Ok(()) => writeln!(m.writer(), "NOR flash formatted and mounted")?,
+                    Err(e) => writeln!(m.writer(), "Error: {e}")?,
+                }
+            }
             ["dev", "ce"] => {
                 let result = interrupt_free(|cs| {
                     QSPI_STORE.borrow(cs).borrow_mut().as_deref_mut().unwrap().chip_erase()
@@ -134,6 +607,19 @@ pub const NOR: MenuItem<'static, TerminalWriter> = MenuItem::Command {
                         writeln!(m.writer(), "Error: {e:?}")?;
                     }
                 }
+                let result = interrupt_free(|cs| {
+                    QSPI_STORE
+                        .borrow(cs)
+                        .borrow_mut()
+                        .as_deref_mut()
+                        .unwrap()
+                        .discover()
+                        .cloned()
+                });
+                match result {
+                    Ok(parameters) => write_flash_parameters(m.writer(), &parameters)?,
+                    Err(e) => writeln!(m.writer(), "SFDP: {e}, using MX25L defaults")?,
+                }
             },
             ["dev", "config"] => {
                 let result = interrupt_free(|cs| {
@@ -160,6 +646,23 @@ pub const NOR: MenuItem<'static, TerminalWriter> = MenuItem::Command {
                     QSPI_STORE.borrow(cs).borrow_mut().as_deref_mut().unwrap().read_status()
                 });
                 match result {
+                    Ok(status) if m.output() == OutputMode::Json => {
+                        json::write_object(m.writer(), |o| {
+                            o.num("status", status)?
+                                .bool("srwd", (status & mx25l_status::SRWD) > 0)?
+                                .bool("qe", (status & mx25l_status::QE) > 0)?
+                                .bool("bp3", (status & mx25l_status::BP3) > 0)?
+                                .bool("bp2", (status & mx25l_status::BP2) > 0)?
+                                .bool("bp1", (status & mx25l_status::BP1) > 0)?
+                                .bool("bp0", (status & mx25l_status::BP0) > 0)?
+                                .bool("wel", (status & mx25l_status::WEL) > 0)?
+                                .bool("wip", (status & mx25l_status::WIP) > 0)?;
+                            Ok(())
+                        })?;
+                    }
+                    Err(e) if m.output() == OutputMode::Json => {
+                        json::write_error(m.writer(), format_args!("{e:?}"))?;
+                    }
                     Ok(status) => {
                         writeln!(m.writer(), "Status: {status:08b}")?;
                         writeln!(
@@ -242,21 +745,65 @@ pub const NOR: MenuItem<'static, TerminalWriter> = MenuItem::Command {
                     return Err(MenuError::InvalidArgument);
                 }
 
-                for (offset, [upper, lower]) in hex_str.as_bytes().array_chunks::<2>().enumerate() {
-                    let byte = from_hex(*upper, *lower).unwrap();
+                let bytes = hex_str
+                    .as_bytes()
+                    .array_chunks::<2>()
+                    .map(|[upper, lower]| from_hex(*upper, *lower).unwrap())
+                    .collect::<Vec<u8>>();
+                for byte in &bytes {
                     write!(m.writer(), "0x{byte:02x} ")?;
-                    let result = interrupt_free(|cs| {
-                        QSPI_STORE.borrow(cs).borrow_mut().as_deref_mut().unwrap().write(address + offset as u32, &[byte])
-                    });
-
-                    if let Err(e) = result {
-                        writeln!(m.writer(), "Error: {e:?}")?;
-                        break;
-                    }
                 }
                 writeln!(m.writer())?;
+                let result = interrupt_free(|cs| {
+                    QSPI_STORE.borrow(cs).borrow_mut().as_deref_mut().unwrap().write(address, &bytes)
+                });
+                if let Err(e) = result {
+                    writeln!(m.writer(), "Error: {e:?}")?;
+                }
+            },
+            ["dev", "erase", address_str] => {
+                let address = u32::from_str_radix(address_str, 16).map_err(|_| MenuError::InvalidArgument)?;
+                let result = interrupt_free(|cs| {
+                    QSPI_STORE.borrow(cs).borrow_mut().as_deref_mut().unwrap().sector_erase(address)
+                });
+                writeln!(m.writer(), "{result:?}")?;
+            },
+            ["dev", "quad", state @ ("on" | "off")] => {
+                let result = interrupt_free(|cs| {
+                    let mut flash = QSPI_STORE.borrow(cs).borrow_mut();
+                    let flash = flash.as_deref_mut().unwrap();
+                    match *state {
+                        "on" => flash.enter_quad_mode(),
+                        _ => flash.exit_quad_mode(),
+                    }
+                });
+                writeln!(m.writer(), "{result:?}")?;
+            },
+            ["dev", "xip", state @ ("on" | "off")] => {
+                let result = interrupt_free(|cs| {
+                    let mut flash = QSPI_STORE.borrow(cs).borrow_mut();
+                    let flash = flash.as_deref_mut().unwrap();
+                    match *state {
+                        "on" => flash.enter_memory_mapped().map(|mapped| mapped.as_ptr()),
+                        _ => {
+                            flash.exit_memory_mapped();
+                            Ok(core::ptr::null())
+                        }
+                    }
+                });
+                match result {
+                    Ok(ptr) if !ptr.is_null() => writeln!(m.writer(), "Mapped at {ptr:p}")?,
+                    Ok(_) => writeln!(m.writer(), "Unmapped")?,
+                    Err(e) => writeln!(m.writer(), "Error: {e:?}")?,
+                }
             },
             _ => {
+                writeln!(m.writer(), "Expected:")?;
+                writeln!(m.writer(), "\ti | info - NOR filesystem info")?;
+                writeln!(m.writer(), "\tm | mount - Mount NOR filesystem on 'nor:'")?;
+                writeln!(m.writer(), "\tu | unmount - Unmount NOR filesystem")?;
+                writeln!(m.writer(), "\tf | format - Format and mount NOR filesystem")?;
+                writeln!(m.writer(), "\tdev <id|status|config|read|write|erase|quad|xip|ce|reset> - Raw flash access")?;
                 return Err(MenuError::InvalidArgument)
             }
         }
@@ -265,98 +812,400 @@ pub const NOR: MenuItem<'static, TerminalWriter> = MenuItem::Command {
     },
 };
 
+pub const RAM: MenuItem<'static, TerminalWriter> = MenuItem::Command {
+    name: "ram",
+    help: "ram <(i|info)|(m|mount) [size]|(u|unmount)> - Info/Mount/Unmount RAM disk",
+    description: "Info/Mount/Unmount RAM disk in SDRAM",
+    action: |m, args| {
+        match args {
+            ["i" | "info"] => {
+                let mounted = vfs::is_mounted(ram_disk::DEVICE);
+                writeln!(
+                    m.writer(),
+                    "{:LABEL_WIDTH$} {}",
+                    "RAM disk mounted",
+                    mounted
+                )?;
+                if mounted {
+                    match vfs::with_fs(Path::new("ram:"), |fs| fs.stats()) {
+                        Ok(stats) => {
+                            writeln!(m.writer(), "{:LABEL_WIDTH$} {}", "Size", stats.total_bytes)?;
+                            if let Some(free_bytes) = stats.free_bytes {
+                                writeln!(m.writer(), "{:LABEL_WIDTH$} {free_bytes}", "Free")?;
+                            }
+                        }
+                        Err(e) => writeln!(m.writer(), "Error: {e}")?,
+                    }
+                }
+                writeln!(
+                    m.writer(),
+                    "{:LABEL_WIDTH$} {}",
+                    "Heap available",
+                    ram_disk::available()
+                )?;
+            }
+            ["m" | "mount", size @ ..] if size.len() <= 1 => {
+                let size = match size.first() {
+                    Some(size) => parse_size(size).ok_or(MenuError::InvalidArgument)?,
+                    None => ram_disk::DEFAULT_SIZE,
+                };
+                match ram_disk::mount(size) {
+                    Ok(()) => writeln!(m.writer(), "RAM disk mounted")?,
+                    Err(e) => writeln!(m.writer(), "Error: {e}")?,
+                }
+            }
+            ["u" | "unmount"] => match vfs::unmount(ram_disk::DEVICE) {
+                Ok(_) => writeln!(m.writer(), "RAM disk unmounted, its content is gone")?,
+                Err(e) => writeln!(m.writer(), "Error: {e}")?,
+            },
+            _ => {
+                writeln!(m.writer(), "Expected:")?;
+                writeln!(m.writer(), "\ti | info - RAM disk info")?;
+                writeln!(
+                    m.writer(),
+                    "\tm | mount [size] - Mount an empty RAM disk on 'ram:', e.g. 512K or 8M (default 4M)"
+                )?;
+                writeln!(
+                    m.writer(),
+                    "\tu | unmount - Unmount RAM disk and free its memory"
+                )?;
+                return Err(MenuError::InvalidArgument);
+            }
+        }
+
+        Ok(())
+    },
+};
+
 pub const SDCARD: MenuItem<'static, TerminalWriter> = MenuItem::Command {
     name: "sdcard",
-    help: "sdcard <(i|info)|(m|mount)|(u|unmount)> - Info/Mount/Unmount SD Card",
-    description: "Mount/Unmount SD Card",
+    help: "sdcard <(i|info)|(m|mount) [kHz]|(u|unmount)|(f|format) [opts]> - Info/Mount/Unmount/Format SD Card",
+    description: "Mount/Unmount/Format SD Card",
     action: |m, args| match args {
-        ["i" | "info"] => match interrupt_free(|cs| {
-            crate::fs::sdmmc_fs::SD_CARD
-                .borrow(cs)
-                .borrow_mut()
-                .as_mut()
-                .map(|sdfs| (sdfs.is_mounted(), sdfs.card_size()))
-        }) {
-            Some((mounted, size)) => {
+        ["i" | "info"] if m.output() == OutputMode::Json => {
+            json::write_object(m.writer(), sdcard_json)?;
+            Ok(())
+        }
+        ["i" | "info"] => match sdmmc_fs::with_sd_card(|sdfs| {
+            Ok((
+                sdfs.is_mounted(),
+                sdfs.card_size(),
+                sdfs.card_info().cloned(),
+                sdfs.partition_table().cloned(),
+            ))
+        })
+        .ok() {
+            Some((mounted, size, card, partitions)) => {
                 writeln!(m.writer(), "{:LABEL_WIDTH$} {}", "SD Card mounted", mounted)?;
                 match size {
-                    Ok(bytes) => {
-                        writeln!(
-                            m.writer(),
-                            "{:LABEL_WIDTH$} {}GiB",
-                            "Size",
-                            bytes as f64 / (1024 * 1024 * 1024) as f64
-                        )?;
-                        Ok(())
-                    }
-                    Err(_) => {
-                        writeln!(m.writer(), "{:LABEL_WIDTH$} <unavailable>", "Size")?;
-                        Ok(())
-                    }
+                    Ok(bytes) => writeln!(
+                        m.writer(),
+                        "{:LABEL_WIDTH$} {}GiB",
+                        "Size",
+                        bytes as f64 / (1024 * 1024 * 1024) as f64
+                    )?,
+                    Err(_) => writeln!(m.writer(), "{:LABEL_WIDTH$} <unavailable>", "Size")?,
                 }
+                if let Some(card) = card {
+                    write_card_info(m.writer(), &card)?;
+                }
+                if mounted {
+                    write_partitions(m.writer(), partitions.as_ref())?;
+                }
+                Ok(())
             }
             None => {
                 writeln!(m.writer(), "SD Card controller not initialized")?;
                 Ok(())
             }
         },
-        ["m" | "mount"] => m.run("sdcard", &["mount", "400"]),
-        ["m" | "mount", freq_str] => interrupt_free(|cs| {
+        ["m" | "mount"] => {
+            let freq = settings::get().sd_mount_khz.to_string();
+            m.run("sdcard", &["mount", &freq])
+        }
+        ["m" | "mount", freq_str] => {
             let freq = freq_str
                 .parse::<u32>()
                 .map_err(|_| MenuError::InvalidArgument)?;
+            hotplug::resume();
             writeln!(m.writer(), "Attempting to mount SD Card @ {freq}kHz")?;
-            match crate::fs::sdmmc_fs::SD_CARD
-                .borrow(cs)
-                .borrow_mut()
-                .as_mut()
-                .map(|sdfs| sdfs.mount::<hal::delay::Delay, _>(freq.kHz(), 10, None))
-            {
-                Some(Ok(_)) => {
-                    writeln!(m.writer(), "SD Card mounted")?;
-                    Ok(())
-                }
-                Some(Err(e)) => {
-                    writeln!(m.writer(), "{e}")?;
+            let res = sdmmc_fs::with_sd_card(|sdfs| {
+                sdfs.mount::<hal::delay::Delay, _>(freq.kHz(), 10, None)
+            });
+            match res {
+                Ok(()) => {
+                    match sdmmc_fs::mount_volumes() {
+                        Ok(()) => writeln!(m.writer(), "SD Card mounted")?,
+                        Err(e) => writeln!(m.writer(), "Error: {e}")?,
+                    }
                     Ok(())
                 }
-                None => {
+                Err(SdmmcFsError::NotInitialized) => {
                     writeln!(m.writer(), "SD Card controller not initialized")?;
                     Err(MenuError::CommandError(None))
                 }
-            }
-        }),
-        ["u" | "unmount"] => interrupt_free(|cs| {
-            match crate::fs::sdmmc_fs::SD_CARD
-                .borrow(cs)
-                .borrow_mut()
-                .as_mut()
-                .map(|sdfs| sdfs.unmount())
-            {
-                Some(Ok(_)) => {
-                    writeln!(m.writer(), "SD Card unmounted")?;
-                    Ok(())
-                }
-                Some(Err(e)) => {
+                Err(e) => {
                     writeln!(m.writer(), "{e}")?;
                     Ok(())
                 }
-                None => {
-                    writeln!(m.writer(), "SD Card controller not initialized")?;
-                    Err(MenuError::CommandError(None))
-                }
             }
-        }),
-        _ => {
-            writeln!(m.writer(), "Expected:")?;
-            writeln!(m.writer(), "\tm | mount - Mount SD Card")?;
-            writeln!(m.writer(), "\tu | unmount - Unmount SD Card")?;
-            writeln!(m.writer(), "\ti | info - SD Card Info")?;
-            Err(MenuError::InvalidArgument)
         }
+        ["u" | "unmount"] => match sdmmc_fs::with_sd_card(|sdfs| sdfs.unmount()) {
+            Ok(()) => {
+                // Stay unmounted until the next `sdcard mount`
+                hotplug::suspend();
+                sdmmc_fs::unmount_volumes();
+                writeln!(m.writer(), "SD Card unmounted")?;
+                Ok(())
+            }
+            Err(SdmmcFsError::NotInitialized) => {
+                writeln!(m.writer(), "SD Card controller not initialized")?;
+                Err(MenuError::CommandError(None))
+            }
+            Err(e) => {
+                writeln!(m.writer(), "{e}")?;
+                Ok(())
+            }
+        },
+        ["f" | "format", opts @ ..] => match format_options(opts) {
+            Some(mut options) => {
+                options.volume_id = match TimeSource::get_date_time() {
+                    Some(dt) => dt.timestamp() as u32,
+                    None => cortex_m::peripheral::DWT::cycle_count(),
+                };
+                format_sd_card(m, &options)
+            }
+            None => sdcard_usage(m),
+        },
+        _ => sdcard_usage(m),
     },
 };
 
+fn sdcard_usage(m: &mut Menu<'_, TerminalWriter>) -> MenuResult {
+    writeln!(m.writer(), "Expected:")?;
+    writeln!(
+        m.writer(),
+        "\tm | mount [kHz] - Mount SD Card, at `sys config sdcard.freq` by default"
+    )?;
+    writeln!(m.writer(), "\tu | unmount - Unmount SD Card")?;
+    writeln!(m.writer(), "\ti | info - SD Card Info")?;
+    writeln!(
+        m.writer(),
+        "\tf | format [--label NAME] [--fat16|--fat32] - Erase the card and create a FAT filesystem"
+    )?;
+    Err(MenuError::InvalidArgument)
+}
+
+/// Options of `sdcard format`, `None` if one isn't known
+fn format_options<'a>(args: &[&'a str]) -> Option<FormatOptions<'a>> {
+    let mut options = FormatOptions::default();
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        match *arg {
+            "--label" => options.label = Some(args.next()?),
+            "--fat16" => options.fat = Some(FatType::Fat16),
+            "--fat32" => options.fat = Some(FatType::Fat32),
+            _ => return None,
+        }
+    }
+    Some(options)
+}
+
+fn format_sd_card(m: &mut Menu<'_, TerminalWriter>, options: &FormatOptions) -> MenuResult {
+    let layout = match sdmmc_fs::with_sd_card(|sdfs| sdfs.card_size()) {
+        Ok(bytes) => Layout::new(bytes / Block::LEN as u64, options.fat),
+        Err(e) => {
+            writeln!(m.writer(), "Error: {e}")?;
+            return Ok(());
+        }
+    };
+    let label = options.label.map(fat::volume_label).transpose();
+    let layout = match (layout, label) {
+        (Ok(layout), Ok(_)) => layout,
+        (Err(e), _) | (_, Err(e)) => {
+            writeln!(m.writer(), "Error: {e}")?;
+            return Ok(());
+        }
+    };
+    let question = format!(
+        "Format the SD card as {} with {} byte clusters, {} bytes usable? Everything on it is lost.",
+        layout.fat,
+        layout.cluster_len(),
+        layout.data_bytes()
+    );
+    if !confirm(m, &question)? {
+        return Ok(());
+    }
+    sdmmc_fs::unmount_volumes();
+    writeln!(m.writer(), "Formatting...")?;
+    match sdmmc_fs::with_sd_card(|sdfs| sdfs.format(options)) {
+        Ok(_) => {
+            let _ = sdmmc_fs::mount_volumes();
+            writeln!(m.writer(), "SD Card formatted")?;
+        }
+        Err(e) => writeln!(m.writer(), "Error: {e}")?,
+    }
+    Ok(())
+}
+
+fn write_partitions(w: &mut impl Write, table: Option<&PartitionTable>) -> core::fmt::Result {
+    let table = match table {
+        Some(table) => table,
+        None => return writeln!(w, "{:LABEL_WIDTH$} none", "Partition table"),
+    };
+    writeln!(w, "{:LABEL_WIDTH$} {}", "Partition table", table.scheme)?;
+    writeln!(
+        w,
+        "{:>3} {:16} {:12} {:>10} {:>14}  Name",
+        "#", "Device", "Type", "Start", "Size"
+    )?;
+    let first_fat = table.first_fat().map(|p| p.number);
+    for p in &table.partitions {
+        let device = match (p.kind.is_fat(), first_fat == Some(p.number)) {
+            (true, true) => format!("{0}{1}:,{0}:", sdmmc_fs::DEVICE, p.number),
+            (true, false) => format!("{}{}:", sdmmc_fs::DEVICE, p.number),
+            (false, _) => String::from("-"),
+        };
+        writeln!(
+            w,
+            "{:>3} {device:16} {:12} {:>10} {:>14}  {}{}",
+            p.number,
+            p.kind,
+            p.first_lba,
+            p.bytes(),
+            p.name,
+            if p.bootable { " (boot)" } else { "" }
+        )?;
+    }
+    Ok(())
+}
+
+fn write_card_info(w: &mut impl Write, card: &CardInfo) -> core::fmt::Result {
+    let (year, month) = card.manufactured;
+    writeln!(
+        w,
+        "{:LABEL_WIDTH$} {} ({:02x}), OEM {}",
+        "Manufacturer",
+        card.manufacturer(),
+        card.manufacturer_id,
+        card.oem_id
+    )?;
+    writeln!(
+        w,
+        "{:LABEL_WIDTH$} {} rev {}.{}",
+        "Product",
+        card.product_name,
+        card.product_revision >> 4,
+        card.product_revision & 0xf
+    )?;
+    writeln!(w, "{:LABEL_WIDTH$} {:08x}", "Serial", card.serial)?;
+    writeln!(w, "{:LABEL_WIDTH$} {year}-{month:02}", "Manufactured")?;
+    writeln!(
+        w,
+        "{:LABEL_WIDTH$} {} ({} bytes)",
+        "Capacity class", card.capacity_class, card.capacity
+    )?;
+    writeln!(w, "{:LABEL_WIDTH$} {}", "SD spec", card.spec_version)?;
+    writeln!(
+        w,
+        "{:LABEL_WIDTH$} {}Mbit/s",
+        "Max transfer rate",
+        card.max_transfer_rate / 1000
+    )?;
+    match card.speed_class {
+        Some(class) => writeln!(w, "{:LABEL_WIDTH$} C{class}", "Speed class")?,
+        None => writeln!(w, "{:LABEL_WIDTH$} <reserved>", "Speed class")?,
+    }
+    writeln!(w, "{:LABEL_WIDTH$} U{}", "UHS grade", card.uhs_grade)?;
+    writeln!(
+        w,
+        "{:LABEL_WIDTH$} V{}",
+        "Video speed class", card.video_speed_class
+    )?;
+    match card.au_size {
+        Some(au_size) => writeln!(w, "{:LABEL_WIDTH$} {}KiB", "AU size", au_size / 1024)?,
+        None => writeln!(w, "{:LABEL_WIDTH$} <undefined>", "AU size")?,
+    }
+    writeln!(
+        w,
+        "{:LABEL_WIDTH$} {} bit @ {}kHz",
+        "Bus",
+        card.bus_width,
+        card.clock.raw() / 1000
+    )
+}
+
+fn card_json<W: Write>(o: &mut JsonObject<W>, card: &CardInfo) -> core::fmt::Result {
+    let (year, month) = card.manufactured;
+    o.num("manufacturer_id", card.manufacturer_id)?
+        .str("manufacturer", card.manufacturer())?
+        .str("oem_id", &card.oem_id)?
+        .str("product_name", &card.product_name)?
+        .num("product_revision", card.product_revision)?
+        .num("serial", card.serial)?
+        .display("manufactured", format_args!("{year}-{month:02}"))?
+        .display("capacity_class", card.capacity_class)?
+        .num("capacity_bytes", card.capacity)?
+        .str("spec_version", card.spec_version)?
+        .num("max_transfer_kbit_s", card.max_transfer_rate)?
+        .opt_num("speed_class", card.speed_class)?
+        .num("uhs_grade", card.uhs_grade)?
+        .num("video_speed_class", card.video_speed_class)?
+        .opt_num("au_size_bytes", card.au_size)?
+        .num("bus_width", card.bus_width)?
+        .num("clock_hz", card.clock.raw())?;
+    Ok(())
+}
+
+pub(super) fn sdcard_json<W: Write>(o: &mut JsonObject<W>) -> core::fmt::Result {
+    match sdmmc_fs::with_sd_card(|sdfs| {
+        Ok((
+            sdfs.is_mounted(),
+            sdfs.card_size(),
+            sdfs.card_info().cloned(),
+            sdfs.partition_table().cloned(),
+        ))
+    })
+    .ok()
+    {
+        Some((mounted, size, card, partitions)) => {
+            o.bool("initialized", true)?
+                .bool("mounted", mounted)?
+                .opt_num("size_bytes", size.ok())?;
+            match card {
+                Some(card) => o.object("card", |o| card_json(o, &card))?,
+                None => o.null("card")?,
+            };
+            match partitions {
+                Some(table) => o.display("partition_table", table.scheme)?.objects(
+                    "partitions",
+                    &table.partitions,
+                    |o, p| {
+                        o.num("number", p.number)?
+                            .display("type", p.kind)?
+                            .bool("fat", p.kind.is_fat())?
+                            .num("first_lba", p.first_lba)?
+                            .num("size_bytes", p.bytes())?
+                            .bool("bootable", p.bootable)?
+                            .str("name", &p.name)?;
+                        Ok(())
+                    },
+                )?,
+                None => o.null("partition_table")?.null("partitions")?,
+            };
+        }
+        None => {
+            o.bool("initialized", false)?
+                .bool("mounted", false)?
+                .null("size_bytes")?
+                .null("card")?
+                .null("partition_table")?
+                .null("partitions")?;
+        }
+    }
+    Ok(())
+}
+
 pub const CURL: MenuItem<'static, TerminalWriter> = MenuItem::Command {
     name: "curl",
     help: "curl - (TODO) Fetch content from a remote machine over HTTP/HTTPS",
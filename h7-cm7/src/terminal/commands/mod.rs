@@ -2,8 +2,62 @@ pub mod io;
 pub mod program;
 pub mod sys;
 pub mod time;
+pub mod xfer;
 
-pub(super) mod utils;
+pub(super) use h7_shell::{
+    commands::{HEADER_WIDTH, LABEL_WIDTH},
+    utils,
+};
 
-const HEADER_WIDTH: usize = 48;
-const LABEL_WIDTH: usize = 20;
+use {
+    super::{
+        menu::{Menu, MenuError},
+        TerminalWriter,
+    },
+    crate::{fs::path::Path, system::Timeout},
+    alloc::string::String,
+    core::fmt::Write,
+};
+
+/// Wildcard matches above which destructive commands ask before going ahead
+pub(super) const CONFIRM_MATCHES: usize = 5;
+/// Questions without an answer in this time are answered with no
+const CONFIRM_TIMEOUT_MS: u64 = 30_000;
+
+/// `arg` as an absolute path, relative paths start at the session's working directory
+pub(super) fn resolve(m: &Menu<'_, TerminalWriter>, arg: &str) -> String {
+    Path::new(arg).resolve(m.cwd())
+}
+
+/// Ask a yes/no question on the session's console, anything but `y` is a no, as is no answer
+/// within [`CONFIRM_TIMEOUT_MS`]. Input typed before the question doesn't count as an answer.
+pub(super) fn confirm(m: &mut Menu<'_, TerminalWriter>, question: &str) -> Result<bool, MenuError> {
+    let port = m.writer().port();
+    while port.read().is_some() {}
+    write!(m.writer(), "{question} [y/N] ")?;
+
+    let mut timeout = Timeout::new(CONFIRM_TIMEOUT_MS);
+    let mut answer = None;
+    // The command line ended with the CR, its LF may still arrive
+    let mut after_cr = true;
+    loop {
+        match port.read() {
+            Some(b'\n') if after_cr => after_cr = false,
+            Some(b'\r' | b'\n') => break,
+            Some(c) => {
+                after_cr = false;
+                if answer.is_none() {
+                    answer = Some(c);
+                    write!(m.writer(), "{}", c as char)?;
+                }
+            }
+            None if timeout.expired() => {
+                answer = None;
+                break;
+            }
+            None => {}
+        }
+    }
+    writeln!(m.writer())?;
+    Ok(matches!(answer, Some(b'y' | b'Y')))
+}
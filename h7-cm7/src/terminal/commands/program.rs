@@ -1,14 +1,12 @@
 use {
-    super::utils::*,
+    super::{resolve, utils::*},
     crate::{
         app,
-        fs::path::Path,
-        led::Led,
+        fs::{path::Path, vfs},
         terminal::{
             menu::{MenuError, MenuItem},
-            TerminalWriter, TERMINAL_INPUT_FIFO,
+            TerminalWriter,
         },
-        utils::interrupt_free,
     },
     core::fmt::Write,
 };
@@ -19,45 +17,20 @@ pub const PLOAD: MenuItem<'static, TerminalWriter> = MenuItem::Command {
     description: "Load a program into ram",
     action: |m, args| {
         check_args_len(1, args.len())?;
+        // Commands registered by the current app are about to be overwritten
+        m.unregister_dynamic_commands();
         let app_slice = app::app_slice();
         app_slice.fill(0); // .bss
-        let path = Path::new(args[0]);
-        match path.device() {
-            Some("sdcard") => interrupt_free(|cs| {
-                match crate::fs::sdmmc_fs::SD_CARD
-                    .borrow(cs)
-                    .borrow_mut()
-                    .as_mut()
-                    .map(|sdfs| sdfs.read_file(path, app_slice))
-                {
-                    Some(Ok(len)) => {
-                        writeln!(m.writer(), "Program '{}' loaded ({} bytes)", args[0], len)?;
-                        app::print_info(m.writer(), &app_slice[..len])?;
-                        Ok(())
-                    }
-                    Some(Err(e)) => {
-                        writeln!(m.writer(), "Error: {e}")?;
-                        Ok(())
-                    }
-                    None => {
-                        writeln!(m.writer(), "Error: SD Card controller not initialized")?;
-                        Ok(())
-                    }
-                }
-            }),
-            Some("nor") => {
-                writeln!(m.writer(), "Not implemented")?;
-                Ok(())
-            }
-            Some(device) => {
-                writeln!(m.writer(), "Unknown device '{device}'")?;
-                Ok(())
-            }
-            None => {
-                writeln!(m.writer(), "No device selected")?;
-                Ok(())
+        let path = resolve(m, args[0]);
+        let path = Path::new(&path);
+        match vfs::with_fs(path, |fs| fs.read_at(path, 0, app_slice)) {
+            Ok(len) => {
+                writeln!(m.writer(), "Program '{}' loaded ({} bytes)", args[0], len)?;
+                app::print_info(m.writer(), &app_slice[..len])?;
             }
+            Err(e) => writeln!(m.writer(), "Error: {e}")?,
         }
+        Ok(())
     },
 };
 
@@ -73,34 +46,10 @@ pub const PRUN: MenuItem<'static, TerminalWriter> = MenuItem::Command {
             return Err(MenuError::CommandError(Some("Invalid app address")));
         }
         writeln!(m.writer(), "Executing from {app_fn:p}")?;
-        let ret = unsafe {
-            Led::Green.on();
-            Led::Red.on();
-            // Disable cache
-            let mut cp = cortex_m::Peripherals::steal();
-            cp.SCB.disable_icache();
-            cp.SCB.invalidate_icache();
-            cp.SCB.disable_dcache(&mut cp.CPUID);
-            cp.SCB.clean_dcache(&mut cp.CPUID);
-
-            // Sync
-            cortex_m::asm::dmb();
-            cortex_m::asm::dsb();
-            cortex_m::asm::isb();
-
-            // Run
-            let ret = app_fn(&app::API);
-            // TODO: Clear input queue after app exit
-
-            // Enable cache
-            cp.SCB.enable_icache();
-            cp.SCB.enable_dcache(&mut cp.CPUID);
-
-            Led::Green.off();
-            Led::Red.off();
-
-            ret
-        };
+        // Commands registered by a previous run point into this app as well
+        m.unregister_dynamic_commands();
+        // TODO: Clear input queue after app exit
+        let ret = unsafe { app::run_in_app_context(m.writer().port(), || app_fn(&app::API)) };
         writeln!(
             m.writer(),
             "Exit: {} ({})",
@@ -111,6 +60,7 @@ pub const PRUN: MenuItem<'static, TerminalWriter> = MenuItem::Command {
             0 => { /* App did not leak memory */ }
             n => writeln!(m.writer(), "App leaked {n} bytes")?,
         }
+        app::apply_registrations(m)?;
 
         Ok(())
     },
@@ -118,10 +68,12 @@ pub const PRUN: MenuItem<'static, TerminalWriter> = MenuItem::Command {
 
 pub const UPLOAD: MenuItem<'static, TerminalWriter> = MenuItem::Command {
     name: "upload",
-    help: "upload <hex> - Load program into RAM via serial. Data is sent in ascii hex.",
-    description: "Load program into RAM via serial. Data is sent in ascii hex.",
+    help: "upload [hex] - Load program into RAM via serial. Data is sent in ascii hex, without an argument it is pasted and ended with Enter.",
+    description: "Load program into RAM via serial. Data is sent in ascii hex, without an argument it is pasted and ended with Enter.",
     action: |m, args| {
         let mut n = 0usize;
+        // Commands registered by the current app are about to be overwritten
+        m.unregister_dynamic_commands();
         let app_slice = app::app_slice();
         app_slice.fill(0);
         match args {
@@ -157,13 +109,19 @@ pub const UPLOAD: MenuItem<'static, TerminalWriter> = MenuItem::Command {
             _ => {
                 writeln!(m.writer(), "Waiting for data...")?;
                 let mut byte = None::<u8>;
+                // The command line ended with the CR, its LF may still arrive
+                let mut after_cr = true;
                 loop {
-                    match (
-                        byte,
-                        //  interrupt_free(|cs| TERMINAL_INPUT_FIFO.borrow(cs).borrow_mut().pop()),
-                        TERMINAL_INPUT_FIFO.dequeue(),
-                    ) {
-                        (b, Some(b'\n')) => {
+                    //  interrupt_free(|cs| TERMINAL_INPUT_FIFO.borrow(cs).borrow_mut().pop()),
+                    let Some(c) = m.writer().port().read() else {
+                        continue;
+                    };
+                    // Enter is CR in raw mode terminals, LF or CR LF elsewhere
+                    if core::mem::replace(&mut after_cr, c == b'\r') && c == b'\n' {
+                        continue;
+                    }
+                    match (byte, c) {
+                        (b, b'\r' | b'\n') => {
                             if let Some(b) = b {
                                 writeln!(
                                     m.writer(),
@@ -175,7 +133,7 @@ pub const UPLOAD: MenuItem<'static, TerminalWriter> = MenuItem::Command {
                                 break;
                             }
                         }
-                        (Some(x), Some(y)) => match from_hex(x, y) {
+                        (Some(x), y) => match from_hex(x, y) {
                             Some(b) => {
                                 app_slice[n] = b;
                                 n += 1;
@@ -191,8 +149,7 @@ pub const UPLOAD: MenuItem<'static, TerminalWriter> = MenuItem::Command {
                                 return Err(MenuError::InvalidArgument);
                             }
                         },
-                        (None, n) => byte = n,
-                        _ => {}
+                        (None, c) => byte = Some(c),
                     }
                 }
             }
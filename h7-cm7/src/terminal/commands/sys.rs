@@ -4,113 +4,49 @@ use {
     super::{utils::*, HEADER_WIDTH, LABEL_WIDTH},
     crate::{
         consts,
+        fs::vfs::VfsError,
         led::Led,
+        settings::{self, Settings},
+        system::Timeout,
         // logger,
         terminal::{
-            menu::{MenuError, MenuItem},
-            TerminalWriter, MENU,
+            menu::{Menu, MenuError, MenuItem, MenuResult},
+            session, uart, TerminalWriter,
         },
         utils::interrupt_free,
     },
-    chrono::{Datelike, NaiveDate, Timelike},
+    alloc::string::String,
+    chrono::{NaiveDate, NaiveDateTime},
     core::{fmt::Write, str::FromStr},
+    h7_shell::{
+        commands::{
+            sys,
+            time::{write_date_time, Iso8601},
+        },
+        json::{self, JsonObject, OutputMode},
+    },
     stm32h7xx_hal as hal,
 };
 
-pub const HELP: MenuItem<'static, TerminalWriter> = MenuItem::Command {
-    name: "help",
-    help: "help <program> - Show help about a program",
-    description: "Show help about a program",
-    action: |m, args| {
-        check_args_len(1, args.len())?;
-        let program = args[0];
-        let mut command_found = false;
-        iter_menu(m, args, MENU, &mut |menu, _, item, _, _| {
-            match item {
-                MenuItem::Command { name, help, .. } => {
-                    if *name == program {
-                        writeln!(menu.writer(), "{help}")?;
-                        command_found = true;
-                        return Ok(false);
-                    }
-                }
-                MenuItem::Alias { alias, command } => {
-                    if *alias == program {
-                        writeln!(menu.writer(), "'{alias}' aliased to '{command}'")?;
-                        menu.run("help", &[*command])?;
-                        command_found = true;
-                        return Ok(false);
-                    }
-                }
-                MenuItem::Group { .. } => {
-                    // nop
-                }
-            };
-            Ok(true)
-        })?;
+/// Time to confirm a new baud rate before `sys uart baud` switches back
+const BAUD_CONFIRM_MS: u64 = 10_000;
 
-        if command_found {
-            Ok(())
-        } else {
-            Err(MenuError::CommandNotFound)
-        }
-    },
-};
+pub const HELP: MenuItem<'static, TerminalWriter> = sys::help();
 
-pub const MAN: MenuItem<'static, TerminalWriter> = MenuItem::Alias {
-    alias: "man",
-    command: "help",
-};
+pub const MAN: MenuItem<'static, TerminalWriter> = sys::man();
 
-pub const PROGRAMS: MenuItem<'static, TerminalWriter> = MenuItem::Command {
-    name: "programs",
-    help: "programs - Show available builtin programs",
-    description: "Show available builtin programs",
-    action: |m, args| {
-        check_args_len(0, args.len())?;
+pub const PROGRAMS: MenuItem<'static, TerminalWriter> = sys::programs();
 
-        iter_menu(m, args, MENU, &mut |menu, _, item, _, level| {
-            match item {
-                MenuItem::Command {
-                    name, description, ..
-                } => {
-                    writeln!(
-                        menu.writer(),
-                        "{padding}{name:LABEL_WIDTH$} {description}",
-                        padding = PaddedStr::<b' '>("", level),
-                    )?;
-                }
-                MenuItem::Alias { alias, command } => {
-                    writeln!(
-                        menu.writer(),
-                        "{padding}{alias:LABEL_WIDTH$} aliased to {command}",
-                        padding = PaddedStr::<b' '>("", level),
-                    )?;
-                }
-                MenuItem::Group { title, .. } => {
-                    writeln!(
-                        menu.writer(),
-                        "{p}{t:-^-w$}",
-                        p = PaddedStr::<b' '>("", level),
-                        t = PaddedStr::<b' '>(title, 1),
-                        w = HEADER_WIDTH - level - level
-                    )?;
-                }
-            }
-            Ok(true)
-        })
-    },
-};
+pub const COMMANDS: MenuItem<'static, TerminalWriter> = sys::commands();
 
-pub const COMMANDS: MenuItem<'static, TerminalWriter> = MenuItem::Alias {
-    alias: "commands",
-    command: "programs",
-};
+pub const ALIAS: MenuItem<'static, TerminalWriter> = sys::alias();
+
+pub const UNALIAS: MenuItem<'static, TerminalWriter> = sys::unalias();
 
 pub const SYS: MenuItem<'static, TerminalWriter> = MenuItem::Command {
     name: "sys",
-    help: "sys <function> - Test system functionality",
-    description: "Test system functionality",
+    help: "sys <(config [key [value]])|(uart baud [-t] [rate])|function> - Show/Change settings, test system functionality",
+    description: "Change settings and test system functionality",
     action: |m, args| match args {
         ["panic"] => {
             writeln!(m.writer(), "Panicing!")?;
@@ -129,6 +65,47 @@ pub const SYS: MenuItem<'static, TerminalWriter> = MenuItem::Command {
             writeln!(m.writer(), "Resetting!")?;
             cortex_m::peripheral::SCB::sys_reset()
         }
+        ["config"] => {
+            let settings = settings::get();
+            for key in Settings::KEYS {
+                write!(m.writer(), "{key:LABEL_WIDTH$} ")?;
+                let _ = settings.write_value(key, m.writer());
+                writeln!(m.writer())?;
+            }
+            Ok(())
+        }
+        ["config", key] => {
+            let mut value = String::new();
+            match settings::get().write_value(key, &mut value) {
+                Ok(()) => writeln!(m.writer(), "{value}")?,
+                Err(e) => writeln!(m.writer(), "Error: {e}")?,
+            }
+            Ok(())
+        }
+        // Confirmed at the new rate first, a wrong one would lock the host out after a reset
+        ["config", "uart.baud", _] => {
+            writeln!(
+                m.writer(),
+                "Error: uart.baud is changed with `sys uart baud <rate>`"
+            )?;
+            Ok(())
+        }
+        ["config", key, value] => {
+            if let Err(e) = settings::update(|s| s.set(key, value)) {
+                writeln!(m.writer(), "Error: {e}")?;
+                return Ok(());
+            }
+            if let "uart.rx_buffer" | "uart.flow" = *key {
+                uart::configure(&settings::get());
+            }
+            writeln!(m.writer(), "{key} = {value}")?;
+            save_settings(m)
+        }
+        ["uart", "baud"] => {
+            writeln!(m.writer(), "{}", uart::baud())?;
+            Ok(())
+        }
+        ["uart", "baud", args @ ..] => switch_baud(m, args),
         ["loglevel"] => {
             // writeln!(m.writer(), "Current log level: {}", logger::get_log_level())?;
             Ok(())
@@ -150,11 +127,83 @@ pub const SYS: MenuItem<'static, TerminalWriter> = MenuItem::Command {
     },
 };
 
+/// Write the settings to the NOR flash, they still apply until the next boot if that fails
+fn save_settings(m: &mut Menu<'static, TerminalWriter>) -> MenuResult {
+    match settings::save() {
+        Ok(()) => {}
+        Err(VfsError::NotMounted(_)) => writeln!(
+            m.writer(),
+            "Not saved, the NOR flash holding {} isn't mounted",
+            settings::PATH
+        )?,
+        Err(e) => writeln!(m.writer(), "Error: Settings not saved: {e}")?,
+    }
+    Ok(())
+}
+
+/// Switch the UART console's baud rate, going back unless Enter arrives at the new rate in time
+fn switch_baud(m: &mut Menu<'static, TerminalWriter>, args: &[&str]) -> MenuResult {
+    let (temporary, args) = strip_flag(args, "-t");
+    let rate = match args {
+        [rate] => rate.parse::<u32>().map_err(|_| MenuError::InvalidArgument),
+        _ => Err(MenuError::InvalidArgument),
+    };
+    let Ok(rate) = rate else {
+        writeln!(m.writer(), "Expected:")?;
+        writeln!(m.writer(), "\tsys uart baud <rate> - Switch and save")?;
+        writeln!(
+            m.writer(),
+            "\tsys uart baud -t <rate> - Switch until the next boot"
+        )?;
+        return Err(MenuError::InvalidArgument);
+    };
+    if let Err(e) = uart::check_baud(rate) {
+        writeln!(m.writer(), "Error: {e}")?;
+        return Ok(());
+    }
+
+    let old = uart::baud();
+    writeln!(
+        m.writer(),
+        "Switching to {rate} baud, press Enter within {} s to keep it",
+        BAUD_CONFIRM_MS / 1000
+    )?;
+    if let Err(e) = uart::set_baud(rate) {
+        writeln!(m.writer(), "Error: {e}")?;
+        return Ok(());
+    }
+    // Only an Enter sent at the new rate counts, not the rest of the command line or typeahead
+    let port = m.writer().port();
+    while port.read().is_some() {}
+    let mut timeout = Timeout::new(BAUD_CONFIRM_MS);
+    // Anything else is noise from before the other end switched too
+    let confirmed = loop {
+        match port.read() {
+            Some(b'\r' | b'\n') => break true,
+            _ if timeout.expired() => break false,
+            _ => {}
+        }
+    };
+    if !confirmed {
+        let _ = uart::set_baud(old);
+        writeln!(m.writer(), "No confirmation, back to {old} baud")?;
+        return Ok(());
+    }
+
+    writeln!(m.writer(), "Keeping {rate} baud")?;
+    if !temporary {
+        settings::update(|s| s.uart_baud = rate);
+        save_settings(m)?;
+    }
+    Ok(())
+}
+
 pub const INFO: MenuItem<'static, TerminalWriter> = MenuItem::Command {
     name: "info",
     help: "info [target] - Query information from the system",
     description: "Query information from the system",
     action: |m, args| match args {
+        _ if m.output() == OutputMode::Json => info_json(m, args),
         ["mcu"] => {
             writeln!(m.writer(), "{:LABEL_WIDTH$} STM32H747", "MCU")?;
             // Uid is 12 byts, hex string will be 24.
@@ -234,7 +283,54 @@ pub const INFO: MenuItem<'static, TerminalWriter> = MenuItem::Command {
                 m.writer(),
                 "{:LABEL_WIDTH$} {}MiB",
                 "External FLASH",
-                crate::fs::qspi_store::QSPI_FLASH_SIZE / (1024 * 1024)
+                crate::fs::qspi_store::flash_size() / (1024 * 1024)
+            )?;
+            Ok(())
+        }
+        ["uart"] => {
+            writeln!(m.writer(), "{:LABEL_WIDTH$} {} baud", "Speed", uart::baud())?;
+            let Some(status) = uart::status() else {
+                writeln!(m.writer(), "{:LABEL_WIDTH$} unavailable", "Input buffer")?;
+                return Ok(());
+            };
+            writeln!(
+                m.writer(),
+                "{:LABEL_WIDTH$} {}",
+                "Flow control",
+                status.flow
+            )?;
+            writeln!(
+                m.writer(),
+                "{:LABEL_WIDTH$} {} / {} bytes (peak {})",
+                "Input buffer",
+                status.buffered,
+                status.capacity,
+                status.stats.peak
+            )?;
+            writeln!(
+                m.writer(),
+                "{:LABEL_WIDTH$} {} bytes",
+                "Received",
+                status.stats.received
+            )?;
+            writeln!(
+                m.writer(),
+                "{:LABEL_WIDTH$} {} bytes in {} overflows",
+                "Buffer full",
+                status.stats.dropped,
+                status.stats.overflows
+            )?;
+            writeln!(
+                m.writer(),
+                "{:LABEL_WIDTH$} {}",
+                "UART overruns",
+                status.overruns
+            )?;
+            writeln!(
+                m.writer(),
+                "{:LABEL_WIDTH$} {} bytes",
+                "Sent past XOFF",
+                status.forced
             )?;
             Ok(())
         }
@@ -272,46 +368,15 @@ pub const INFO: MenuItem<'static, TerminalWriter> = MenuItem::Command {
                 "Debug",
                 cfg!(debug_assertions)
             )?;
-            let dt = NaiveDate::from_ymd_opt(
-                consts::COMPILE_TIME_YEAR,
-                consts::COMPILE_TIME_MONTH,
-                consts::COMPILE_TIME_DAY,
-            )
-            .and_then(|dt| {
-                dt.and_hms_opt(
-                    consts::COMPILE_TIME_HOUR,
-                    consts::COMPILE_TIME_MINUTE,
-                    consts::COMPILE_TIME_SECOND,
-                )
-            })
-            .unwrap();
-            writeln!(
-                m.writer(),
-                "{:LABEL_WIDTH$} {weekday} {month} {day} {hh:02}:{mm:02}:{ss:02} {year}",
-                "Compiled",
-                weekday = dt.weekday(),
-                month = month_to_str(dt.month()),
-                day = dt.day(),
-                hh = dt.hour(),
-                mm = dt.minute(),
-                ss = dt.second(),
-                year = dt.year()
-            )?;
+            write!(m.writer(), "{:LABEL_WIDTH$} ", "Compiled")?;
+            write_date_time(m.writer(), &compile_time())?;
+            writeln!(m.writer())?;
 
             match interrupt_free(|cs| *crate::time::BOOT_TIME.borrow(cs).borrow()) {
                 Some(dt) => {
-                    writeln!(
-                        m.writer(),
-                        "{:LABEL_WIDTH$} {weekday} {month} {day} {hh:02}:{mm:02}:{ss:02} {year}",
-                        "Boot time",
-                        weekday = dt.weekday(),
-                        month = month_to_str(dt.month()),
-                        day = dt.day(),
-                        hh = dt.hour(),
-                        mm = dt.minute(),
-                        ss = dt.second(),
-                        year = dt.year()
-                    )?;
+                    write!(m.writer(), "{:LABEL_WIDTH$} ", "Boot time")?;
+                    write_date_time(m.writer(), &dt)?;
+                    writeln!(m.writer())?;
                 }
                 None => {
                     writeln!(m.writer(), "{:LABEL_WIDTH$} unavailable", "Boot time")?;
@@ -331,6 +396,8 @@ pub const INFO: MenuItem<'static, TerminalWriter> = MenuItem::Command {
             m.run("info", &["flash"])?;
             writeln!(m.writer(), "{:-^-HEADER_WIDTH$}", " SD Card ")?;
             m.run("sdcard", &["info"])?;
+            writeln!(m.writer(), "{:-^-HEADER_WIDTH$}", " UART ")?;
+            m.run("info", &["uart"])?;
             writeln!(m.writer(), "{:-^-HEADER_WIDTH$}", " OS ")?;
             m.run("info", &["os"])?;
             writeln!(m.writer(), "{:-^-HEADER_WIDTH$}", " Date/Time ")?;
@@ -344,6 +411,122 @@ pub const INFO: MenuItem<'static, TerminalWriter> = MenuItem::Command {
     },
 };
 
+fn compile_time() -> NaiveDateTime {
+    NaiveDate::from_ymd_opt(
+        consts::COMPILE_TIME_YEAR,
+        consts::COMPILE_TIME_MONTH,
+        consts::COMPILE_TIME_DAY,
+    )
+    .and_then(|dt| {
+        dt.and_hms_opt(
+            consts::COMPILE_TIME_HOUR,
+            consts::COMPILE_TIME_MINUTE,
+            consts::COMPILE_TIME_SECOND,
+        )
+    })
+    .unwrap()
+}
+
+fn info_json(m: &mut Menu<'static, TerminalWriter>, args: &[&str]) -> MenuResult {
+    match args {
+        ["mcu"] => json::write_object(m.writer(), mcu_json)?,
+        ["cpu"] => json::write_object(m.writer(), cpu_json)?,
+        ["ram"] => json::write_object(m.writer(), ram_json)?,
+        ["flash"] => json::write_object(m.writer(), flash_json)?,
+        ["uart"] => json::write_object(m.writer(), uart_json)?,
+        ["os"] => json::write_object(m.writer(), os_json)?,
+        [] => json::write_object(m.writer(), |o| {
+            o.object("mcu", mcu_json)?
+                .object("cpu", cpu_json)?
+                .object("ram", ram_json)?
+                .object("flash", flash_json)?
+                .object("sdcard", super::io::sdcard_json)?
+                .object("uart", uart_json)?
+                .object("os", os_json)?
+                .opt_display(
+                    "date_time",
+                    TimeSource::get_date_time().as_ref().map(Iso8601),
+                )?;
+            Ok(())
+        })?,
+        _ => json::write_error(m.writer(), "Unknown query")?,
+    }
+    Ok(())
+}
+
+fn mcu_json<W: Write>(o: &mut JsonObject<W>) -> core::fmt::Result {
+    // Uid is 12 byts, hex string will be 24.
+    let (id, id_len) = to_hex::<24>(hal::signature::Uid::read(), false);
+    // SAFETY: to_hex always returns valid hex
+    let id_str = unsafe { core::str::from_utf8_unchecked(&id[0..id_len]) };
+    o.str("mcu", "STM32H747")?.str("unique_id", id_str)?;
+    Ok(())
+}
+
+fn cpu_json<W: Write>(o: &mut JsonObject<W>) -> core::fmt::Result {
+    o.str("core", "Cortex-M7F")?
+        .opt_num(
+            "frequency_hz",
+            interrupt_free(crate::system::cpu_freq).map(|freq| freq.to_Hz()),
+        )?
+        .opt_num("temperature_c", interrupt_free(crate::system::cpu_temp))?
+        .num("cycle_count", cortex_m::peripheral::DWT::cycle_count())?
+        .bool(
+            "instruction_cache",
+            cortex_m::peripheral::SCB::icache_enabled(),
+        )?
+        .bool("data_cache", cortex_m::peripheral::SCB::dcache_enabled())?;
+    Ok(())
+}
+
+fn ram_json<W: Write>(o: &mut JsonObject<W>) -> core::fmt::Result {
+    o.num("internal_bytes", crate::system::ram_size())?
+        .num("sdram_bytes", crate::mem::sdram::SDRAM_SIZE)?;
+    Ok(())
+}
+
+fn flash_json<W: Write>(o: &mut JsonObject<W>) -> core::fmt::Result {
+    o.num("internal_bytes", crate::system::flash_size())?
+        .num("external_bytes", crate::fs::qspi_store::flash_size())?;
+    Ok(())
+}
+
+fn uart_json<W: Write>(o: &mut JsonObject<W>) -> core::fmt::Result {
+    o.num("baud", uart::baud())?;
+    if let Some(status) = uart::status() {
+        o.display("flow_control", status.flow)?
+            .num("rx_buffer_bytes", status.capacity)?
+            .num("rx_buffered_bytes", status.buffered)?
+            .num("rx_peak_bytes", status.stats.peak)?
+            .num("received_bytes", status.stats.received)?
+            .num("dropped_bytes", status.stats.dropped)?
+            .num("overflows", status.stats.overflows)?
+            .num("overruns", status.overruns)?
+            .num("forced_bytes", status.forced)?;
+    }
+    Ok(())
+}
+
+fn os_json<W: Write>(o: &mut JsonObject<W>) -> core::fmt::Result {
+    o.num("heap_used_bytes", crate::mem::ALLOCATOR.used())?
+        .num("heap_size_bytes", crate::mem::HEAP_SIZE)?
+        .num(
+            "gpu_reserved_bytes",
+            crate::display::FRAME_BUFFER_ALLOC_SIZE,
+        )?
+        .str("rust_version", consts::RUSTC_VERSION)?
+        .str("version", consts::GIT_DESCRIBE)?
+        .bool("debug", cfg!(debug_assertions))?
+        .display("compiled", Iso8601(&compile_time()))?
+        .opt_display(
+            "boot_time",
+            interrupt_free(|cs| *crate::time::BOOT_TIME.borrow(cs).borrow())
+                .as_ref()
+                .map(Iso8601),
+        )?;
+    Ok(())
+}
+
 pub const WIFICTL: MenuItem<'static, TerminalWriter> = MenuItem::Command {
     name: "wifictl",
     help: "wifictl - Control WIFI networks and connections",
@@ -380,10 +563,21 @@ pub const UPTIME: MenuItem<'static, TerminalWriter> = MenuItem::Command {
     description: "Query the system uptime",
     action: |m, args| {
         check_args_len(0, args.len())?;
-        match (
-            TimeSource::get_date_time(),
-            interrupt_free(|cs| *crate::time::BOOT_TIME.borrow(cs).borrow()),
-        ) {
+        let now = TimeSource::get_date_time();
+        let boot_time = interrupt_free(|cs| *crate::time::BOOT_TIME.borrow(cs).borrow());
+        if m.output() == OutputMode::Json {
+            json::write_object(m.writer(), |o| {
+                o.opt_num(
+                    "uptime_s",
+                    now.zip(boot_time)
+                        .map(|(now, boot_time)| (now - boot_time).num_seconds()),
+                )?
+                .opt_display("boot_time", boot_time.as_ref().map(Iso8601))?;
+                Ok(())
+            })?;
+            return Ok(());
+        }
+        match (now, boot_time) {
             (Some(now), Some(boot_time)) => {
                 let dur = now - boot_time;
                 write!(m.writer(), "Uptime: ")?;
@@ -407,6 +601,68 @@ pub const UPTIME: MenuItem<'static, TerminalWriter> = MenuItem::Command {
     },
 };
 
+pub const SET: MenuItem<'static, TerminalWriter> = MenuItem::Command {
+    name: "set",
+    help: "set [output <text|json>] - Show or change session settings",
+    description: "Show or change session settings",
+    action: |m, args| {
+        match args {
+            [] => {
+                let output = m.output();
+                writeln!(m.writer(), "{:LABEL_WIDTH$} {output}", "output")?;
+            }
+            ["output", mode] => {
+                let mode = mode
+                    .parse::<OutputMode>()
+                    .map_err(|_| MenuError::InvalidArgument)?;
+                m.set_output(mode);
+            }
+            _ => return Err(MenuError::InvalidArgument),
+        }
+        Ok(())
+    },
+};
+
+pub const WHO: MenuItem<'static, TerminalWriter> = MenuItem::Command {
+    name: "who",
+    help: "who - Show connected terminal sessions",
+    description: "Show connected terminal sessions",
+    action: |m, args| {
+        check_args_len(0, args.len())?;
+        let port = m.writer().port();
+        let now = TimeSource::get_date_time();
+        for info in session::sessions() {
+            write!(m.writer(), "{:8} ", info.port)?;
+            match info.since {
+                Some(since) => write_date_time(m.writer(), &since)?,
+                None => write!(m.writer(), "unavailable")?,
+            }
+            if let (Some(now), Some(last_active)) = (now, info.last_active) {
+                write!(m.writer(), ", idle ")?;
+                crate::utils::write_pretty_duration(m.writer(), now - last_active)?;
+            }
+            if info.port == port {
+                write!(m.writer(), " (you)")?;
+            }
+            writeln!(m.writer())?;
+        }
+        Ok(())
+    },
+};
+
+pub const WALL: MenuItem<'static, TerminalWriter> = MenuItem::Command {
+    name: "wall",
+    help: "wall <message..> - Write a message to all other terminal sessions",
+    description: "Write a message to all other terminal sessions",
+    action: |m, args| {
+        if args.is_empty() {
+            return Err(MenuError::InvalidArgument);
+        }
+        session::wall(m.writer().port(), &args.join(" "))?;
+        Ok(())
+    },
+};
+
 pub const LEDCTL: MenuItem<'static, TerminalWriter> = MenuItem::Command {
     name: "ledctl",
     help: "ledctl <(r|red)|(g|green)|(b|blue)> <0|1> - Control RGB LED",
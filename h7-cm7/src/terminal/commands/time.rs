@@ -7,13 +7,13 @@ use {
         },
         time::TimeSource,
     },
-    chrono::{Datelike, NaiveDate, NaiveDateTime, NaiveTime, Timelike},
+    chrono::{NaiveDate, NaiveDateTime, NaiveTime},
     core::fmt::Write,
+    h7_shell::commands::time::{
+        write_calendar, write_date_time, DATE_PARSE_FORMAT, TIME_PARSE_FORMAT,
+    },
 };
 
-const DATE_PARSE_FORMAT: &str = "%Y-%m-%d";
-const TIME_PARSE_FORMAT: &str = "%H:%M:%S";
-
 pub const DATE: MenuItem<'static, TerminalWriter> = MenuItem::Command {
     name: "date",
     help: "date [set <(date|time|date time)>] - Get/set system date and time",
@@ -72,17 +72,10 @@ pub const DATE: MenuItem<'static, TerminalWriter> = MenuItem::Command {
                 writeln!(m.writer(), "Set time: date set {TIME_PARSE_FORMAT}")
             }
             [] => match TimeSource::get_date_time() {
-                Some(dt) => writeln!(
-                    m.writer(),
-                    "{weekday} {month} {day} {hh:02}:{mm:02}:{ss:02} {year}",
-                    weekday = dt.weekday(),
-                    month = month_to_str(dt.month()),
-                    day = dt.day(),
-                    hh = dt.hour(),
-                    mm = dt.minute(),
-                    ss = dt.second(),
-                    year = dt.year()
-                ),
+                Some(dt) => {
+                    write_date_time(m.writer(), &dt)?;
+                    writeln!(m.writer())
+                }
                 None => writeln!(m.writer(), "Error: RTC not initialized"),
             },
             _ => writeln!(m.writer(), "Invalid usage"),
@@ -99,42 +92,7 @@ pub const CAL: MenuItem<'static, TerminalWriter> = MenuItem::Command {
     action: |m, args| {
         check_args_len(0, args.len())?;
         match TimeSource::get_date_time() {
-            Some(ref dt) => {
-                let top = dt.with_day0(0).unwrap();
-                let day = dt.day0();
-                let wd = top.weekday() as u32;
-                let week = top.iso_week().week() - 1;
-                let n_days = days_in_month(dt);
-                writeln!(
-                    m.writer(),
-                    "         {m} {y}",
-                    m = month_to_str(dt.month()),
-                    y = dt.year()
-                )?;
-                writeln!(m.writer(), "Wk | Mo Tu We Th Fr Sa Su")?;
-                write!(m.writer(), "{:2} |", week + 1)?;
-                for _ in 0..wd {
-                    write!(m.writer(), "   ")?;
-                }
-                for i in 0..n_days {
-                    let c = i + wd + 1;
-                    if i == day {
-                        write!(m.writer(), "[{:2}", i + 1)?;
-                        if wd % 7 == 0 {
-                            write!(m.writer(), "]")?;
-                        }
-                    } else if i == day + 1 && c % 7 != 1 {
-                        write!(m.writer(), "]{:2}", i + 1)?;
-                    } else {
-                        write!(m.writer(), " {:2}", i + 1)?;
-                    }
-                    if c % 7 == 0 && i != n_days - 1 {
-                        writeln!(m.writer())?;
-                        write!(m.writer(), "{:2} |", 1 + ((week + (c / 7)) % 52))?;
-                    }
-                }
-                writeln!(m.writer())
-            }
+            Some(ref dt) => write_calendar(m.writer(), dt),
             None => writeln!(m.writer(), "Error: RTC not initialized"),
         }?;
         Ok(())
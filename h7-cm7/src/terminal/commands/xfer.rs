@@ -0,0 +1,515 @@
+use {
+    super::{
+        resolve,
+        utils::{check_args_len, strip_flag},
+    },
+    crate::{
+        app,
+        fs::{
+            path::Path,
+            vfs::{self, VfsError, WriteMode},
+        },
+        system::Timeout,
+        terminal::{
+            menu::{Menu, MenuError, MenuItem},
+            Port, TerminalWriter,
+        },
+        utils::interrupt_free,
+    },
+    alloc::{
+        string::{String, ToString},
+        vec::Vec,
+    },
+    core::{cell::RefCell, fmt::Write},
+    critical_section::Mutex,
+    h7_xfer::{
+        frame::{self, Target},
+        ymodem::{self, Config, Mode},
+        Channel, Sink, Source, Transfer,
+    },
+};
+
+/// Quiet time after a transfer before the shell reads input again
+const SETTLE_MS: u32 = 100;
+
+/// Size and received bytes of a program upload that was cut short, to resume it
+static PARTIAL_APP: Mutex<RefCell<Option<(u32, u32)>>> = Mutex::new(RefCell::new(None));
+
+pub const RX: MenuItem<'static, TerminalWriter> = MenuItem::Command {
+    name: "rx",
+    help: "rx [-a | destination] - Receive files with YMODEM or XMODEM into a directory or file, or with -a a program into RAM",
+    description: "Receive files over the console",
+    action: |m, args| {
+        let (to_app, args) = strip_flag(args, "-a");
+        match (to_app, args) {
+            (true, []) => {
+                // Commands registered by the current app are about to be overwritten
+                m.unregister_dynamic_commands();
+                let mut sink = AppSink {
+                    slice: app::app_slice(),
+                    len: 0,
+                };
+                if let Some(transfer) = receive(m, &mut sink)? {
+                    writeln!(m.writer(), "Program loaded ({} bytes)", transfer.bytes)?;
+                    if sink.len > 8 {
+                        app::print_info(m.writer(), &sink.slice[..sink.len])?;
+                    } else {
+                        writeln!(m.writer(), "Not enough data")?;
+                    }
+                }
+            }
+            (false, [] | [_]) => {
+                let dest = resolve(m, args.first().copied().unwrap_or("."));
+                let is_dir = match vfs::is_dir(Path::new(&dest)) {
+                    Ok(is_dir) => is_dir,
+                    Err(VfsError::NotFound) => false,
+                    Err(e) => {
+                        writeln!(m.writer(), "Error: {e}")?;
+                        return Ok(());
+                    }
+                };
+                let mut sink = FileSink {
+                    dest,
+                    is_dir,
+                    files: Vec::new(),
+                };
+                let result = receive(m, &mut sink)?;
+                for (path, size) in &sink.files {
+                    writeln!(m.writer(), "{path} ({size} bytes)")?;
+                }
+                if let Some(transfer) = result {
+                    writeln!(
+                        m.writer(),
+                        "Received {} file(s), {} bytes",
+                        transfer.files,
+                        transfer.bytes
+                    )?;
+                }
+            }
+            _ => {
+                writeln!(m.writer(), "Expected:")?;
+                writeln!(m.writer(), "\t[destination] - Directory or file to write to")?;
+                writeln!(m.writer(), "\t-a - Load a program into RAM, like pload")?;
+                return Err(MenuError::InvalidArgument);
+            }
+        }
+        Ok(())
+    },
+};
+
+pub const SX: MenuItem<'static, TerminalWriter> = MenuItem::Command {
+    name: "sx",
+    help: "sx [-x] <file>... - Send files with YMODEM, or a single one with -x using XMODEM",
+    description: "Send files over the console",
+    action: |m, args| {
+        let (xmodem, args) = strip_flag(args, "-x");
+        if args.is_empty() || (xmodem && args.len() > 1) {
+            writeln!(m.writer(), "Expected:")?;
+            writeln!(
+                m.writer(),
+                "\t<file>... - Files to send with YMODEM, wildcards allowed"
+            )?;
+            writeln!(
+                m.writer(),
+                "\t-x <file> - A single file to send with XMODEM"
+            )?;
+            return Err(MenuError::InvalidArgument);
+        }
+
+        let mut files = Vec::new();
+        for arg in args {
+            let paths = match vfs::glob(Path::new(&resolve(m, arg))) {
+                Ok(paths) => paths,
+                Err(e) => {
+                    writeln!(m.writer(), "Error: {arg}: {e}")?;
+                    return Ok(());
+                }
+            };
+            for path in paths {
+                match FileSource::open(path) {
+                    Ok(file) => files.push(file),
+                    Err((path, e)) => {
+                        writeln!(m.writer(), "Error: {path}: {e}")?;
+                        return Ok(());
+                    }
+                }
+            }
+        }
+        if xmodem && files.len() > 1 {
+            writeln!(m.writer(), "Error: XMODEM sends a single file")?;
+            return Ok(());
+        }
+
+        let mode = if xmodem { Mode::Xmodem } else { Mode::Ymodem };
+        writeln!(
+            m.writer(),
+            "Sending {} file(s) with {}, start receiving now (Ctrl-X twice to cancel)",
+            files.len(),
+            if xmodem { "XMODEM" } else { "YMODEM" }
+        )?;
+        let mut console = Console::new(m.writer().port());
+        let result = ymodem::send(&mut console, &mut files, mode, &Config::default());
+        let _ = console.purge(SETTLE_MS);
+        match result {
+            Ok(transfer) => writeln!(
+                m.writer(),
+                "Sent {} file(s), {} bytes",
+                transfer.files,
+                transfer.bytes
+            )?,
+            Err(e) => writeln!(m.writer(), "Error: {e}")?,
+        }
+        Ok(())
+    },
+};
+
+pub const FRX: MenuItem<'static, TerminalWriter> = MenuItem::Command {
+    name: "frx",
+    help: "frx - Receive a file or program from h7-uart-terminal's :send, resuming interrupted uploads",
+    description: "Receive an upload from h7-uart-terminal",
+    action: |m, args| {
+        check_args_len(0, args.len())?;
+        let mut target = UploadTarget {
+            cwd: m.cwd().to_string(),
+            path: None,
+            is_app: false,
+        };
+        let mut console = Console::new(m.writer().port());
+        let result = frame::receive(&mut console, &mut target, &frame::Config::default());
+        let _ = console.purge(SETTLE_MS);
+        if target.is_app {
+            // Commands registered by the previous app point into what was overwritten
+            m.unregister_dynamic_commands();
+        }
+        let received = match result {
+            Ok(received) => received,
+            Err(e) => {
+                writeln!(m.writer(), "Error: {e}")?;
+                return Ok(());
+            }
+        };
+        match &target.path {
+            Some(path) => write!(m.writer(), "Received {path} ({} bytes", received.size)?,
+            None => write!(m.writer(), "Program loaded ({} bytes", received.size)?,
+        }
+        match received.resumed_from {
+            0 => writeln!(m.writer(), ")")?,
+            n => writeln!(m.writer(), ", resumed at {n})")?,
+        }
+        if target.is_app {
+            match received.size as usize {
+                len if len > 8 => app::print_info(m.writer(), &app::app_slice()[..len])?,
+                _ => writeln!(m.writer(), "Not enough data")?,
+            }
+        }
+        Ok(())
+    },
+};
+
+/// Run a receiver on the session's console, `None` if it failed and the error was printed
+fn receive<S: Sink<Error = XferError>>(
+    m: &mut Menu<'_, TerminalWriter>,
+    sink: &mut S,
+) -> Result<Option<Transfer>, MenuError> {
+    writeln!(
+        m.writer(),
+        "Ready to receive, start the YMODEM or XMODEM upload now (Ctrl-X twice to cancel)"
+    )?;
+    let mut console = Console::new(m.writer().port());
+    let result = ymodem::receive(&mut console, sink, &Config::default());
+    let _ = console.purge(SETTLE_MS);
+    match result {
+        Ok(transfer) => Ok(Some(transfer)),
+        Err(e) => {
+            writeln!(m.writer(), "Error: {e}")?;
+            Ok(None)
+        }
+    }
+}
+
+#[derive(Debug)]
+enum XferError {
+    Vfs(VfsError),
+    /// Doesn't fit the program area of this many bytes
+    TooLarge(usize),
+    /// An XMODEM upload into a directory
+    NoName,
+}
+
+impl From<VfsError> for XferError {
+    fn from(e: VfsError) -> Self {
+        Self::Vfs(e)
+    }
+}
+
+impl core::fmt::Display for XferError {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        match self {
+            Self::Vfs(e) => write!(f, "{e}"),
+            Self::TooLarge(max) => write!(f, "Program larger than {max} bytes"),
+            Self::NoName => write!(f, "The sender gave no file name, name a destination file"),
+        }
+    }
+}
+
+/// The session's console, read straight from its input queue.
+/// XON/XOFF are data while it exists.
+struct Console {
+    port: Port,
+}
+
+impl Console {
+    fn new(port: Port) -> Self {
+        port.set_raw(true);
+        Self { port }
+    }
+}
+
+impl Drop for Console {
+    fn drop(&mut self) {
+        self.port.set_raw(false);
+    }
+}
+
+impl Channel for Console {
+    type Error = XferError;
+
+    fn read_byte(&mut self, timeout_ms: u32) -> Result<Option<u8>, XferError> {
+        let mut timeout = Timeout::new(timeout_ms as u64);
+        loop {
+            if let Some(b) = self.port.read() {
+                return Ok(Some(b));
+            }
+            if timeout.expired() {
+                return Ok(None);
+            }
+        }
+    }
+
+    fn write_all(&mut self, data: &[u8]) -> Result<(), XferError> {
+        TerminalWriter::new(self.port).write_bytes(data);
+        Ok(())
+    }
+}
+
+/// Writes a received program to the app area
+struct AppSink {
+    slice: &'static mut [u8],
+    len: usize,
+}
+
+impl Sink for AppSink {
+    type Error = XferError;
+
+    fn create(&mut self, _name: &str, _size: Option<u64>) -> Result<(), XferError> {
+        // .bss, and a second file of a batch replaces the first
+        self.slice.fill(0);
+        self.len = 0;
+        Ok(())
+    }
+
+    fn write(&mut self, data: &[u8]) -> Result<(), XferError> {
+        let end = self.len + data.len();
+        let dst = self
+            .slice
+            .get_mut(self.len..end)
+            .ok_or(XferError::TooLarge(self.slice.len()))?;
+        dst.copy_from_slice(data);
+        self.len = end;
+        Ok(())
+    }
+
+    fn finish(&mut self) -> Result<(), XferError> {
+        Ok(())
+    }
+}
+
+/// Writes received files into a directory under the sender's names, or to one file
+struct FileSink {
+    dest: String,
+    is_dir: bool,
+    /// Paths written so far and their sizes, the last one is in progress
+    files: Vec<(String, u64)>,
+}
+
+impl Sink for FileSink {
+    type Error = XferError;
+
+    fn create(&mut self, name: &str, _size: Option<u64>) -> Result<(), XferError> {
+        let path = match self.is_dir {
+            // Senders may include directories, only the name is used
+            true => match Path::new(name).file_name() {
+                Some(name) => Path::new(&self.dest).join(name),
+                None => return Err(XferError::NoName),
+            },
+            false => self.dest.clone(),
+        };
+        // Created up front so empty files arrive too
+        let p = Path::new(&path);
+        vfs::with_fs(p, |fs| fs.write(p, WriteMode::Truncate, &[]))?;
+        self.files.push((path, 0));
+        Ok(())
+    }
+
+    fn write(&mut self, data: &[u8]) -> Result<(), XferError> {
+        let Some((path, size)) = self.files.last_mut() else {
+            return Ok(());
+        };
+        let p = Path::new(path);
+        vfs::with_fs(p, |fs| fs.write(p, WriteMode::Append, data))?;
+        *size += data.len() as u64;
+        Ok(())
+    }
+
+    fn finish(&mut self) -> Result<(), XferError> {
+        Ok(())
+    }
+}
+
+/// A file being sent, read a chunk at a time
+struct FileSource {
+    path: String,
+    name: String,
+    size: u64,
+    offset: u32,
+}
+
+impl FileSource {
+    fn open(path: String) -> Result<Self, (String, VfsError)> {
+        let p = Path::new(&path);
+        let entry = match vfs::with_fs(p, |fs| fs.metadata(p)) {
+            Ok(entry) if entry.is_dir => return Err((path, VfsError::IsADirectory)),
+            Ok(entry) => entry,
+            Err(e) => return Err((path, e)),
+        };
+        Ok(Self {
+            name: p.file_name().unwrap_or_default().to_string(),
+            size: entry.size.into(),
+            path,
+            offset: 0,
+        })
+    }
+}
+
+impl Source for FileSource {
+    type Error = XferError;
+
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn size(&self) -> u64 {
+        self.size
+    }
+
+    fn read(&mut self, buf: &mut [u8]) -> Result<usize, XferError> {
+        let p = Path::new(&self.path);
+        let n = vfs::with_fs(p, |fs| fs.read_at(p, self.offset, buf))?;
+        self.offset += n as u32;
+        Ok(n)
+    }
+}
+
+/// Where `frx` puts an upload, a file or the app area
+struct UploadTarget {
+    /// Working directory relative destinations start at
+    cwd: String,
+    /// Destination file, `None` for the app area
+    path: Option<String>,
+    is_app: bool,
+}
+
+impl Target for UploadTarget {
+    type Error = XferError;
+
+    fn open(
+        &mut self,
+        name: &str,
+        dest: Option<&str>,
+        size: u32,
+        truncate: bool,
+    ) -> Result<u32, XferError> {
+        let Some(dest) = dest else {
+            self.is_app = true;
+            let slice = app::app_slice();
+            if size as usize > slice.len() {
+                return Err(XferError::TooLarge(slice.len()));
+            }
+            return Ok(interrupt_free(|cs| {
+                let mut partial = PARTIAL_APP.borrow(cs).borrow_mut();
+                match *partial {
+                    Some((total, received)) if total == size && !truncate => received,
+                    _ => {
+                        // .bss
+                        slice.fill(0);
+                        *partial = Some((size, 0));
+                        0
+                    }
+                }
+            }));
+        };
+
+        let dest = Path::new(dest).resolve(&self.cwd);
+        let path = match vfs::is_dir(Path::new(&dest)) {
+            Ok(true) => match Path::new(name).file_name() {
+                Some(name) => Path::new(&dest).join(name),
+                None => return Err(XferError::NoName),
+            },
+            Ok(false) | Err(VfsError::NotFound) => dest,
+            Err(e) => return Err(e.into()),
+        };
+        let p = Path::new(&path);
+        let existing = match vfs::with_fs(p, |fs| fs.metadata(p)) {
+            Ok(entry) if !truncate && entry.size <= size => entry.size,
+            Ok(_) | Err(VfsError::NotFound) => {
+                vfs::with_fs(p, |fs| fs.write(p, WriteMode::Truncate, &[]))?;
+                0
+            }
+            Err(e) => return Err(e.into()),
+        };
+        self.path = Some(path);
+        Ok(existing)
+    }
+
+    fn read_at(&mut self, offset: u32, buf: &mut [u8]) -> Result<usize, XferError> {
+        match &self.path {
+            Some(path) => {
+                let p = Path::new(path);
+                Ok(vfs::with_fs(p, |fs| fs.read_at(p, offset, buf))?)
+            }
+            None => {
+                let slice = &app::app_slice()[offset as usize..];
+                let n = buf.len().min(slice.len());
+                buf[..n].copy_from_slice(&slice[..n]);
+                Ok(n)
+            }
+        }
+    }
+
+    fn write(&mut self, data: &[u8]) -> Result<(), XferError> {
+        match &self.path {
+            Some(path) => {
+                let p = Path::new(path);
+                Ok(vfs::with_fs(p, |fs| fs.write(p, WriteMode::Append, data))?)
+            }
+            None => interrupt_free(|cs| {
+                let mut partial = PARTIAL_APP.borrow(cs).borrow_mut();
+                let Some((_, received)) = partial.as_mut() else {
+                    unreachable!("Program upload written before it was opened")
+                };
+                // The receiver checks uploads against their size, which fits the app area
+                let start = *received as usize;
+                app::app_slice()[start..start + data.len()].copy_from_slice(data);
+                *received += data.len() as u32;
+                Ok(())
+            }),
+        }
+    }
+
+    fn finish(&mut self) -> Result<(), XferError> {
+        if self.path.is_none() {
+            interrupt_free(|cs| PARTIAL_APP.borrow(cs).replace(None));
+        }
+        Ok(())
+    }
+}
@@ -2,51 +2,146 @@ use {
     crate::utils::interrupt_free,
     core::{cell::RefCell, fmt::Write},
     critical_section::Mutex,
-    heapless::mpmc::Q64,
     menu::MenuItem,
-    stm32h7xx_hal::{interrupt, pac, prelude::*, serial},
+    stm32h7xx_hal::{pac, serial},
 };
 
 mod commands;
-pub mod menu;
+pub use h7_shell::menu;
+pub mod session;
+pub mod uart;
+pub mod usb;
 
-pub struct TerminalWriter;
+/// Console a shell session runs on
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Port {
+    Uart,
+    Usb,
+}
+
+impl Port {
+    pub const ALL: [Port; 2] = [Port::Uart, Port::Usb];
+
+    /// Next byte of input, if any
+    pub fn read(self) -> Option<u8> {
+        match self {
+            Port::Uart => uart::read(),
+            Port::Usb => usb::read(),
+        }
+    }
+
+    /// Turn off XON/XOFF handling for binary transfers, USB has its own flow control
+    pub fn set_raw(self, raw: bool) {
+        if self == Port::Uart {
+            uart::set_raw(raw)
+        }
+    }
+
+    /// Is something connected to read the output
+    pub fn is_open(self) -> bool {
+        match self {
+            Port::Uart => interrupt_free(|cs| UART_TERMINAL_TX.borrow(cs).borrow().is_some()),
+            Port::Usb => usb::is_open(),
+        }
+    }
+
+    /// Returns `true` once after the console was opened by a host
+    pub fn take_opened(self) -> bool {
+        match self {
+            Port::Uart => false,
+            Port::Usb => usb::take_opened(),
+        }
+    }
+}
+
+impl core::fmt::Display for Port {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        match self {
+            Port::Uart => f.pad("uart"),
+            Port::Usb => f.pad("usb"),
+        }
+    }
+}
+
+pub struct TerminalWriter {
+    port: Port,
+}
+
+impl TerminalWriter {
+    pub const fn new(port: Port) -> Self {
+        Self { port }
+    }
+
+    pub fn port(&self) -> Port {
+        self.port
+    }
+
+    /// Write binary data as is, for file transfers
+    pub fn write_bytes(&mut self, bytes: &[u8]) {
+        match self.port {
+            Port::Uart => uart::write(bytes),
+            Port::Usb => usb::write(bytes),
+        }
+    }
+}
 
 impl core::fmt::Write for TerminalWriter {
     fn write_str(&mut self, s: &str) -> core::fmt::Result {
-        interrupt_free(|cs| {
-            if let Some(tx) = &mut *UART_TERMINAL_TX.borrow(cs).borrow_mut() {
-                write!(tx, "{s}")?
+        match self.port {
+            Port::Uart => {
+                uart::write(s.as_bytes());
+                Ok(())
             }
-            Ok(())
-        })
+            Port::Usb => usb::write_str(s),
+        }
+    }
+}
+
+/// Writes to every console, used for logging
+pub struct BroadcastWriter;
+
+impl core::fmt::Write for BroadcastWriter {
+    fn write_str(&mut self, s: &str) -> core::fmt::Result {
+        for port in Port::ALL {
+            TerminalWriter::new(port).write_str(s)?;
+        }
+        Ok(())
     }
 }
 
 // Terminal
-// pub static TERMINAL_INPUT_FIFO: Mutex<RefCell<Queue<u8, 64>>> =
-//     Mutex::new(RefCell::new(Queue::new()));
-pub static TERMINAL_INPUT_FIFO: Q64<u8> = Q64::new();
 pub static UART_TERMINAL_RX: Mutex<RefCell<Option<serial::Rx<pac::USART1>>>> =
     Mutex::new(RefCell::new(None));
 pub static UART_TERMINAL_TX: Mutex<RefCell<Option<serial::Tx<pac::USART1>>>> =
     Mutex::new(RefCell::new(None));
 
-#[allow(dead_code)]
 pub const UART_TERMINAL_BAUD: u32 = 115_200;
 
 pub const MENU: &[MenuItem<TerminalWriter>] = &[
     MenuItem::Group {
         title: "I/O",
         commands: &[
+            commands::io::CD,
             commands::io::CP,
             commands::io::RM,
             commands::io::MV,
             commands::io::LS,
             commands::io::CAT,
+            commands::io::MKDIR,
+            commands::io::RMDIR,
+            commands::io::TOUCH,
+            commands::io::MOUNT,
+            commands::io::PWD,
+            commands::io::DF,
+            commands::io::DU,
+            commands::io::FIND,
             commands::io::NOR,
+            commands::io::RAM,
             commands::io::SDCARD,
             commands::io::CURL,
+            commands::xfer::RX,
+            commands::xfer::SX,
+            commands::xfer::FRX,
         ],
     },
     MenuItem::Group {
@@ -60,16 +155,21 @@ pub const MENU: &[MenuItem<TerminalWriter>] = &[
     MenuItem::Group {
         title: "System",
         commands: &[
+            commands::sys::ALIAS,
             commands::sys::COMMANDS,
             commands::sys::HELP,
             commands::sys::MAN,
             commands::sys::INFO,
             commands::sys::PROGRAMS,
+            commands::sys::SET,
             commands::sys::SYS,
             commands::sys::WIFICTL,
             commands::sys::BTCTL,
             commands::sys::ETHCTL,
+            commands::sys::UNALIAS,
             commands::sys::UPTIME,
+            commands::sys::WALL,
+            commands::sys::WHO,
             commands::sys::LEDCTL,
             commands::sys::CORECTL,
         ],
@@ -102,14 +202,3 @@ pub const MENU: &[MenuItem<TerminalWriter>] = &[
         }],
     },
 ];
-
-#[interrupt]
-fn USART1() {
-    interrupt_free(|cs| {
-        if let Some(uart) = &mut *UART_TERMINAL_RX.borrow(cs).borrow_mut() {
-            if let Ok(w) = uart.read() {
-                let _ = TERMINAL_INPUT_FIFO.enqueue(w);
-            }
-        }
-    });
-}
@@ -0,0 +1,97 @@
+use {
+    super::{menu::Menu, Port, TerminalWriter, MENU},
+    crate::{time::TimeSource, utils::interrupt_free},
+    chrono::NaiveDateTime,
+    core::{cell::RefCell, fmt::Write},
+    critical_section::Mutex,
+    h7_shell::Shell,
+};
+
+pub const LINE_BUFFER_LEN: usize = 1024;
+
+/// What other sessions can see about a session, see `who`
+#[derive(Debug, Clone, Copy)]
+pub struct SessionInfo {
+    pub port: Port,
+    /// When the console was opened
+    pub since: Option<NaiveDateTime>,
+    /// Last time a line was run
+    pub last_active: Option<NaiveDateTime>,
+}
+
+static SESSIONS: Mutex<RefCell<[Option<SessionInfo>; Port::ALL.len()]>> =
+    Mutex::new(RefCell::new([None; Port::ALL.len()]));
+
+/// A shell running on one console, owning its line buffer and menu
+pub struct Session {
+    shell: Shell<'static, TerminalWriter, LINE_BUFFER_LEN>,
+}
+
+impl Session {
+    pub fn new(port: Port) -> Self {
+        update(port, |info| info.since = TimeSource::get_date_time());
+        let mut shell = Shell::new(Menu::new(TerminalWriter::new(port), MENU));
+        // Terminals send key presses as they come, the shell echoes and edits
+        shell.set_interactive(true);
+        Self { shell }
+    }
+
+    pub fn port(&mut self) -> Port {
+        self.shell.menu().writer().port()
+    }
+
+    /// Process one byte of pending input, returns `true` if a line was run
+    pub fn poll(&mut self) -> bool {
+        let port = self.port();
+        if port.take_opened() {
+            // Someone (re)connected, greet them with a prompt
+            update(port, |info| info.since = TimeSource::get_date_time());
+            let _ = self.shell.prompt();
+        }
+
+        match port.read() {
+            Some(c) if self.shell.push(c) => {
+                update(port, |info| info.last_active = TimeSource::get_date_time());
+                true
+            }
+            _ => false,
+        }
+    }
+
+    /// Drop input received while the line ran and show a new prompt
+    pub fn finish_line(&mut self) {
+        let port = self.port();
+        while port.read().is_some() {}
+        let _ = self.shell.prompt();
+    }
+}
+
+fn update(port: Port, f: impl FnOnce(&mut SessionInfo)) {
+    interrupt_free(|cs| {
+        let mut sessions = SESSIONS.borrow(cs).borrow_mut();
+        let info = sessions[port as usize].get_or_insert(SessionInfo {
+            port,
+            since: None,
+            last_active: None,
+        });
+        f(info)
+    })
+}
+
+/// Sessions with a connected console
+pub fn sessions() -> impl Iterator<Item = SessionInfo> {
+    interrupt_free(|cs| *SESSIONS.borrow(cs).borrow())
+        .into_iter()
+        .flatten()
+        .filter(|info| info.port.is_open())
+}
+
+/// Write a message to every connected session except `from`
+pub fn wall(from: Port, msg: &str) -> core::fmt::Result {
+    for info in sessions().filter(|info| info.port != from) {
+        let mut writer = TerminalWriter::new(info.port);
+        writeln!(writer, "\nBroadcast message from {from}:\n{msg}")?;
+        write!(writer, "> ")?;
+    }
+    Ok(())
+}
@@ -0,0 +1,287 @@
+//! UART console: input and output buffers, the USART1 interrupt and XON/XOFF flow control.
+//!
+//! Output is queued and sent by the transmit interrupt, so printing doesn't mask interrupts for
+//! the length of the transmission. It is written directly when the queue can't drain: with
+//! interrupts masked, from an interrupt handler while the queue is full, and after a panic.
+//! Writing directly ignores an XOFF from the host, such bytes are counted for `info uart`.
+//!
+//! RTS/CTS isn't available, the USART1 CTS/RTS pins (PA11/PA12) carry USB on the GIGA.
+
+use {
+    super::{Port, UART_TERMINAL_BAUD, UART_TERMINAL_RX, UART_TERMINAL_TX},
+    crate::{
+        settings::Settings,
+        system::Timeout,
+        utils::{can_wait, interrupt_free},
+    },
+    core::{
+        cell::RefCell,
+        sync::atomic::{AtomicBool, AtomicU32, Ordering},
+    },
+    critical_section::{CriticalSection, Mutex},
+    h7_shell::input::{Flow, InputBuffer, Stats},
+    heapless::Deque,
+    stm32h7xx_hal::{interrupt, nb, pac, prelude::*, serial, time::Hertz},
+};
+
+/// Longest a writer waits for room in the output queue, e.g. while the host holds back output
+/// with XOFF, before it writes directly
+const PAUSE_TIMEOUT_MS: u64 = 2000;
+/// Output queue size, about 180ms at 115200 baud
+const TX_BUFFER_LEN: usize = 2048;
+
+static INPUT: Mutex<RefCell<Option<InputBuffer>>> = Mutex::new(RefCell::new(None));
+static OUTPUT: Mutex<RefCell<Deque<u8, TX_BUFFER_LEN>>> = Mutex::new(RefCell::new(Deque::new()));
+
+/// Set once the system panicked, output bypasses the queue from then on
+static BLOCKING: AtomicBool = AtomicBool::new(false);
+
+/// Bytes the UART lost before the interrupt could read them
+static OVERRUNS: AtomicU32 = AtomicU32::new(0);
+/// Bytes written directly although the host had sent XOFF
+static FORCED: AtomicU32 = AtomicU32::new(0);
+
+/// Clock the baud rate is divided from, known once the UART was brought up
+static KERNEL_CLOCK_HZ: AtomicU32 = AtomicU32::new(0);
+static BAUD: AtomicU32 = AtomicU32::new(UART_TERMINAL_BAUD);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BaudError {
+    NotRunning,
+    /// The rate is out of reach of the baud rate divider
+    OutOfRange,
+}
+
+impl core::fmt::Display for BaudError {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        match self {
+            Self::NotRunning => write!(f, "UART console isn't running"),
+            Self::OutOfRange => write!(f, "Baud rate out of range"),
+        }
+    }
+}
+
+/// Input buffer state for `info uart`
+#[derive(Debug, Clone, Copy)]
+pub struct Status {
+    pub capacity: usize,
+    pub buffered: usize,
+    pub flow: Flow,
+    pub stats: Stats,
+    pub overruns: u32,
+    pub forced: u32,
+}
+
+/// Remember the clock and rate the UART was brought up with, for [`set_baud`]
+pub fn init(kernel_clock: Hertz, baud: u32) {
+    KERNEL_CLOCK_HZ.store(kernel_clock.raw(), Ordering::Relaxed);
+    BAUD.store(baud, Ordering::Relaxed);
+}
+
+pub fn baud() -> u32 {
+    BAUD.load(Ordering::Relaxed)
+}
+
+/// Can the UART run at `baud`
+pub fn check_baud(baud: u32) -> Result<(), BaudError> {
+    divider(baud).map(|_| ())
+}
+
+fn divider(baud: u32) -> Result<u32, BaudError> {
+    let clock = KERNEL_CLOCK_HZ.load(Ordering::Relaxed);
+    if clock == 0 || !Port::Uart.is_open() {
+        return Err(BaudError::NotRunning);
+    }
+    // 16x oversampling, the divider has 16 bits
+    let divider = match baud {
+        0 => return Err(BaudError::OutOfRange),
+        baud => (clock + baud / 2) / baud,
+    };
+    match (16..=0xFFFF).contains(&divider) {
+        true => Ok(divider),
+        false => Err(BaudError::OutOfRange),
+    }
+}
+
+/// Switch to another baud rate once pending output went out at the current one
+pub fn set_baud(baud: u32) -> Result<(), BaudError> {
+    let divider = divider(baud)?;
+
+    write_blocking(&[]);
+    interrupt_free(|_| {
+        // SAFETY: Only the baud rate changes, which needs the UART disabled for a moment. The
+        // halves held by `UART_TERMINAL_RX`/`TX` can't touch it inside the critical section.
+        unsafe {
+            let usart = &*pac::USART1::ptr();
+            // Let the last byte leave the shift register
+            while usart.isr.read().tc().bit_is_clear() {}
+            usart.cr1.modify(|_, w| w.ue().disabled());
+            usart.brr.write(|w| w.brr().bits(divider as u16));
+            usart.cr1.modify(|_, w| w.ue().enabled());
+        }
+    });
+    BAUD.store(baud, Ordering::Relaxed);
+    Ok(())
+}
+
+/// (Re)allocate the input buffer with the configured size and flow control.
+/// Anything buffered is dropped and the counters start over.
+pub fn configure(settings: &Settings) {
+    let input = InputBuffer::new(settings.uart_rx_buffer, settings.uart_flow);
+    interrupt_free(|cs| INPUT.borrow(cs).replace(Some(input)));
+    OVERRUNS.store(0, Ordering::Relaxed);
+    FORCED.store(0, Ordering::Relaxed);
+}
+
+pub fn status() -> Option<Status> {
+    interrupt_free(|cs| {
+        INPUT.borrow_ref(cs).as_ref().map(|input| Status {
+            capacity: input.capacity(),
+            buffered: input.len(),
+            flow: input.flow(),
+            stats: input.stats(),
+            overruns: OVERRUNS.load(Ordering::Relaxed),
+            forced: FORCED.load(Ordering::Relaxed),
+        })
+    })
+}
+
+pub fn read() -> Option<u8> {
+    interrupt_free(|cs| {
+        let mut input = INPUT.borrow_ref_mut(cs);
+        let input = input.as_mut()?;
+        let byte = input.pop();
+        send_control(cs, input);
+        byte
+    })
+}
+
+/// Pass XON/XOFF through as data while binary transfers run
+pub fn set_raw(raw: bool) {
+    interrupt_free(|cs| {
+        if let Some(input) = INPUT.borrow_ref_mut(cs).as_mut() {
+            input.set_raw(raw);
+            send_control(cs, input);
+        }
+    })
+}
+
+/// Queue output for the transmit interrupt, waiting for room while the queue is full
+pub fn write(mut bytes: &[u8]) {
+    if BLOCKING.load(Ordering::Relaxed) {
+        return write_blocking(bytes);
+    }
+
+    let mut timeout = Timeout::new(PAUSE_TIMEOUT_MS);
+    while !bytes.is_empty() {
+        let queued = interrupt_free(|cs| {
+            let Some(tx) = &mut *UART_TERMINAL_TX.borrow_ref_mut(cs) else {
+                // Nobody to write to
+                return bytes.len();
+            };
+            let mut output = OUTPUT.borrow_ref_mut(cs);
+            let queued = bytes
+                .iter()
+                .take_while(|&&b| output.push_back(b).is_ok())
+                .count();
+            if !is_paused(cs) {
+                tx.listen();
+            }
+            queued
+        });
+        bytes = &bytes[queued..];
+
+        if queued > 0 {
+            timeout = Timeout::new(PAUSE_TIMEOUT_MS);
+        } else if !can_wait() || timeout.expired() {
+            interrupt_free(|cs| {
+                if is_paused(cs) {
+                    let len = OUTPUT.borrow_ref(cs).len() + bytes.len();
+                    FORCED.fetch_add(len as u32, Ordering::Relaxed);
+                }
+            });
+            return write_blocking(bytes);
+        }
+    }
+}
+
+/// Send everything queued and write directly from now on, for the panic handler
+pub fn enter_blocking_mode() {
+    BLOCKING.store(true, Ordering::Relaxed);
+    write_blocking(&[]);
+}
+
+/// Write the queued output and then `bytes` with interrupts masked
+fn write_blocking(bytes: &[u8]) {
+    interrupt_free(|cs| {
+        if let Some(tx) = &mut *UART_TERMINAL_TX.borrow_ref_mut(cs) {
+            let mut output = OUTPUT.borrow_ref_mut(cs);
+            let queued = core::iter::from_fn(|| output.pop_front());
+            for b in queued.chain(bytes.iter().copied()) {
+                while tx.write(b).is_err() {}
+            }
+            tx.unlisten();
+        }
+    })
+}
+
+fn is_paused(cs: CriticalSection) -> bool {
+    INPUT
+        .borrow_ref(cs)
+        .as_ref()
+        .is_some_and(|input| input.is_paused())
+}
+
+/// Send the XON/XOFF the buffer asks for, ahead of any pending output
+fn send_control(cs: CriticalSection, input: &mut InputBuffer) {
+    if let Some(c) = input.take_control() {
+        if let Some(tx) = &mut *UART_TERMINAL_TX.borrow_ref_mut(cs) {
+            while tx.write(c).is_err() {}
+        }
+    }
+}
+
+/// Move as much queued output to the UART as it takes, listening for TXE while there is more
+fn transmit(cs: CriticalSection) {
+    let Some(tx) = &mut *UART_TERMINAL_TX.borrow_ref_mut(cs) else {
+        return;
+    };
+    if is_paused(cs) {
+        // Picked up again when the XON arrives
+        tx.unlisten();
+        return;
+    }
+    let mut output = OUTPUT.borrow_ref_mut(cs);
+    while let Some(&b) = output.front() {
+        if tx.write(b).is_err() {
+            break;
+        }
+        output.pop_front();
+    }
+    if output.is_empty() {
+        tx.unlisten();
+    } else {
+        tx.listen();
+    }
+}
+
+#[interrupt]
+fn USART1() {
+    interrupt_free(|cs| {
+        if let Some(uart) = &mut *UART_TERMINAL_RX.borrow_ref_mut(cs) {
+            match uart.read() {
+                Ok(w) => {
+                    if let Some(input) = INPUT.borrow_ref_mut(cs).as_mut() {
+                        input.push(w);
+                        send_control(cs, input);
+                    }
+                }
+                Err(nb::Error::Other(serial::Error::Overrun)) => {
+                    OVERRUNS.fetch_add(1, Ordering::Relaxed);
+                }
+                Err(_) => {}
+            }
+        }
+        transmit(cs);
+    });
+}
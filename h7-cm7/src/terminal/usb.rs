@@ -0,0 +1,225 @@
+//! USB console: a CDC-ACM port on the native USB connector.
+//!
+//! Output is queued and moved to the IN endpoint by the OTG_FS interrupt. Input waits in
+//! [`USB_TERMINAL_INPUT_FIFO`]; while it is full the OUT endpoint isn't read, so the host gets
+//! NAKs and holds back instead of its bytes being dropped.
+
+use {
+    crate::{
+        system::Timeout,
+        utils::{can_wait, interrupt_free},
+    },
+    core::{
+        cell::RefCell,
+        sync::atomic::{AtomicBool, Ordering},
+    },
+    critical_section::Mutex,
+    heapless::{mpmc::Q64, Deque},
+    stm32h7xx_hal::{
+        interrupt, pac,
+        usb_hs::{UsbBus, USB2},
+    },
+    usb_device::{class_prelude::UsbBusAllocator, prelude::*},
+    usbd_serial::SerialPort,
+};
+
+// Arduino GIGA R1 WiFi
+const USB_VID: u16 = 0x2341;
+const USB_PID: u16 = 0x0266;
+
+/// Longest a writer waits for room in the output queue before the rest is dropped
+const WRITE_TIMEOUT_MS: u64 = 100;
+/// Output queue size
+const TX_BUFFER_LEN: usize = 1024;
+/// Bulk packet size
+const PACKET_LEN: usize = 64;
+
+pub static USB_TERMINAL_INPUT_FIFO: Q64<u8> = Q64::new();
+
+static mut EP_MEMORY: [u32; 1024] = [0; 1024];
+static mut USB_BUS: Option<UsbBusAllocator<UsbBus<USB2>>> = None;
+
+static USB_TERMINAL: Mutex<RefCell<Option<UsbTerminal>>> = Mutex::new(RefCell::new(None));
+static OUTPUT: Mutex<RefCell<Deque<u8, TX_BUFFER_LEN>>> = Mutex::new(RefCell::new(Deque::new()));
+
+// Set while received bytes wait for room in the input FIFO
+static INPUT_STALLED: AtomicBool = AtomicBool::new(false);
+
+// Set when a host opens the port, cleared by `take_opened`
+static USB_TERMINAL_OPENED: AtomicBool = AtomicBool::new(false);
+
+struct UsbTerminal {
+    device: UsbDevice<'static, UsbBus<USB2>>,
+    serial: SerialPort<'static, UsbBus<USB2>>,
+    dtr: bool,
+    /// Read from the endpoint, not yet in the input FIFO
+    received: Deque<u8, PACKET_LEN>,
+}
+
+impl UsbTerminal {
+    /// Service the USB peripheral, move received bytes to the input FIFO and queued output to
+    /// the endpoint
+    fn poll(&mut self, output: &mut Deque<u8, TX_BUFFER_LEN>) {
+        self.device.poll(&mut [&mut self.serial]);
+        self.receive();
+
+        let dtr = self.serial.dtr();
+        if dtr && !self.dtr {
+            USB_TERMINAL_OPENED.store(true, Ordering::Relaxed);
+        }
+        self.dtr = dtr;
+
+        self.transmit(output);
+    }
+
+    /// Only reads the endpoint once everything read before fit into the input FIFO
+    fn receive(&mut self) {
+        loop {
+            while let Some(&b) = self.received.front() {
+                if USB_TERMINAL_INPUT_FIFO.enqueue(b).is_err() {
+                    // Picked up again by `read`
+                    INPUT_STALLED.store(true, Ordering::Relaxed);
+                    return;
+                }
+                self.received.pop_front();
+            }
+
+            let mut buf = [0u8; PACKET_LEN];
+            match self.serial.read(&mut buf) {
+                Ok(n) if n > 0 => buf[..n].iter().for_each(|&b| {
+                    let _ = self.received.push_back(b);
+                }),
+                _ => return,
+            }
+        }
+    }
+
+    /// Move as much queued output to the endpoint as it takes, the rest follows once the host
+    /// read it
+    fn transmit(&mut self, output: &mut Deque<u8, TX_BUFFER_LEN>) {
+        // Nobody is listening
+        if !self.is_open() {
+            output.clear();
+            return;
+        }
+        while !output.is_empty() {
+            let mut buf = [0u8; PACKET_LEN];
+            let len = buf
+                .iter_mut()
+                .zip(output.iter())
+                .map(|(dst, &b)| *dst = b)
+                .count();
+            match self.serial.write(&buf[..len]) {
+                Ok(n) => (0..n).for_each(|_| {
+                    output.pop_front();
+                }),
+                Err(_) => return,
+            }
+        }
+    }
+
+    fn is_open(&self) -> bool {
+        self.device.state() == UsbDeviceState::Configured && self.dtr
+    }
+}
+
+/// Bring up the CDC-ACM console on the native USB port
+///
+/// # Safety
+/// Must only be called once
+pub unsafe fn init(usb: USB2) {
+    USB_BUS = Some(UsbBus::new(usb, &mut EP_MEMORY));
+    let bus = USB_BUS.as_ref().unwrap();
+
+    let serial = SerialPort::new(bus);
+    let device = UsbDeviceBuilder::new(bus, UsbVidPid(USB_VID, USB_PID))
+        .manufacturer("Arduino")
+        .product("h7 terminal")
+        .serial_number("h7")
+        .device_class(usbd_serial::USB_CLASS_CDC)
+        .build();
+
+    interrupt_free(|cs| {
+        USB_TERMINAL.borrow(cs).replace(Some(UsbTerminal {
+            device,
+            serial,
+            dtr: false,
+            received: Deque::new(),
+        }))
+    });
+
+    cortex_m::peripheral::NVIC::unmask(pac::Interrupt::OTG_FS);
+}
+
+pub fn is_open() -> bool {
+    interrupt_free(|cs| {
+        USB_TERMINAL
+            .borrow(cs)
+            .borrow()
+            .as_ref()
+            .map(UsbTerminal::is_open)
+            .unwrap_or(false)
+    })
+}
+
+/// Returns `true` once after a host opened the port
+pub fn take_opened() -> bool {
+    USB_TERMINAL_OPENED.swap(false, Ordering::Relaxed)
+}
+
+pub fn write_str(s: &str) -> core::fmt::Result {
+    write(s.as_bytes());
+    Ok(())
+}
+
+/// Next byte of input, reading the endpoint again if it was held back for room
+pub fn read() -> Option<u8> {
+    let byte = USB_TERMINAL_INPUT_FIFO.dequeue();
+    if byte.is_some() && INPUT_STALLED.swap(false, Ordering::Relaxed) {
+        cortex_m::peripheral::NVIC::pend(pac::Interrupt::OTG_FS);
+    }
+    byte
+}
+
+/// Queue output for the OTG_FS interrupt, waiting for room while the queue is full. Output the
+/// host doesn't take within [`WRITE_TIMEOUT_MS`] is dropped, as is everything while no host has
+/// the port open.
+pub fn write(mut bytes: &[u8]) {
+    let mut timeout = Timeout::new(WRITE_TIMEOUT_MS);
+    while !bytes.is_empty() {
+        let queued = interrupt_free(|cs| {
+            // Might already be borrowed when panicking from within the terminal
+            let Ok(mut terminal) = USB_TERMINAL.borrow(cs).try_borrow_mut() else {
+                return bytes.len();
+            };
+            let Some(terminal) = terminal.as_mut().filter(|terminal| terminal.is_open()) else {
+                return bytes.len();
+            };
+            let mut output = OUTPUT.borrow_ref_mut(cs);
+            let queued = bytes
+                .iter()
+                .take_while(|&&b| output.push_back(b).is_ok())
+                .count();
+            // Starts the transfer, the interrupt continues it
+            terminal.transmit(&mut output);
+            queued
+        });
+        bytes = &bytes[queued..];
+
+        if queued > 0 {
+            timeout = Timeout::new(WRITE_TIMEOUT_MS);
+        } else if !can_wait() || timeout.expired() {
+            // Host stopped reading, drop the rest
+            return;
+        }
+    }
+}
+
+#[interrupt]
+fn OTG_FS() {
+    interrupt_free(|cs| {
+        if let Some(terminal) = &mut *USB_TERMINAL.borrow(cs).borrow_mut() {
+            terminal.poll(&mut OUTPUT.borrow_ref_mut(cs));
+        }
+    });
+}
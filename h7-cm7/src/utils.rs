@@ -1,5 +1,6 @@
 use {
     core::cell::RefCell,
+    cortex_m::peripheral::{scb::VectActive, SCB},
     critical_section::{CriticalSection, Mutex},
     stm32h7xx_hal::crc::{Config, Crc},
 };
@@ -25,6 +26,12 @@ where
     critical_section::with(f)
 }
 
+/// Can interrupts run while we wait, i.e. they aren't masked and we're not in a handler.
+/// Waiting for an interrupt to drain a queue is pointless otherwise.
+pub fn can_wait() -> bool {
+    cortex_m::register::primask::read().is_active() && SCB::vect_active() == VectActive::ThreadMode
+}
+
 #[inline(always)]
 pub fn into_ok_or_err<T>(result: Result<T, T>) -> T {
     match result {
@@ -0,0 +1,433 @@
+//! Formatting disks as FAT16 or FAT32 with a single partition.
+//!
+//! Parameters follow Microsoft's FAT specification (fatgen103): cluster sizes from its tables,
+//! two FATs, and for FAT32 an FSInfo sector plus a backup boot sector. The partition starts on a
+//! 4 MiB boundary, the usual erase unit of SD cards, or on 1 MiB on small disks.
+
+use {
+    crate::partition::BLOCK_LEN,
+    alloc::{vec, vec::Vec},
+};
+
+/// Access to a disk in blocks of [`BLOCK_LEN`] bytes
+pub trait Disk {
+    type Error;
+
+    fn num_blocks(&self) -> Result<u64, Self::Error>;
+
+    /// Write `data`, a multiple of [`BLOCK_LEN`] bytes, starting at block `lba`
+    fn write_blocks(&mut self, lba: u64, data: &[u8]) -> Result<(), Self::Error>;
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FatType {
+    Fat16,
+    Fat32,
+}
+
+impl core::fmt::Display for FatType {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        f.pad(match self {
+            Self::Fat16 => "FAT16",
+            Self::Fat32 => "FAT32",
+        })
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FormatError {
+    /// Too few clusters for the FAT type
+    TooSmall,
+    /// Too many clusters for the FAT type
+    TooLarge,
+    InvalidLabel,
+}
+
+impl core::fmt::Display for FormatError {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        match self {
+            Self::TooSmall => write!(f, "Disk too small for the FAT type"),
+            Self::TooLarge => write!(f, "Disk too large for the FAT type"),
+            Self::InvalidLabel => write!(f, "Invalid volume label"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Error<E> {
+    Io(E),
+    Format(FormatError),
+}
+
+impl<E> From<FormatError> for Error<E> {
+    fn from(err: FormatError) -> Self {
+        Self::Format(err)
+    }
+}
+
+impl<E: core::fmt::Debug> core::fmt::Display for Error<E> {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        match self {
+            Self::Io(e) => write!(f, "I/O error: {e:?}"),
+            Self::Format(e) => write!(f, "{e}"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default)]
+pub struct FormatOptions<'a> {
+    /// FAT16 up to about 2 GiB, FAT32 above if `None`
+    pub fat: Option<FatType>,
+    pub label: Option<&'a str>,
+    /// Serial number of the volume, usually derived from the time of formatting
+    pub volume_id: u32,
+}
+
+const FAT16_MIN_CLUSTERS: u32 = 4085;
+const FAT32_MIN_CLUSTERS: u32 = 65525;
+const FAT32_MAX_CLUSTERS: u32 = 0x0FFF_FFF5;
+const ALIGNMENT: u64 = 8192;
+const SMALL_ALIGNMENT: u64 = 2048;
+/// Disks below this get the partition aligned to [`SMALL_ALIGNMENT`]
+const SMALL_DISK: u64 = 64 * ALIGNMENT;
+const NUM_FATS: u8 = 2;
+const FAT16_ROOT_ENTRIES: u16 = 512;
+const DIR_ENTRY_LEN: usize = 32;
+const FAT16_ROOT_SECTORS: u32 = (FAT16_ROOT_ENTRIES as usize * DIR_ENTRY_LEN / BLOCK_LEN) as u32;
+const FAT32_RESERVED: u16 = 32;
+const FAT32_ROOT_CLUSTER: u32 = 2;
+const FAT32_FS_INFO: u16 = 1;
+const FAT32_BACKUP_BOOT: u16 = 6;
+const MEDIA_FIXED: u8 = 0xF8;
+const ATTR_VOLUME_ID: u8 = 0x08;
+const MBR_TYPE_FAT16_LBA: u8 = 0x0E;
+const MBR_TYPE_FAT32_LBA: u8 = 0x0C;
+/// Blocks the primary GPT takes up, cleared so the disk isn't mistaken for GPT anymore
+const GPT_BLOCKS: u64 = 34;
+const NO_NAME: &[u8; 11] = b"NO NAME    ";
+/// Blocks of zeros written at once
+const ZERO_BLOCKS: usize = 16;
+
+/// Where everything goes, decided before anything is written
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Layout {
+    pub fat: FatType,
+    /// First block of the partition
+    pub partition_start: u64,
+    /// Blocks in the partition
+    pub sectors: u32,
+    pub sectors_per_cluster: u8,
+    pub reserved_sectors: u16,
+    /// Blocks per FAT
+    pub fat_size: u32,
+    pub clusters: u32,
+}
+
+impl Layout {
+    /// Without a `fat` type FAT16 is used if the disk fits, FAT32 otherwise
+    pub fn new(num_blocks: u64, fat: Option<FatType>) -> Result<Self, FormatError> {
+        match fat {
+            Some(fat) => Self::with_type(num_blocks, fat),
+            None => Self::with_type(num_blocks, FatType::Fat16)
+                .or_else(|_| Self::with_type(num_blocks, FatType::Fat32)),
+        }
+    }
+
+    fn with_type(num_blocks: u64, fat: FatType) -> Result<Self, FormatError> {
+        let partition_start = match num_blocks < SMALL_DISK {
+            true => SMALL_ALIGNMENT,
+            false => ALIGNMENT,
+        };
+        let sectors = num_blocks
+            .checked_sub(partition_start)
+            .ok_or(FormatError::TooSmall)?;
+        let sectors = u32::try_from(sectors).map_err(|_| FormatError::TooLarge)?;
+        let (sectors_per_cluster, reserved_sectors, root_sectors) = match fat {
+            FatType::Fat16 => (fat16_cluster(sectors)?, 1, FAT16_ROOT_SECTORS),
+            FatType::Fat32 => (fat32_cluster(sectors)?, FAT32_RESERVED, 0),
+        };
+
+        // fatgen103, "FAT Type Determination"
+        let tmp1 = sectors - (reserved_sectors as u32 + root_sectors);
+        let tmp2 = match fat {
+            FatType::Fat16 => 256 * sectors_per_cluster as u32 + NUM_FATS as u32,
+            FatType::Fat32 => (256 * sectors_per_cluster as u32 + NUM_FATS as u32) / 2,
+        };
+        let fat_size = tmp1.div_ceil(tmp2);
+        let data_sectors = tmp1 - NUM_FATS as u32 * fat_size;
+        let clusters = data_sectors / sectors_per_cluster as u32;
+        let (min, max) = match fat {
+            FatType::Fat16 => (FAT16_MIN_CLUSTERS, FAT32_MIN_CLUSTERS - 1),
+            FatType::Fat32 => (FAT32_MIN_CLUSTERS, FAT32_MAX_CLUSTERS - 2),
+        };
+        if clusters < min {
+            return Err(FormatError::TooSmall);
+        }
+        if clusters > max {
+            return Err(FormatError::TooLarge);
+        }
+        Ok(Self {
+            fat,
+            partition_start,
+            sectors,
+            sectors_per_cluster,
+            reserved_sectors,
+            fat_size,
+            clusters,
+        })
+    }
+
+    pub fn cluster_len(&self) -> usize {
+        self.sectors_per_cluster as usize * BLOCK_LEN
+    }
+
+    /// Bytes available for files
+    pub fn data_bytes(&self) -> u64 {
+        self.clusters as u64 * self.cluster_len() as u64
+    }
+
+    fn root_sectors(&self) -> u32 {
+        match self.fat {
+            FatType::Fat16 => FAT16_ROOT_SECTORS,
+            FatType::Fat32 => 0,
+        }
+    }
+
+    /// First block of the first FAT, relative to the partition
+    fn fat_start(&self) -> u64 {
+        self.reserved_sectors as u64
+    }
+
+    /// First block after the FATs, the FAT16 root directory or cluster 2
+    fn fats_end(&self) -> u64 {
+        self.fat_start() + NUM_FATS as u64 * self.fat_size as u64
+    }
+}
+
+/// Cluster size for FAT16 in sectors, fatgen103's table
+fn fat16_cluster(sectors: u32) -> Result<u8, FormatError> {
+    Ok(match sectors {
+        0..=8400 => return Err(FormatError::TooSmall),
+        8401..=32680 => 2,
+        32681..=262144 => 4,
+        262145..=524288 => 8,
+        524289..=1048576 => 16,
+        1048577..=2097152 => 32,
+        2097153..=4194304 => 64,
+        _ => return Err(FormatError::TooLarge),
+    })
+}
+
+/// Cluster size for FAT32 in sectors, fatgen103's table
+fn fat32_cluster(sectors: u32) -> Result<u8, FormatError> {
+    Ok(match sectors {
+        0..=66600 => return Err(FormatError::TooSmall),
+        66601..=532480 => 1,
+        532481..=16777216 => 8,
+        16777217..=33554432 => 16,
+        33554433..=67108864 => 32,
+        _ => 64,
+    })
+}
+
+/// The 11 bytes of a volume label, upper case and padded with spaces
+pub fn volume_label(name: &str) -> Result<[u8; 11], FormatError> {
+    let mut label = [b' '; 11];
+    if name.len() > label.len() || name.starts_with(' ') {
+        return Err(FormatError::InvalidLabel);
+    }
+    for (dst, c) in label.iter_mut().zip(name.bytes()) {
+        *dst = match c.to_ascii_uppercase() {
+            c @ (b'A'..=b'Z' | b'0'..=b'9' | b' ') => c,
+            c @ (b'!' | b'#' | b'$' | b'%' | b'&' | b'\'' | b'(' | b')' | b'-' | b'@' | b'^') => c,
+            c @ (b'_' | b'`' | b'{' | b'}' | b'~') => c,
+            _ => return Err(FormatError::InvalidLabel),
+        };
+    }
+    Ok(label)
+}
+
+/// Write an MBR with one partition spanning the disk and an empty FAT filesystem on it.
+/// Everything on the disk is lost.
+pub fn format<D: Disk>(disk: &mut D, options: &FormatOptions) -> Result<Layout, Error<D::Error>> {
+    let label = options.label.map(volume_label).transpose()?;
+    let num_blocks = disk.num_blocks().map_err(Error::Io)?;
+    let layout = Layout::new(num_blocks, options.fat)?;
+    let start = layout.partition_start;
+
+    // Old filesystem signatures first, so a failed format doesn't leave a half valid one behind.
+    // Then the reserved sectors, FATs and the root directory.
+    let system_end = start + layout.fats_end() + layout.root_sectors() as u64;
+    let root_end = match layout.fat {
+        FatType::Fat16 => system_end,
+        FatType::Fat32 => system_end + layout.sectors_per_cluster as u64,
+    };
+    for (from, to) in [
+        (start, start + 1),
+        (1, GPT_BLOCKS.min(start)),
+        (num_blocks - 1, num_blocks),
+        (start + 1, root_end),
+    ] {
+        write_zeros(disk, from, to).map_err(Error::Io)?;
+    }
+
+    let mut block = [0u8; BLOCK_LEN];
+    for fat in 0..NUM_FATS as u64 {
+        block.fill(0);
+        match layout.fat {
+            FatType::Fat16 => {
+                block[..2].copy_from_slice(&(0xFF00 | MEDIA_FIXED as u16).to_le_bytes());
+                block[2..4].copy_from_slice(&0xFFFFu16.to_le_bytes());
+            }
+            FatType::Fat32 => {
+                block[..4].copy_from_slice(&(0x0FFF_FF00 | MEDIA_FIXED as u32).to_le_bytes());
+                block[4..8].copy_from_slice(&0x0FFF_FFFFu32.to_le_bytes());
+                // End of the root directory's chain
+                block[8..12].copy_from_slice(&0x0FFF_FFFFu32.to_le_bytes());
+            }
+        }
+        let lba = start + layout.fat_start() + fat * layout.fat_size as u64;
+        disk.write_blocks(lba, &block).map_err(Error::Io)?;
+    }
+
+    if let Some(label) = label {
+        block.fill(0);
+        block[..11].copy_from_slice(&label);
+        block[11] = ATTR_VOLUME_ID;
+        disk.write_blocks(start + layout.fats_end(), &block)
+            .map_err(Error::Io)?;
+    }
+
+    if layout.fat == FatType::Fat32 {
+        let info = fs_info(&layout);
+        disk.write_blocks(start + FAT32_FS_INFO as u64, &info)
+            .map_err(Error::Io)?;
+        disk.write_blocks(start + FAT32_BACKUP_BOOT as u64 + 1, &info)
+            .map_err(Error::Io)?;
+    }
+    let boot = boot_sector(
+        &layout,
+        label.as_ref().unwrap_or(NO_NAME),
+        options.volume_id,
+    );
+    if layout.fat == FatType::Fat32 {
+        disk.write_blocks(start + FAT32_BACKUP_BOOT as u64, &boot)
+            .map_err(Error::Io)?;
+    }
+    disk.write_blocks(start, &boot).map_err(Error::Io)?;
+    disk.write_blocks(0, &mbr(&layout)).map_err(Error::Io)?;
+    Ok(layout)
+}
+
+fn write_zeros<D: Disk>(disk: &mut D, from: u64, to: u64) -> Result<(), D::Error> {
+    static ZEROS: [u8; ZERO_BLOCKS * BLOCK_LEN] = [0; ZERO_BLOCKS * BLOCK_LEN];
+    let mut lba = from;
+    while lba < to {
+        let n = (to - lba).min(ZERO_BLOCKS as u64);
+        disk.write_blocks(lba, &ZEROS[..n as usize * BLOCK_LEN])?;
+        lba += n;
+    }
+    Ok(())
+}
+
+fn mbr(layout: &Layout) -> [u8; BLOCK_LEN] {
+    let kind = match layout.fat {
+        FatType::Fat16 => MBR_TYPE_FAT16_LBA,
+        FatType::Fat32 => MBR_TYPE_FAT32_LBA,
+    };
+    let mut block = [0u8; BLOCK_LEN];
+    let entry = &mut block[446..462];
+    // CHS fields set to their maximum, LBA is what counts
+    entry[1..4].copy_from_slice(&[0xFE, 0xFF, 0xFF]);
+    entry[4] = kind;
+    entry[5..8].copy_from_slice(&[0xFE, 0xFF, 0xFF]);
+    entry[8..12].copy_from_slice(&(layout.partition_start as u32).to_le_bytes());
+    entry[12..16].copy_from_slice(&layout.sectors.to_le_bytes());
+    block[510..].copy_from_slice(&[0x55, 0xAA]);
+    block
+}
+
+fn boot_sector(layout: &Layout, label: &[u8; 11], volume_id: u32) -> [u8; BLOCK_LEN] {
+    let mut b = [0u8; BLOCK_LEN];
+    let fat32 = layout.fat == FatType::Fat32;
+    // Jump over the BPB
+    b[..3].copy_from_slice(&[0xEB, if fat32 { 0x58 } else { 0x3C }, 0x90]);
+    b[3..11].copy_from_slice(b"MSWIN4.1");
+    b[11..13].copy_from_slice(&(BLOCK_LEN as u16).to_le_bytes());
+    b[13] = layout.sectors_per_cluster;
+    b[14..16].copy_from_slice(&layout.reserved_sectors.to_le_bytes());
+    b[16] = NUM_FATS;
+    let root_entries = if fat32 { 0 } else { FAT16_ROOT_ENTRIES };
+    b[17..19].copy_from_slice(&root_entries.to_le_bytes());
+    match (fat32, u16::try_from(layout.sectors)) {
+        (false, Ok(sectors)) => b[19..21].copy_from_slice(&sectors.to_le_bytes()),
+        _ => b[32..36].copy_from_slice(&layout.sectors.to_le_bytes()),
+    }
+    b[21] = MEDIA_FIXED;
+    if !fat32 {
+        b[22..24].copy_from_slice(&(layout.fat_size as u16).to_le_bytes());
+    }
+    // Sectors per track and heads, only of interest to the BIOS
+    b[24..26].copy_from_slice(&63u16.to_le_bytes());
+    b[26..28].copy_from_slice(&255u16.to_le_bytes());
+    b[28..32].copy_from_slice(&(layout.partition_start as u32).to_le_bytes());
+
+    let ext = match fat32 {
+        true => {
+            b[36..40].copy_from_slice(&layout.fat_size.to_le_bytes());
+            b[44..48].copy_from_slice(&FAT32_ROOT_CLUSTER.to_le_bytes());
+            b[48..50].copy_from_slice(&FAT32_FS_INFO.to_le_bytes());
+            b[50..52].copy_from_slice(&FAT32_BACKUP_BOOT.to_le_bytes());
+            64
+        }
+        false => 36,
+    };
+    b[ext] = 0x80; // Drive number
+    b[ext + 2] = 0x29; // Extended boot signature
+    b[ext + 3..ext + 7].copy_from_slice(&volume_id.to_le_bytes());
+    b[ext + 7..ext + 18].copy_from_slice(label);
+    b[ext + 18..ext + 26].copy_from_slice(match fat32 {
+        true => b"FAT32   ",
+        false => b"FAT16   ",
+    });
+    b[510..].copy_from_slice(&[0x55, 0xAA]);
+    b
+}
+
+fn fs_info(layout: &Layout) -> [u8; BLOCK_LEN] {
+    let mut b = [0u8; BLOCK_LEN];
+    b[..4].copy_from_slice(&0x4161_5252u32.to_le_bytes());
+    b[484..488].copy_from_slice(&0x6141_7272u32.to_le_bytes());
+    // The root directory has the first cluster
+    b[488..492].copy_from_slice(&(layout.clusters - 1).to_le_bytes());
+    b[492..496].copy_from_slice(&(FAT32_ROOT_CLUSTER + 1).to_le_bytes());
+    b[508..].copy_from_slice(&0xAA55_0000u32.to_le_bytes());
+    b
+}
+
+/// A disk in memory, for tests and for preparing images
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MemDisk(pub Vec<u8>);
+
+impl MemDisk {
+    pub fn new(blocks: usize) -> Self {
+        Self(vec![0; blocks * BLOCK_LEN])
+    }
+}
+
+impl Disk for MemDisk {
+    type Error = ();
+
+    fn num_blocks(&self) -> Result<u64, ()> {
+        Ok((self.0.len() / BLOCK_LEN) as u64)
+    }
+
+    fn write_blocks(&mut self, lba: u64, data: &[u8]) -> Result<(), ()> {
+        let start = lba as usize * BLOCK_LEN;
+        self.0
+            .get_mut(start..start + data.len())
+            .ok_or(())?
+            .copy_from_slice(data);
+        Ok(())
+    }
+}
@@ -0,0 +1,91 @@
+//! Shell-style wildcards: `*`, `?` and `[abc]`, `[a-z]` or `[!abc]`.
+
+/// Whether `s` contains a wildcard
+pub fn is_pattern(s: &str) -> bool {
+    s.contains(['*', '?', '['])
+}
+
+/// Whether all of `name` matches `pattern`. A `[` without a closing `]` matches itself.
+pub fn matches(pattern: &str, name: &str, case_sensitive: bool) -> bool {
+    let (mut p, mut n) = (pattern, name);
+    // Pattern after the last `*` and the name it was last tried on
+    let mut star: Option<(&str, &str)> = None;
+    loop {
+        if let Some(rest) = p.strip_prefix('*') {
+            p = rest;
+            star = Some((p, n));
+            continue;
+        }
+        let Some(c) = n.chars().next() else {
+            return p.is_empty();
+        };
+        if let Some(len) = match_one(p, c, case_sensitive) {
+            p = &p[len..];
+            n = &n[c.len_utf8()..];
+            continue;
+        }
+        // Let the `*` take one more character
+        match star {
+            Some((star_p, star_n)) => {
+                let skip = star_n.chars().next().map_or(0, char::len_utf8);
+                star = Some((star_p, &star_n[skip..]));
+                (p, n) = (star_p, &star_n[skip..]);
+            }
+            None => return false,
+        }
+    }
+}
+
+/// Match `c` against the start of `pattern`, returns the length of pattern it took
+fn match_one(pattern: &str, c: char, case_sensitive: bool) -> Option<usize> {
+    let pc = pattern.chars().next()?;
+    match pc {
+        '?' => Some(1),
+        '[' => match class(&pattern[1..], c, case_sensitive) {
+            Some((true, len)) => Some(1 + len),
+            Some((false, _)) => None,
+            None => (c == '[').then_some(1),
+        },
+        _ if same(pc, c, case_sensitive) => Some(pc.len_utf8()),
+        _ => None,
+    }
+}
+
+/// Match `c` against the class after a `[`, returns whether it matched and the length of the
+/// class with its `]`. `None` if the class isn't closed.
+fn class(class: &str, c: char, case_sensitive: bool) -> Option<(bool, usize)> {
+    let (negated, body) = match class.strip_prefix(['!', '^']) {
+        Some(body) => (true, body),
+        None => (false, class),
+    };
+    // A `]` right at the start is part of the set
+    let end = match body.strip_prefix(']') {
+        Some(rest) => rest.find(']')? + 1,
+        None => body.find(']')?,
+    };
+    let candidates = match case_sensitive {
+        true => [c, c],
+        false => [c.to_ascii_lowercase(), c.to_ascii_uppercase()],
+    };
+    let mut set = body[..end].chars().peekable();
+    let mut found = false;
+    while let Some(lo) = set.next() {
+        let mut range = set.clone();
+        let hi = match (range.next(), range.next()) {
+            (Some('-'), Some(hi)) => {
+                set = range;
+                hi
+            }
+            _ => lo,
+        };
+        found |= candidates.iter().any(|c| (lo..=hi).contains(c));
+    }
+    Some((found != negated, class.len() - body.len() + end + 1))
+}
+
+fn same(a: char, b: char, case_sensitive: bool) -> bool {
+    match case_sensitive {
+        true => a == b,
+        false => a.eq_ignore_ascii_case(&b),
+    }
+}
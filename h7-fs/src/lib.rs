@@ -0,0 +1,12 @@
+#![cfg_attr(target_os = "none", no_std)]
+
+extern crate alloc;
+
+pub mod fat;
+pub mod glob;
+pub mod mv;
+pub mod norfs;
+pub mod partition;
+pub mod path;
+pub mod sd;
+pub mod sfdp;
@@ -0,0 +1,42 @@
+//! Moving a file by copying and deleting it, for filesystems without a rename of their own and
+//! for moves across devices.
+
+use crate::path::Path;
+
+/// The source and destination side of a move, which may be the same filesystem
+pub trait CopyDelete {
+    type Error;
+
+    /// Whether `path` on the source side is a directory, fails if it doesn't exist
+    fn source_is_dir(&mut self, path: Path) -> Result<bool, Self::Error>;
+
+    /// Whether anything is at `path` on the destination side
+    fn target_exists(&mut self, path: Path) -> Result<bool, Self::Error>;
+
+    /// Copy `from` to a new file `to`, fails if `to` exists
+    fn copy_new(&mut self, from: Path, to: Path) -> Result<(), Self::Error>;
+
+    fn delete_source(&mut self, path: Path) -> Result<(), Self::Error>;
+
+    fn delete_target(&mut self, path: Path) -> Result<(), Self::Error>;
+
+    /// Returned for a directory source, only files are moved this way
+    fn is_a_directory(&self) -> Self::Error;
+}
+
+/// Copy `from` to `to` and delete `from`. A missing or directory source fails before anything is
+/// written, and a failed copy is only removed again if it was this move that created `to`.
+pub fn move_file<T: CopyDelete + ?Sized>(fs: &mut T, from: Path, to: Path) -> Result<(), T::Error> {
+    if fs.source_is_dir(from)? {
+        return Err(fs.is_a_directory());
+    }
+    let existed = fs.target_exists(to)?;
+    if let Err(e) = fs.copy_new(from, to) {
+        // Don't leave a partial copy behind
+        if !existed {
+            let _ = fs.delete_target(to);
+        }
+        return Err(e);
+    }
+    fs.delete_source(from)
+}
@@ -0,0 +1,42 @@
+use embedded_storage::nor_flash::NorFlashError;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Error<E> {
+    Flash(E),
+    /// No valid filesystem was found while mounting
+    NotFormatted,
+    /// The flash geometry can't hold the filesystem
+    Unsupported,
+    NotFound,
+    AlreadyExists,
+    NotADirectory,
+    IsADirectory,
+    DirectoryNotEmpty,
+    InvalidPath,
+    NameTooLong,
+    NoSpace,
+}
+
+impl<E> From<E> for Error<E> {
+    fn from(err: E) -> Self {
+        Self::Flash(err)
+    }
+}
+
+impl<E: NorFlashError> core::fmt::Display for Error<E> {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        match self {
+            Self::Flash(e) => write!(f, "Flash: {:?} ({})", e, e.kind()),
+            Self::NotFormatted => write!(f, "Not formatted"),
+            Self::Unsupported => write!(f, "Unsupported flash geometry"),
+            Self::NotFound => write!(f, "Not Found"),
+            Self::AlreadyExists => write!(f, "Already exists"),
+            Self::NotADirectory => write!(f, "Not a directory"),
+            Self::IsADirectory => write!(f, "Is a directory"),
+            Self::DirectoryNotEmpty => write!(f, "Directory not empty"),
+            Self::InvalidPath => write!(f, "Invalid path"),
+            Self::NameTooLong => write!(f, "Name too long"),
+            Self::NoSpace => write!(f, "No space left"),
+        }
+    }
+}
@@ -0,0 +1,147 @@
+use {
+    alloc::{vec, vec::Vec},
+    embedded_storage::nor_flash::{
+        check_erase, check_read, check_write, ErrorType, NorFlash, NorFlashError,
+        NorFlashErrorKind, ReadNorFlash,
+    },
+};
+
+/// NOR flash kept in memory.
+///
+/// Like the real thing, erasing sets bits and programming can only clear them. Power loss can be
+/// simulated with [`MemFlash::cut_power_after`], the interrupted operation is left half done.
+#[derive(Clone)]
+pub struct MemFlash<const ERASE_SIZE: usize = 4096> {
+    data: Vec<u8>,
+    /// Program/erase operations left before power is lost
+    power: Option<usize>,
+    erases: u64,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MemFlashError {
+    OutOfBounds,
+    NotAligned,
+    PowerLoss,
+}
+
+impl NorFlashError for MemFlashError {
+    fn kind(&self) -> NorFlashErrorKind {
+        match self {
+            MemFlashError::OutOfBounds => NorFlashErrorKind::OutOfBounds,
+            MemFlashError::NotAligned => NorFlashErrorKind::NotAligned,
+            MemFlashError::PowerLoss => NorFlashErrorKind::Other,
+        }
+    }
+}
+
+impl From<NorFlashErrorKind> for MemFlashError {
+    fn from(kind: NorFlashErrorKind) -> Self {
+        match kind {
+            NorFlashErrorKind::NotAligned => MemFlashError::NotAligned,
+            _ => MemFlashError::OutOfBounds,
+        }
+    }
+}
+
+impl<const ERASE_SIZE: usize> MemFlash<ERASE_SIZE> {
+    /// Erased flash of `blocks` erase blocks
+    pub fn new(blocks: usize) -> Self {
+        Self {
+            data: vec![0xff; blocks * ERASE_SIZE],
+            power: None,
+            erases: 0,
+        }
+    }
+
+    /// The operation after the next `ops` program/erase operations is interrupted half way,
+    /// it and everything after it fails until [`MemFlash::restore_power`]
+    pub fn cut_power_after(&mut self, ops: usize) {
+        self.power = Some(ops + 1);
+    }
+
+    pub fn restore_power(&mut self) {
+        self.power = None;
+    }
+
+    /// Whether power was cut since the last [`MemFlash::restore_power`]
+    pub fn lost_power(&self) -> bool {
+        self.power == Some(0)
+    }
+
+    /// Number of blocks erased over the lifetime of the flash
+    pub fn erases(&self) -> u64 {
+        self.erases
+    }
+
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.data
+    }
+
+    /// `Ok(true)` if the operation can run to completion, `Ok(false)` if it is interrupted
+    fn use_power(&mut self) -> Result<bool, MemFlashError> {
+        match self.power {
+            None => Ok(true),
+            Some(0) => Err(MemFlashError::PowerLoss),
+            Some(ops) => {
+                self.power = Some(ops - 1);
+                Ok(ops > 1)
+            }
+        }
+    }
+}
+
+impl<const ERASE_SIZE: usize> ErrorType for MemFlash<ERASE_SIZE> {
+    type Error = MemFlashError;
+}
+
+impl<const ERASE_SIZE: usize> ReadNorFlash for MemFlash<ERASE_SIZE> {
+    const READ_SIZE: usize = 1;
+
+    fn read(&mut self, offset: u32, bytes: &mut [u8]) -> Result<(), Self::Error> {
+        check_read(self, offset, bytes.len())?;
+        let offset = offset as usize;
+        bytes.copy_from_slice(&self.data[offset..offset + bytes.len()]);
+        Ok(())
+    }
+
+    fn capacity(&self) -> usize {
+        self.data.len()
+    }
+}
+
+impl<const ERASE_SIZE: usize> NorFlash for MemFlash<ERASE_SIZE> {
+    const WRITE_SIZE: usize = 1;
+    const ERASE_SIZE: usize = ERASE_SIZE;
+
+    fn erase(&mut self, from: u32, to: u32) -> Result<(), Self::Error> {
+        check_erase(self, from, to)?;
+        let (from, to) = (from as usize, to as usize);
+        let complete = self.use_power()?;
+        let to = if complete { to } else { from + (to - from) / 2 };
+        self.data[from..to].fill(0xff);
+        self.erases += ((to - from) / ERASE_SIZE) as u64;
+        match complete {
+            true => Ok(()),
+            false => Err(MemFlashError::PowerLoss),
+        }
+    }
+
+    fn write(&mut self, offset: u32, bytes: &[u8]) -> Result<(), Self::Error> {
+        check_write(self, offset, bytes.len())?;
+        let complete = self.use_power()?;
+        let len = if complete {
+            bytes.len()
+        } else {
+            bytes.len() / 2
+        };
+        let offset = offset as usize;
+        for (cell, byte) in self.data[offset..offset + len].iter_mut().zip(bytes) {
+            *cell &= byte;
+        }
+        match complete {
+            true => Ok(()),
+            false => Err(MemFlashError::PowerLoss),
+        }
+    }
+}
@@ -0,0 +1,967 @@
+//! Power-fail-safe, wear-levelled filesystem for NOR flash.
+//!
+//! Every erase block starts with a [`Header`]. Metadata blocks hold a log of [`Record`]s grouped
+//! in batches that only take effect once their `Commit` record made it to the flash, data blocks
+//! hold one chunk of a file and are never modified after they are written. Writing a file writes
+//! new data blocks and then commits the records pointing at them, so an interrupted write leaves
+//! the previous content in place.
+//!
+//! The log is compacted into a snapshot under a new generation once it grows past twice the size
+//! of the snapshot, the old generation stays valid until the new one is committed. Blocks are
+//! allocated round robin, and cold data is moved out of blocks that fall too far behind in erase
+//! count.
+
+mod crc;
+mod error;
+mod mem;
+mod record;
+
+pub use {
+    error::Error,
+    mem::{MemFlash, MemFlashError},
+    record::NAME_MAX,
+};
+
+use {
+    alloc::{collections::BTreeMap, string::String, vec, vec::Vec},
+    crc::crc32,
+    embedded_storage::nor_flash::NorFlash,
+    record::{align, u32_at, Decoded, Record, RECORD_MAX},
+};
+
+const MAGIC: u32 = 0x5346_3748;
+const HEADER_LEN: usize = 24;
+const KIND_META: u8 = 1;
+const KIND_DATA: u8 = 2;
+
+const ROOT: u32 = 0;
+/// Smallest erase block that holds a header and a few records
+const MIN_BLOCK_SIZE: usize = 256;
+const MIN_BLOCKS: usize = 8;
+/// Metadata blocks the log may grow by past twice the snapshot before it is compacted
+const LOG_SLACK: usize = 4;
+/// Commits between two wear levelling checks
+const WEAR_CHECK_INTERVAL: u32 = 16;
+const EXTENT_LEN: usize = align(2 + 12 + 4);
+
+/// Erase count difference between the most worn block and the least worn block holding data
+/// before that data is moved
+pub const WEAR_SPREAD: u32 = 8;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WriteMode {
+    /// Fail if the file exists
+    Create,
+    /// Create or truncate
+    Truncate,
+    /// Create or append
+    Append,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Metadata {
+    pub name: String,
+    pub is_dir: bool,
+    pub size: u32,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Stats {
+    pub block_size: usize,
+    pub blocks: usize,
+    pub free_blocks: usize,
+    pub total_bytes: u64,
+    /// Space left for file content, blocks kept for compaction excluded
+    pub free_bytes: u64,
+    pub min_erases: u32,
+    pub max_erases: u32,
+}
+
+struct Header {
+    kind: u8,
+    seq: u32,
+    generation: u32,
+    erases: u32,
+}
+
+impl Header {
+    fn encode(&self) -> [u8; HEADER_LEN] {
+        let mut buf = [0u8; HEADER_LEN];
+        buf[0..4].copy_from_slice(&MAGIC.to_le_bytes());
+        buf[4] = self.kind;
+        buf[8..12].copy_from_slice(&self.seq.to_le_bytes());
+        buf[12..16].copy_from_slice(&self.generation.to_le_bytes());
+        buf[16..20].copy_from_slice(&self.erases.to_le_bytes());
+        let crc = crc32(&buf[..20]);
+        buf[20..24].copy_from_slice(&crc.to_le_bytes());
+        buf
+    }
+
+    fn decode(buf: &[u8; HEADER_LEN]) -> Option<Self> {
+        if u32_at(buf, 0) != MAGIC || crc32(&buf[..20]) != u32_at(buf, 20) {
+            return None;
+        }
+        Some(Self {
+            kind: buf[4],
+            seq: u32_at(buf, 8),
+            generation: u32_at(buf, 12),
+            erases: u32_at(buf, 16),
+        })
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum State {
+    Free,
+    Meta,
+    Data,
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Block {
+    state: State,
+    erases: u32,
+}
+
+struct Entry {
+    parent: u32,
+    name: String,
+    dir: bool,
+    size: u32,
+    blocks: Vec<u32>,
+}
+
+/// Filesystem mounted on a [`NorFlash`].
+///
+/// After a flash error the state kept in memory may be out of step with the flash, remount to
+/// recover.
+pub struct NorFs<F> {
+    flash: F,
+    blocks: Vec<Block>,
+    entries: BTreeMap<u32, Entry>,
+    next_id: u32,
+    seq: u32,
+    generation: u32,
+    next_generation: u32,
+    /// Metadata blocks of the current generation, oldest first
+    log: Vec<u32>,
+    /// Where the next record goes in the last block of the log
+    offset: usize,
+    /// Log length that triggers a compaction
+    compact_at: usize,
+    cursor: usize,
+    commits: u32,
+}
+
+impl<F: NorFlash> NorFs<F> {
+    /// Write an empty filesystem, erase counts left by a previous one are kept
+    pub fn format(flash: F) -> Result<Self, Error<F::Error>> {
+        let mut fs = Self::new(flash)?;
+        fs.scan()?;
+        fs.generation = fs.next_generation;
+        fs.next_generation += 1;
+        fs.offset = fs.block_size();
+        fs.write_batch(&[])?;
+        fs.compact_at = fs.log.len() * 2 + LOG_SLACK;
+        Ok(fs)
+    }
+
+    pub fn mount(flash: F) -> Result<Self, Error<F::Error>> {
+        let mut fs = Self::new(flash)?;
+        let headers = fs.scan()?;
+        let mut generations: Vec<u32> = headers
+            .iter()
+            .filter(|(_, header)| header.kind == KIND_META)
+            .map(|(_, header)| header.generation)
+            .collect();
+        generations.sort_unstable();
+        generations.dedup();
+
+        // Newest first, an interrupted compaction leaves a generation without a snapshot
+        for &generation in generations.iter().rev() {
+            let mut log: Vec<(u32, u32)> = headers
+                .iter()
+                .filter(|(_, header)| header.kind == KIND_META && header.generation == generation)
+                .map(|(block, header)| (header.seq, *block))
+                .collect();
+            log.sort_unstable();
+            let log = log.into_iter().map(|(_, block)| block).collect();
+            if fs.replay(log)? {
+                fs.generation = generation;
+                return Ok(fs);
+            }
+        }
+        Err(Error::NotFormatted)
+    }
+
+    pub fn into_inner(self) -> F {
+        self.flash
+    }
+
+    pub fn stats(&self) -> Stats {
+        let free_blocks = self.free_blocks();
+        let payload = self.payload() as u64;
+        let reserved = self.reserved(0);
+        Stats {
+            block_size: self.block_size(),
+            blocks: self.blocks.len(),
+            free_blocks,
+            total_bytes: self.blocks.len() as u64 * payload,
+            free_bytes: free_blocks.saturating_sub(reserved) as u64 * payload,
+            min_erases: self.blocks.iter().map(|b| b.erases).min().unwrap_or(0),
+            max_erases: self.blocks.iter().map(|b| b.erases).max().unwrap_or(0),
+        }
+    }
+
+    pub fn metadata(&self, path: &str) -> Result<Metadata, Error<F::Error>> {
+        let id = self.lookup(path)?;
+        Ok(self.describe(id))
+    }
+
+    pub fn exists(&self, path: &str) -> bool {
+        self.lookup(path).is_ok()
+    }
+
+    /// Call `f` with every entry of the directory at `path`, in creation order
+    pub fn read_dir(
+        &self,
+        path: &str,
+        mut f: impl FnMut(&Metadata),
+    ) -> Result<(), Error<F::Error>> {
+        let id = self.lookup(path)?;
+        if !self.is_dir(id) {
+            return Err(Error::NotADirectory);
+        }
+        for (&child, entry) in &self.entries {
+            if entry.parent == id {
+                f(&self.describe(child));
+            }
+        }
+        Ok(())
+    }
+
+    /// Read from `offset` into `buf`, returns the number of bytes read, 0 at the end of the file
+    pub fn read(
+        &mut self,
+        path: &str,
+        offset: u32,
+        buf: &mut [u8],
+    ) -> Result<usize, Error<F::Error>> {
+        let id = self.lookup(path)?;
+        let entry = match self.entries.get(&id) {
+            Some(entry) if !entry.dir => entry,
+            _ => return Err(Error::IsADirectory),
+        };
+        let (size, offset) = (entry.size as usize, offset as usize);
+        if offset >= size {
+            return Ok(0);
+        }
+        let len = buf.len().min(size - offset);
+        let (block_size, payload) = (self.block_size(), self.payload());
+        let mut done = 0;
+        while done < len {
+            let pos = offset + done;
+            let within = pos % payload;
+            let n = (len - done).min(payload - within);
+            match entry.blocks.get(pos / payload) {
+                Some(&block) => {
+                    let address = block as usize * block_size + HEADER_LEN + within;
+                    self.flash
+                        .read(address as u32, &mut buf[done..done + n])
+                        .map_err(Error::Flash)?;
+                }
+                None => buf[done..done + n].fill(0),
+            }
+            done += n;
+        }
+        Ok(len)
+    }
+
+    pub fn write(
+        &mut self,
+        path: &str,
+        mode: WriteMode,
+        data: &[u8],
+    ) -> Result<(), Error<F::Error>> {
+        let (parent, name) = self.split(path)?;
+        let mut records = Vec::new();
+        let (id, size) = match self.child(parent, name) {
+            Some(id) if self.entries[&id].dir => return Err(Error::IsADirectory),
+            Some(_) if mode == WriteMode::Create => return Err(Error::AlreadyExists),
+            Some(id) => (id, self.entries[&id].size),
+            None => {
+                records.push(Record::Entry {
+                    id: self.next_id,
+                    parent,
+                    dir: false,
+                    name: name.into(),
+                });
+                (self.next_id, 0)
+            }
+        };
+        let start = match mode {
+            WriteMode::Append => size as usize,
+            _ => 0,
+        };
+        let end = start + data.len();
+        if end > u32::MAX as usize {
+            return Err(Error::NoSpace);
+        }
+        let payload = self.payload();
+        let indices = match data.is_empty() {
+            true => 0..0,
+            false => start / payload..end.div_ceil(payload),
+        };
+        self.ensure_space(indices.len())?;
+
+        let mut allocated = Vec::new();
+        let mut result = self.write_blocks(id, start, data, indices, &mut records, &mut allocated);
+        if result.is_ok() {
+            if end as u32 != size || records.is_empty() {
+                records.push(Record::Size {
+                    id,
+                    size: end as u32,
+                });
+            }
+            result = self.commit(records);
+        }
+        if result.is_err() {
+            for block in allocated {
+                self.blocks[block as usize].state = State::Free;
+            }
+        }
+        result
+    }
+
+    /// Create an empty file, an existing one is left as is
+    pub fn touch(&mut self, path: &str) -> Result<(), Error<F::Error>> {
+        let (parent, name) = self.split(path)?;
+        if self.child(parent, name).is_some() {
+            return Ok(());
+        }
+        self.create(parent, name, false)
+    }
+
+    /// Create a directory, with `parents` missing directories above it are created too
+    pub fn create_dir(&mut self, path: &str, parents: bool) -> Result<(), Error<F::Error>> {
+        if !parents {
+            let (parent, name) = self.split(path)?;
+            if self.child(parent, name).is_some() {
+                return Err(Error::AlreadyExists);
+            }
+            return self.create(parent, name, true);
+        }
+
+        // All missing directories go in one batch
+        let (mut parent, mut id) = (ROOT, self.next_id);
+        let mut records = Vec::new();
+        for name in components(path) {
+            check_name(name)?;
+            parent = match self.child(parent, name) {
+                Some(child) if records.is_empty() && self.is_dir(child) => child,
+                Some(_) if records.is_empty() => return Err(Error::NotADirectory),
+                _ => {
+                    records.push(Record::Entry {
+                        id,
+                        parent,
+                        dir: true,
+                        name: name.into(),
+                    });
+                    id += 1;
+                    id - 1
+                }
+            };
+        }
+        if records.is_empty() {
+            return Ok(());
+        }
+        self.ensure_space(0)?;
+        self.commit(records)
+    }
+
+    pub fn remove_file(&mut self, path: &str) -> Result<(), Error<F::Error>> {
+        let id = self.lookup(path)?;
+        match self.entries.get(&id) {
+            None => Err(Error::InvalidPath),
+            Some(entry) if entry.dir => Err(Error::IsADirectory),
+            Some(_) => self.commit(vec![Record::Remove { id }]),
+        }
+    }
+
+    /// Remove an empty directory
+    pub fn remove_dir(&mut self, path: &str) -> Result<(), Error<F::Error>> {
+        let id = self.lookup(path)?;
+        match self.entries.get(&id) {
+            None => Err(Error::InvalidPath),
+            Some(entry) if !entry.dir => Err(Error::NotADirectory),
+            _ if self.entries.values().any(|e| e.parent == id) => Err(Error::DirectoryNotEmpty),
+            Some(_) => self.commit(vec![Record::Remove { id }]),
+        }
+    }
+
+    /// Move a file or directory, the destination must not exist
+    pub fn rename(&mut self, from: &str, to: &str) -> Result<(), Error<F::Error>> {
+        let id = self.lookup(from)?;
+        let dir = match self.entries.get(&id) {
+            Some(entry) => entry.dir,
+            None => return Err(Error::InvalidPath),
+        };
+        let (parent, name) = self.split(to)?;
+        if self.child(parent, name).is_some() {
+            return Err(Error::AlreadyExists);
+        }
+        // A directory can't move below itself
+        let mut ancestor = parent;
+        while ancestor != ROOT {
+            if ancestor == id {
+                return Err(Error::InvalidPath);
+            }
+            ancestor = self.entries[&ancestor].parent;
+        }
+        self.ensure_space(0)?;
+        self.commit(vec![Record::Entry {
+            id,
+            parent,
+            dir,
+            name: name.into(),
+        }])
+    }
+
+    fn new(flash: F) -> Result<Self, Error<F::Error>> {
+        let block_size = F::ERASE_SIZE;
+        let blocks = flash.capacity() / block_size;
+        if F::READ_SIZE != 1
+            || 4 % F::WRITE_SIZE != 0
+            || block_size < MIN_BLOCK_SIZE
+            || block_size % 4 != 0
+            || blocks < MIN_BLOCKS
+            || flash.capacity() > u32::MAX as usize
+        {
+            return Err(Error::Unsupported);
+        }
+        Ok(Self {
+            flash,
+            blocks: vec![
+                Block {
+                    state: State::Free,
+                    erases: 0,
+                };
+                blocks
+            ],
+            entries: BTreeMap::new(),
+            next_id: ROOT + 1,
+            seq: 0,
+            generation: 0,
+            next_generation: 0,
+            log: Vec::new(),
+            offset: block_size,
+            compact_at: LOG_SLACK,
+            cursor: 0,
+            commits: 0,
+        })
+    }
+
+    fn block_size(&self) -> usize {
+        F::ERASE_SIZE
+    }
+
+    /// File content held by a data block
+    fn payload(&self) -> usize {
+        self.block_size() - HEADER_LEN
+    }
+
+    fn address(&self, block: u32) -> u32 {
+        (block as usize * self.block_size()) as u32
+    }
+
+    fn free_blocks(&self) -> usize {
+        self.blocks
+            .iter()
+            .filter(|b| b.state == State::Free)
+            .count()
+    }
+
+    /// Read every block header, picking up erase counts and the sequence numbers in use
+    fn scan(&mut self) -> Result<Vec<(u32, Header)>, Error<F::Error>> {
+        let mut headers = Vec::new();
+        let mut newest = None;
+        for block in 0..self.blocks.len() as u32 {
+            let mut buf = [0u8; HEADER_LEN];
+            self.flash.read(self.address(block), &mut buf)?;
+            if let Some(header) = Header::decode(&buf) {
+                self.blocks[block as usize].erases = header.erases;
+                if !matches!(newest, Some((seq, _)) if header.seq < seq) {
+                    newest = Some((header.seq, block));
+                }
+                self.seq = self.seq.max(header.seq.wrapping_add(1));
+                self.next_generation = self.next_generation.max(header.generation + 1);
+                headers.push((block, header));
+            }
+        }
+        if let Some((_, block)) = newest {
+            self.cursor = (block as usize + 1) % self.blocks.len();
+        }
+        Ok(headers)
+    }
+
+    /// Rebuild the state from a log, `false` if it holds no committed batch
+    fn replay(&mut self, log: Vec<u32>) -> Result<bool, Error<F::Error>> {
+        self.entries.clear();
+        self.next_id = ROOT + 1;
+        let block_size = self.block_size();
+        let mut buf = vec![0u8; block_size];
+        let mut committed = false;
+        let mut batch: Option<Vec<Record>> = None;
+        let mut offset = block_size;
+
+        for &block in &log {
+            self.flash.read(self.address(block), &mut buf)?;
+            offset = HEADER_LEN;
+            loop {
+                match Record::decode(&buf[offset..]) {
+                    Decoded::End => break,
+                    Decoded::Invalid => {
+                        batch = None;
+                        offset = block_size;
+                        break;
+                    }
+                    Decoded::Record(record, len) => {
+                        offset += len;
+                        match record {
+                            Record::Begin => batch = Some(Vec::new()),
+                            Record::Commit => {
+                                if let Some(records) = batch.take() {
+                                    records.into_iter().for_each(|r| self.apply(r));
+                                    committed = true;
+                                }
+                            }
+                            record => {
+                                if let Some(records) = batch.as_mut() {
+                                    records.push(record);
+                                }
+                            }
+                        }
+                    }
+                }
+            }
+        }
+        if !committed {
+            return Ok(false);
+        }
+
+        // Appending after the last record needs the rest of the block to still be erased
+        if buf[offset.min(block_size)..].iter().any(|&b| b != 0xff) {
+            offset = block_size;
+        }
+        self.offset = offset;
+        for block in self.blocks.iter_mut() {
+            block.state = State::Free;
+        }
+        for &block in &log {
+            self.blocks[block as usize].state = State::Meta;
+        }
+        for entry in self.entries.values() {
+            for &block in &entry.blocks {
+                if let Some(block) = self.blocks.get_mut(block as usize) {
+                    block.state = State::Data;
+                }
+            }
+        }
+        self.log = log;
+        self.compact_at = self.blocks_for(self.snapshot_len()) * 2 + LOG_SLACK;
+        Ok(true)
+    }
+
+    fn apply(&mut self, record: Record) {
+        let payload = self.payload();
+        let blocks = &mut self.blocks;
+        let mut release = |block: u32| {
+            if let Some(block) = blocks.get_mut(block as usize) {
+                block.state = State::Free;
+            }
+        };
+        match record {
+            Record::Begin | Record::Commit => {}
+            Record::Entry {
+                id,
+                parent,
+                dir,
+                name,
+            } => {
+                match self.entries.get_mut(&id) {
+                    Some(entry) => {
+                        entry.parent = parent;
+                        entry.name = name;
+                    }
+                    None => {
+                        self.entries.insert(
+                            id,
+                            Entry {
+                                parent,
+                                name,
+                                dir,
+                                size: 0,
+                                blocks: Vec::new(),
+                            },
+                        );
+                    }
+                }
+                self.next_id = self.next_id.max(id + 1);
+            }
+            Record::Remove { id } => {
+                if let Some(entry) = self.entries.remove(&id) {
+                    entry.blocks.into_iter().for_each(release);
+                }
+            }
+            Record::Size { id, size } => {
+                if let Some(entry) = self.entries.get_mut(&id) {
+                    entry.size = size;
+                    let keep = (size as usize).div_ceil(payload);
+                    if keep < entry.blocks.len() {
+                        entry.blocks.drain(keep..).for_each(release);
+                    }
+                }
+            }
+            Record::Extent { id, index, block } => {
+                if let Some(entry) = self.entries.get_mut(&id) {
+                    match entry.blocks.get_mut(index as usize) {
+                        Some(old) => release(core::mem::replace(old, block)),
+                        None => entry.blocks.push(block),
+                    }
+                    if let Some(block) = blocks.get_mut(block as usize) {
+                        block.state = State::Data;
+                    }
+                }
+            }
+        }
+    }
+
+    /// Append `records` as one batch and apply them
+    // `is_multiple_of` is newer than the MSRV
+    #[allow(clippy::manual_is_multiple_of)]
+    fn commit(&mut self, records: Vec<Record>) -> Result<(), Error<F::Error>> {
+        if self.log.len() >= self.compact_at {
+            self.compact()?;
+        }
+        self.write_batch(&records)?;
+        records.into_iter().for_each(|r| self.apply(r));
+
+        self.commits = self.commits.wrapping_add(1);
+        if self.commits % WEAR_CHECK_INTERVAL == 0 {
+            // Best effort, the filesystem is consistent either way
+            let _ = self.level_wear();
+        }
+        Ok(())
+    }
+
+    fn write_batch(&mut self, records: &[Record]) -> Result<(), Error<F::Error>> {
+        let result = core::iter::once(&Record::Begin)
+            .chain(records)
+            .chain(core::iter::once(&Record::Commit))
+            .try_for_each(|record| self.append(record));
+        if result.is_err() {
+            // The tail of the block may hold a torn record
+            self.offset = self.block_size();
+        }
+        result
+    }
+
+    fn append(&mut self, record: &Record) -> Result<(), Error<F::Error>> {
+        let mut buf = [0xff; RECORD_MAX];
+        let len = record.encode(&mut buf);
+        if self.offset + len > self.block_size() {
+            let block = self.allocate(State::Meta)?;
+            self.log.push(block);
+            self.offset = HEADER_LEN;
+        }
+        let block = self.log[self.log.len() - 1];
+        let address = self.address(block) + self.offset as u32;
+        self.flash.write(address, &buf[..len])?;
+        self.offset += len;
+        Ok(())
+    }
+
+    /// Write the current state as the snapshot of a new generation and drop the old log
+    fn compact(&mut self) -> Result<(), Error<F::Error>> {
+        let snapshot = self.snapshot();
+        let old_log = core::mem::take(&mut self.log);
+        let (old_offset, old_generation) = (self.offset, self.generation);
+        self.generation = self.next_generation;
+        self.next_generation += 1;
+        self.offset = self.block_size();
+
+        match self.write_batch(&snapshot) {
+            Ok(()) => {
+                for block in old_log {
+                    self.blocks[block as usize].state = State::Free;
+                }
+                self.compact_at = self.log.len() * 2 + LOG_SLACK;
+                Ok(())
+            }
+            Err(e) => {
+                for block in core::mem::replace(&mut self.log, old_log) {
+                    self.blocks[block as usize].state = State::Free;
+                }
+                self.offset = old_offset;
+                self.generation = old_generation;
+                Err(e)
+            }
+        }
+    }
+
+    fn snapshot(&self) -> Vec<Record> {
+        let mut records = Vec::new();
+        for (&id, entry) in &self.entries {
+            records.push(Record::Entry {
+                id,
+                parent: entry.parent,
+                dir: entry.dir,
+                name: entry.name.clone(),
+            });
+            if entry.size > 0 {
+                records.push(Record::Size {
+                    id,
+                    size: entry.size,
+                });
+            }
+            for (index, &block) in entry.blocks.iter().enumerate() {
+                records.push(Record::Extent {
+                    id,
+                    index: index as u32,
+                    block,
+                });
+            }
+        }
+        records
+    }
+
+    /// Encoded length of [`NorFs::snapshot`], without building it
+    fn snapshot_len(&self) -> usize {
+        let size_len = Record::Size { id: 0, size: 0 }.encoded_len();
+        self.entries
+            .values()
+            .map(|entry| {
+                align(2 + 9 + entry.name.len() + 4) + size_len + entry.blocks.len() * EXTENT_LEN
+            })
+            .sum::<usize>()
+            + 2 * Record::Begin.encoded_len()
+    }
+
+    /// Metadata blocks needed for `len` bytes of records, which don't straddle blocks
+    fn blocks_for(&self, len: usize) -> usize {
+        len.div_ceil(self.block_size() - HEADER_LEN - RECORD_MAX)
+            .max(1)
+    }
+
+    /// Blocks that must stay free for a compaction after `data_blocks` more are used
+    fn reserved(&self, data_blocks: usize) -> usize {
+        let batch = data_blocks * EXTENT_LEN + 2 * RECORD_MAX;
+        self.blocks_for(self.snapshot_len() + batch) + self.blocks_for(batch) + 1
+    }
+
+    fn ensure_space(&self, data_blocks: usize) -> Result<(), Error<F::Error>> {
+        match self.free_blocks() >= data_blocks + self.reserved(data_blocks) {
+            true => Ok(()),
+            false => Err(Error::NoSpace),
+        }
+    }
+
+    /// Take the next free block round robin, erased and with its header written
+    fn allocate(&mut self, state: State) -> Result<u32, Error<F::Error>> {
+        let count = self.blocks.len();
+        let block = (0..count)
+            .map(|i| (self.cursor + i) % count)
+            .find(|&block| self.blocks[block].state == State::Free)
+            .ok_or(Error::NoSpace)?;
+        self.cursor = (block + 1) % count;
+        self.prepare(block as u32, state)?;
+        Ok(block as u32)
+    }
+
+    fn prepare(&mut self, block: u32, state: State) -> Result<(), Error<F::Error>> {
+        let address = self.address(block);
+        self.flash
+            .erase(address, address + self.block_size() as u32)?;
+        let erases = self.blocks[block as usize].erases.saturating_add(1);
+        self.blocks[block as usize].erases = erases;
+        let header = Header {
+            kind: match state {
+                State::Meta => KIND_META,
+                _ => KIND_DATA,
+            },
+            seq: self.seq,
+            generation: self.generation,
+            erases,
+        };
+        self.seq = self.seq.wrapping_add(1);
+        self.flash.write(address, &header.encode())?;
+        self.blocks[block as usize].state = state;
+        Ok(())
+    }
+
+    fn write_blocks(
+        &mut self,
+        id: u32,
+        start: usize,
+        data: &[u8],
+        indices: core::ops::Range<usize>,
+        records: &mut Vec<Record>,
+        allocated: &mut Vec<u32>,
+    ) -> Result<(), Error<F::Error>> {
+        let payload = self.payload();
+        let end = start + data.len();
+        let mut buf = vec![0xff; payload];
+        for index in indices {
+            let base = index * payload;
+            buf.fill(0xff);
+            // Keep what the block already holds before `start`
+            if base < start {
+                let old = self
+                    .entries
+                    .get(&id)
+                    .and_then(|entry| entry.blocks.get(index))
+                    .copied();
+                if let Some(old) = old {
+                    let address = self.address(old) + HEADER_LEN as u32;
+                    self.flash.read(address, &mut buf[..start - base])?;
+                }
+            }
+            let (from, to) = (base.max(start), (base + payload).min(end));
+            buf[from - base..to - base].copy_from_slice(&data[from - start..to - start]);
+
+            let block = self.allocate(State::Data)?;
+            allocated.push(block);
+            let address = self.address(block) + HEADER_LEN as u32;
+            self.flash.write(address, &buf[..align(to - base)])?;
+            records.push(Record::Extent {
+                id,
+                index: index as u32,
+                block,
+            });
+        }
+        Ok(())
+    }
+
+    /// Move the data of the least worn block into the most worn free block, so that blocks
+    /// holding data that never changes take their share of erases
+    fn level_wear(&mut self) -> Result<(), Error<F::Error>> {
+        let cold = self
+            .blocks
+            .iter()
+            .enumerate()
+            .filter(|(_, b)| b.state == State::Data)
+            .min_by_key(|(_, b)| b.erases)
+            .map(|(i, b)| (i as u32, b.erases));
+        let worn = self
+            .blocks
+            .iter()
+            .enumerate()
+            .filter(|(_, b)| b.state == State::Free)
+            .max_by_key(|(_, b)| b.erases)
+            .map(|(i, b)| (i as u32, b.erases));
+        let ((cold, cold_erases), (worn, worn_erases)) = match (cold, worn) {
+            (Some(cold), Some(worn)) => (cold, worn),
+            _ => return Ok(()),
+        };
+        if worn_erases.saturating_sub(cold_erases) < WEAR_SPREAD {
+            return Ok(());
+        }
+        self.ensure_space(1)?;
+        let owner = self.entries.iter().find_map(|(&id, entry)| {
+            let index = entry.blocks.iter().position(|&b| b == cold)?;
+            Some((id, index as u32))
+        });
+        let (id, index) = match owner {
+            Some(owner) => owner,
+            None => return Ok(()),
+        };
+
+        let mut buf = vec![0u8; self.payload()];
+        self.flash
+            .read(self.address(cold) + HEADER_LEN as u32, &mut buf)?;
+        let result = self.prepare(worn, State::Data).and_then(|()| {
+            self.flash
+                .write(self.address(worn) + HEADER_LEN as u32, &buf)?;
+            self.commit(vec![Record::Extent {
+                id,
+                index,
+                block: worn,
+            }])
+        });
+        if result.is_err() {
+            self.blocks[worn as usize].state = State::Free;
+        }
+        result
+    }
+
+    fn create(&mut self, parent: u32, name: &str, dir: bool) -> Result<(), Error<F::Error>> {
+        self.ensure_space(0)?;
+        self.commit(vec![Record::Entry {
+            id: self.next_id,
+            parent,
+            dir,
+            name: name.into(),
+        }])
+    }
+
+    fn describe(&self, id: u32) -> Metadata {
+        match self.entries.get(&id) {
+            Some(entry) => Metadata {
+                name: entry.name.clone(),
+                is_dir: entry.dir,
+                size: entry.size,
+            },
+            None => Metadata {
+                name: String::new(),
+                is_dir: true,
+                size: 0,
+            },
+        }
+    }
+
+    fn is_dir(&self, id: u32) -> bool {
+        id == ROOT || self.entries.get(&id).is_some_and(|entry| entry.dir)
+    }
+
+    fn child(&self, parent: u32, name: &str) -> Option<u32> {
+        self.entries
+            .iter()
+            .find(|(_, entry)| entry.parent == parent && entry.name == name)
+            .map(|(&id, _)| id)
+    }
+
+    fn lookup(&self, path: &str) -> Result<u32, Error<F::Error>> {
+        let mut id = ROOT;
+        for name in components(path) {
+            if !self.is_dir(id) {
+                return Err(Error::NotADirectory);
+            }
+            id = self.child(id, name).ok_or(Error::NotFound)?;
+        }
+        Ok(id)
+    }
+
+    /// Split `path` into the directory holding it and its name
+    fn split<'p>(&self, path: &'p str) -> Result<(u32, &'p str), Error<F::Error>> {
+        let path = path.trim_end_matches('/');
+        let (dir, name) = path.rsplit_once('/').unwrap_or(("", path));
+        check_name(name)?;
+        let parent = self.lookup(dir)?;
+        match self.is_dir(parent) {
+            true => Ok((parent, name)),
+            false => Err(Error::NotADirectory),
+        }
+    }
+}
+
+fn components(path: &str) -> impl Iterator<Item = &str> {
+    path.split('/').filter(|name| !name.is_empty())
+}
+
+fn check_name<E>(name: &str) -> Result<(), Error<E>> {
+    match name {
+        "" | "." | ".." => Err(Error::InvalidPath),
+        _ if name.len() > NAME_MAX => Err(Error::NameTooLong),
+        _ => Ok(()),
+    }
+}
@@ -0,0 +1,174 @@
+//! Metadata log records.
+//!
+//! `tag: u8, len: u8, payload: [u8; len], crc: u32` padded to a multiple of 4 with `0xff`.
+//! An erased tag byte marks the end of the records in a block.
+
+use {super::crc::crc32, alloc::string::String};
+
+const BEGIN: u8 = 1;
+const COMMIT: u8 = 2;
+const ENTRY: u8 = 3;
+const REMOVE: u8 = 4;
+const SIZE: u8 = 5;
+const EXTENT: u8 = 6;
+
+const ERASED: u8 = 0xff;
+const OVERHEAD: usize = 2 + 4;
+
+/// Longest name an entry can have, in bytes
+pub const NAME_MAX: usize = 64;
+
+/// Upper bound of an encoded record
+pub(super) const RECORD_MAX: usize = align(OVERHEAD + 9 + NAME_MAX);
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(super) enum Record {
+    /// Starts a batch, records up to the next `Commit` are applied together or not at all
+    Begin,
+    Commit,
+    /// Create an entry, or move/rename it if `id` exists
+    Entry {
+        id: u32,
+        parent: u32,
+        dir: bool,
+        name: String,
+    },
+    Remove {
+        id: u32,
+    },
+    /// Set the size of a file, blocks past the end are dropped
+    Size {
+        id: u32,
+        size: u32,
+    },
+    /// Block `index` of a file now lives in `block`
+    Extent {
+        id: u32,
+        index: u32,
+        block: u32,
+    },
+}
+
+pub(super) enum Decoded {
+    /// Erased flash, nothing more was written to this block
+    End,
+    /// Torn or corrupt, nothing after it in this block can be trusted
+    Invalid,
+    Record(Record, usize),
+}
+
+pub(super) const fn align(len: usize) -> usize {
+    (len + 3) & !3
+}
+
+impl Record {
+    /// Encode into `buf`, returns the padded length
+    pub fn encode(&self, buf: &mut [u8; RECORD_MAX]) -> usize {
+        buf.fill(ERASED);
+        let mut len = 0;
+        let mut put = |bytes: &[u8]| {
+            buf[2 + len..2 + len + bytes.len()].copy_from_slice(bytes);
+            len += bytes.len();
+        };
+        let tag = match self {
+            Record::Begin => BEGIN,
+            Record::Commit => COMMIT,
+            Record::Entry {
+                id,
+                parent,
+                dir,
+                name,
+            } => {
+                put(&id.to_le_bytes());
+                put(&parent.to_le_bytes());
+                put(&[*dir as u8]);
+                put(name.as_bytes());
+                ENTRY
+            }
+            Record::Remove { id } => {
+                put(&id.to_le_bytes());
+                REMOVE
+            }
+            Record::Size { id, size } => {
+                put(&id.to_le_bytes());
+                put(&size.to_le_bytes());
+                SIZE
+            }
+            Record::Extent { id, index, block } => {
+                put(&id.to_le_bytes());
+                put(&index.to_le_bytes());
+                put(&block.to_le_bytes());
+                EXTENT
+            }
+        };
+        buf[0] = tag;
+        buf[1] = len as u8;
+        let crc = crc32(&buf[..2 + len]);
+        buf[2 + len..2 + len + 4].copy_from_slice(&crc.to_le_bytes());
+        align(OVERHEAD + len)
+    }
+
+    pub fn decode(bytes: &[u8]) -> Decoded {
+        match bytes.first() {
+            None | Some(&ERASED) => return Decoded::End,
+            _ if bytes.len() < OVERHEAD => return Decoded::Invalid,
+            _ => {}
+        }
+        let len = bytes[1] as usize;
+        if bytes.len() < OVERHEAD + len {
+            return Decoded::Invalid;
+        }
+        let payload = &bytes[2..2 + len];
+        if crc32(&bytes[..2 + len]) != u32_at(bytes, 2 + len) {
+            return Decoded::Invalid;
+        }
+
+        let record = match (bytes[0], len) {
+            (BEGIN, 0) => Record::Begin,
+            (COMMIT, 0) => Record::Commit,
+            (ENTRY, 9..) if len - 9 <= NAME_MAX => match core::str::from_utf8(&payload[9..]) {
+                Ok(name) => Record::Entry {
+                    id: u32_at(payload, 0),
+                    parent: u32_at(payload, 4),
+                    dir: payload[8] != 0,
+                    name: name.into(),
+                },
+                Err(_) => return Decoded::Invalid,
+            },
+            (REMOVE, 4) => Record::Remove {
+                id: u32_at(payload, 0),
+            },
+            (SIZE, 8) => Record::Size {
+                id: u32_at(payload, 0),
+                size: u32_at(payload, 4),
+            },
+            (EXTENT, 12) => Record::Extent {
+                id: u32_at(payload, 0),
+                index: u32_at(payload, 4),
+                block: u32_at(payload, 8),
+            },
+            _ => return Decoded::Invalid,
+        };
+        Decoded::Record(record, align(OVERHEAD + len))
+    }
+
+    /// Length once encoded, padding included
+    pub fn encoded_len(&self) -> usize {
+        align(
+            OVERHEAD
+                + match self {
+                    Record::Begin | Record::Commit => 0,
+                    Record::Entry { name, .. } => 9 + name.len(),
+                    Record::Remove { .. } => 4,
+                    Record::Size { .. } => 8,
+                    Record::Extent { .. } => 12,
+                },
+        )
+    }
+}
+
+pub(super) fn u32_at(bytes: &[u8], offset: usize) -> u32 {
+    let mut buf = [0u8; 4];
+    buf.copy_from_slice(&bytes[offset..offset + 4]);
+    u32::from_le_bytes(buf)
+}
@@ -0,0 +1,332 @@
+//! MBR and GPT partition tables.
+//!
+//! Only primary MBR partitions are listed, logical partitions inside an extended one are not
+//! followed. The GPT header's checksum is verified, the backup header at the end of the disk is
+//! not consulted.
+
+use alloc::{string::String, vec::Vec};
+
+pub const BLOCK_LEN: usize = 512;
+
+const MBR_SIGNATURE: usize = 510;
+const MBR_ENTRIES: usize = 446;
+const MBR_ENTRY_LEN: usize = 16;
+const MBR_STATUS_BOOTABLE: u8 = 0x80;
+const MBR_TYPE_GPT_PROTECTIVE: u8 = 0xEE;
+/// Type the MBR from [`single_partition_mbr`] gives its partition
+const MBR_TYPE_FAT32_LBA: u8 = 0x0C;
+
+const GPT_HEADER_LBA: u64 = 1;
+const GPT_SIGNATURE: &[u8; 8] = b"EFI PART";
+const GPT_HEADER_SIZE: usize = 12;
+const GPT_HEADER_CRC: usize = 16;
+const GPT_ENTRIES_LBA: usize = 72;
+const GPT_ENTRY_COUNT: usize = 80;
+const GPT_ENTRY_SIZE: usize = 84;
+const GPT_ENTRY_FIRST_LBA: usize = 32;
+const GPT_ENTRY_LAST_LBA: usize = 40;
+const GPT_ENTRY_ATTRIBUTES: usize = 48;
+const GPT_ENTRY_NAME: usize = 56;
+const GPT_ENTRY_NAME_LEN: usize = 72;
+/// Legacy BIOS bootable attribute
+const GPT_ATTR_BOOTABLE: u64 = 1 << 2;
+/// Entries beyond this are not looked at, 128 is what every tool creates
+const GPT_MAX_ENTRIES: u32 = 128;
+
+/// A GPT GUID as stored on disk, the first three fields little endian
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Guid(pub [u8; 16]);
+
+impl Guid {
+    pub const EFI_SYSTEM: Self = Self::new(0xC12A7328, 0xF81F, 0x11D2, 0xBA4B_00A0C93EC93B);
+    pub const BASIC_DATA: Self = Self::new(0xEBD0A0A2, 0xB9E5, 0x4433, 0x87C0_68B6B72699C7);
+    pub const LINUX: Self = Self::new(0x0FC63DAF, 0x8483, 0x4772, 0x8E79_3D69D8477DE4);
+
+    /// From the fields as written in text, `a-b-c-d` with `d` being the last 8 bytes
+    pub const fn new(a: u32, b: u16, c: u16, d: u64) -> Self {
+        let (a, b, c, d) = (
+            a.to_le_bytes(),
+            b.to_le_bytes(),
+            c.to_le_bytes(),
+            d.to_be_bytes(),
+        );
+        Self([
+            a[0], a[1], a[2], a[3], b[0], b[1], c[0], c[1], d[0], d[1], d[2], d[3], d[4], d[5],
+            d[6], d[7],
+        ])
+    }
+
+    fn is_nil(&self) -> bool {
+        self.0.iter().all(|&b| b == 0)
+    }
+}
+
+impl core::fmt::Display for Guid {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        let g = &self.0;
+        write!(
+            f,
+            "{:08X}-{:04X}-{:04X}-{:02X}{:02X}-",
+            le_u32(g, 0),
+            u16::from_le_bytes([g[4], g[5]]),
+            u16::from_le_bytes([g[6], g[7]]),
+            g[8],
+            g[9]
+        )?;
+        g[10..].iter().try_for_each(|b| write!(f, "{b:02X}"))
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Scheme {
+    Mbr,
+    Gpt,
+}
+
+impl core::fmt::Display for Scheme {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        f.pad(match self {
+            Self::Mbr => "MBR",
+            Self::Gpt => "GPT",
+        })
+    }
+}
+
+/// Partition type, the MBR type byte or the GPT type GUID
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Kind {
+    Mbr(u8),
+    Gpt(Guid),
+}
+
+impl Kind {
+    /// What the type usually holds
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::Mbr(0x01) => "FAT12",
+            Self::Mbr(0x04 | 0x06 | 0x0E) => "FAT16",
+            Self::Mbr(0x0B | 0x0C) => "FAT32",
+            Self::Mbr(0x05 | 0x0F) => "Extended",
+            Self::Mbr(0x07) => "NTFS/exFAT",
+            Self::Mbr(0x82) => "Linux swap",
+            Self::Mbr(0x83) => "Linux",
+            Self::Mbr(0xEF) => "EFI System",
+            Self::Gpt(Guid::EFI_SYSTEM) => "EFI System",
+            Self::Gpt(Guid::BASIC_DATA) => "Basic data",
+            Self::Gpt(Guid::LINUX) => "Linux",
+            _ => "Unknown",
+        }
+    }
+
+    /// Whether the type is used for FAT, GPT basic data partitions may hold NTFS or exFAT as well
+    pub fn is_fat(&self) -> bool {
+        matches!(
+            self,
+            Self::Mbr(0x01 | 0x04 | 0x06 | 0x0E | 0x0B | 0x0C | 0xEF)
+                | Self::Gpt(Guid::EFI_SYSTEM | Guid::BASIC_DATA)
+        )
+    }
+}
+
+impl core::fmt::Display for Kind {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        match (self.name(), self) {
+            ("Unknown", Self::Mbr(t)) => write!(f, "Unknown (0x{t:02X})"),
+            ("Unknown", Self::Gpt(guid)) => write!(f, "Unknown ({guid})"),
+            (name, _) => f.write_str(name),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Partition {
+    /// 1-based, the MBR slot or the GPT entry index. Empty slots keep their number.
+    pub number: usize,
+    pub kind: Kind,
+    pub first_lba: u64,
+    pub sectors: u64,
+    pub bootable: bool,
+    /// GPT partition name, empty on MBR disks
+    pub name: String,
+}
+
+impl Partition {
+    pub fn bytes(&self) -> u64 {
+        self.sectors.saturating_mul(BLOCK_LEN as u64)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PartitionTable {
+    pub scheme: Scheme,
+    pub partitions: Vec<Partition>,
+}
+
+impl PartitionTable {
+    pub fn get(&self, number: usize) -> Option<&Partition> {
+        self.partitions.iter().find(|p| p.number == number)
+    }
+
+    /// The partition a plain `sdcard:` refers to
+    pub fn first_fat(&self) -> Option<&Partition> {
+        self.partitions.iter().find(|p| p.kind.is_fat())
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Error<E> {
+    Io(E),
+    /// Protective MBR without a valid GPT header
+    InvalidGpt,
+}
+
+impl<E: core::fmt::Debug> core::fmt::Display for Error<E> {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        match self {
+            Self::Io(e) => write!(f, "I/O error: {e:?}"),
+            Self::InvalidGpt => write!(f, "Invalid GPT header"),
+        }
+    }
+}
+
+/// Read the partition table through `read_block`, which fills in the block at the given LBA.
+///
+/// `None` when block 0 is no MBR, e.g. when the card was formatted without partitions.
+pub fn read<E>(
+    mut read_block: impl FnMut(u64, &mut [u8; BLOCK_LEN]) -> Result<(), E>,
+) -> Result<Option<PartitionTable>, Error<E>> {
+    let mut block = [0u8; BLOCK_LEN];
+    read_block(0, &mut block).map_err(Error::Io)?;
+    let partitions = match parse_mbr(&block) {
+        Some(partitions) => partitions,
+        None => return Ok(None),
+    };
+    match partitions
+        .iter()
+        .any(|p| p.kind == Kind::Mbr(MBR_TYPE_GPT_PROTECTIVE))
+    {
+        true => read_gpt(read_block, &mut block).map(Some),
+        false => Ok(Some(PartitionTable {
+            scheme: Scheme::Mbr,
+            partitions,
+        })),
+    }
+}
+
+fn parse_mbr(block: &[u8; BLOCK_LEN]) -> Option<Vec<Partition>> {
+    if block[MBR_SIGNATURE..] != [0x55, 0xAA] {
+        return None;
+    }
+    let mut partitions = Vec::new();
+    for (i, entry) in block[MBR_ENTRIES..MBR_SIGNATURE]
+        .chunks_exact(MBR_ENTRY_LEN)
+        .enumerate()
+    {
+        // A FAT boot sector has code here, which rarely passes for four entries
+        if entry[0] & !MBR_STATUS_BOOTABLE != 0 {
+            return None;
+        }
+        let (kind, first_lba, sectors) = (entry[4], le_u32(entry, 8), le_u32(entry, 12));
+        if kind != 0 && sectors != 0 {
+            partitions.push(Partition {
+                number: i + 1,
+                kind: Kind::Mbr(kind),
+                first_lba: first_lba as u64,
+                sectors: sectors as u64,
+                bootable: entry[0] == MBR_STATUS_BOOTABLE,
+                name: String::new(),
+            });
+        }
+    }
+    match partitions.is_empty() {
+        true => None,
+        false => Some(partitions),
+    }
+}
+
+fn read_gpt<E>(
+    mut read_block: impl FnMut(u64, &mut [u8; BLOCK_LEN]) -> Result<(), E>,
+    block: &mut [u8; BLOCK_LEN],
+) -> Result<PartitionTable, Error<E>> {
+    read_block(GPT_HEADER_LBA, block).map_err(Error::Io)?;
+    let header_size = le_u32(block, GPT_HEADER_SIZE) as usize;
+    if &block[..8] != GPT_SIGNATURE || !(92..=BLOCK_LEN).contains(&header_size) {
+        return Err(Error::InvalidGpt);
+    }
+    let crc = le_u32(block, GPT_HEADER_CRC);
+    block[GPT_HEADER_CRC..GPT_HEADER_CRC + 4].fill(0);
+    if crc32(&block[..header_size]) != crc {
+        return Err(Error::InvalidGpt);
+    }
+    let mut lba = le_u64(block, GPT_ENTRIES_LBA);
+    let count = le_u32(block, GPT_ENTRY_COUNT).min(GPT_MAX_ENTRIES) as usize;
+    let entry_size = le_u32(block, GPT_ENTRY_SIZE) as usize;
+    if !(128..=BLOCK_LEN).contains(&entry_size) || !entry_size.is_power_of_two() {
+        return Err(Error::InvalidGpt);
+    }
+
+    let mut partitions = Vec::new();
+    let mut number = 0;
+    while number < count {
+        read_block(lba, block).map_err(Error::Io)?;
+        lba += 1;
+        for entry in block.chunks_exact(entry_size).take(count - number) {
+            number += 1;
+            let kind = Guid(entry[..16].try_into().unwrap());
+            if kind.is_nil() {
+                continue;
+            }
+            let first_lba = le_u64(entry, GPT_ENTRY_FIRST_LBA);
+            let last_lba = le_u64(entry, GPT_ENTRY_LAST_LBA);
+            let name = &entry[GPT_ENTRY_NAME..GPT_ENTRY_NAME + GPT_ENTRY_NAME_LEN];
+            let name = name
+                .chunks_exact(2)
+                .map(|c| u16::from_le_bytes([c[0], c[1]]))
+                .take_while(|&c| c != 0);
+            partitions.push(Partition {
+                number,
+                kind: Kind::Gpt(kind),
+                first_lba,
+                sectors: last_lba.saturating_add(1).saturating_sub(first_lba),
+                bootable: le_u64(entry, GPT_ENTRY_ATTRIBUTES) & GPT_ATTR_BOOTABLE != 0,
+                name: char::decode_utf16(name)
+                    .map(|c| c.unwrap_or(char::REPLACEMENT_CHARACTER))
+                    .collect(),
+            });
+        }
+    }
+    Ok(PartitionTable {
+        scheme: Scheme::Gpt,
+        partitions,
+    })
+}
+
+/// An MBR with a single FAT32 partition. Presented in place of the real block 0, it lets a FAT
+/// driver that only understands MBR disks open any partition, GPT ones included.
+pub fn single_partition_mbr(first_lba: u32, sectors: u32) -> [u8; BLOCK_LEN] {
+    let mut block = [0u8; BLOCK_LEN];
+    let entry = &mut block[MBR_ENTRIES..MBR_ENTRIES + MBR_ENTRY_LEN];
+    entry[4] = MBR_TYPE_FAT32_LBA;
+    entry[8..12].copy_from_slice(&first_lba.to_le_bytes());
+    entry[12..16].copy_from_slice(&sectors.to_le_bytes());
+    block[MBR_SIGNATURE..].copy_from_slice(&[0x55, 0xAA]);
+    block
+}
+
+/// CRC-32 (IEEE) as used by GPT
+pub fn crc32(data: &[u8]) -> u32 {
+    !data.iter().fold(!0u32, |crc, &b| {
+        (0..8).fold(crc ^ b as u32, |crc, _| match crc & 1 {
+            1 => (crc >> 1) ^ 0xEDB8_8320,
+            _ => crc >> 1,
+        })
+    })
+}
+
+fn le_u32(data: &[u8], offset: usize) -> u32 {
+    u32::from_le_bytes(data[offset..offset + 4].try_into().unwrap())
+}
+
+fn le_u64(data: &[u8], offset: usize) -> u64 {
+    u64::from_le_bytes(data[offset..offset + 8].try_into().unwrap())
+}
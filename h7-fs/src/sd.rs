@@ -0,0 +1,113 @@
+//! Decoding of SD card register fields (SD Physical Layer Simplified Specification).
+
+/// Card capacity class, from the CSD structure version and the size
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CapacityClass {
+    /// Standard capacity, up to 2 GB
+    Sdsc,
+    /// High capacity, up to 32 GB
+    Sdhc,
+    /// Extended capacity, up to 2 TB
+    Sdxc,
+    /// Ultra capacity, up to 128 TB
+    Sduc,
+}
+
+impl CapacityClass {
+    /// `csd_version` is the CSD_STRUCTURE field, 0 for version 1.0
+    pub fn new(csd_version: u8, bytes: u64) -> Self {
+        match csd_version {
+            0 => Self::Sdsc,
+            1 if bytes <= 32 * 1000 * 1000 * 1000 => Self::Sdhc,
+            1 => Self::Sdxc,
+            _ => Self::Sduc,
+        }
+    }
+}
+
+impl core::fmt::Display for CapacityClass {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        f.write_str(match self {
+            Self::Sdsc => "SDSC",
+            Self::Sdhc => "SDHC",
+            Self::Sdxc => "SDXC",
+            Self::Sduc => "SDUC",
+        })
+    }
+}
+
+/// Manufacturer of a card by the CID's MID, as far as it is publicly known.
+/// Fake cards often carry an unknown or mismatching MID.
+pub fn manufacturer(id: u8) -> Option<&'static str> {
+    Some(match id {
+        0x01 => "Panasonic",
+        0x02 => "Toshiba",
+        0x03 => "SanDisk",
+        0x1b => "Samsung",
+        0x1d => "ADATA",
+        0x27 => "Phison",
+        0x28 => "Lexar",
+        0x31 => "Silicon Power",
+        0x41 => "Kingston",
+        0x74 => "Transcend",
+        0x76 => "Patriot",
+        0x82 => "Sony",
+        0x9c => "Angelbird",
+        _ => return None,
+    })
+}
+
+/// Maximum transfer rate in kbit/s from the CSD's TRAN_SPEED
+pub fn transfer_rate_kbit(tran_speed: u8) -> u32 {
+    // Tenths of the time value
+    const VALUES: [u32; 16] = [
+        0, 10, 12, 13, 15, 20, 25, 30, 35, 40, 45, 50, 55, 60, 70, 80,
+    ];
+    // Tenths of the unit, in kbit/s
+    const UNITS: [u32; 4] = [10, 100, 1000, 10000];
+    let value = VALUES[(tran_speed >> 3) as usize & 0xf];
+    match UNITS.get(tran_speed as usize & 0x7) {
+        Some(unit) => value * unit,
+        None => 0,
+    }
+}
+
+/// Physical layer version from the SCR's SD_SPEC, SD_SPEC3, SD_SPEC4 and SD_SPECX
+pub fn spec_version(sd_spec: u8, spec3: bool, spec4: bool, specx: u8) -> &'static str {
+    match (sd_spec, spec3, spec4, specx) {
+        (0, ..) => "1.0",
+        (1, ..) => "1.1",
+        (2, false, ..) => "2.0",
+        (2, true, _, 1) => "5.x",
+        (2, true, _, 2) => "6.x",
+        (2, true, _, 3) => "7.x",
+        (2, true, _, 4) => "8.x",
+        (2, true, _, 5) => "9.x",
+        (2, true, true, _) => "4.x",
+        (2, true, false, _) => "3.0",
+        _ => "unknown",
+    }
+}
+
+/// Speed class from the SD status' SPEED_CLASS, `None` for reserved codes
+pub fn speed_class(code: u8) -> Option<u8> {
+    match code {
+        0..=3 => Some(code * 2),
+        4 => Some(10),
+        _ => None,
+    }
+}
+
+/// Allocation unit size in bytes from the SD status' AU_SIZE, `None` if not defined
+pub fn au_size(code: u8) -> Option<u32> {
+    const MIB: u32 = 1024 * 1024;
+    match code {
+        1..=10 => Some((16 * 1024) << (code - 1)),
+        11 => Some(12 * MIB),
+        12 => Some(16 * MIB),
+        13 => Some(24 * MIB),
+        14 => Some(32 * MIB),
+        15 => Some(64 * MIB),
+        _ => None,
+    }
+}
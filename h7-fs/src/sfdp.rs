@@ -0,0 +1,249 @@
+//! JEDEC Serial Flash Discoverable Parameters (JESD216).
+//!
+//! The SFDP space of a flash part starts with a header and a list of parameter headers, each
+//! pointing at a table. The Basic Flash Parameter Table (BFPT) every part has describes the
+//! density, erase types and fast read modes, which is what [`read`] decodes.
+
+use alloc::vec::Vec;
+
+const SIGNATURE: u32 = 0x5044_4653;
+const BFPT_ID: u16 = 0xff00;
+/// DWORDs of the JESD216 BFPT, later revisions only append to it
+const BFPT_MIN_DWORDS: usize = 9;
+/// The highest DWORD decoded, page size
+const BFPT_MAX_DWORDS: usize = 11;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Error<E> {
+    Read(E),
+    /// The SFDP signature is missing, the part doesn't support SFDP
+    NoSignature,
+    NoBasicTable,
+    /// A table is shorter than its revision requires
+    Truncated,
+    /// The basic table has sizes no part can have, it is corrupt
+    Invalid,
+}
+
+impl<E> From<E> for Error<E> {
+    fn from(err: E) -> Self {
+        Self::Read(err)
+    }
+}
+
+impl<E: core::fmt::Debug> core::fmt::Display for Error<E> {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        match self {
+            Self::Read(e) => write!(f, "Read failed: {e:?}"),
+            Self::NoSignature => write!(f, "No SFDP signature"),
+            Self::NoBasicTable => write!(f, "No basic flash parameter table"),
+            Self::Truncated => write!(f, "Parameter table truncated"),
+            Self::Invalid => write!(f, "Parameter table invalid"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ParameterHeader {
+    pub id: u16,
+    pub major: u8,
+    pub minor: u8,
+    pub dwords: u8,
+    /// Byte address of the table in the SFDP space
+    pub pointer: u32,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AddressBytes {
+    Three,
+    ThreeOrFour,
+    Four,
+}
+
+/// Lines used for instruction, address and data, e.g. `1-4-4`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ReadMode {
+    Read112,
+    Read122,
+    Read114,
+    Read144,
+    Read222,
+    Read444,
+}
+
+impl core::fmt::Display for ReadMode {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        f.write_str(match self {
+            Self::Read112 => "1-1-2",
+            Self::Read122 => "1-2-2",
+            Self::Read114 => "1-1-4",
+            Self::Read144 => "1-4-4",
+            Self::Read222 => "2-2-2",
+            Self::Read444 => "4-4-4",
+        })
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FastRead {
+    pub mode: ReadMode,
+    pub opcode: u8,
+    pub dummy_cycles: u8,
+    /// Mode bit clocks, sent between address and dummy cycles
+    pub mode_clocks: u8,
+}
+
+impl FastRead {
+    /// Clocks between address and data, mode bits included
+    pub fn wait_cycles(&self) -> u8 {
+        self.dummy_cycles + self.mode_clocks
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct EraseType {
+    pub size: u32,
+    pub opcode: u8,
+}
+
+/// What the basic flash parameter table says about a part
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FlashParameters {
+    pub major: u8,
+    pub minor: u8,
+    pub capacity: u64,
+    /// Program page size, 256 when the table predates JESD216B
+    pub page_size: u32,
+    pub address_bytes: AddressBytes,
+    /// Erase types, smallest first
+    pub erase_types: Vec<EraseType>,
+    pub fast_reads: Vec<FastRead>,
+}
+
+impl FlashParameters {
+    pub fn fast_read(&self, mode: ReadMode) -> Option<&FastRead> {
+        self.fast_reads.iter().find(|read| read.mode == mode)
+    }
+
+    /// Smallest erase type that is at least `size` bytes
+    pub fn erase_type(&self, size: u32) -> Option<&EraseType> {
+        self.erase_types.iter().find(|erase| erase.size >= size)
+    }
+}
+
+/// Read the SFDP header and parameter headers through `read`, which reads SFDP space at an
+/// address
+pub fn read_headers<E>(
+    read: &mut impl FnMut(u32, &mut [u8]) -> Result<(), E>,
+) -> Result<Vec<ParameterHeader>, Error<E>> {
+    let mut header = [0u8; 8];
+    read(0, &mut header)?;
+    if dword(&header, 0) != SIGNATURE {
+        return Err(Error::NoSignature);
+    }
+    // The count is stored minus one
+    let count = header[6] as usize + 1;
+    let mut headers = Vec::with_capacity(count);
+    for i in 0..count {
+        let mut raw = [0u8; 8];
+        read(8 + 8 * i as u32, &mut raw)?;
+        headers.push(ParameterHeader {
+            id: u16::from_le_bytes([raw[0], raw[7]]),
+            minor: raw[1],
+            major: raw[2],
+            dwords: raw[3],
+            pointer: u32::from_le_bytes([raw[4], raw[5], raw[6], 0]),
+        });
+    }
+    Ok(headers)
+}
+
+/// Find and decode the basic flash parameter table
+pub fn read<E>(
+    mut read: impl FnMut(u32, &mut [u8]) -> Result<(), E>,
+) -> Result<FlashParameters, Error<E>> {
+    let headers = read_headers(&mut read)?;
+    // Parts may list several revisions of the table, take the newest one
+    let header = headers
+        .iter()
+        .filter(|header| header.id == BFPT_ID && header.major == 1)
+        .max_by_key(|header| header.minor)
+        .ok_or(Error::NoBasicTable)?;
+    let dwords = header.dwords as usize;
+    if dwords < BFPT_MIN_DWORDS {
+        return Err(Error::Truncated);
+    }
+    let mut table = [0u8; BFPT_MAX_DWORDS * 4];
+    let len = dwords.min(BFPT_MAX_DWORDS) * 4;
+    read(header.pointer, &mut table[..len])?;
+    parse_basic_table(header.major, header.minor, &table[..len]).ok_or(Error::Invalid)
+}
+
+/// Decode a basic flash parameter table of at least 9 DWORDs, `None` if the sizes in it are out
+/// of range
+pub fn parse_basic_table(major: u8, minor: u8, table: &[u8]) -> Option<FlashParameters> {
+    let dw = |n: usize| dword(table, (n - 1) * 4);
+
+    let density = dw(2);
+    let bits = match density & (1 << 31) {
+        0 => density as u64 + 1,
+        _ => 1u64.checked_shl(density & 0x7fff_ffff)?,
+    };
+
+    let address_bytes = match (dw(1) >> 17) & 0b11 {
+        0b00 => AddressBytes::Three,
+        0b01 => AddressBytes::ThreeOrFour,
+        _ => AddressBytes::Four,
+    };
+
+    let mut fast_reads = Vec::new();
+    let mut fast_read = |supported: bool, mode, bits: u32| {
+        let settings = bits & 0xff;
+        if supported {
+            fast_reads.push(FastRead {
+                mode,
+                opcode: (bits >> 8) as u8,
+                dummy_cycles: (settings & 0x1f) as u8,
+                mode_clocks: (settings >> 5) as u8,
+            });
+        }
+    };
+    fast_read(dw(1) & (1 << 16) != 0, ReadMode::Read112, dw(4) & 0xffff);
+    fast_read(dw(1) & (1 << 20) != 0, ReadMode::Read122, dw(4) >> 16);
+    fast_read(dw(1) & (1 << 22) != 0, ReadMode::Read114, dw(3) >> 16);
+    fast_read(dw(1) & (1 << 21) != 0, ReadMode::Read144, dw(3) & 0xffff);
+    fast_read(dw(5) & (1 << 0) != 0, ReadMode::Read222, dw(6) >> 16);
+    fast_read(dw(5) & (1 << 4) != 0, ReadMode::Read444, dw(7) >> 16);
+
+    let mut erase_types = Vec::new();
+    for bits in [dw(8), dw(8) >> 16, dw(9), dw(9) >> 16] {
+        if bits & 0xff != 0 {
+            erase_types.push(EraseType {
+                size: 1u32.checked_shl(bits & 0xff)?,
+                opcode: (bits >> 8) as u8,
+            });
+        }
+    }
+    erase_types.sort_by_key(|erase| erase.size);
+
+    let page_size = match table.len() >= 11 * 4 {
+        true => 1 << ((dw(11) >> 4) & 0xf),
+        false => 256,
+    };
+
+    Some(FlashParameters {
+        major,
+        minor,
+        capacity: bits / 8,
+        page_size,
+        address_bytes,
+        erase_types,
+        fast_reads,
+    })
+}
+
+fn dword(bytes: &[u8], offset: usize) -> u32 {
+    let mut buf = [0u8; 4];
+    buf.copy_from_slice(&bytes[offset..offset + 4]);
+    u32::from_le_bytes(buf)
+}
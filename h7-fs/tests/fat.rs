@@ -0,0 +1,226 @@
+use h7_fs::{
+    fat::{format, volume_label, FatType, FormatError, FormatOptions, Layout, MemDisk},
+    partition::{self, Kind, BLOCK_LEN},
+};
+
+const MIB: usize = 1024 * 1024 / BLOCK_LEN;
+
+fn u16_at(data: &[u8], offset: usize) -> u32 {
+    u16::from_le_bytes([data[offset], data[offset + 1]]) as u32
+}
+
+fn u32_at(data: &[u8], offset: usize) -> u32 {
+    u32::from_le_bytes(data[offset..offset + 4].try_into().unwrap())
+}
+
+fn block(disk: &MemDisk, lba: u64) -> &[u8] {
+    &disk.0[lba as usize * BLOCK_LEN..][..BLOCK_LEN]
+}
+
+/// Cluster count from the BPB, the way a FAT driver determines the FAT type
+fn clusters_from_bpb(bpb: &[u8]) -> u32 {
+    let fat_size = match u16_at(bpb, 22) {
+        0 => u32_at(bpb, 36),
+        n => n,
+    };
+    let total = match u16_at(bpb, 19) {
+        0 => u32_at(bpb, 32),
+        n => n,
+    };
+    let root_sectors = (u16_at(bpb, 17) * 32).div_ceil(BLOCK_LEN as u32);
+    let data = total - u16_at(bpb, 14) - bpb[16] as u32 * fat_size - root_sectors;
+    data / bpb[13] as u32
+}
+
+fn format_image(blocks: usize, options: &FormatOptions) -> (MemDisk, Layout) {
+    let mut disk = MemDisk::new(blocks);
+    let layout = format(&mut disk, options).unwrap();
+    (disk, layout)
+}
+
+#[test]
+fn layouts() {
+    let layout = Layout::new(8 * MIB as u64, None).unwrap();
+    assert_eq!(layout.fat, FatType::Fat16);
+    assert_eq!(layout.partition_start, 2048);
+    assert_eq!(layout.cluster_len(), 1024);
+
+    let layout = Layout::new(64 * MIB as u64, Some(FatType::Fat32)).unwrap();
+    assert_eq!(layout.fat, FatType::Fat32);
+    assert_eq!(layout.partition_start, 2048);
+    assert_eq!(layout.cluster_len(), 512);
+
+    // A 4 GB card
+    let layout = Layout::new(7_774_208, None).unwrap();
+    assert_eq!(layout.fat, FatType::Fat32);
+    assert_eq!(layout.partition_start, 8192);
+    assert_eq!(layout.cluster_len(), 4096);
+    assert!(layout.data_bytes() < 7_774_208 * BLOCK_LEN as u64);
+
+    assert_eq!(Layout::new(MIB as u64, None), Err(FormatError::TooSmall));
+    assert_eq!(
+        Layout::new(16 * MIB as u64, Some(FatType::Fat32)),
+        Err(FormatError::TooSmall)
+    );
+    assert_eq!(
+        Layout::new(4096 * MIB as u64, Some(FatType::Fat16)),
+        Err(FormatError::TooLarge)
+    );
+}
+
+#[test]
+fn labels() {
+    assert_eq!(volume_label("h7 data"), Ok(*b"H7 DATA    "));
+    assert_eq!(volume_label("BOOT_1"), Ok(*b"BOOT_1     "));
+    assert_eq!(
+        volume_label("TOO LONG NAME"),
+        Err(FormatError::InvalidLabel)
+    );
+    assert_eq!(volume_label("A.B"), Err(FormatError::InvalidLabel));
+    assert_eq!(volume_label(" LEADING"), Err(FormatError::InvalidLabel));
+
+    let mut disk = MemDisk::new(8 * MIB);
+    let options = FormatOptions {
+        label: Some("bad/label"),
+        ..Default::default()
+    };
+    assert_eq!(
+        format(&mut disk, &options),
+        Err(h7_fs::fat::Error::Format(FormatError::InvalidLabel))
+    );
+    // Nothing was written
+    assert!(disk.0.iter().all(|&b| b == 0));
+}
+
+#[test]
+fn fat16_image() {
+    let options = FormatOptions {
+        label: Some("h7"),
+        volume_id: 0x1234_5678,
+        ..Default::default()
+    };
+    let (disk, layout) = format_image(8 * MIB, &options);
+
+    let table = partition::read(|lba, data| {
+        data.copy_from_slice(block(&disk, lba));
+        Ok::<_, ()>(())
+    })
+    .unwrap()
+    .unwrap();
+    assert_eq!(table.partitions.len(), 1);
+    let p = &table.partitions[0];
+    assert_eq!((p.kind, p.first_lba), (Kind::Mbr(0x0E), 2048));
+    assert_eq!(p.first_lba + p.sectors, 8 * MIB as u64);
+
+    let bpb = block(&disk, 2048);
+    assert_eq!(&bpb[510..], [0x55, 0xAA]);
+    assert_eq!(u16_at(bpb, 11), 512);
+    assert_eq!(bpb[13], 2);
+    assert_eq!(u16_at(bpb, 17), 512);
+    assert_eq!(u16_at(bpb, 19), p.sectors as u32);
+    assert_eq!(u32_at(bpb, 28), 2048);
+    assert_eq!(u32_at(bpb, 39), 0x1234_5678);
+    assert_eq!(&bpb[43..54], b"H7         ");
+    assert_eq!(&bpb[54..62], b"FAT16   ");
+    assert_eq!(clusters_from_bpb(bpb), layout.clusters);
+
+    for fat in 0..2 {
+        let fat = block(&disk, 2048 + 1 + fat * layout.fat_size as u64);
+        assert_eq!(fat[..4], [0xF8, 0xFF, 0xFF, 0xFF]);
+        assert!(fat[4..].iter().all(|&b| b == 0));
+    }
+    let root = block(&disk, 2048 + 1 + 2 * layout.fat_size as u64);
+    assert_eq!(&root[..11], b"H7         ");
+    assert_eq!(root[11], 0x08);
+    assert!(root[32..].iter().all(|&b| b == 0));
+}
+
+#[test]
+fn fat32_image() {
+    let options = FormatOptions {
+        fat: Some(FatType::Fat32),
+        ..Default::default()
+    };
+    let (disk, layout) = format_image(64 * MIB, &options);
+    let start = layout.partition_start;
+    assert_eq!(block(&disk, 0)[446 + 4], 0x0C);
+
+    let bpb = block(&disk, start);
+    assert_eq!(u16_at(bpb, 14), 32);
+    assert_eq!(
+        (u16_at(bpb, 17), u16_at(bpb, 19), u16_at(bpb, 22)),
+        (0, 0, 0)
+    );
+    assert_eq!(u32_at(bpb, 36), layout.fat_size);
+    assert_eq!(u32_at(bpb, 44), 2);
+    assert_eq!(&bpb[71..82], b"NO NAME    ");
+    assert_eq!(&bpb[82..90], b"FAT32   ");
+    assert_eq!(clusters_from_bpb(bpb), layout.clusters);
+    assert_eq!(block(&disk, start + 6), bpb);
+
+    let info = block(&disk, start + 1);
+    assert_eq!(u32_at(info, 0), 0x4161_5252);
+    assert_eq!(u32_at(info, 484), 0x6141_7272);
+    assert_eq!(u32_at(info, 488), layout.clusters - 1);
+    assert_eq!(block(&disk, start + 7), info);
+
+    let fat = block(&disk, start + 32);
+    assert_eq!(u32_at(fat, 0), 0x0FFF_FFF8);
+    assert_eq!(u32_at(fat, 8), 0x0FFF_FFFF);
+    // Root directory in cluster 2, right after the FATs, is empty
+    let root = start + 32 + 2 * layout.fat_size as u64;
+    assert!(block(&disk, root).iter().all(|&b| b == 0));
+}
+
+#[test]
+fn replaces_gpt() {
+    let mut disk = MemDisk::new(64 * MIB);
+    disk.0[BLOCK_LEN..BLOCK_LEN + 8].copy_from_slice(b"EFI PART");
+    let last = disk.0.len() - BLOCK_LEN;
+    disk.0[last..last + 8].copy_from_slice(b"EFI PART");
+    disk.0[446 + 4] = 0xEE;
+    format(&mut disk, &FormatOptions::default()).unwrap();
+
+    assert!(block(&disk, 1).iter().all(|&b| b == 0));
+    assert!(disk.0[last..].iter().all(|&b| b == 0));
+    let table = partition::read(|lba, data| {
+        data.copy_from_slice(block(&disk, lba));
+        Ok::<_, ()>(())
+    })
+    .unwrap()
+    .unwrap();
+    assert_eq!(table.scheme, partition::Scheme::Mbr);
+}
+
+/// Checks the images with dosfstools, where installed
+#[test]
+fn fsck_accepts_images() {
+    let fsck = match std::process::Command::new("fsck.fat")
+        .arg("--help")
+        .output()
+    {
+        Ok(_) => "fsck.fat",
+        Err(_) => {
+            eprintln!("fsck.fat not installed, skipped");
+            return;
+        }
+    };
+    for (blocks, fat) in [(8 * MIB, FatType::Fat16), (64 * MIB, FatType::Fat32)] {
+        let options = FormatOptions {
+            fat: Some(fat),
+            label: Some("H7"),
+            volume_id: 1,
+        };
+        let (disk, layout) = format_image(blocks, &options);
+        let start = layout.partition_start as usize * BLOCK_LEN;
+        let path = std::env::temp_dir().join(format!("h7-fs-{fat}.img"));
+        std::fs::write(&path, &disk.0[start..]).unwrap();
+        let status = std::process::Command::new(fsck)
+            .arg("-n")
+            .arg(&path)
+            .status()
+            .unwrap();
+        let _ = std::fs::remove_file(&path);
+        assert!(status.success(), "{fat}");
+    }
+}
@@ -0,0 +1,71 @@
+use h7_fs::glob::{is_pattern, matches};
+
+fn m(pattern: &str, name: &str) -> bool {
+    matches(pattern, name, true)
+}
+
+#[test]
+fn patterns() {
+    assert!(is_pattern("*.h7"));
+    assert!(is_pattern("log?.txt"));
+    assert!(is_pattern("[ab].txt"));
+    assert!(!is_pattern("plain.txt"));
+}
+
+#[test]
+fn literal() {
+    assert!(m("hello.h7", "hello.h7"));
+    assert!(!m("hello.h7", "hello.h"));
+    assert!(!m("hello", "hello.h7"));
+    assert!(m("", ""));
+    assert!(!m("", "a"));
+}
+
+#[test]
+fn star() {
+    assert!(m("*", ""));
+    assert!(m("*", "anything"));
+    assert!(m("*.h7", "app.h7"));
+    assert!(!m("*.h7", "app.h7.bak"));
+    assert!(m("2024-*.txt", "2024-01-01.txt"));
+    assert!(!m("2024-*.txt", "2023-01-01.txt"));
+    assert!(m("a*b*c", "aXXbYYbc"));
+    assert!(m("**a", "bba"));
+    assert!(!m("a*b", "acbd"));
+}
+
+#[test]
+fn question_mark() {
+    assert!(m("log?.txt", "log1.txt"));
+    assert!(!m("log?.txt", "log.txt"));
+    assert!(!m("log?.txt", "log12.txt"));
+    assert!(m("?", "é"));
+}
+
+#[test]
+fn classes() {
+    assert!(m("[abc].txt", "b.txt"));
+    assert!(!m("[abc].txt", "d.txt"));
+    assert!(m("log[0-9]", "log7"));
+    assert!(!m("log[0-9]", "logx"));
+    assert!(m("[!0-9]x", "ax"));
+    assert!(!m("[!0-9]x", "5x"));
+    assert!(m("[^a]", "b"));
+    assert!(m("[]]", "]"));
+    assert!(m("[a-]", "-"));
+    assert!(m("*[0-9][0-9].log", "run42.log"));
+}
+
+#[test]
+fn unclosed_class_is_literal() {
+    assert!(m("a[b", "a[b"));
+    assert!(!m("a[b", "ab"));
+}
+
+#[test]
+fn case_insensitive() {
+    assert!(matches("*.h7", "APP.H7", false));
+    assert!(!matches("*.h7", "APP.H7", true));
+    assert!(matches("[a-c]*", "BOOT.TXT", false));
+    assert!(!matches("[!a-c]*", "BOOT.TXT", false));
+}
@@ -0,0 +1,127 @@
+use {
+    h7_fs::{
+        mv::{move_file, CopyDelete},
+        path::Path,
+    },
+    std::collections::BTreeMap,
+};
+
+#[derive(Debug, PartialEq, Eq)]
+enum Error {
+    NotFound,
+    AlreadyExists,
+    IsADirectory,
+    DiskFull,
+}
+
+/// Files and directories by path, copies fail halfway once `fail_copies` is set
+#[derive(Default)]
+struct MemFs {
+    files: BTreeMap<String, Option<Vec<u8>>>,
+    fail_copies: bool,
+}
+
+impl MemFs {
+    fn with(entries: &[(&str, Option<&str>)]) -> Self {
+        let files = entries
+            .iter()
+            .map(|(path, data)| (path.to_string(), data.map(|d| d.as_bytes().to_vec())))
+            .collect();
+        Self {
+            files,
+            fail_copies: false,
+        }
+    }
+
+    fn file(&self, path: &str) -> Option<&[u8]> {
+        self.files.get(path)?.as_deref()
+    }
+}
+
+impl CopyDelete for MemFs {
+    type Error = Error;
+
+    fn source_is_dir(&mut self, path: Path) -> Result<bool, Error> {
+        match self.files.get(path.raw()) {
+            Some(data) => Ok(data.is_none()),
+            None => Err(Error::NotFound),
+        }
+    }
+
+    fn target_exists(&mut self, path: Path) -> Result<bool, Error> {
+        Ok(self.files.contains_key(path.raw()))
+    }
+
+    fn copy_new(&mut self, from: Path, to: Path) -> Result<(), Error> {
+        if self.files.contains_key(to.raw()) {
+            return Err(Error::AlreadyExists);
+        }
+        let data = self.file(from.raw()).ok_or(Error::NotFound)?.to_vec();
+        if self.fail_copies {
+            self.files
+                .insert(to.raw().into(), Some(data[..data.len() / 2].to_vec()));
+            return Err(Error::DiskFull);
+        }
+        self.files.insert(to.raw().into(), Some(data));
+        Ok(())
+    }
+
+    fn delete_source(&mut self, path: Path) -> Result<(), Error> {
+        self.files
+            .remove(path.raw())
+            .map(|_| ())
+            .ok_or(Error::NotFound)
+    }
+
+    fn delete_target(&mut self, path: Path) -> Result<(), Error> {
+        self.delete_source(path)
+    }
+
+    fn is_a_directory(&self) -> Error {
+        Error::IsADirectory
+    }
+}
+
+fn mv(fs: &mut MemFs, from: &str, to: &str) -> Result<(), Error> {
+    move_file(fs, Path::new(from), Path::new(to))
+}
+
+#[test]
+fn moves_file() {
+    let mut fs = MemFs::with(&[("sd:/a.txt", Some("hello"))]);
+    mv(&mut fs, "sd:/a.txt", "sd:/b.txt").unwrap();
+    assert_eq!(fs.file("sd:/b.txt"), Some(&b"hello"[..]));
+    assert!(!fs.files.contains_key("sd:/a.txt"));
+}
+
+#[test]
+fn failed_move_keeps_existing_destination() {
+    let mut fs = MemFs::with(&[
+        ("sd:/important.txt", Some("keep")),
+        ("sd:/dir", None),
+        ("sd:/a.txt", Some("hello")),
+    ]);
+    assert_eq!(
+        mv(&mut fs, "sd:/missing", "sd:/important.txt"),
+        Err(Error::NotFound)
+    );
+    assert_eq!(
+        mv(&mut fs, "sd:/dir", "sd:/important.txt"),
+        Err(Error::IsADirectory)
+    );
+    assert_eq!(
+        mv(&mut fs, "sd:/a.txt", "sd:/important.txt"),
+        Err(Error::AlreadyExists)
+    );
+    assert_eq!(fs.file("sd:/important.txt"), Some(&b"keep"[..]));
+    assert_eq!(fs.file("sd:/a.txt"), Some(&b"hello"[..]));
+}
+
+#[test]
+fn failed_copy_is_removed() {
+    let mut fs = MemFs::with(&[("sd:/a.txt", Some("hello"))]);
+    fs.fail_copies = true;
+    assert_eq!(mv(&mut fs, "sd:/a.txt", "sd:/b.txt"), Err(Error::DiskFull));
+    assert!(!fs.files.contains_key("sd:/b.txt"));
+    assert_eq!(fs.file("sd:/a.txt"), Some(&b"hello"[..]));
+}
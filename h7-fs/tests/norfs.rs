@@ -0,0 +1,262 @@
+use {
+    h7_fs::norfs::{Error, MemFlash, MemFlashError, NorFs, WriteMode, WEAR_SPREAD},
+    std::collections::BTreeMap,
+};
+
+type Flash = MemFlash<256>;
+type Fs = NorFs<Flash>;
+type Op = Box<dyn Fn(&mut Fs) -> Result<(), Error<MemFlashError>>>;
+
+const BLOCKS: usize = 48;
+
+fn formatted() -> Fs {
+    NorFs::format(Flash::new(BLOCKS)).unwrap()
+}
+
+fn remount(fs: Fs) -> Fs {
+    NorFs::mount(fs.into_inner()).unwrap()
+}
+
+fn pattern(len: usize, seed: u8) -> Vec<u8> {
+    (0..len)
+        .map(|i| (i as u8).wrapping_mul(31) ^ seed)
+        .collect()
+}
+
+fn read(fs: &mut Fs, path: &str) -> Vec<u8> {
+    let size = fs.metadata(path).unwrap().size as usize;
+    let mut buf = vec![0; size + 10];
+    assert_eq!(fs.read(path, 0, &mut buf).unwrap(), size);
+    buf.truncate(size);
+    buf
+}
+
+/// Every path with its content, `None` for directories
+fn dump(fs: &mut Fs) -> BTreeMap<String, Option<Vec<u8>>> {
+    fn visit(fs: &mut Fs, dir: &str, out: &mut BTreeMap<String, Option<Vec<u8>>>) {
+        let mut entries = Vec::new();
+        fs.read_dir(dir, |meta| entries.push(meta.clone())).unwrap();
+        for meta in entries {
+            let path = format!("{dir}/{}", meta.name);
+            if meta.is_dir {
+                out.insert(path.clone(), None);
+                visit(fs, &path, out);
+            } else {
+                let content = read(fs, &path);
+                out.insert(path, Some(content));
+            }
+        }
+    }
+    let mut out = BTreeMap::new();
+    visit(fs, "", &mut out);
+    out
+}
+
+#[test]
+fn blank_flash_is_not_formatted() {
+    assert!(matches!(
+        NorFs::mount(Flash::new(BLOCKS)),
+        Err(Error::NotFormatted)
+    ));
+    assert!(matches!(
+        NorFs::mount(MemFlash::<256>::new(4)),
+        Err(Error::Unsupported)
+    ));
+}
+
+#[test]
+fn files_and_directories() {
+    let mut fs = formatted();
+    fs.create_dir("a/b/c", true).unwrap();
+    fs.write("a/b/one", WriteMode::Create, b"hello").unwrap();
+    fs.touch("a/empty").unwrap();
+    assert_eq!(read(&mut fs, "a/b/one"), b"hello");
+    assert_eq!(fs.metadata("a/empty").unwrap().size, 0);
+
+    assert_eq!(
+        fs.write("a/b/one", WriteMode::Create, b"x"),
+        Err(Error::AlreadyExists)
+    );
+    assert_eq!(
+        fs.write("a/b", WriteMode::Truncate, b"x"),
+        Err(Error::IsADirectory)
+    );
+    assert_eq!(
+        fs.write("nope/x", WriteMode::Truncate, b"x"),
+        Err(Error::NotFound)
+    );
+    assert_eq!(fs.create_dir("a/b", false), Err(Error::AlreadyExists));
+    assert_eq!(fs.remove_dir("a/b"), Err(Error::DirectoryNotEmpty));
+    assert_eq!(fs.remove_file("a/b"), Err(Error::IsADirectory));
+    assert_eq!(fs.remove_dir("a/b/one"), Err(Error::NotADirectory));
+    assert_eq!(fs.rename("a", "a/b/c/a"), Err(Error::InvalidPath));
+    assert_eq!(
+        fs.touch(&"n".repeat(h7_fs::norfs::NAME_MAX + 1)),
+        Err(Error::NameTooLong)
+    );
+
+    fs.rename("a/b/one", "a/two").unwrap();
+    fs.remove_dir("a/b/c").unwrap();
+    fs.remove_file("a/empty").unwrap();
+    let mut names = Vec::new();
+    fs.read_dir("a", |meta| names.push(meta.name.clone()))
+        .unwrap();
+    assert_eq!(names, ["b", "two"]);
+
+    let mut fs = remount(fs);
+    assert_eq!(read(&mut fs, "a/two"), b"hello");
+    assert!(!fs.exists("a/b/c"));
+    assert!(!fs.exists("a/empty"));
+}
+
+#[test]
+fn write_modes_across_blocks() {
+    let mut fs = formatted();
+    let big = pattern(1000, 1);
+    fs.write("f", WriteMode::Truncate, &big).unwrap();
+    assert_eq!(read(&mut fs, "f"), big);
+
+    let tail = pattern(300, 2);
+    fs.write("f", WriteMode::Append, &tail).unwrap();
+    let mut expected = big.clone();
+    expected.extend_from_slice(&tail);
+    assert_eq!(read(&mut fs, "f"), expected);
+
+    let mut buf = [0; 50];
+    assert_eq!(fs.read("f", 990, &mut buf).unwrap(), 50);
+    assert_eq!(buf[..], expected[990..1040]);
+    assert_eq!(fs.read("f", 1300, &mut buf).unwrap(), 0);
+
+    fs.write("f", WriteMode::Truncate, b"short").unwrap();
+    let mut fs = remount(fs);
+    assert_eq!(read(&mut fs, "f"), b"short");
+}
+
+#[test]
+fn space_is_reclaimed() {
+    let mut fs = formatted();
+    let free = fs.stats().free_blocks;
+    // Far more metadata than fits without compaction
+    for i in 0..500 {
+        fs.write("counter", WriteMode::Truncate, &pattern(40, i as u8))
+            .unwrap();
+    }
+    assert!(fs.stats().free_blocks + 8 >= free);
+    let mut fs = remount(fs);
+    assert_eq!(read(&mut fs, "counter"), pattern(40, 243));
+
+    fs.remove_file("counter").unwrap();
+    let mut i = 0;
+    let err = loop {
+        if let Err(e) = fs.write(&format!("f{i}"), WriteMode::Create, &pattern(1000, 0)) {
+            break e;
+        }
+        i += 1;
+    };
+    assert_eq!(err, Error::NoSpace);
+    assert!(i > 0);
+    assert!(fs.stats().free_bytes < 1000);
+
+    // A full filesystem can still delete and mount
+    fs.remove_file("f0").unwrap();
+    let mut fs = remount(fs);
+    assert!(!fs.exists("f0"));
+    assert_eq!(read(&mut fs, &format!("f{}", i - 1)), pattern(1000, 0));
+}
+
+#[test]
+fn wear_is_levelled() {
+    let mut fs = formatted();
+    let cold = pattern(2000, 7);
+    fs.write("cold", WriteMode::Create, &cold).unwrap();
+    for i in 0..3000 {
+        fs.write("hot", WriteMode::Truncate, &[i as u8; 8]).unwrap();
+    }
+    let stats = fs.stats();
+    assert!(stats.min_erases > 0, "{stats:?}");
+    assert!(
+        stats.max_erases - stats.min_erases <= 3 * WEAR_SPREAD,
+        "{stats:?}"
+    );
+    let mut fs = remount(fs);
+    assert_eq!(read(&mut fs, "cold"), cold);
+}
+
+#[test]
+fn erase_counts_survive_format() {
+    let mut fs = formatted();
+    for i in 0..200 {
+        fs.write("f", WriteMode::Truncate, &[i as u8; 300]).unwrap();
+    }
+    let erases = fs.stats().max_erases;
+    let fs = NorFs::format(fs.into_inner()).unwrap();
+    assert!(fs.stats().max_erases >= erases);
+    let mut fs = remount(fs);
+    assert!(dump(&mut fs).is_empty());
+}
+
+fn operations() -> Vec<Op> {
+    let mut ops: Vec<Op> = vec![
+        Box::new(|fs| fs.create_dir("d/e", true)),
+        Box::new(|fs| fs.write("d/a", WriteMode::Create, &pattern(600, 1))),
+        Box::new(|fs| fs.write("d/a", WriteMode::Append, &pattern(100, 2))),
+        Box::new(|fs| fs.write("b", WriteMode::Truncate, b"bee")),
+        Box::new(|fs| fs.rename("d/a", "d/e/a")),
+        Box::new(|fs| fs.write("d/e/a", WriteMode::Truncate, &pattern(250, 3))),
+        Box::new(|fs| fs.touch("d/t")),
+        Box::new(|fs| fs.remove_file("b")),
+        Box::new(|fs| fs.rename("d/e", "e")),
+        Box::new(|fs| fs.remove_file("d/t")),
+        Box::new(|fs| fs.remove_dir("d")),
+    ];
+    // Enough commits for compactions and wear levelling
+    for i in 0..40u8 {
+        ops.push(Box::new(move |fs| {
+            fs.write("log", WriteMode::Append, &pattern(37, i))
+        }));
+        ops.push(Box::new(move |fs| {
+            fs.write("hot", WriteMode::Truncate, &pattern(i as usize * 7, i))
+        }));
+    }
+    ops
+}
+
+#[test]
+fn power_loss_at_every_step() {
+    let ops = operations();
+    let start = || remount(formatted()).into_inner();
+
+    let mut fs = NorFs::mount(start()).unwrap();
+    let mut states = vec![dump(&mut fs)];
+    for op in &ops {
+        op(&mut fs).unwrap();
+        states.push(dump(&mut fs));
+    }
+
+    for cut in 0.. {
+        let mut flash = start();
+        flash.cut_power_after(cut);
+        let mut fs = NorFs::mount(flash).unwrap();
+        let failed = ops.iter().position(|op| op(&mut fs).is_err());
+        let mut flash = fs.into_inner();
+        if !flash.lost_power() {
+            assert_eq!(failed, None);
+            assert!(cut > 500, "only {cut} flash operations");
+            break;
+        }
+
+        flash.restore_power();
+        let mut fs = NorFs::mount(flash).unwrap();
+        let step = failed.unwrap_or(ops.len());
+        let state = dump(&mut fs);
+        assert!(
+            state == states[step] || states.get(step + 1) == Some(&state),
+            "power lost after {cut} operations, during step {step}"
+        );
+
+        fs.write("after", WriteMode::Truncate, b"still works")
+            .unwrap();
+        let mut fs = remount(fs);
+        assert_eq!(read(&mut fs, "after"), b"still works");
+    }
+}
@@ -0,0 +1,170 @@
+use h7_fs::partition::{
+    crc32, read, single_partition_mbr, Error, Guid, Kind, Partition, Scheme, BLOCK_LEN,
+};
+
+/// A disk image of `blocks` zeroed blocks
+fn disk(blocks: usize) -> Vec<u8> {
+    vec![0; blocks * BLOCK_LEN]
+}
+
+fn read_table(
+    disk: &[u8],
+) -> Result<Option<h7_fs::partition::PartitionTable>, Error<&'static str>> {
+    read(|lba, block| {
+        let start = lba as usize * BLOCK_LEN;
+        let data = disk.get(start..start + BLOCK_LEN).ok_or("out of range")?;
+        block.copy_from_slice(data);
+        Ok(())
+    })
+}
+
+fn mbr_entry(disk: &mut [u8], slot: usize, status: u8, kind: u8, first_lba: u32, sectors: u32) {
+    let entry = &mut disk[446 + slot * 16..][..16];
+    entry[0] = status;
+    entry[4] = kind;
+    entry[8..12].copy_from_slice(&first_lba.to_le_bytes());
+    entry[12..16].copy_from_slice(&sectors.to_le_bytes());
+    disk[510..512].copy_from_slice(&[0x55, 0xAA]);
+}
+
+/// Protective MBR, GPT header at LBA 1 and the entries from LBA 2
+fn gpt(disk: &mut [u8], entries: &[(Guid, u64, u64, &str)]) {
+    mbr_entry(disk, 0, 0, 0xEE, 1, u32::MAX);
+    for (i, (kind, first, last, name)) in entries.iter().enumerate() {
+        let entry = &mut disk[2 * BLOCK_LEN + i * 128..][..128];
+        entry[..16].copy_from_slice(&kind.0);
+        entry[32..40].copy_from_slice(&first.to_le_bytes());
+        entry[40..48].copy_from_slice(&last.to_le_bytes());
+        for (j, c) in name.encode_utf16().enumerate() {
+            entry[56 + 2 * j..][..2].copy_from_slice(&c.to_le_bytes());
+        }
+    }
+    let entries_crc = crc32(&disk[2 * BLOCK_LEN..2 * BLOCK_LEN + 128 * 128]);
+    let header = &mut disk[BLOCK_LEN..2 * BLOCK_LEN];
+    header[..8].copy_from_slice(b"EFI PART");
+    header[8..12].copy_from_slice(&0x0001_0000u32.to_le_bytes());
+    header[12..16].copy_from_slice(&92u32.to_le_bytes());
+    header[72..80].copy_from_slice(&2u64.to_le_bytes());
+    header[80..84].copy_from_slice(&128u32.to_le_bytes());
+    header[84..88].copy_from_slice(&128u32.to_le_bytes());
+    header[88..92].copy_from_slice(&entries_crc.to_le_bytes());
+    let crc = crc32(&header[..92]);
+    header[16..20].copy_from_slice(&crc.to_le_bytes());
+}
+
+#[test]
+fn crc() {
+    assert_eq!(crc32(b""), 0);
+    assert_eq!(crc32(b"123456789"), 0xCBF4_3926);
+}
+
+#[test]
+fn guids() {
+    assert_eq!(
+        Guid::BASIC_DATA.to_string(),
+        "EBD0A0A2-B9E5-4433-87C0-68B6B72699C7"
+    );
+    assert_eq!(Guid::BASIC_DATA.0[..4], [0xA2, 0xA0, 0xD0, 0xEB]);
+}
+
+#[test]
+fn no_table() {
+    assert_eq!(read_table(&disk(4)), Ok(None));
+
+    // A FAT boot sector without partitions, boot code where the entries would be
+    let mut superfloppy = disk(4);
+    superfloppy[..3].copy_from_slice(&[0xEB, 0x58, 0x90]);
+    superfloppy[446..510].fill(0x3C);
+    superfloppy[510..512].copy_from_slice(&[0x55, 0xAA]);
+    assert_eq!(read_table(&superfloppy), Ok(None));
+}
+
+#[test]
+fn mbr_partitions() {
+    let mut image = disk(4);
+    mbr_entry(&mut image, 0, 0x80, 0x0E, 2048, 65536);
+    mbr_entry(&mut image, 2, 0, 0x83, 67584, 1 << 20);
+    let table = read_table(&image).unwrap().unwrap();
+    assert_eq!(table.scheme, Scheme::Mbr);
+    assert_eq!(
+        table.partitions,
+        [
+            Partition {
+                number: 1,
+                kind: Kind::Mbr(0x0E),
+                first_lba: 2048,
+                sectors: 65536,
+                bootable: true,
+                name: String::new(),
+            },
+            Partition {
+                number: 3,
+                kind: Kind::Mbr(0x83),
+                first_lba: 67584,
+                sectors: 1 << 20,
+                bootable: false,
+                name: String::new(),
+            },
+        ]
+    );
+    assert_eq!(table.get(2), None);
+    assert_eq!(table.first_fat().unwrap().number, 1);
+    assert_eq!(table.partitions[0].bytes(), 32 * 1024 * 1024);
+    assert_eq!(table.partitions[0].kind.to_string(), "FAT16");
+    assert_eq!(Kind::Mbr(0x42).to_string(), "Unknown (0x42)");
+}
+
+#[test]
+fn gpt_partitions() {
+    let mut image = disk(40);
+    gpt(
+        &mut image,
+        &[
+            (Guid::EFI_SYSTEM, 34, 1057, "boot"),
+            (Guid::LINUX, 1058, 9999, "rootfs"),
+            (Guid([0; 16]), 0, 0, ""),
+            (Guid::BASIC_DATA, 10000, 19999, "data"),
+        ],
+    );
+    let table = read_table(&image).unwrap().unwrap();
+    assert_eq!(table.scheme, Scheme::Gpt);
+    let numbers: Vec<_> = table.partitions.iter().map(|p| p.number).collect();
+    assert_eq!(numbers, [1, 2, 4]);
+    assert_eq!(table.partitions[0].name, "boot");
+    assert_eq!(table.partitions[0].sectors, 1024);
+    assert_eq!(table.partitions[1].kind.to_string(), "Linux");
+    assert_eq!(table.first_fat().unwrap().name, "boot");
+    assert!(table.get(4).unwrap().kind.is_fat());
+    assert!(!table.get(2).unwrap().kind.is_fat());
+}
+
+#[test]
+fn corrupt_gpt() {
+    let mut image = disk(40);
+    gpt(&mut image, &[(Guid::BASIC_DATA, 34, 99, "data")]);
+    image[BLOCK_LEN + 72] ^= 1;
+    assert_eq!(read_table(&image), Err(Error::InvalidGpt));
+
+    // An entry ending at the last possible block doesn't overflow
+    let mut image = disk(40);
+    gpt(&mut image, &[(Guid::BASIC_DATA, 34, u64::MAX, "huge")]);
+    let table = read_table(&image).unwrap().unwrap();
+    assert_eq!(table.partitions[0].sectors, u64::MAX - 34);
+    assert_eq!(table.partitions[0].bytes(), u64::MAX);
+
+    // Protective MBR on a disk too small for the header
+    let mut image = disk(1);
+    mbr_entry(&mut image, 0, 0, 0xEE, 1, u32::MAX);
+    assert_eq!(read_table(&image), Err(Error::Io("out of range")));
+}
+
+#[test]
+fn single_partition() {
+    let mut image = disk(4);
+    image[..BLOCK_LEN].copy_from_slice(&single_partition_mbr(2048, 4096));
+    let table = read_table(&image).unwrap().unwrap();
+    assert_eq!(table.partitions.len(), 1);
+    assert_eq!(table.partitions[0].first_lba, 2048);
+    assert_eq!(table.partitions[0].sectors, 4096);
+    assert!(table.partitions[0].kind.is_fat());
+}
@@ -0,0 +1,62 @@
+use h7_fs::sd::{
+    au_size, manufacturer, spec_version, speed_class, transfer_rate_kbit, CapacityClass,
+};
+
+#[test]
+fn capacity_class() {
+    assert_eq!(
+        CapacityClass::new(0, 2 * 1024 * 1024 * 1024),
+        CapacityClass::Sdsc
+    );
+    assert_eq!(
+        CapacityClass::new(1, 16 * 1000 * 1000 * 1000),
+        CapacityClass::Sdhc
+    );
+    assert_eq!(
+        CapacityClass::new(1, 64 * 1000 * 1000 * 1000),
+        CapacityClass::Sdxc
+    );
+    assert_eq!(
+        CapacityClass::new(2, 4 * 1000 * 1000 * 1000 * 1000),
+        CapacityClass::Sduc
+    );
+    assert_eq!(CapacityClass::Sdxc.to_string(), "SDXC");
+}
+
+#[test]
+fn transfer_rates() {
+    // Default speed, 25 MHz
+    assert_eq!(transfer_rate_kbit(0x32), 25_000);
+    // High speed, 50 MHz
+    assert_eq!(transfer_rate_kbit(0x5a), 50_000);
+    assert_eq!(transfer_rate_kbit(0x0b), 100_000);
+    // Reserved unit
+    assert_eq!(transfer_rate_kbit(0x36), 0);
+}
+
+#[test]
+fn sd_status_fields() {
+    assert_eq!(speed_class(0), Some(0));
+    assert_eq!(speed_class(2), Some(4));
+    assert_eq!(speed_class(4), Some(10));
+    assert_eq!(speed_class(5), None);
+    assert_eq!(au_size(0), None);
+    assert_eq!(au_size(1), Some(16 * 1024));
+    assert_eq!(au_size(9), Some(4 * 1024 * 1024));
+    assert_eq!(au_size(13), Some(24 * 1024 * 1024));
+}
+
+#[test]
+fn scr_fields() {
+    assert_eq!(spec_version(0, false, false, 0), "1.0");
+    assert_eq!(spec_version(2, false, false, 0), "2.0");
+    assert_eq!(spec_version(2, true, false, 0), "3.0");
+    assert_eq!(spec_version(2, true, true, 0), "4.x");
+    assert_eq!(spec_version(2, true, true, 2), "6.x");
+}
+
+#[test]
+fn manufacturers() {
+    assert_eq!(manufacturer(0x03), Some("SanDisk"));
+    assert_eq!(manufacturer(0x00), None);
+}
@@ -0,0 +1,144 @@
+use h7_fs::sfdp::{self, AddressBytes, EraseType, Error, FastRead, ReadMode};
+
+/// SFDP space of the MX25L12833F on the GIGA R1, a JESD216 table of 9 DWORDs plus the
+/// Macronix vendor table
+const MX25L12833F: &[u8] = &[
+    0x53, 0x46, 0x44, 0x50, 0x00, 0x01, 0x01, 0xff, // SFDP 1.0, two parameter headers
+    0x00, 0x00, 0x01, 0x09, 0x30, 0x00, 0x00, 0xff, // BFPT 1.0, 9 DWORDs at 0x30
+    0xc2, 0x00, 0x01, 0x04, 0x60, 0x00, 0x00, 0xff, // Vendor table, 4 DWORDs at 0x60
+    0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, //
+    0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, //
+    0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, //
+    0xe5, 0x20, 0xf1, 0xff, 0xff, 0xff, 0xff, 0x07, // 0x30
+    0x44, 0xeb, 0x08, 0x6b, 0x08, 0x3b, 0x04, 0xbb, //
+    0xfe, 0xff, 0xff, 0xff, 0xff, 0xff, 0x00, 0xff, //
+    0xff, 0xff, 0x44, 0xeb, 0x0c, 0x20, 0x0f, 0x52, //
+    0x10, 0xd8, 0x00, 0xff, 0xff, 0xff, 0xff, 0xff, //
+    0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, //
+    0x00, 0x36, 0x00, 0x27, 0xf4, 0x4f, 0xff, 0xff, // 0x60
+    0xd9, 0xc8, 0xff, 0xff,
+];
+
+/// A 256 Mbit part with a JESD216B table, listing the older revision of the table first
+const JESD216B: &[u8] = &[
+    0x53, 0x46, 0x44, 0x50, 0x06, 0x01, 0x01, 0xff, // SFDP 1.6, two parameter headers
+    0x00, 0x00, 0x01, 0x09, 0x20, 0x00, 0x00, 0xff, // BFPT 1.0, 9 DWORDs at 0x20
+    0x00, 0x06, 0x01, 0x10, 0x20, 0x00, 0x00, 0xff, // BFPT 1.6, 16 DWORDs at 0x20
+    0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, //
+    0xe5, 0x20, 0xfb, 0xff, 0xff, 0xff, 0xff, 0x0f, // 0x20, 3 or 4 address bytes
+    0x44, 0xeb, 0x08, 0x6b, 0x08, 0x3b, 0x80, 0xbb, //
+    0xee, 0xff, 0xff, 0xff, 0xff, 0xff, 0x00, 0xff, //
+    0xff, 0xff, 0x00, 0xff, 0x0c, 0x20, 0x0f, 0x52, //
+    0x10, 0xd8, 0x00, 0xff, 0x23, 0x72, 0xf5, 0x00, //
+    0x82, 0xed, 0x04, 0xcc, 0x44, 0x83, 0x48, 0x44, // page size 2^8
+    0x30, 0xb0, 0x30, 0xb0, 0xf7, 0xc4, 0xd5, 0x5c, //
+    0x00, 0xbe, 0x29, 0xff, 0xf0, 0xd0, 0xff, 0xff, //
+];
+
+fn reader(space: &[u8]) -> impl FnMut(u32, &mut [u8]) -> Result<(), ()> + '_ {
+    move |address, buf| {
+        let start = address as usize;
+        let bytes = space.get(start..start + buf.len()).ok_or(())?;
+        buf.copy_from_slice(bytes);
+        Ok(())
+    }
+}
+
+#[test]
+fn mx25l12833f() {
+    let params = sfdp::read(reader(MX25L12833F)).unwrap();
+    assert_eq!((params.major, params.minor), (1, 0));
+    assert_eq!(params.capacity, 16 * 1024 * 1024);
+    assert_eq!(params.page_size, 256);
+    assert_eq!(params.address_bytes, AddressBytes::Three);
+    assert_eq!(
+        params.erase_types,
+        [
+            EraseType {
+                size: 4096,
+                opcode: 0x20
+            },
+            EraseType {
+                size: 32 * 1024,
+                opcode: 0x52
+            },
+            EraseType {
+                size: 64 * 1024,
+                opcode: 0xd8
+            },
+        ]
+    );
+    let modes: Vec<_> = params.fast_reads.iter().map(|r| r.mode).collect();
+    assert_eq!(
+        modes,
+        [
+            ReadMode::Read112,
+            ReadMode::Read122,
+            ReadMode::Read114,
+            ReadMode::Read144,
+            ReadMode::Read444
+        ]
+    );
+    assert_eq!(
+        params.fast_read(ReadMode::Read444),
+        Some(&FastRead {
+            mode: ReadMode::Read444,
+            opcode: 0xeb,
+            dummy_cycles: 4,
+            mode_clocks: 2,
+        })
+    );
+    assert_eq!(
+        params.fast_read(ReadMode::Read444).unwrap().wait_cycles(),
+        6
+    );
+    assert_eq!(params.fast_read(ReadMode::Read114).unwrap().opcode, 0x6b);
+    assert_eq!(params.fast_read(ReadMode::Read222), None);
+    assert_eq!(params.erase_type(8192).unwrap().opcode, 0x52);
+}
+
+#[test]
+fn newest_basic_table_is_used() {
+    let headers = sfdp::read_headers(&mut reader(JESD216B)).unwrap();
+    assert_eq!(headers.len(), 2);
+    assert_eq!(headers[1].dwords, 16);
+
+    let params = sfdp::read(reader(JESD216B)).unwrap();
+    assert_eq!((params.major, params.minor), (1, 6));
+    assert_eq!(params.capacity, 32 * 1024 * 1024);
+    assert_eq!(params.page_size, 256);
+    assert_eq!(params.address_bytes, AddressBytes::ThreeOrFour);
+    // 4-4-4 is flagged as unsupported in DWORD 5
+    assert_eq!(params.fast_read(ReadMode::Read444), None);
+    assert_eq!(params.fast_read(ReadMode::Read122).unwrap().mode_clocks, 4);
+}
+
+#[test]
+fn invalid_spaces() {
+    let mut blank = vec![0xff; 64];
+    assert_eq!(sfdp::read(reader(&blank)), Err(Error::NoSignature));
+
+    blank[..8].copy_from_slice(&MX25L12833F[..8]);
+    blank[6] = 0;
+    blank[8..16].copy_from_slice(&MX25L12833F[16..24]);
+    assert_eq!(sfdp::read(reader(&blank)), Err(Error::NoBasicTable));
+
+    let mut short = MX25L12833F.to_vec();
+    short[11] = 4;
+    assert_eq!(sfdp::read(reader(&short)), Err(Error::Truncated));
+
+    assert_eq!(
+        sfdp::read(reader(&MX25L12833F[..0x40])),
+        Err(Error::Read(()))
+    );
+
+    // 2^255 bits
+    let mut huge = MX25L12833F.to_vec();
+    huge[0x34..0x38].copy_from_slice(&0x8000_00ffu32.to_le_bytes());
+    assert_eq!(sfdp::read(reader(&huge)), Err(Error::Invalid));
+
+    // 2^32 byte erase size
+    let mut huge = MX25L12833F.to_vec();
+    huge[0x4c] = 32;
+    assert_eq!(sfdp::read(reader(&huge)), Err(Error::Invalid));
+}
@@ -0,0 +1,5 @@
+pub mod sys;
+pub mod time;
+
+pub const HEADER_WIDTH: usize = 48;
+pub const LABEL_WIDTH: usize = 20;
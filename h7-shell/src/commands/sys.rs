@@ -0,0 +1,222 @@
+use {
+    super::{HEADER_WIDTH, LABEL_WIDTH},
+    crate::{
+        menu::{
+            registry::{DynamicItem, Help},
+            MenuError, MenuItem,
+        },
+        utils::*,
+    },
+    alloc::string::String,
+    core::fmt::Write,
+};
+
+pub const fn help<W: Write>() -> MenuItem<'static, W> {
+    MenuItem::Command {
+        name: "help",
+        help: "help <program> - Show help about a program",
+        description: "Show help about a program",
+        action: |m, args| {
+            check_args_len(1, args.len())?;
+            let program = args[0];
+            let mut command_found = false;
+            let items = m.items();
+            iter_menu(m, args, items, &mut |menu, _, item, _, _| {
+                match item {
+                    MenuItem::Command { name, help, .. } => {
+                        if *name == program {
+                            writeln!(menu.writer(), "{help}")?;
+                            command_found = true;
+                            return Ok(false);
+                        }
+                    }
+                    MenuItem::Alias { alias, command } => {
+                        if *alias == program {
+                            writeln!(menu.writer(), "'{alias}' aliased to '{command}'")?;
+                            menu.run("help", &[*command])?;
+                            command_found = true;
+                            return Ok(false);
+                        }
+                    }
+                    MenuItem::Group { .. } => {
+                        // nop
+                    }
+                };
+                Ok(true)
+            })?;
+
+            if command_found {
+                return Ok(());
+            }
+
+            let (writer, registry) = m.writer_and_registry();
+            let alias_command = match registry.help(program) {
+                Some(Help::Command(help)) => {
+                    writeln!(writer, "{help}")?;
+                    None
+                }
+                Some(Help::Alias(command)) => {
+                    writeln!(writer, "'{program}' aliased to '{command}'")?;
+                    Some(String::from(command))
+                }
+                None => return Err(MenuError::CommandNotFound),
+            };
+            if let Some(command) = alias_command {
+                if let Some(target) = command.split_whitespace().next() {
+                    m.run("help", &[target])?;
+                }
+            }
+            Ok(())
+        },
+    }
+}
+
+pub const fn man<W: Write>() -> MenuItem<'static, W> {
+    MenuItem::Alias {
+        alias: "man",
+        command: "help",
+    }
+}
+
+pub const fn programs<W: Write>() -> MenuItem<'static, W> {
+    MenuItem::Command {
+        name: "programs",
+        help: "programs - Show available builtin programs",
+        description: "Show available builtin programs",
+        action: |m, args| {
+            check_args_len(0, args.len())?;
+
+            let items = m.items();
+            let (writer, registry) = m.writer_and_registry();
+            for item in items {
+                write_program(writer, item, 0)?;
+            }
+
+            if !registry.items().is_empty() {
+                write_group_title(writer, "Runtime", 0)?;
+                for item in registry.items() {
+                    match item {
+                        DynamicItem::Item(item) => write_program(writer, item, 1)?,
+                        DynamicItem::Command {
+                            name, description, ..
+                        } => write_command(writer, name, description, 1)?,
+                        DynamicItem::Alias { alias, command } => {
+                            write_alias(writer, alias, command, 1)?
+                        }
+                    }
+                }
+            }
+            Ok(())
+        },
+    }
+}
+
+fn write_program<W: Write, M: Write>(
+    writer: &mut W,
+    item: &MenuItem<M>,
+    level: usize,
+) -> core::fmt::Result {
+    match item {
+        MenuItem::Command {
+            name, description, ..
+        } => write_command(writer, name, description, level),
+        MenuItem::Alias { alias, command } => write_alias(writer, alias, command, level),
+        MenuItem::Group { title, commands } => {
+            write_group_title(writer, title, level)?;
+            commands
+                .iter()
+                .try_for_each(|item| write_program(writer, item, level + 1))
+        }
+    }
+}
+
+fn write_command<W: Write>(
+    writer: &mut W,
+    name: &str,
+    description: &str,
+    level: usize,
+) -> core::fmt::Result {
+    writeln!(
+        writer,
+        "{padding}{name:LABEL_WIDTH$} {description}",
+        padding = PaddedStr::<b' '>("", level),
+    )
+}
+
+fn write_alias<W: Write>(
+    writer: &mut W,
+    alias: &str,
+    command: &str,
+    level: usize,
+) -> core::fmt::Result {
+    writeln!(
+        writer,
+        "{padding}{alias:LABEL_WIDTH$} aliased to {command}",
+        padding = PaddedStr::<b' '>("", level),
+    )
+}
+
+fn write_group_title<W: Write>(writer: &mut W, title: &str, level: usize) -> core::fmt::Result {
+    writeln!(
+        writer,
+        "{p}{t:-^-w$}",
+        p = PaddedStr::<b' '>("", level),
+        t = PaddedStr::<b' '>(title, 1),
+        w = HEADER_WIDTH - level - level
+    )
+}
+
+pub const fn commands<W: Write>() -> MenuItem<'static, W> {
+    MenuItem::Alias {
+        alias: "commands",
+        command: "programs",
+    }
+}
+
+pub const fn alias<W: Write>() -> MenuItem<'static, W> {
+    MenuItem::Command {
+        name: "alias",
+        help: "alias [name ['command args..']] - List, show or create command aliases",
+        description: "List, show or create command aliases",
+        action: |m, args| match args {
+            [] => {
+                let (writer, registry) = m.writer_and_registry();
+                for item in registry.items() {
+                    if let DynamicItem::Alias { alias, command } = item {
+                        writeln!(writer, "{alias:LABEL_WIDTH$} '{command}'")?;
+                    }
+                }
+                Ok(())
+            }
+            [name] => {
+                let (writer, registry) = m.writer_and_registry();
+                match registry.help(name) {
+                    Some(Help::Alias(command)) => {
+                        writeln!(writer, "'{name}' aliased to '{command}'")?;
+                        Ok(())
+                    }
+                    _ => Err(MenuError::CommandNotFound),
+                }
+            }
+            [name, command @ ..] => {
+                let command = command.join(" ");
+                m.alias(name, &command)
+            }
+        },
+    }
+}
+
+pub const fn unalias<W: Write>() -> MenuItem<'static, W> {
+    MenuItem::Command {
+        name: "unalias",
+        help: "unalias <name> - Remove an alias created with 'alias'",
+        description: "Remove an alias",
+        action: |m, args| {
+            check_args_len(1, args.len())?;
+            match m.registry().help(args[0]) {
+                Some(Help::Alias(_)) => m.unregister(args[0]),
+                _ => Err(MenuError::CommandNotFound),
+            }
+        },
+    }
+}
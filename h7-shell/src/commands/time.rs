@@ -0,0 +1,84 @@
+use {
+    crate::utils::{days_in_month, month_to_str},
+    chrono::{Datelike, Timelike},
+    core::fmt::Write,
+};
+
+pub const DATE_PARSE_FORMAT: &str = "%Y-%m-%d";
+pub const TIME_PARSE_FORMAT: &str = "%H:%M:%S";
+
+/// Write a date and time as `Mon Jan 1 00:00:00 2024`, without a newline
+pub fn write_date_time<W: Write, DT: Datelike + Timelike>(
+    writer: &mut W,
+    dt: &DT,
+) -> core::fmt::Result {
+    write!(
+        writer,
+        "{weekday} {month} {day} {hh:02}:{mm:02}:{ss:02} {year}",
+        weekday = dt.weekday(),
+        month = month_to_str(dt.month()),
+        day = dt.day(),
+        hh = dt.hour(),
+        mm = dt.minute(),
+        ss = dt.second(),
+        year = dt.year()
+    )
+}
+
+/// Formats as ISO 8601 `2024-01-01T00:00:00`, used for machine-readable output
+pub struct Iso8601<'d, DT>(pub &'d DT);
+
+impl<'d, DT: Datelike + Timelike> core::fmt::Display for Iso8601<'d, DT> {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        write!(
+            f,
+            "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}",
+            self.0.year(),
+            self.0.month(),
+            self.0.day(),
+            self.0.hour(),
+            self.0.minute(),
+            self.0.second()
+        )
+    }
+}
+
+/// Write the calendar of the month `d` is in, with `d` highlighted
+// `is_multiple_of` is newer than the MSRV
+#[allow(clippy::manual_is_multiple_of)]
+pub fn write_calendar<W: Write, D: Datelike>(writer: &mut W, d: &D) -> core::fmt::Result {
+    let top = d.with_day0(0).unwrap();
+    let day = d.day0();
+    let wd = top.weekday() as u32;
+    let week = top.iso_week().week() - 1;
+    let n_days = days_in_month(d);
+    writeln!(
+        writer,
+        "         {m} {y}",
+        m = month_to_str(d.month()),
+        y = d.year()
+    )?;
+    writeln!(writer, "Wk | Mo Tu We Th Fr Sa Su")?;
+    write!(writer, "{:2} |", week + 1)?;
+    for _ in 0..wd {
+        write!(writer, "   ")?;
+    }
+    for i in 0..n_days {
+        let c = i + wd + 1;
+        if i == day {
+            write!(writer, "[{:2}", i + 1)?;
+            if wd % 7 == 0 {
+                write!(writer, "]")?;
+            }
+        } else if i == day + 1 && c % 7 != 1 {
+            write!(writer, "]{:2}", i + 1)?;
+        } else {
+            write!(writer, " {:2}", i + 1)?;
+        }
+        if c % 7 == 0 && i != n_days - 1 {
+            writeln!(writer)?;
+            write!(writer, "{:2} |", 1 + ((week + (c / 7)) % 52))?;
+        }
+    }
+    writeln!(writer)
+}
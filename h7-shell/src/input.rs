@@ -0,0 +1,189 @@
+//! Console input buffer with overflow accounting and XON/XOFF flow control
+
+use alloc::{vec, vec::Vec};
+
+/// Resume transmission (DC1)
+pub const XON: u8 = 0x11;
+/// Pause transmission (DC3)
+pub const XOFF: u8 = 0x13;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Flow {
+    None,
+    XonXoff,
+}
+
+impl Flow {
+    pub fn parse(s: &str) -> Option<Self> {
+        match s {
+            "none" | "off" => Some(Self::None),
+            "xonxoff" | "xon" => Some(Self::XonXoff),
+            _ => None,
+        }
+    }
+}
+
+impl core::fmt::Display for Flow {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        match self {
+            Self::None => f.pad("none"),
+            Self::XonXoff => f.pad("xonxoff"),
+        }
+    }
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct Stats {
+    /// Bytes stored, flow control bytes aren't counted
+    pub received: u64,
+    /// Bytes dropped because the buffer was full
+    pub dropped: u64,
+    /// Times the buffer ran full, a run of dropped bytes counts once
+    pub overflows: u32,
+    /// Most bytes the buffer held at once
+    pub peak: usize,
+}
+
+/// Ring buffer between the receive interrupt and the session reading it.
+///
+/// With [`Flow::XonXoff`] the buffer asks the sender to pause once it is three
+/// quarters full and to resume once it drained to a quarter, the bytes to send
+/// are picked up with [`InputBuffer::take_control`]. XON and XOFF from the other
+/// end pause our own output, see [`InputBuffer::is_paused`]. Raw mode passes every
+/// byte through for binary transfers, which acknowledge their blocks themselves.
+pub struct InputBuffer {
+    data: Vec<u8>,
+    head: usize,
+    len: usize,
+    flow: Flow,
+    raw: bool,
+    /// The sender was told to stop
+    stopped: bool,
+    /// The other end told us to stop
+    paused: bool,
+    control: Option<u8>,
+    overflowing: bool,
+    stats: Stats,
+}
+
+impl InputBuffer {
+    pub fn new(capacity: usize, flow: Flow) -> Self {
+        Self {
+            data: vec![0; capacity.max(1)],
+            head: 0,
+            len: 0,
+            flow,
+            raw: false,
+            stopped: false,
+            paused: false,
+            control: None,
+            overflowing: false,
+            stats: Stats::default(),
+        }
+    }
+
+    pub fn capacity(&self) -> usize {
+        self.data.len()
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    pub fn flow(&self) -> Flow {
+        self.flow
+    }
+
+    pub fn stats(&self) -> Stats {
+        self.stats
+    }
+
+    /// Is flow control in effect, it is suspended in raw mode
+    fn controlled(&self) -> bool {
+        self.flow == Flow::XonXoff && !self.raw
+    }
+
+    /// Pass XON and XOFF through as data, and stop sending them
+    pub fn set_raw(&mut self, raw: bool) {
+        self.raw = raw;
+        self.paused = false;
+        if raw && self.stopped {
+            // The other end might not know flow control in binary mode
+            self.stopped = false;
+            self.control = Some(XON);
+        }
+        self.update_flow();
+    }
+
+    /// Store a byte received from the line
+    pub fn push(&mut self, byte: u8) {
+        if self.controlled() {
+            if let XON | XOFF = byte {
+                self.paused = byte == XOFF;
+                return;
+            }
+        }
+
+        if self.len == self.data.len() {
+            self.stats.dropped += 1;
+            if !self.overflowing {
+                self.overflowing = true;
+                self.stats.overflows += 1;
+            }
+            return;
+        }
+        self.overflowing = false;
+        let tail = (self.head + self.len) % self.data.len();
+        self.data[tail] = byte;
+        self.len += 1;
+        self.stats.received += 1;
+        self.stats.peak = self.stats.peak.max(self.len);
+        self.update_flow();
+    }
+
+    pub fn pop(&mut self) -> Option<u8> {
+        if self.len == 0 {
+            return None;
+        }
+        let byte = self.data[self.head];
+        self.head = (self.head + 1) % self.data.len();
+        self.len -= 1;
+        self.update_flow();
+        Some(byte)
+    }
+
+    /// Drop everything buffered
+    pub fn clear(&mut self) {
+        self.head = 0;
+        self.len = 0;
+        self.update_flow();
+    }
+
+    /// XON or XOFF to send to the other end, if the buffer crossed a watermark
+    pub fn take_control(&mut self) -> Option<u8> {
+        self.control.take()
+    }
+
+    /// The other end sent XOFF, hold back output until it sends XON
+    pub fn is_paused(&self) -> bool {
+        self.paused
+    }
+
+    fn update_flow(&mut self) {
+        if !self.controlled() {
+            return;
+        }
+        let capacity = self.data.len();
+        if !self.stopped && self.len * 4 >= capacity * 3 {
+            self.stopped = true;
+            self.control = Some(XOFF);
+        } else if self.stopped && self.len * 4 <= capacity {
+            self.stopped = false;
+            self.control = Some(XON);
+        }
+    }
+}
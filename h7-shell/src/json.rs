@@ -0,0 +1,197 @@
+use core::fmt::{self, Display, Write};
+
+/// Output format of commands that support machine-readable output
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum OutputMode {
+    #[default]
+    Text,
+    Json,
+}
+
+impl core::str::FromStr for OutputMode {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "text" => Ok(OutputMode::Text),
+            "json" => Ok(OutputMode::Json),
+            _ => Err(()),
+        }
+    }
+}
+
+impl Display for OutputMode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            OutputMode::Text => f.pad("text"),
+            OutputMode::Json => f.pad("json"),
+        }
+    }
+}
+
+/// Escapes everything written through it for use inside a JSON string
+struct Escaper<'w, W: Write>(&'w mut W);
+
+impl<'w, W: Write> Write for Escaper<'w, W> {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        for c in s.chars() {
+            match c {
+                '"' => self.0.write_str("\\\"")?,
+                '\\' => self.0.write_str("\\\\")?,
+                '\n' => self.0.write_str("\\n")?,
+                '\r' => self.0.write_str("\\r")?,
+                '\t' => self.0.write_str("\\t")?,
+                c if (c as u32) < 0x20 => write!(self.0, "\\u{:04x}", c as u32)?,
+                c => self.0.write_char(c)?,
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Streaming writer for a JSON object, keys are written in call order.
+///
+/// ```
+/// # use h7_shell::json::JsonObject;
+/// let mut s = String::new();
+/// let mut obj = JsonObject::new(&mut s).unwrap();
+/// obj.str("name", "h7").unwrap().num("size", 42).unwrap();
+/// obj.finish().unwrap();
+/// assert_eq!(s, r#"{"name":"h7","size":42}"#);
+/// ```
+pub struct JsonObject<'w, W: Write> {
+    writer: &'w mut W,
+    empty: bool,
+}
+
+impl<'w, W: Write> JsonObject<'w, W> {
+    pub fn new(writer: &'w mut W) -> Result<Self, fmt::Error> {
+        writer.write_char('{')?;
+        Ok(Self {
+            writer,
+            empty: true,
+        })
+    }
+
+    fn key(&mut self, key: &str) -> fmt::Result {
+        if !core::mem::replace(&mut self.empty, false) {
+            self.writer.write_char(',')?;
+        }
+        self.writer.write_char('"')?;
+        Escaper(self.writer).write_str(key)?;
+        self.writer.write_str("\":")
+    }
+
+    pub fn str(&mut self, key: &str, value: &str) -> Result<&mut Self, fmt::Error> {
+        self.display(key, value)
+    }
+
+    /// Write `value` formatted with [`Display`] as a JSON string
+    pub fn display(&mut self, key: &str, value: impl Display) -> Result<&mut Self, fmt::Error> {
+        self.key(key)?;
+        self.writer.write_char('"')?;
+        write!(Escaper(self.writer), "{value}")?;
+        self.writer.write_char('"')?;
+        Ok(self)
+    }
+
+    /// Write `value` as is, it must format as a valid JSON number
+    pub fn num(&mut self, key: &str, value: impl Display) -> Result<&mut Self, fmt::Error> {
+        self.key(key)?;
+        write!(self.writer, "{value}")?;
+        Ok(self)
+    }
+
+    pub fn bool(&mut self, key: &str, value: bool) -> Result<&mut Self, fmt::Error> {
+        self.key(key)?;
+        write!(self.writer, "{value}")?;
+        Ok(self)
+    }
+
+    pub fn null(&mut self, key: &str) -> Result<&mut Self, fmt::Error> {
+        self.key(key)?;
+        self.writer.write_str("null")?;
+        Ok(self)
+    }
+
+    /// Write a number, or `null` if it is unavailable
+    pub fn opt_num(
+        &mut self,
+        key: &str,
+        value: Option<impl Display>,
+    ) -> Result<&mut Self, fmt::Error> {
+        match value {
+            Some(value) => self.num(key, value),
+            None => self.null(key),
+        }
+    }
+
+    /// Write a string, or `null` if it is unavailable
+    pub fn opt_display(
+        &mut self,
+        key: &str,
+        value: Option<impl Display>,
+    ) -> Result<&mut Self, fmt::Error> {
+        match value {
+            Some(value) => self.display(key, value),
+            None => self.null(key),
+        }
+    }
+
+    /// Write a nested object filled in by `f`
+    pub fn object(
+        &mut self,
+        key: &str,
+        f: impl FnOnce(&mut JsonObject<W>) -> fmt::Result,
+    ) -> Result<&mut Self, fmt::Error> {
+        self.key(key)?;
+        let mut obj = JsonObject::new(&mut *self.writer)?;
+        f(&mut obj)?;
+        obj.finish()?;
+        Ok(self)
+    }
+
+    /// Write an array with one object per item, each filled in by `f`
+    pub fn objects<T>(
+        &mut self,
+        key: &str,
+        items: impl IntoIterator<Item = T>,
+        mut f: impl FnMut(&mut JsonObject<W>, T) -> fmt::Result,
+    ) -> Result<&mut Self, fmt::Error> {
+        self.key(key)?;
+        self.writer.write_char('[')?;
+        for (i, item) in items.into_iter().enumerate() {
+            if i > 0 {
+                self.writer.write_char(',')?;
+            }
+            let mut obj = JsonObject::new(&mut *self.writer)?;
+            f(&mut obj, item)?;
+            obj.finish()?;
+        }
+        self.writer.write_char(']')?;
+        Ok(self)
+    }
+
+    pub fn finish(self) -> fmt::Result {
+        self.writer.write_char('}')
+    }
+}
+
+/// Write one JSON object filled in by `f` on its own line
+pub fn write_object<W: Write>(
+    writer: &mut W,
+    f: impl FnOnce(&mut JsonObject<W>) -> fmt::Result,
+) -> fmt::Result {
+    let mut obj = JsonObject::new(writer)?;
+    f(&mut obj)?;
+    obj.finish()?;
+    writeln!(writer)
+}
+
+/// Write `{"error":"<error>"}` on its own line
+pub fn write_error<W: Write>(writer: &mut W, error: impl Display) -> fmt::Result {
+    write_object(writer, |o| {
+        o.display("error", error)?;
+        Ok(())
+    })
+}
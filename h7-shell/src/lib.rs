@@ -0,0 +1,12 @@
+#![cfg_attr(target_os = "none", no_std)]
+
+extern crate alloc;
+
+pub mod commands;
+pub mod input;
+pub mod json;
+pub mod menu;
+pub mod shell;
+pub mod utils;
+
+pub use shell::Shell;
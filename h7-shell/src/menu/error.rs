@@ -6,6 +6,8 @@ pub enum MenuError {
     NotEnoughArgs(u8, u8),
     /// Command not found
     CommandNotFound,
+    /// A command or alias with the same name already exists
+    CommandExists,
     /// Write stdout/stderr error
     WriteError(core::fmt::Error),
     /// Command error
@@ -37,6 +39,7 @@ impl core::fmt::Display for MenuError {
                 )
             }
             Self::CommandNotFound => write!(f, "Command not found"),
+            Self::CommandExists => write!(f, "Command already exists"),
             Self::WriteError(we) => write!(f, "Write error: {we:?}"),
             Self::CommandError(Some(err)) => write!(f, "Command error: {err}"),
             Self::CommandError(None) => write!(f, "Command error"),
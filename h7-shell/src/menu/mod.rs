@@ -0,0 +1,257 @@
+mod error;
+pub mod registry;
+pub mod tokenizer;
+pub use error::{MenuError, MenuResult};
+
+use {
+    crate::json::OutputMode,
+    alloc::string::String,
+    registry::{DynamicAction, DynamicItem, Registry, Resolved},
+};
+
+pub type MenuAction<W> = fn(writer: &mut Menu<W>, args: &[&str]) -> MenuResult;
+
+/// How deep aliases may expand into other aliases
+const MAX_ALIAS_DEPTH: u8 = 8;
+
+pub enum MenuItem<'i, W: core::fmt::Write> {
+    Command {
+        name: &'i str,
+        help: &'i str,
+        description: &'i str,
+        action: MenuAction<W>,
+    },
+    Alias {
+        alias: &'i str,
+        command: &'i str,
+    },
+    Group {
+        title: &'i str,
+        commands: &'i [MenuItem<'i, W>],
+    },
+}
+
+pub struct Menu<'m, W: core::fmt::Write> {
+    writer: W,
+    menu: &'m [MenuItem<'m, W>],
+    registry: Registry<'m, W>,
+    alias_depth: u8,
+    output: OutputMode,
+    /// Working directory relative paths start at, empty until a device is chosen with `cd`
+    cwd: String,
+}
+
+impl<'m: 'i, 'i, W: core::fmt::Write> Menu<'m, W> {
+    pub fn new(writer: W, menu: &'m [MenuItem<'i, W>]) -> Self {
+        Self {
+            writer,
+            menu,
+            registry: Registry::new(),
+            alias_depth: 0,
+            output: OutputMode::Text,
+            cwd: String::new(),
+        }
+    }
+
+    pub fn writer(&mut self) -> &mut W {
+        &mut self.writer
+    }
+}
+
+impl<'m, W: core::fmt::Write> Menu<'m, W> {
+    /// The builtin menu
+    pub fn items(&self) -> &'m [MenuItem<'m, W>] {
+        self.menu
+    }
+
+    /// Commands and aliases added at runtime
+    pub fn registry(&self) -> &Registry<'m, W> {
+        &self.registry
+    }
+
+    /// Output format commands should use
+    pub fn output(&self) -> OutputMode {
+        self.output
+    }
+
+    pub fn set_output(&mut self, output: OutputMode) {
+        self.output = output;
+    }
+
+    /// Working directory, e.g. `sdcard:/logs`
+    pub fn cwd(&self) -> &str {
+        &self.cwd
+    }
+
+    pub fn set_cwd(&mut self, cwd: String) {
+        self.cwd = cwd;
+    }
+
+    /// Borrow the writer and the registry at the same time, for listing runtime items
+    pub fn writer_and_registry(&mut self) -> (&mut W, &Registry<'m, W>) {
+        (&mut self.writer, &self.registry)
+    }
+
+    /// Register a driver provided item, names may not shadow existing commands
+    pub fn register(&mut self, item: MenuItem<'m, W>) -> MenuResult {
+        match item {
+            MenuItem::Command { name, .. } => self.check_builtin(name)?,
+            MenuItem::Alias { alias, .. } => self.check_builtin(alias)?,
+            MenuItem::Group { commands, .. } => {
+                for command in commands {
+                    match command {
+                        MenuItem::Command { name, .. } => self.check_builtin(name)?,
+                        MenuItem::Alias { alias, .. } => self.check_builtin(alias)?,
+                        MenuItem::Group { .. } => {}
+                    }
+                }
+            }
+        }
+        self.registry.register(item)
+    }
+
+    /// Register a command whose action captures state
+    pub fn register_command(
+        &mut self,
+        name: &str,
+        help: &str,
+        description: &str,
+        action: DynamicAction<'m, W>,
+    ) -> MenuResult {
+        self.check_builtin(name)?;
+        self.registry
+            .register_command(name, help, description, action)
+    }
+
+    /// Create or replace an alias, `command` may include arguments
+    pub fn alias(&mut self, alias: &str, command: &str) -> MenuResult {
+        self.check_builtin(alias)?;
+        self.registry.alias(alias, command)
+    }
+
+    /// Remove a command, alias or group added at runtime
+    pub fn unregister(&mut self, name: &str) -> MenuResult {
+        self.registry.unregister(name)
+    }
+
+    /// Remove all commands registered through [`Menu::register_command`]
+    pub fn unregister_dynamic_commands(&mut self) {
+        self.registry.unregister_dynamic_commands()
+    }
+
+    /// Call `func` with the name of every command and alias, builtin and registered
+    pub fn for_each_name(&self, func: &mut dyn FnMut(&str)) {
+        fn for_each_impl<W: core::fmt::Write>(
+            items: &[MenuItem<'_, W>],
+            func: &mut dyn FnMut(&str),
+        ) {
+            for item in items {
+                match item {
+                    MenuItem::Command { name, .. } => func(name),
+                    MenuItem::Alias { alias, .. } => func(alias),
+                    MenuItem::Group { commands, .. } => for_each_impl(commands, func),
+                }
+            }
+        }
+
+        for_each_impl(self.menu, func);
+        for item in self.registry.items() {
+            match item {
+                DynamicItem::Item(item) => for_each_impl(core::slice::from_ref(item), func),
+                DynamicItem::Command { name, .. } => func(name),
+                DynamicItem::Alias { alias, .. } => func(alias),
+            }
+        }
+    }
+
+    pub fn run(&mut self, cmd: &str, args: &[&str]) -> MenuResult {
+        fn run_impl<'m, W: core::fmt::Write>(
+            menu: &mut Menu<'m, W>,
+            cmd: &str,
+            args: &[&str],
+            menu_items: &[MenuItem<'m, W>],
+        ) -> MenuResult {
+            for item in menu_items {
+                match item {
+                    MenuItem::Command { name, action, .. } => {
+                        if *name == cmd {
+                            action(menu, args)?;
+                            return Ok(());
+                        }
+                    }
+                    MenuItem::Alias { alias, command } => {
+                        if *alias == cmd {
+                            menu.run(command, args)?;
+                            return Ok(());
+                        }
+                    }
+                    MenuItem::Group { commands, .. } => match run_impl(menu, cmd, args, commands) {
+                        Ok(_) => return Ok(()),
+                        Err(MenuError::CommandNotFound) => continue,
+                        Err(e) => return Err(e),
+                    },
+                }
+            }
+            Err(MenuError::CommandNotFound)
+        }
+
+        if contains(self.menu, cmd) {
+            run_impl(self, cmd, args, self.menu)
+        } else {
+            match self.registry.resolve(cmd) {
+                Some(Resolved::Action(action)) => action(self, args),
+                Some(Resolved::Dynamic(action)) => action(self, args),
+                Some(Resolved::Alias(command)) => self.run_alias(&command, args),
+                None => Err(MenuError::CommandNotFound),
+            }
+        }
+    }
+
+    fn run_alias(&mut self, command: &str, args: &[&str]) -> MenuResult {
+        let mut tokens = [""; tokenizer::MAX_TOKENS];
+        let mut n = tokenizer::tokenize(command, &mut tokens)
+            .map_err(|_| MenuError::CommandError(Some("Invalid alias")))?;
+        if n == 0 {
+            return Err(MenuError::CommandError(Some("Empty alias")));
+        }
+        if n + args.len() > tokens.len() {
+            return Err(MenuError::TooManyArgs(
+                (tokens.len() - n) as u8,
+                args.len() as u8,
+            ));
+        }
+        tokens[n..n + args.len()].copy_from_slice(args);
+        n += args.len();
+
+        if self.alias_depth >= MAX_ALIAS_DEPTH {
+            return Err(MenuError::CommandError(Some("Alias nested too deep")));
+        }
+        self.alias_depth += 1;
+        let res = self.run(tokens[0], &tokens[1..n]);
+        self.alias_depth -= 1;
+        res
+    }
+
+    fn check_builtin(&self, name: &str) -> MenuResult {
+        if contains(self.menu, name) {
+            Err(MenuError::CommandExists)
+        } else {
+            Ok(())
+        }
+    }
+}
+
+/// Check if `name` is a command or alias in `menu_items` or any of its groups
+fn contains<W: core::fmt::Write>(menu_items: &[MenuItem<W>], name: &str) -> bool {
+    menu_items.iter().any(|item| match item {
+        MenuItem::Command { name: n, .. } => *n == name,
+        MenuItem::Alias { alias, .. } => *alias == name,
+        MenuItem::Group { commands, .. } => contains(commands, name),
+    })
+}
+
+impl<'m, W: core::fmt::Write> core::fmt::Write for Menu<'m, W> {
+    fn write_str(&mut self, s: &str) -> core::fmt::Result {
+        self.writer().write_str(s)
+    }
+}
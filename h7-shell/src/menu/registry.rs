@@ -0,0 +1,195 @@
+use {
+    super::{Menu, MenuAction, MenuError, MenuItem, MenuResult},
+    alloc::{rc::Rc, string::String, vec::Vec},
+};
+
+/// Action of a command registered at runtime, may capture state (e.g. a callback into a loaded app)
+pub type DynamicAction<'m, W> = Rc<dyn Fn(&mut Menu<'m, W>, &[&str]) -> MenuResult + 'm>;
+
+pub enum DynamicItem<'m, W: core::fmt::Write> {
+    /// Item provided by a driver, same as the ones in the builtin menu
+    Item(MenuItem<'m, W>),
+    /// Command provided by a loaded program
+    Command {
+        name: String,
+        help: String,
+        description: String,
+        action: DynamicAction<'m, W>,
+    },
+    /// User defined alias, `command` may contain arguments
+    Alias { alias: String, command: String },
+}
+
+/// Help text of a runtime registered item
+pub enum Help<'r> {
+    Command(&'r str),
+    Alias(&'r str),
+}
+
+/// What a name in the registry resolves to, detached from the registry so
+/// the menu can be borrowed mutably while running it.
+pub(super) enum Resolved<'m, W: core::fmt::Write> {
+    Action(MenuAction<W>),
+    Dynamic(DynamicAction<'m, W>),
+    Alias(String),
+}
+
+/// Commands and aliases added at runtime, layered on top of the builtin menu
+pub struct Registry<'m, W: core::fmt::Write> {
+    items: Vec<DynamicItem<'m, W>>,
+}
+
+impl<'m, W: core::fmt::Write> Registry<'m, W> {
+    pub const fn new() -> Self {
+        Self { items: Vec::new() }
+    }
+
+    pub fn items(&self) -> &[DynamicItem<'m, W>] {
+        &self.items
+    }
+
+    /// Register a driver provided menu item
+    pub(super) fn register(&mut self, item: MenuItem<'m, W>) -> MenuResult {
+        match item {
+            MenuItem::Command { name, .. } => self.check_available(name)?,
+            MenuItem::Alias { alias, .. } => self.check_available(alias)?,
+            MenuItem::Group { commands, .. } => {
+                for command in commands {
+                    match command {
+                        MenuItem::Command { name, .. } => self.check_available(name)?,
+                        MenuItem::Alias { alias, .. } => self.check_available(alias)?,
+                        MenuItem::Group { .. } => return Err(MenuError::InvalidArgument),
+                    }
+                }
+            }
+        }
+        self.items.push(DynamicItem::Item(item));
+        Ok(())
+    }
+
+    /// Register a command with a capturing action
+    pub(super) fn register_command(
+        &mut self,
+        name: &str,
+        help: &str,
+        description: &str,
+        action: DynamicAction<'m, W>,
+    ) -> MenuResult {
+        self.check_available(name)?;
+        self.items.push(DynamicItem::Command {
+            name: name.into(),
+            help: help.into(),
+            description: description.into(),
+            action,
+        });
+        Ok(())
+    }
+
+    /// Create or replace a user alias
+    pub(super) fn alias(&mut self, alias: &str, command: &str) -> MenuResult {
+        self.items
+            .retain(|item| !matches!(item, DynamicItem::Alias { alias: a, .. } if a == alias));
+        self.check_available(alias)?;
+        self.items.push(DynamicItem::Alias {
+            alias: alias.into(),
+            command: command.into(),
+        });
+        Ok(())
+    }
+
+    /// Remove a command, alias or group (by title)
+    pub(super) fn unregister(&mut self, name: &str) -> MenuResult {
+        let len = self.items.len();
+        self.items.retain(|item| match item {
+            DynamicItem::Item(MenuItem::Command { name: n, .. }) => *n != name,
+            DynamicItem::Item(MenuItem::Alias { alias, .. }) => *alias != name,
+            DynamicItem::Item(MenuItem::Group { title, .. }) => *title != name,
+            DynamicItem::Command { name: n, .. } => n != name,
+            DynamicItem::Alias { alias, .. } => alias != name,
+        });
+        if self.items.len() == len {
+            Err(MenuError::CommandNotFound)
+        } else {
+            Ok(())
+        }
+    }
+
+    /// Remove all commands registered with a capturing action.
+    /// Used when the program that registered them is unloaded.
+    pub(super) fn unregister_dynamic_commands(&mut self) {
+        self.items
+            .retain(|item| !matches!(item, DynamicItem::Command { .. }));
+    }
+
+    pub fn help(&self, name: &str) -> Option<Help<'_>> {
+        fn help_item<'r, W: core::fmt::Write>(
+            item: &'r MenuItem<W>,
+            name: &str,
+        ) -> Option<Help<'r>> {
+            match item {
+                MenuItem::Command { name: n, help, .. } if *n == name => Some(Help::Command(help)),
+                MenuItem::Alias { alias, command } if *alias == name => Some(Help::Alias(command)),
+                MenuItem::Group { commands, .. } => {
+                    commands.iter().find_map(|c| help_item(c, name))
+                }
+                _ => None,
+            }
+        }
+
+        self.items.iter().find_map(|item| match item {
+            DynamicItem::Item(item) => help_item(item, name),
+            DynamicItem::Command { name: n, help, .. } if n == name => Some(Help::Command(help)),
+            DynamicItem::Alias { alias, command } if alias == name => Some(Help::Alias(command)),
+            _ => None,
+        })
+    }
+
+    pub fn contains(&self, name: &str) -> bool {
+        self.resolve(name).is_some()
+    }
+
+    pub(super) fn resolve(&self, name: &str) -> Option<Resolved<'m, W>> {
+        fn resolve_item<'m, W: core::fmt::Write>(
+            item: &MenuItem<'m, W>,
+            name: &str,
+        ) -> Option<Resolved<'m, W>> {
+            match item {
+                MenuItem::Command {
+                    name: n, action, ..
+                } if *n == name => Some(Resolved::Action(*action)),
+                MenuItem::Alias { alias, command } if *alias == name => {
+                    Some(Resolved::Alias((*command).into()))
+                }
+                MenuItem::Group { commands, .. } => {
+                    commands.iter().find_map(|c| resolve_item(c, name))
+                }
+                _ => None,
+            }
+        }
+
+        self.items.iter().find_map(|item| match item {
+            DynamicItem::Item(item) => resolve_item(item, name),
+            DynamicItem::Command {
+                name: n, action, ..
+            } if n == name => Some(Resolved::Dynamic(action.clone())),
+            DynamicItem::Alias { alias, command } if alias == name => {
+                Some(Resolved::Alias(command.clone()))
+            }
+            _ => None,
+        })
+    }
+
+    fn check_available(&self, name: &str) -> MenuResult {
+        if self.contains(name) {
+            Err(MenuError::CommandExists)
+        } else {
+            Ok(())
+        }
+    }
+}
+
+impl<'m, W: core::fmt::Write> Default for Registry<'m, W> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
@@ -0,0 +1,51 @@
+/// Maximum number of tokens (command + arguments) on a single line
+pub const MAX_TOKENS: usize = 17;
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum TokenizeError {
+    /// A quote was opened but never closed
+    UnterminatedQuote,
+    /// More tokens than fit in the output buffer
+    TooManyTokens,
+}
+
+impl core::fmt::Display for TokenizeError {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        match self {
+            Self::UnterminatedQuote => write!(f, "Unterminated quote"),
+            Self::TooManyTokens => write!(f, "Too many arguments"),
+        }
+    }
+}
+
+/// Split `line` on whitespace into `tokens`, returns the number of tokens found.
+///
+/// A token starting with `'` or `"` extends to the matching quote, which allows
+/// whitespace inside a single argument (`alias ll 'ls -l'`). The quotes themselves
+/// are not part of the token.
+pub fn tokenize<'l>(line: &'l str, tokens: &mut [&'l str]) -> Result<usize, TokenizeError> {
+    let mut n = 0;
+    let mut rest = line.trim_start();
+
+    while !rest.is_empty() {
+        let (token, remaining) = match rest.as_bytes()[0] {
+            quote @ (b'\'' | b'"') => match rest[1..].find(quote as char) {
+                Some(end) => (&rest[1..end + 1], &rest[end + 2..]),
+                None => return Err(TokenizeError::UnterminatedQuote),
+            },
+            _ => match rest.find(char::is_whitespace) {
+                Some(end) => (&rest[..end], &rest[end..]),
+                None => (rest, ""),
+            },
+        };
+
+        match tokens.get_mut(n) {
+            Some(slot) => *slot = token,
+            None => return Err(TokenizeError::TooManyTokens),
+        }
+        n += 1;
+        rest = remaining.trim_start();
+    }
+
+    Ok(n)
+}
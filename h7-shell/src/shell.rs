@@ -0,0 +1,322 @@
+use {
+    crate::{
+        json::{self, OutputMode},
+        menu::{tokenizer, Menu},
+    },
+    alloc::string::String,
+    core::fmt::Write,
+};
+
+const CTRL_C: u8 = 0x03;
+const BACKSPACE: u8 = 0x08;
+const TAB: u8 = 0x09;
+const LINE_FEED: u8 = 10;
+const CARRIAGE_RETURN: u8 = 13;
+const CTRL_U: u8 = 0x15;
+const ESCAPE: u8 = 0x1B;
+const DELETE: u8 = 0x7F;
+const JSON_FLAG: &str = "--json";
+
+/// Where the shell is within an escape sequence sent by a terminal's special keys
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Escape {
+    None,
+    /// After ESC
+    Start,
+    /// `ESC [`, parameters up to a final byte
+    Csi,
+    /// `ESC O`, one more byte
+    Ss3,
+}
+
+/// Line buffer in front of a [`Menu`], fed one byte at a time
+pub struct Shell<'m, W: Write, const N: usize = 1024> {
+    menu: Menu<'m, W>,
+    line: [u8; N],
+    line_len: usize,
+    /// Echo and line editing for terminals in raw mode, see [`Shell::set_interactive`]
+    interactive: bool,
+    escape: Escape,
+    /// The last byte was a carriage return, a line feed after it ends no second line
+    after_cr: bool,
+    /// Last line run, for the up key
+    previous: [u8; N],
+    previous_len: usize,
+}
+
+impl<'m, W: Write, const N: usize> Shell<'m, W, N> {
+    pub fn new(menu: Menu<'m, W>) -> Self {
+        Self {
+            menu,
+            line: [0; N],
+            line_len: 0,
+            interactive: false,
+            escape: Escape::None,
+            after_cr: false,
+            previous: [0; N],
+            previous_len: 0,
+        }
+    }
+
+    /// Echo input and handle editing keys, for terminals that send each key as it is pressed.
+    ///
+    /// Carriage return runs the line like line feed does, backspace/delete remove the last
+    /// character, Ctrl-C drops the line, Ctrl-U clears it, Tab completes command names and the up
+    /// key brings back the previous line. Other escape sequences and control characters are
+    /// ignored.
+    pub fn set_interactive(&mut self, interactive: bool) {
+        self.interactive = interactive;
+    }
+
+    pub fn menu(&mut self) -> &mut Menu<'m, W> {
+        &mut self.menu
+    }
+
+    pub fn prompt(&mut self) -> core::fmt::Result {
+        write!(self.menu.writer(), "> ")
+    }
+
+    /// Feed one byte of input, a line feed runs the buffered line.
+    /// Returns `true` if a line was run.
+    pub fn push(&mut self, byte: u8) -> bool {
+        if self.interactive {
+            return self.push_interactive(byte);
+        }
+        match byte {
+            LINE_FEED => {
+                self.run_line();
+                true
+            }
+            c => {
+                if self.line_len < self.line.len() {
+                    self.line[self.line_len] = c;
+                    self.line_len += 1;
+                } else {
+                    let _ = writeln!(self.menu, "Error: Buffer full");
+                }
+                false
+            }
+        }
+    }
+
+    fn push_interactive(&mut self, byte: u8) -> bool {
+        match self.escape {
+            Escape::None => {}
+            Escape::Start => {
+                self.escape = match byte {
+                    b'[' => Escape::Csi,
+                    b'O' => Escape::Ss3,
+                    _ => Escape::None,
+                };
+                return false;
+            }
+            Escape::Csi if !(0x40..=0x7E).contains(&byte) => return false,
+            Escape::Csi | Escape::Ss3 => {
+                self.escape = Escape::None;
+                if byte == b'A' {
+                    self.recall();
+                }
+                return false;
+            }
+        }
+
+        let after_cr = core::mem::replace(&mut self.after_cr, byte == CARRIAGE_RETURN);
+        match byte {
+            LINE_FEED if after_cr => return false,
+            CARRIAGE_RETURN | LINE_FEED => {
+                let _ = writeln!(self.menu.writer());
+                let len = self.line_len;
+                if len > 0 {
+                    self.previous[..len].copy_from_slice(&self.line[..len]);
+                    self.previous_len = len;
+                }
+                self.run_line();
+                return true;
+            }
+            BACKSPACE | DELETE => {
+                if self.pop_char() {
+                    let _ = write!(self.menu.writer(), "\x08 \x08");
+                }
+            }
+            CTRL_C => {
+                self.line_len = 0;
+                let _ = writeln!(self.menu.writer(), "^C");
+                let _ = self.prompt();
+            }
+            CTRL_U => self.clear_line(),
+            TAB => self.complete(),
+            ESCAPE => self.escape = Escape::Start,
+            c if c < 0x20 => {}
+            c => {
+                if self.line_len < self.line.len() {
+                    self.line[self.line_len] = c;
+                    self.line_len += 1;
+                    self.echo_last_char();
+                }
+            }
+        }
+        false
+    }
+
+    /// Where the last character of the line starts, it may still be incomplete
+    fn char_start(&self) -> usize {
+        let mut start = self.line_len.saturating_sub(1);
+        while start > 0 && self.line_len - start < 4 && self.line[start] & 0xC0 == 0x80 {
+            start -= 1;
+        }
+        start
+    }
+
+    /// Echo the character just typed once all of its bytes are there
+    fn echo_last_char(&mut self) {
+        let start = self.char_start();
+        if let Ok(c) = core::str::from_utf8(&self.line[start..self.line_len]) {
+            let _ = self.menu.writer().write_str(c);
+        }
+    }
+
+    /// Remove the last character, returns `false` if the line was empty
+    fn pop_char(&mut self) -> bool {
+        if self.line_len == 0 {
+            return false;
+        }
+        self.line_len = self.char_start();
+        true
+    }
+
+    /// Empty the line and erase it on the terminal
+    fn clear_line(&mut self) {
+        let line = &self.line[..core::mem::replace(&mut self.line_len, 0)];
+        let chars = core::str::from_utf8(line).map_or(line.len(), |s| s.chars().count());
+        for _ in 0..chars {
+            let _ = write!(self.menu.writer(), "\x08 \x08");
+        }
+    }
+
+    /// Replace the line with the previous one
+    fn recall(&mut self) {
+        self.clear_line();
+        let len = self.previous_len;
+        self.line[..len].copy_from_slice(&self.previous[..len]);
+        self.line_len = len;
+        if let Ok(line) = core::str::from_utf8(&self.line[..len]) {
+            let _ = self.menu.writer().write_str(line);
+        }
+    }
+
+    /// Complete the command name being typed as far as it is unambiguous, or list the
+    /// candidates if it already is
+    fn complete(&mut self) {
+        let prefix = match core::str::from_utf8(&self.line[..self.line_len]) {
+            Ok(prefix) if !prefix.contains(' ') => String::from(prefix),
+            _ => return,
+        };
+        let mut first = None::<String>;
+        let mut common = 0;
+        let mut matches = 0;
+        let mut candidates = String::new();
+        self.menu.for_each_name(&mut |name| {
+            if !name.starts_with(prefix.as_str()) {
+                return;
+            }
+            match &first {
+                None => {
+                    common = name.len();
+                    first = Some(String::from(name));
+                }
+                Some(first) => {
+                    common = common.min(
+                        first
+                            .bytes()
+                            .zip(name.bytes())
+                            .take_while(|(a, b)| a == b)
+                            .count(),
+                    )
+                }
+            }
+            matches += 1;
+            candidates.push_str(name);
+            candidates.push_str("  ");
+        });
+        let Some(first) = first else {
+            return;
+        };
+        // Names that differ within a multi-byte character only share the characters before it
+        while !first.is_char_boundary(common) {
+            common -= 1;
+        }
+
+        let mut completion = String::from(&first[prefix.len()..common]);
+        if matches == 1 {
+            completion.push(' ');
+        }
+        if completion.is_empty() {
+            let _ = writeln!(self.menu.writer());
+            let _ = writeln!(self.menu.writer(), "{}", candidates.trim_end());
+            let _ = self.prompt();
+            let _ = self.menu.writer().write_str(&prefix);
+            return;
+        }
+        for c in completion.bytes() {
+            if self.line_len < self.line.len() {
+                self.line[self.line_len] = c;
+                self.line_len += 1;
+                self.echo_last_char();
+            }
+        }
+    }
+
+    /// Tokenize and run a complete line, errors are written to the menu writer
+    pub fn execute(&mut self, line: &str) {
+        execute(&mut self.menu, line)
+    }
+
+    fn run_line(&mut self) {
+        let len = core::mem::replace(&mut self.line_len, 0);
+        match core::str::from_utf8(&self.line[..len]) {
+            Ok(s) => execute(&mut self.menu, s),
+            Err(e) => {
+                let _ = writeln!(self.menu, "Error: {e}");
+            }
+        }
+    }
+}
+
+fn execute<W: Write>(menu: &mut Menu<W>, line: &str) {
+    // Command and up to 16 arguments
+    let mut tokens = [""; tokenizer::MAX_TOKENS];
+    match tokenizer::tokenize(line, &mut tokens) {
+        Ok(0) => {}
+        Ok(n) => {
+            // `--json` anywhere on the line switches the output mode for this command only
+            let mut len = 0;
+            let mut json = false;
+            for i in 0..n {
+                if tokens[i] == JSON_FLAG {
+                    json = true;
+                } else {
+                    tokens[len] = tokens[i];
+                    len += 1;
+                }
+            }
+            if len == 0 {
+                return;
+            }
+
+            let output = menu.output();
+            if json {
+                menu.set_output(OutputMode::Json);
+            }
+            if let Err(e) = menu.run(tokens[0], &tokens[1..len]) {
+                let _ = match menu.output() {
+                    OutputMode::Json => json::write_error(menu.writer(), e),
+                    OutputMode::Text => writeln!(menu.writer(), "Error: {e}"),
+                };
+            }
+            menu.set_output(output);
+        }
+        Err(e) => {
+            let _ = writeln!(menu.writer(), "Error: {e}");
+        }
+    }
+}
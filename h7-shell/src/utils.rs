@@ -1,5 +1,5 @@
 use {
-    crate::terminal::menu::{Menu, MenuError, MenuItem, MenuResult},
+    crate::menu::{Menu, MenuError, MenuItem, MenuResult},
     chrono::{Datelike, NaiveDate},
 };
 
@@ -159,3 +159,93 @@ pub const fn check_args_len(expected: u8, actual: usize) -> MenuResult {
         Ok(())
     }
 }
+
+/// Split a leading `flag` off `args`, e.g. the `-r` in `rm -r <dir>`
+pub fn strip_flag<'a, 'b>(args: &'a [&'b str], flag: &str) -> (bool, &'a [&'b str]) {
+    match args.split_first() {
+        Some((first, rest)) if *first == flag => (true, rest),
+        _ => (false, args),
+    }
+}
+
+/// Parse a size in bytes with an optional binary suffix, e.g. `512`, `64K` or `4M`
+pub fn parse_size(s: &str) -> Option<usize> {
+    let (digits, shift) = match s.as_bytes().last()? {
+        b'k' | b'K' => (&s[..s.len() - 1], 10),
+        b'm' | b'M' => (&s[..s.len() - 1], 20),
+        b'g' | b'G' => (&s[..s.len() - 1], 30),
+        _ => (s, 0),
+    };
+    digits.parse::<usize>().ok()?.checked_mul(1 << shift)
+}
+
+/// Writes bytes as text, invalid UTF-8 is replaced with `�`.
+/// Sequences split across calls to [`Utf8Lossy::write_bytes`] are kept together.
+pub struct Utf8Lossy<'w, W: core::fmt::Write> {
+    writer: &'w mut W,
+    pending: [u8; 4],
+    pending_len: usize,
+}
+
+impl<'w, W: core::fmt::Write> Utf8Lossy<'w, W> {
+    pub fn new(writer: &'w mut W) -> Self {
+        Self {
+            writer,
+            pending: [0; 4],
+            pending_len: 0,
+        }
+    }
+
+    pub fn write_bytes(&mut self, mut bytes: &[u8]) -> core::fmt::Result {
+        // Complete a sequence left over from the previous call
+        while self.pending_len > 0 && !bytes.is_empty() {
+            self.pending[self.pending_len] = bytes[0];
+            match core::str::from_utf8(&self.pending[..self.pending_len + 1]) {
+                Ok(s) => {
+                    self.writer.write_str(s)?;
+                    self.pending_len = 0;
+                }
+                Err(e) if e.error_len().is_some() => {
+                    // Not a continuation byte, it starts over below
+                    self.writer.write_char(char::REPLACEMENT_CHARACTER)?;
+                    self.pending_len = 0;
+                    break;
+                }
+                Err(_) => self.pending_len += 1,
+            }
+            bytes = &bytes[1..];
+        }
+
+        loop {
+            match core::str::from_utf8(bytes) {
+                Ok(s) => return self.writer.write_str(s),
+                Err(e) => {
+                    let (valid, rest) = bytes.split_at(e.valid_up_to());
+                    // SAFETY: checked by from_utf8 above
+                    self.writer
+                        .write_str(unsafe { core::str::from_utf8_unchecked(valid) })?;
+                    match e.error_len() {
+                        Some(len) => {
+                            self.writer.write_char(char::REPLACEMENT_CHARACTER)?;
+                            bytes = &rest[len..];
+                        }
+                        None => {
+                            // Incomplete sequence at the end, wait for more
+                            self.pending[..rest.len()].copy_from_slice(rest);
+                            self.pending_len = rest.len();
+                            return Ok(());
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    /// Flush an incomplete trailing sequence
+    pub fn finish(self) -> core::fmt::Result {
+        if self.pending_len > 0 {
+            self.writer.write_char(char::REPLACEMENT_CHARACTER)?;
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,138 @@
+#![allow(dead_code)]
+
+use {
+    chrono::NaiveDate,
+    core::fmt::Write,
+    h7_shell::{
+        commands::{sys, time},
+        json::{JsonObject, OutputMode},
+        menu::{Menu, MenuItem},
+        Shell,
+    },
+};
+
+/// Date used by the clock dependent commands, Wednesday 14 Feb 2024 13:37:00
+pub fn now() -> chrono::NaiveDateTime {
+    NaiveDate::from_ymd_opt(2024, 2, 14)
+        .and_then(|d| d.and_hms_opt(13, 37, 0))
+        .unwrap()
+}
+
+pub const MENU: &[MenuItem<'static, String>] = &[
+    MenuItem::Group {
+        title: "System",
+        commands: &[
+            sys::alias(),
+            sys::commands(),
+            sys::help(),
+            sys::man(),
+            sys::programs(),
+            sys::unalias(),
+        ],
+    },
+    MenuItem::Group {
+        title: "Date / Time",
+        commands: &[
+            MenuItem::Command {
+                name: "cal",
+                help: "Show calendar",
+                description: "Show calendar",
+                action: |m, _| {
+                    time::write_calendar(m.writer(), &now())?;
+                    Ok(())
+                },
+            },
+            MenuItem::Command {
+                name: "date",
+                help: "date - Get system date and time",
+                description: "Get system date and time",
+                action: |m, _| {
+                    time::write_date_time(m.writer(), &now())?;
+                    writeln!(m.writer())?;
+                    Ok(())
+                },
+            },
+        ],
+    },
+    MenuItem::Group {
+        title: "Other",
+        commands: &[
+            MenuItem::Command {
+                name: "echo",
+                help: "echo [args..] - Print arguments",
+                description: "Print arguments",
+                action: |m, args| {
+                    writeln!(m.writer(), "{}", args.join(" "))?;
+                    Ok(())
+                },
+            },
+            MenuItem::Command {
+                name: "status",
+                help: "status [args..] - Print arguments in the current output mode",
+                description: "Print arguments in the current output mode",
+                action: |m, args| {
+                    match m.output() {
+                        OutputMode::Text => writeln!(m.writer(), "{:20} {}", "Args", args.len())?,
+                        OutputMode::Json => {
+                            let mut obj = JsonObject::new(m.writer())?;
+                            obj.num("args", args.len())?;
+                            obj.finish()?;
+                            writeln!(m.writer())?;
+                        }
+                    }
+                    Ok(())
+                },
+            },
+        ],
+    },
+];
+
+/// Runs lines through a [`Shell`] writing into a `String`
+pub struct Harness<const N: usize = 1024> {
+    shell: Shell<'static, String, N>,
+}
+
+impl<const N: usize> Harness<N> {
+    pub fn new() -> Self {
+        Self {
+            shell: Shell::new(Menu::new(String::new(), MENU)),
+        }
+    }
+
+    /// Echo and line editing on, like the firmware's sessions
+    pub fn interactive() -> Self {
+        let mut harness = Self::new();
+        harness.shell.set_interactive(true);
+        harness
+    }
+
+    pub fn menu(&mut self) -> &mut Menu<'static, String> {
+        self.shell.menu()
+    }
+
+    /// Feed `line` followed by a line feed byte by byte, returns everything written meanwhile
+    pub fn run(&mut self, line: &str) -> String {
+        for byte in line.bytes() {
+            assert!(!self.shell.push(byte), "line ran before line feed");
+        }
+        assert!(self.shell.push(b'\n'));
+        self.take_output()
+    }
+
+    /// Feed a single byte, returns `true` if a line was run
+    pub fn run_byte(&mut self, byte: u8) -> bool {
+        self.shell.push(byte)
+    }
+
+    /// Feed key presses as a raw terminal sends them, returns everything written meanwhile
+    pub fn keys(&mut self, keys: &str) -> String {
+        for byte in keys.bytes() {
+            self.shell.push(byte);
+        }
+        self.take_output()
+    }
+
+    pub fn take_output(&mut self) -> String {
+        core::mem::take(self.shell.menu().writer())
+    }
+}
@@ -0,0 +1,116 @@
+use h7_shell::input::{Flow, InputBuffer, Stats, XOFF, XON};
+
+fn drain(buf: &mut InputBuffer) -> Vec<u8> {
+    core::iter::from_fn(|| buf.pop()).collect()
+}
+
+#[test]
+fn wraps_around() {
+    let mut buf = InputBuffer::new(4, Flow::None);
+    for round in 0..3u8 {
+        for b in 0..3 {
+            buf.push(round * 10 + b);
+        }
+        assert_eq!(
+            drain(&mut buf),
+            [round * 10, round * 10 + 1, round * 10 + 2]
+        );
+    }
+    assert!(buf.is_empty());
+}
+
+#[test]
+fn counts_overflows() {
+    let mut buf = InputBuffer::new(4, Flow::None);
+    for b in 0..7 {
+        buf.push(b);
+    }
+    assert_eq!(buf.pop(), Some(0));
+    buf.push(7);
+    buf.push(8);
+    assert_eq!(drain(&mut buf), [1, 2, 3, 7]);
+    assert_eq!(
+        buf.stats(),
+        Stats {
+            received: 5,
+            dropped: 4,
+            overflows: 2,
+            peak: 4,
+        }
+    );
+}
+
+#[test]
+fn asks_sender_to_pause_and_resume() {
+    let mut buf = InputBuffer::new(8, Flow::XonXoff);
+    for b in 0..5 {
+        buf.push(b);
+        assert_eq!(buf.take_control(), None);
+    }
+    buf.push(5);
+    assert_eq!(buf.take_control(), Some(XOFF));
+    buf.push(6);
+    assert_eq!(buf.take_control(), None);
+
+    for _ in 0..4 {
+        buf.pop();
+        assert_eq!(buf.take_control(), None);
+    }
+    buf.pop();
+    assert_eq!(buf.take_control(), Some(XON));
+    assert_eq!(buf.stats().dropped, 0);
+}
+
+#[test]
+fn pauses_output_on_xoff() {
+    let mut buf = InputBuffer::new(8, Flow::XonXoff);
+    buf.push(XOFF);
+    assert!(buf.is_paused());
+    buf.push(b'a');
+    buf.push(XON);
+    assert!(!buf.is_paused());
+    assert_eq!(drain(&mut buf), b"a");
+}
+
+#[test]
+fn without_flow_control_xon_xoff_are_data() {
+    let mut buf = InputBuffer::new(4, Flow::None);
+    for b in [XOFF, 1, 2, XON] {
+        buf.push(b);
+    }
+    assert!(!buf.is_paused());
+    assert_eq!(buf.take_control(), None);
+    assert_eq!(drain(&mut buf), [XOFF, 1, 2, XON]);
+}
+
+#[test]
+fn raw_mode_passes_everything_through() {
+    let mut buf = InputBuffer::new(4, Flow::XonXoff);
+    buf.push(1);
+    buf.push(2);
+    buf.push(3);
+    assert_eq!(buf.take_control(), Some(XOFF));
+
+    // Entering raw mode lets the stopped sender go on
+    buf.set_raw(true);
+    assert_eq!(buf.take_control(), Some(XON));
+    buf.clear();
+    for b in [XOFF, XON, XOFF, XON] {
+        buf.push(b);
+    }
+    assert!(!buf.is_paused());
+    assert_eq!(buf.take_control(), None);
+    assert_eq!(drain(&mut buf), [XOFF, XON, XOFF, XON]);
+
+    buf.set_raw(false);
+    buf.push(XOFF);
+    assert!(buf.is_paused());
+}
+
+#[test]
+fn parses_flow_names() {
+    assert_eq!(Flow::parse("xonxoff"), Some(Flow::XonXoff));
+    assert_eq!(Flow::parse("none"), Some(Flow::None));
+    assert_eq!(Flow::parse("rtscts"), None);
+    assert_eq!(Flow::XonXoff.to_string(), "xonxoff");
+}
@@ -0,0 +1,149 @@
+mod harness;
+
+use {
+    h7_shell::{
+        commands::time::Iso8601,
+        json::{self, JsonObject, OutputMode},
+    },
+    harness::Harness,
+};
+
+fn object(f: impl FnOnce(&mut JsonObject<String>) -> core::fmt::Result) -> String {
+    let mut s = String::new();
+    let mut obj = JsonObject::new(&mut s).unwrap();
+    f(&mut obj).unwrap();
+    obj.finish().unwrap();
+    s
+}
+
+#[test]
+fn empty_object() {
+    assert_eq!(object(|_| Ok(())), "{}");
+}
+
+#[test]
+fn values() {
+    assert_eq!(
+        object(|o| {
+            o.str("s", "h7")?
+                .num("n", 42)?
+                .num("f", 1.5)?
+                .bool("b", true)?
+                .null("z")?
+                .opt_num("some", Some(-1))?
+                .opt_num("none", None::<u32>)?
+                .display("d", format_args!("{}MHz", 480))?
+                .opt_display("od", None::<&str>)?;
+            Ok(())
+        }),
+        r#"{"s":"h7","n":42,"f":1.5,"b":true,"z":null,"some":-1,"none":null,"d":"480MHz","od":null}"#
+    );
+}
+
+#[test]
+fn arrays_of_objects() {
+    assert_eq!(
+        object(|o| {
+            o.objects("items", [1, 2], |o, n| {
+                o.num("n", n)?;
+                Ok(())
+            })?
+            .objects("none", [(); 0], |_, _| Ok(()))?;
+            Ok(())
+        }),
+        r#"{"items":[{"n":1},{"n":2}],"none":[]}"#
+    );
+}
+
+#[test]
+fn escapes_strings() {
+    assert_eq!(
+        object(|o| {
+            o.str("q\"k", "a \"quoted\" \\ path\n\ttab\u{1}°C")?;
+            Ok(())
+        }),
+        r#"{"q\"k":"a \"quoted\" \\ path\n\ttab\u0001°C"}"#
+    );
+}
+
+#[test]
+fn nested_objects() {
+    assert_eq!(
+        object(|o| {
+            o.num("a", 1)?
+                .object("inner", |o| {
+                    o.num("b", 2)?.object("empty", |_| Ok(()))?;
+                    Ok(())
+                })?
+                .num("c", 3)?;
+            Ok(())
+        }),
+        r#"{"a":1,"inner":{"b":2,"empty":{}},"c":3}"#
+    );
+}
+
+#[test]
+fn output_mode_parse() {
+    assert_eq!("json".parse(), Ok(OutputMode::Json));
+    assert_eq!("text".parse(), Ok(OutputMode::Text));
+    assert!("xml".parse::<OutputMode>().is_err());
+    assert_eq!(OutputMode::default(), OutputMode::Text);
+}
+
+#[test]
+fn json_flag() {
+    let mut h = Harness::<1024>::new();
+    assert_eq!(h.run("status a b"), "Args                 2\n");
+    assert_eq!(h.run("status --json a b"), "{\"args\":2}\n");
+    assert_eq!(h.run("status a b --json"), "{\"args\":2}\n");
+    // Only for the one command
+    assert_eq!(h.menu().output(), OutputMode::Text);
+    assert_eq!(h.run("status"), "Args                 0\n");
+}
+
+#[test]
+fn json_flag_through_alias() {
+    let mut h = Harness::<1024>::new();
+    h.run("alias st status");
+    assert_eq!(h.run("st x --json"), "{\"args\":1}\n");
+    assert_eq!(h.run("--json"), "");
+}
+
+#[test]
+fn output_mode_setting() {
+    let mut h = Harness::<1024>::new();
+    h.menu().set_output(OutputMode::Json);
+    assert_eq!(h.run("status"), "{\"args\":0}\n");
+    // Restored after a command with the flag
+    assert_eq!(h.run("status --json"), "{\"args\":0}\n");
+    assert_eq!(h.menu().output(), OutputMode::Json);
+}
+
+#[test]
+fn errors_in_json_mode() {
+    let mut h = Harness::<1024>::new();
+    let text = h.run("nope");
+    assert!(text.starts_with("Error: "), "{text}");
+    let json = h.run("nope --json");
+    assert!(json.starts_with("{\"error\":\""), "{json}");
+    assert!(json.ends_with("\"}\n"), "{json}");
+    assert_eq!(h.menu().output(), OutputMode::Text);
+
+    h.menu().set_output(OutputMode::Json);
+    assert_eq!(h.run("nope"), json);
+}
+
+#[test]
+fn write_object_and_error() {
+    let mut s = String::new();
+    json::write_object(&mut s, |o| {
+        o.display("time", Iso8601(&harness::now()))?;
+        Ok(())
+    })
+    .unwrap();
+    json::write_error(&mut s, "Not \"mounted\"").unwrap();
+    assert_eq!(
+        s,
+        "{\"time\":\"2024-02-14T13:37:00\"}\n{\"error\":\"Not \\\"mounted\\\"\"}\n"
+    );
+}
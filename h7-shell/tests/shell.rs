@@ -0,0 +1,304 @@
+mod harness;
+
+use {
+    core::{cell::Cell, fmt::Write},
+    h7_shell::menu::MenuItem,
+    harness::Harness,
+    std::rc::Rc,
+};
+
+#[test]
+fn runs_command_with_args() {
+    let mut h = Harness::<1024>::new();
+    assert_eq!(h.run("echo hello   world"), "hello world\n");
+}
+
+#[test]
+fn empty_line_does_nothing() {
+    let mut h = Harness::<1024>::new();
+    assert_eq!(h.run(""), "");
+    assert_eq!(h.run("   \r"), "");
+}
+
+#[test]
+fn quoted_arguments() {
+    let mut h = Harness::<1024>::new();
+    assert_eq!(h.run("echo 'a  b' \"c d\" e"), "a  b c d e\n");
+    assert_eq!(h.run("echo 'a b"), "Error: Unterminated quote\n");
+}
+
+#[test]
+fn too_many_arguments() {
+    let mut h = Harness::<1024>::new();
+    assert_eq!(
+        h.run("echo 1 2 3 4 5 6 7 8 9 10 11 12 13 14 15 16"),
+        "1 2 3 4 5 6 7 8 9 10 11 12 13 14 15 16\n"
+    );
+    assert_eq!(
+        h.run("echo 1 2 3 4 5 6 7 8 9 10 11 12 13 14 15 16 17"),
+        "Error: Too many arguments\n"
+    );
+}
+
+#[test]
+fn unknown_command() {
+    let mut h = Harness::<1024>::new();
+    assert_eq!(h.run("nope"), "Error: Command not found\n");
+}
+
+#[test]
+fn buffer_full() {
+    let mut h = Harness::<4>::new();
+    assert_eq!(
+        h.run("echo x"),
+        "Error: Buffer full\nError: Buffer full\n\n"
+    );
+}
+
+#[test]
+fn invalid_utf8() {
+    let mut h = Harness::<1024>::new();
+    for byte in [0xff, 0xfe, b'\n'] {
+        h.run_byte(byte);
+    }
+    assert!(h.take_output().starts_with("Error: invalid utf-8"));
+}
+
+#[test]
+fn help() {
+    let mut h = Harness::<1024>::new();
+    assert_eq!(h.run("help echo"), "echo [args..] - Print arguments\n");
+    assert_eq!(h.run("help nope"), "Error: Command not found\n");
+    assert_eq!(h.run("help"), "Error: Expected 1 argument, got 0\n");
+    assert_eq!(h.run("man echo"), "echo [args..] - Print arguments\n");
+    assert_eq!(
+        h.run("help man"),
+        "'man' aliased to 'help'\nhelp <program> - Show help about a program\n"
+    );
+}
+
+#[test]
+fn programs() {
+    let mut h = Harness::<1024>::new();
+    let out = h.run("programs");
+    assert_eq!(out, h.run("commands"));
+    let lines = out.lines().collect::<Vec<_>>();
+    assert_eq!(lines[0], "-------------------- System --------------------");
+    assert_eq!(
+        lines[1],
+        "  alias                List, show or create command aliases"
+    );
+    assert_eq!(lines[2], "  commands             aliased to programs");
+    assert!(lines.contains(&"----------------- Date / Time ------------------"));
+    assert!(lines.contains(&"  echo                 Print arguments"));
+    assert!(!lines.iter().any(|l| l.contains("Runtime")));
+}
+
+#[test]
+fn alias() {
+    let mut h = Harness::<1024>::new();
+    assert_eq!(h.run("alias greet 'echo hello world'"), "");
+    assert_eq!(h.run("greet again"), "hello world again\n");
+    assert_eq!(h.run("alias hi echo hi there"), "");
+    assert_eq!(h.run("hi"), "hi there\n");
+    assert_eq!(
+        h.run("alias"),
+        "greet                'echo hello world'\nhi                   'echo hi there'\n"
+    );
+    assert_eq!(h.run("alias hi"), "'hi' aliased to 'echo hi there'\n");
+    assert_eq!(
+        h.run("help greet"),
+        "'greet' aliased to 'echo hello world'\necho [args..] - Print arguments\n"
+    );
+    assert!(h
+        .run("programs")
+        .contains("------------------- Runtime --------------------\n  greet                aliased to echo hello world\n"));
+
+    // Replace
+    assert_eq!(h.run("alias hi 'echo bye'"), "");
+    assert_eq!(h.run("hi"), "bye\n");
+
+    assert_eq!(h.run("unalias hi"), "");
+    assert_eq!(h.run("hi"), "Error: Command not found\n");
+    assert_eq!(h.run("unalias hi"), "Error: Command not found\n");
+}
+
+#[test]
+fn alias_can_not_shadow_builtin() {
+    let mut h = Harness::<1024>::new();
+    assert_eq!(
+        h.run("alias echo 'echo x'"),
+        "Error: Command already exists\n"
+    );
+    assert_eq!(h.run("unalias echo"), "Error: Command not found\n");
+}
+
+#[test]
+fn alias_loop() {
+    let mut h = Harness::<1024>::new();
+    h.run("alias a b");
+    h.run("alias b a");
+    assert_eq!(h.run("a"), "Error: Command error: Alias nested too deep\n");
+    // Depth is reset after the failure
+    h.run("alias c 'echo ok'");
+    assert_eq!(h.run("c"), "ok\n");
+}
+
+#[test]
+fn register_driver_item() {
+    const DRIVER: MenuItem<'static, String> = MenuItem::Group {
+        title: "Driver",
+        commands: &[MenuItem::Command {
+            name: "drv",
+            help: "drv - Driver command",
+            description: "Driver command",
+            action: |m, args| {
+                writeln!(m.writer(), "drv {args:?}")?;
+                Ok(())
+            },
+        }],
+    };
+
+    let mut h = Harness::<1024>::new();
+    h.menu().register(DRIVER).unwrap();
+    assert!(h.menu().register(DRIVER).is_err());
+    assert_eq!(h.run("drv a"), "drv [\"a\"]\n");
+    assert_eq!(h.run("help drv"), "drv - Driver command\n");
+    assert!(h.run("programs").contains(
+        "------------------- Runtime --------------------\n  \
+         ------------------- Driver -------------------\n    \
+         drv                  Driver command\n"
+    ));
+
+    h.menu().unregister("Driver").unwrap();
+    assert_eq!(h.run("drv"), "Error: Command not found\n");
+}
+
+#[test]
+fn register_dynamic_command() {
+    let calls = Rc::new(Cell::new(0));
+    let mut h = Harness::<1024>::new();
+    let counter = calls.clone();
+    h.menu()
+        .register_command(
+            "count",
+            "count - Count calls",
+            "Count calls",
+            Rc::new(move |m, _| {
+                counter.set(counter.get() + 1);
+                writeln!(m.writer(), "{}", counter.get())?;
+                Ok(())
+            }),
+        )
+        .unwrap();
+    assert!(h
+        .menu()
+        .register_command("help", "", "", Rc::new(|_, _| Ok(())))
+        .is_err());
+
+    assert_eq!(h.run("count"), "1\n");
+    assert_eq!(h.run("count"), "2\n");
+    assert_eq!(calls.get(), 2);
+    assert!(h
+        .run("programs")
+        .contains("  count                Count calls\n"));
+
+    h.menu().unregister_dynamic_commands();
+    assert_eq!(h.run("count"), "Error: Command not found\n");
+}
+
+#[test]
+fn date() {
+    let mut h = Harness::<1024>::new();
+    assert_eq!(h.run("date"), "Wed Feb 14 13:37:00 2024\n");
+}
+
+#[test]
+fn cal() {
+    let mut h = Harness::<1024>::new();
+    assert_eq!(
+        h.run("cal"),
+        concat!(
+            "         Feb 2024\n",
+            "Wk | Mo Tu We Th Fr Sa Su\n",
+            " 5 |           1  2  3  4\n",
+            " 6 |  5  6  7  8  9 10 11\n",
+            " 7 | 12 13[14]15 16 17 18\n",
+            " 8 | 19 20 21 22 23 24 25\n",
+            " 9 | 26 27 28 29\n",
+        )
+    );
+}
+
+#[test]
+fn working_directory_is_kept() {
+    let mut h = Harness::<1024>::new();
+    assert_eq!(h.menu().cwd(), "");
+    h.menu().set_cwd("sdcard:/logs".to_string());
+    h.run("echo hi");
+    assert_eq!(h.menu().cwd(), "sdcard:/logs");
+}
+
+#[test]
+fn interactive_echo_and_carriage_return() {
+    let mut h = Harness::<1024>::interactive();
+    assert_eq!(h.keys("echo hi"), "echo hi");
+    assert!(h.run_byte(b'\r'));
+    assert_eq!(h.take_output(), "\nhi\n");
+    // The line feed of a CR LF pair runs no second line
+    assert!(!h.run_byte(b'\n'));
+    assert!(h.run_byte(b'\n'));
+}
+
+#[test]
+fn interactive_backspace() {
+    let mut h = Harness::<1024>::interactive();
+    assert_eq!(h.keys("echo hä\x7f\x7fo"), "echo hä\x08 \x08\x08 \x08o");
+    assert_eq!(
+        h.keys("\x08\x08\x08\x08\x08\x08\x08"),
+        "\x08 \x08".repeat(6)
+    );
+    assert_eq!(h.keys("date\r"), "date\nWed Feb 14 13:37:00 2024\n");
+}
+
+#[test]
+fn interactive_ctrl_c_and_ctrl_u() {
+    let mut h = Harness::<1024>::interactive();
+    assert_eq!(h.keys("echo a\x03"), "echo a^C\n> ");
+    assert_eq!(h.keys("xy\x15"), "xy\x08 \x08\x08 \x08");
+    assert_eq!(h.keys("echo b\r"), "echo b\nb\n");
+}
+
+#[test]
+fn interactive_recall_previous_line() {
+    let mut h = Harness::<1024>::interactive();
+    h.keys("echo one\r");
+    assert_eq!(h.keys("ec\x1b[A"), "ec\x08 \x08\x08 \x08echo one");
+    assert_eq!(h.keys("\r"), "\none\n");
+    // Application cursor mode, other keys are ignored
+    assert_eq!(h.keys("\x1bOA\x1b[1;5C\x1b[D\r"), "echo one\none\n");
+}
+
+#[test]
+fn interactive_tab_completion() {
+    let mut h = Harness::<1024>::interactive();
+    assert_eq!(h.keys("ec\t"), "echo ");
+    assert_eq!(h.keys("x\r"), "x\nx\n");
+    // Ambiguous, nothing to add: the candidates are listed
+    assert_eq!(h.keys("c\t"), "c\ncommands  cal\n> c");
+    assert_eq!(h.keys("\x15zz\t"), "\x08 \x08zz");
+}
+
+#[test]
+fn interactive_tab_completion_multibyte() {
+    let mut h = Harness::<1024>::interactive();
+    h.keys("alias xé echo\r");
+    h.keys("alias xè echo\r");
+    // Both start with the same byte of 'é' and 'è'
+    let out = h.keys("x\t");
+    assert!(out.starts_with("x\n"), "{out:?}");
+    assert!(out.contains("xé") && out.contains("xè"), "{out:?}");
+    assert!(out.ends_with("> x"), "{out:?}");
+    h.keys("\x15");
+    assert_eq!(h.keys("xé\t"), "xé ");
+}
@@ -0,0 +1,60 @@
+use h7_shell::utils::{parse_size, strip_flag, Utf8Lossy};
+
+fn lossy(chunks: &[&[u8]]) -> String {
+    let mut s = String::new();
+    let mut w = Utf8Lossy::new(&mut s);
+    for chunk in chunks {
+        w.write_bytes(chunk).unwrap();
+    }
+    w.finish().unwrap();
+    s
+}
+
+#[test]
+fn valid_text() {
+    assert_eq!(lossy(&[b"hello ", "wörld".as_bytes()]), "hello wörld");
+}
+
+#[test]
+fn invalid_bytes_replaced() {
+    assert_eq!(lossy(&[b"a\xffb\xfe"]), "a\u{fffd}b\u{fffd}");
+}
+
+#[test]
+fn sequence_split_across_chunks() {
+    let s = "°C €".as_bytes();
+    // Split in every possible place
+    for i in 0..s.len() {
+        for j in i..s.len() {
+            assert_eq!(lossy(&[&s[..i], &s[i..j], &s[j..]]), "°C €");
+        }
+    }
+    // One byte at a time
+    let chunks = s.chunks(1).collect::<Vec<_>>();
+    assert_eq!(lossy(&chunks), "°C €");
+}
+
+#[test]
+fn truncated_sequence() {
+    assert_eq!(lossy(&[b"ok\xe2\x82"]), "ok\u{fffd}");
+    assert_eq!(lossy(&[b"\xe2\x82", b"x"]), "\u{fffd}x");
+}
+
+#[test]
+fn leading_flag_stripped() {
+    assert_eq!(strip_flag(&["-r", "a", "b"], "-r"), (true, &["a", "b"][..]));
+    assert_eq!(strip_flag(&["a", "-r"], "-r"), (false, &["a", "-r"][..]));
+    assert_eq!(strip_flag(&[], "-r"), (false, &[][..]));
+}
+
+#[test]
+fn sizes() {
+    assert_eq!(parse_size("512"), Some(512));
+    assert_eq!(parse_size("64K"), Some(64 * 1024));
+    assert_eq!(parse_size("4m"), Some(4 * 1024 * 1024));
+    assert_eq!(parse_size("1G"), Some(1024 * 1024 * 1024));
+    assert_eq!(parse_size(""), None);
+    assert_eq!(parse_size("M"), None);
+    assert_eq!(parse_size("4X"), None);
+    assert_eq!(parse_size("-1"), None);
+}
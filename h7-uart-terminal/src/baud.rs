@@ -0,0 +1,83 @@
+//! Changing the board's baud rate and ours together, with `sys uart baud -t` on the board.
+
+use {
+    serialport::{ClearBuffer, SerialPort},
+    std::{
+        io::{self, ErrorKind},
+        thread,
+        time::{Duration, Instant},
+    },
+};
+
+/// How long the board waits for Enter at the new rate before it goes back to the old one
+pub const REVERT_TIMEOUT: Duration = Duration::from_secs(10);
+/// For each reply of the board
+pub const REPLY_TIMEOUT: Duration = Duration::from_secs(2);
+/// The board switches once its announcement went out, give it the time to
+const SWITCH_DELAY: Duration = Duration::from_millis(20);
+/// Read timeout while watching for a reply
+const POLL_INTERVAL: Duration = Duration::from_millis(50);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Switch {
+    /// Both ends run at the new rate
+    Switched,
+    /// The board didn't take the command, nothing changed
+    Refused,
+    /// The board switched but never heard the confirmation. `port` is back at the old rate,
+    /// the board too once [`REVERT_TIMEOUT`] passed.
+    Lost,
+}
+
+/// Switch the board's UART console and `port` to `rate`, until the board is reset
+pub fn switch(port: &mut dyn SerialPort, rate: u32, reply_timeout: Duration) -> io::Result<Switch> {
+    let old = port.baud_rate()?;
+    let timeout = port.timeout();
+    let result = handshake(port, rate, reply_timeout);
+    port.set_timeout(timeout)?;
+    match result {
+        Ok(Switch::Switched) => Ok(Switch::Switched),
+        result => {
+            port.set_baud_rate(old)?;
+            result
+        }
+    }
+}
+
+fn handshake(port: &mut dyn SerialPort, rate: u32, reply_timeout: Duration) -> io::Result<Switch> {
+    port.write_all(format!("sys uart baud -t {rate}\n").as_bytes())?;
+    if !wait_for_line(port, &format!("Switching to {rate} baud"), reply_timeout)? {
+        return Ok(Switch::Refused);
+    }
+
+    thread::sleep(SWITCH_DELAY);
+    port.set_baud_rate(rate)?;
+    port.clear(ClearBuffer::Input)?;
+    port.write_all(b"\n")?;
+    match wait_for_line(port, &format!("Keeping {rate} baud"), reply_timeout)? {
+        true => Ok(Switch::Switched),
+        false => Ok(Switch::Lost),
+    }
+}
+
+/// Read until a whole line containing `text` went by, `false` if it didn't in time
+fn wait_for_line(port: &mut dyn SerialPort, text: &str, timeout: Duration) -> io::Result<bool> {
+    let deadline = Instant::now() + timeout;
+    port.set_timeout(POLL_INTERVAL)?;
+    let mut seen = Vec::new();
+    let mut buf = [0u8; 256];
+    while Instant::now() < deadline {
+        match port.read(&mut buf) {
+            Ok(n) => seen.extend_from_slice(&buf[..n]),
+            Err(e) if e.kind() == ErrorKind::TimedOut => continue,
+            Err(e) => return Err(e),
+        }
+        let found = seen
+            .windows(text.len())
+            .position(|window| window == text.as_bytes());
+        if found.is_some_and(|start| seen[start..].contains(&b'\n')) {
+            return Ok(true);
+        }
+    }
+    Ok(false)
+}
@@ -0,0 +1,110 @@
+//! Keyboard input in raw mode. Key presses go to the board as they are typed, Ctrl-A starts a
+//! command for the terminal itself.
+
+use std::io::{self, Write};
+
+/// Starts a terminal command, pressed twice it sends itself
+pub const ESCAPE_KEY: u8 = 0x01;
+
+pub const HELP: &str = "\
+Ctrl-A q       Quit
+Ctrl-A s       Send a file to the board
+Ctrl-A l       Start or stop logging the board's output
+Ctrl-A Ctrl-A  Send Ctrl-A";
+
+const CTRL_C: u8 = 0x03;
+const BACKSPACE: u8 = 0x08;
+const ESCAPE: u8 = 0x1B;
+const DELETE: u8 = 0x7F;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Key {
+    /// A byte for the board
+    Forward(u8),
+    Quit,
+    Send,
+    Log,
+    /// Anything else after the escape key
+    Help,
+}
+
+/// Splits the key presses into bytes for the board and terminal commands
+#[derive(Debug, Default)]
+pub struct Keys {
+    escaped: bool,
+}
+
+impl Keys {
+    /// `None` while a command is being entered
+    pub fn push(&mut self, byte: u8) -> Option<Key> {
+        if !core::mem::take(&mut self.escaped) {
+            if byte == ESCAPE_KEY {
+                self.escaped = true;
+                return None;
+            }
+            return Some(Key::Forward(byte));
+        }
+        Some(match byte {
+            ESCAPE_KEY => Key::Forward(ESCAPE_KEY),
+            b'q' | b'Q' => Key::Quit,
+            b's' | b'S' => Key::Send,
+            b'l' | b'L' => Key::Log,
+            _ => Key::Help,
+        })
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Input {
+    Pending,
+    Line(String),
+    /// Ctrl-C or Esc
+    Cancelled,
+}
+
+/// A line typed for the terminal, edited locally since the tty is in raw mode
+#[derive(Debug, Default)]
+pub struct LineInput {
+    line: String,
+    /// Start of a multi-byte character
+    partial: Vec<u8>,
+}
+
+impl LineInput {
+    /// Feed a key press, echoing it to `out`
+    pub fn push(&mut self, byte: u8, out: &mut dyn Write) -> io::Result<Input> {
+        let input = match byte {
+            b'\r' | b'\n' => {
+                out.write_all(b"\n")?;
+                Input::Line(core::mem::take(&mut self.line))
+            }
+            CTRL_C | ESCAPE => {
+                out.write_all(b"\n")?;
+                self.line.clear();
+                Input::Cancelled
+            }
+            BACKSPACE | DELETE => {
+                if self.line.pop().is_some() {
+                    out.write_all(b"\x08 \x08")?;
+                }
+                Input::Pending
+            }
+            c if c < 0x20 => Input::Pending,
+            c => {
+                self.partial.push(c);
+                match core::str::from_utf8(&self.partial) {
+                    Ok(s) => {
+                        out.write_all(s.as_bytes())?;
+                        self.line.push_str(s);
+                        self.partial.clear();
+                    }
+                    Err(e) if e.error_len().is_some() => self.partial.clear(),
+                    Err(_) => {}
+                }
+                Input::Pending
+            }
+        };
+        out.flush()?;
+        Ok(input)
+    }
+}
@@ -0,0 +1,9 @@
+//! Host side of the board's serial console.
+
+#[cfg(all(feature = "linux", not(feature = "rpi")))]
+pub mod baud;
+pub mod keys;
+pub mod options;
+#[cfg(all(feature = "linux", not(feature = "rpi")))]
+pub mod send;
+pub mod tty;
@@ -2,61 +2,339 @@
 use rppal::uart::{Parity, Uart};
 
 #[cfg(all(feature = "linux", not(feature = "rpi")))]
-use std::io::ErrorKind;
+use {
+    h7_uart_terminal::{
+        baud::{self, Switch},
+        send,
+    },
+    serialport::{SerialPort, TTYPort},
+    std::path::Path,
+};
 
-use std::io::{Read, Write};
+use {
+    h7_uart_terminal::{
+        keys::{self, Input, Key, Keys, LineInput},
+        options::{self, Options},
+        tty::RawMode,
+    },
+    std::{
+        fs::{File, OpenOptions},
+        io::{self, ErrorKind, Read, Write},
+        sync::mpsc::{self, Receiver, TryRecvError},
+        thread,
+        time::{Duration, Instant},
+    },
+};
+
+#[cfg(all(feature = "rpi", not(feature = "linux")))]
+type Device = Uart;
+#[cfg(all(feature = "linux", not(feature = "rpi")))]
+type Device = TTYPort;
+
+/// Uploads from this size on are sent at `--upload-baud`
+#[cfg(all(feature = "linux", not(feature = "rpi")))]
+const FAST_UPLOAD_MIN: u64 = 64 * 1024;
+/// Between attempts to open the device again after it went away
+const RECONNECT_INTERVAL: Duration = Duration::from_millis(500);
 
 fn main() {
-    #[cfg(all(feature = "rpi", not(feature = "linux")))]
-    let mut uart = Uart::new(115_200, Parity::None, 8, 1).unwrap();
-    #[cfg(all(feature = "linux", not(feature = "rpi")))]
-    let mut uart = {
-        let port = std::env::args().nth(1).expect("Expected serial device");
-        serialport::new(port, 115_200).open_native().unwrap()
+    let options = match Options::parse(std::env::args().skip(1)) {
+        Ok(options) => options,
+        Err(e) => {
+            eprintln!("Error: {e}");
+            eprintln!("{}", options::USAGE);
+            std::process::exit(2);
+        }
     };
+    #[cfg(all(feature = "linux", not(feature = "rpi")))]
+    if options.device.is_none() {
+        eprintln!("Error: Expected serial device");
+        eprintln!("{}", options::USAGE);
+        std::process::exit(2);
+    }
 
-    let (tx, rx) = std::sync::mpsc::channel::<String>();
+    let uart = match open(&options) {
+        Ok(uart) => uart,
+        Err(e) => {
+            eprintln!("Error: {e}");
+            std::process::exit(1);
+        }
+    };
 
-    std::thread::spawn(move || loop {
-        let mut s = String::new();
-        match std::io::stdin().read_line(&mut s) {
-            Ok(_) => tx.send(s).unwrap(),
-            Err(e) => eprintln!("Error: {:#?}", e),
+    let (tx, rx) = mpsc::channel::<Vec<u8>>();
+    thread::spawn(move || {
+        let mut buf = [0u8; 256];
+        loop {
+            match io::stdin().read(&mut buf) {
+                Ok(0) => break,
+                Ok(len) => {
+                    if tx.send(buf[..len].to_vec()).is_err() {
+                        break;
+                    }
+                }
+                Err(e) => {
+                    eprintln!("Error: {e}");
+                    break;
+                }
+            }
         }
     });
 
-    loop {
+    // Not a terminal, e.g. piped input: the bytes go to the board all the same
+    let _raw_mode = RawMode::enable().ok();
+    eprintln!("Ctrl-A h for help, Ctrl-A q to quit");
+    Terminal::new(&options, uart).run(&rx);
+}
+
+struct Terminal<'o> {
+    options: &'o Options,
+    /// `None` while the device is gone
+    uart: Option<Device>,
+    /// Last attempt to reconnect
+    retried: Instant,
+    keys: Keys,
+    /// File name being typed after Ctrl-A s
+    prompt: Option<LineInput>,
+    log: Option<File>,
+}
+
+impl<'o> Terminal<'o> {
+    fn new(options: &'o Options, uart: Device) -> Self {
+        Self {
+            options,
+            uart: Some(uart),
+            retried: Instant::now(),
+            keys: Keys::default(),
+            prompt: None,
+            log: None,
+        }
+    }
+
+    /// Pass output and key presses on until Ctrl-A q
+    fn run(&mut self, keys: &Receiver<Vec<u8>>) {
+        loop {
+            self.receive();
+
+            match keys.try_recv() {
+                Ok(bytes) => {
+                    for byte in bytes {
+                        if !self.key(byte) {
+                            return;
+                        }
+                    }
+                }
+                // Input ended, keep showing output
+                Err(TryRecvError::Empty | TryRecvError::Disconnected) => {}
+            }
+
+            thread::sleep(Duration::from_millis(10));
+        }
+    }
+
+    /// Show and log what the board sent
+    fn receive(&mut self) {
+        let Some(uart) = &mut self.uart else {
+            return self.reconnect();
+        };
         let mut buf = [0u8; 256];
-        match uart.read(&mut buf) {
+        match read(uart, &mut buf) {
             Ok(0) => {}
             Ok(len) => {
-                // println!("Data: {:?}", &buf[0..len]);
-                match core::str::from_utf8(&buf[0..len]) {
-                    Ok(s) => {
-                        print!("{}", s);
-                        std::io::stdout().flush().unwrap();
+                let mut stdout = io::stdout().lock();
+                let _ = stdout.write_all(&buf[..len]).and_then(|_| stdout.flush());
+                if let Some(log) = &mut self.log {
+                    if let Err(e) = log.write_all(&buf[..len]) {
+                        eprintln!("\nError: {}: {e}, logging stopped", self.options.log);
+                        self.log = None;
                     }
-                    Err(e) => eprintln!("Error: {:#?}", e),
                 }
             }
-            #[cfg(all(feature = "linux", not(feature = "rpi")))]
-            Err(e) if e.kind() == ErrorKind::TimedOut => {}
-            #[cfg(all(feature = "linux", not(feature = "rpi")))]
-            Err(e) if e.kind() == ErrorKind::BrokenPipe => {
-                eprintln!("Broken pipe");
-                break;
+            Err(e) => self.disconnected(e),
+        }
+    }
+
+    /// Handle a key press, returns `false` to quit
+    fn key(&mut self, byte: u8) -> bool {
+        if let Some(prompt) = &mut self.prompt {
+            match prompt.push(byte, &mut io::stderr()) {
+                Ok(Input::Pending) => {}
+                Ok(Input::Line(line)) => {
+                    self.prompt = None;
+                    self.send(&line);
+                }
+                Ok(Input::Cancelled) | Err(_) => self.prompt = None,
+            }
+            return true;
+        }
+
+        match self.keys.push(byte) {
+            None => {}
+            Some(Key::Forward(byte)) => self.forward(&[byte]),
+            Some(Key::Quit) => return false,
+            Some(Key::Send) => {
+                eprint!("\nSend <file> [dest]: ");
+                self.prompt = Some(LineInput::default());
             }
-            Err(e) => eprintln!("Error: {:#?}", e),
+            Some(Key::Log) => self.toggle_log(),
+            Some(Key::Help) => eprintln!("\n{}", keys::HELP),
         }
+        true
+    }
 
-        match rx.try_recv() {
-            Ok(s) => {
-                let _ = uart.write(s.as_bytes()).unwrap();
+    fn forward(&mut self, bytes: &[u8]) {
+        if let Some(uart) = &mut self.uart {
+            if let Err(e) = write(uart, bytes) {
+                self.disconnected(e);
             }
-            Err(std::sync::mpsc::TryRecvError::Empty) => {}
-            Err(e) => eprintln!("Error: {:#?}", e),
         }
+    }
 
-        std::thread::sleep(std::time::Duration::from_millis(10));
+    fn toggle_log(&mut self) {
+        let path = &self.options.log;
+        if self.log.take().is_some() {
+            eprintln!("\nStopped logging to {path}");
+            return;
+        }
+        match OpenOptions::new().create(true).append(true).open(path) {
+            Ok(file) => {
+                eprintln!("\nLogging to {path}");
+                self.log = Some(file);
+            }
+            Err(e) => eprintln!("\nError: {path}: {e}"),
+        }
     }
+
+    fn disconnected(&mut self, e: io::Error) {
+        self.uart = None;
+        self.retried = Instant::now();
+        let device = self.options.device.as_deref().unwrap_or("UART");
+        eprintln!("\nDisconnected: {e}, waiting for {device} to come back");
+    }
+
+    /// Open the device again, every [`RECONNECT_INTERVAL`]
+    fn reconnect(&mut self) {
+        if self.retried.elapsed() < RECONNECT_INTERVAL {
+            return;
+        }
+        self.retried = Instant::now();
+        if let Ok(uart) = open(self.options) {
+            eprintln!("Reconnected");
+            self.uart = Some(uart);
+        }
+    }
+
+    /// Upload for Ctrl-A s, `line` is `<file> [dest]`
+    #[cfg(all(feature = "linux", not(feature = "rpi")))]
+    fn send(&mut self, line: &str) {
+        let mut args = line.split_whitespace();
+        let (Some(file), dest, None) = (args.next(), args.next(), args.next()) else {
+            eprintln!("Expected: <file> [dest], without dest a program into RAM");
+            return;
+        };
+        let Some(uart) = &mut self.uart else {
+            eprintln!("Error: Not connected");
+            return;
+        };
+
+        let path = Path::new(file);
+        let large = path
+            .metadata()
+            .is_ok_and(|metadata| metadata.len() >= FAST_UPLOAD_MIN);
+        let slow = uart.baud_rate().unwrap_or(self.options.baud);
+        let fast =
+            large && self.options.upload_baud > slow && switch_baud(uart, self.options.upload_baud);
+        if let Err(e) = send::send_file(uart, path, dest, &mut io::stdout()) {
+            eprintln!("Error: {e}");
+        }
+        if fast {
+            switch_baud(uart, slow);
+        }
+    }
+
+    #[cfg(all(feature = "rpi", not(feature = "linux")))]
+    fn send(&mut self, _line: &str) {
+        eprintln!("Error: Uploads need the linux feature");
+    }
+}
+
+/// Switch the board and `uart` to `rate`, returns `true` if both did
+#[cfg(all(feature = "linux", not(feature = "rpi")))]
+fn switch_baud(uart: &mut TTYPort, rate: u32) -> bool {
+    match baud::switch(uart, rate, baud::REPLY_TIMEOUT) {
+        Ok(Switch::Switched) => true,
+        Ok(Switch::Refused) => {
+            eprintln!("The board didn't switch to {rate} baud");
+            false
+        }
+        Ok(Switch::Lost) => {
+            eprintln!("The board didn't confirm {rate} baud, waiting for it to switch back");
+            std::thread::sleep(baud::REVERT_TIMEOUT);
+            let _ = uart.clear(serialport::ClearBuffer::Input);
+            false
+        }
+        Err(e) => {
+            eprintln!("Error: {e}");
+            false
+        }
+    }
+}
+
+#[cfg(all(feature = "rpi", not(feature = "linux")))]
+fn open(options: &Options) -> io::Result<Device> {
+    let parity = match options.parity {
+        options::Parity::None => Parity::None,
+        options::Parity::Even => Parity::Even,
+        options::Parity::Odd => Parity::Odd,
+    };
+    let stop_bits = match options.stop_bits {
+        options::StopBits::One => 1,
+        options::StopBits::Two => 2,
+    };
+    Uart::new(options.baud, parity, 8, stop_bits).map_err(|e| io::Error::new(ErrorKind::Other, e))
+}
+
+#[cfg(all(feature = "linux", not(feature = "rpi")))]
+fn open(options: &Options) -> io::Result<Device> {
+    let parity = match options.parity {
+        options::Parity::None => serialport::Parity::None,
+        options::Parity::Even => serialport::Parity::Even,
+        options::Parity::Odd => serialport::Parity::Odd,
+    };
+    let stop_bits = match options.stop_bits {
+        options::StopBits::One => serialport::StopBits::One,
+        options::StopBits::Two => serialport::StopBits::Two,
+    };
+    let device = options.device.as_deref().unwrap_or_default();
+    Ok(serialport::new(device, options.baud)
+        .parity(parity)
+        .stop_bits(stop_bits)
+        .open_native()?)
+}
+
+/// Whatever arrived, without waiting
+#[cfg(all(feature = "rpi", not(feature = "linux")))]
+fn read(uart: &mut Device, buf: &mut [u8]) -> io::Result<usize> {
+    uart.read(buf)
+        .map_err(|e| io::Error::new(ErrorKind::Other, e))
+}
+
+#[cfg(all(feature = "rpi", not(feature = "linux")))]
+fn write(uart: &mut Device, bytes: &[u8]) -> io::Result<()> {
+    uart.write(bytes)
+        .map(|_| ())
+        .map_err(|e| io::Error::new(ErrorKind::Other, e))
+}
+
+/// Whatever arrived, without waiting
+#[cfg(all(feature = "linux", not(feature = "rpi")))]
+fn read(uart: &mut Device, buf: &mut [u8]) -> io::Result<usize> {
+    match uart.read(buf) {
+        Err(e) if e.kind() == ErrorKind::TimedOut => Ok(0),
+        result => result,
+    }
+}
+
+#[cfg(all(feature = "linux", not(feature = "rpi")))]
+fn write(uart: &mut Device, bytes: &[u8]) -> io::Result<()> {
+    uart.write_all(bytes)
 }
@@ -0,0 +1,98 @@
+//! Command line options.
+
+pub const USAGE: &str = "\
+Usage: h7-uart-terminal [options] <device>
+
+Options:
+    --baud <rate>         Baud rate, 115200 by default
+    --parity <parity>     none, even or odd, none by default
+    --stop-bits <bits>    1 or 2, 1 by default
+    --upload-baud <rate>  Switch the board to this rate for large Ctrl-A s uploads, 921600 by
+                          default, 0 to stay at --baud
+    --log <file>          Where Ctrl-A l logs the board's output, h7-uart-terminal.log by
+                          default";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Parity {
+    None,
+    Even,
+    Odd,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StopBits {
+    One,
+    Two,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Options {
+    /// Serial device, the Raspberry Pi build always uses its UART
+    pub device: Option<String>,
+    pub baud: u32,
+    pub parity: Parity,
+    pub stop_bits: StopBits,
+    pub upload_baud: u32,
+    /// Appended to while logging is on
+    pub log: String,
+}
+
+impl Default for Options {
+    fn default() -> Self {
+        Self {
+            device: None,
+            baud: 115_200,
+            parity: Parity::None,
+            stop_bits: StopBits::One,
+            upload_baud: 921_600,
+            log: "h7-uart-terminal.log".into(),
+        }
+    }
+}
+
+impl Options {
+    /// Parse the arguments after the program name
+    pub fn parse(args: impl IntoIterator<Item = String>) -> Result<Self, String> {
+        let mut options = Self::default();
+        let mut args = args.into_iter();
+        while let Some(arg) = args.next() {
+            let mut value = || args.next().ok_or_else(|| format!("{arg} needs a value"));
+            match arg.as_str() {
+                "--baud" => options.baud = parse_rate(&value()?)?,
+                "--parity" => {
+                    options.parity = match value()?.as_str() {
+                        "none" => Parity::None,
+                        "even" => Parity::Even,
+                        "odd" => Parity::Odd,
+                        other => return Err(format!("Invalid parity: {other}")),
+                    }
+                }
+                "--stop-bits" => {
+                    options.stop_bits = match value()?.as_str() {
+                        "1" => StopBits::One,
+                        "2" => StopBits::Two,
+                        other => return Err(format!("Invalid stop bits: {other}")),
+                    }
+                }
+                "--upload-baud" => {
+                    options.upload_baud = match value()?.as_str() {
+                        "0" => 0,
+                        rate => parse_rate(rate)?,
+                    }
+                }
+                "--log" => options.log = value()?,
+                flag if flag.starts_with("--") => return Err(format!("Unknown option: {flag}")),
+                _ if options.device.is_some() => return Err(format!("Unexpected argument: {arg}")),
+                _ => options.device = Some(arg),
+            }
+        }
+        Ok(options)
+    }
+}
+
+fn parse_rate(s: &str) -> Result<u32, String> {
+    match s.parse() {
+        Ok(rate) if rate > 0 => Ok(rate),
+        _ => Err(format!("Invalid baud rate: {s}")),
+    }
+}
@@ -0,0 +1,197 @@
+//! Ctrl-A s, uploads to the board with the framed protocol from `h7-xfer`.
+
+use {
+    h7_xfer::{
+        frame::{self, Config, Reader, Sent},
+        Channel,
+    },
+    serialport::SerialPort,
+    std::{
+        fs::File,
+        io::{self, ErrorKind, Read, Seek, SeekFrom, Write},
+        path::Path,
+        time::{Duration, Instant},
+    },
+};
+
+/// Board command that receives uploads
+const RECEIVER: &[u8] = b"frx\n";
+/// Between progress updates
+const PROGRESS_INTERVAL: Duration = Duration::from_millis(100);
+
+/// A serial port as a transfer channel
+pub struct Port<'p> {
+    port: &'p mut dyn SerialPort,
+    timeout_ms: Option<u32>,
+    buf: [u8; 256],
+    pos: usize,
+    len: usize,
+}
+
+impl<'p> Port<'p> {
+    pub fn new(port: &'p mut dyn SerialPort) -> Self {
+        Self {
+            port,
+            timeout_ms: None,
+            buf: [0; 256],
+            pos: 0,
+            len: 0,
+        }
+    }
+}
+
+impl Channel for Port<'_> {
+    type Error = io::Error;
+
+    fn read_byte(&mut self, timeout_ms: u32) -> io::Result<Option<u8>> {
+        if self.pos < self.len {
+            self.pos += 1;
+            return Ok(Some(self.buf[self.pos - 1]));
+        }
+        if self.timeout_ms != Some(timeout_ms) {
+            self.port
+                .set_timeout(Duration::from_millis(timeout_ms.into()))?;
+            self.timeout_ms = Some(timeout_ms);
+        }
+        match self.port.read(&mut self.buf) {
+            Ok(0) => Ok(None),
+            Ok(n) => {
+                (self.pos, self.len) = (1, n);
+                Ok(Some(self.buf[0]))
+            }
+            Err(e) if e.kind() == ErrorKind::TimedOut => Ok(None),
+            Err(e) => Err(e),
+        }
+    }
+
+    fn write_all(&mut self, data: &[u8]) -> io::Result<()> {
+        self.port.write_all(data)
+    }
+}
+
+struct FileReader(File);
+
+impl Reader for FileReader {
+    type Error = io::Error;
+
+    fn read_at(&mut self, offset: u32, buf: &mut [u8]) -> io::Result<usize> {
+        self.0.seek(SeekFrom::Start(offset.into()))?;
+        self.0.read(buf)
+    }
+}
+
+/// Start the receiver on the board and upload `path` to `dest`, or into program RAM if `None`.
+/// Progress and throughput are written to `out`.
+pub fn send_file(
+    port: &mut dyn SerialPort,
+    path: &Path,
+    dest: Option<&str>,
+    out: &mut dyn Write,
+) -> Result<Sent, frame::Error<io::Error>> {
+    let file = File::open(path).map_err(frame::Error::Io)?;
+    let size = file.metadata().map_err(frame::Error::Io)?.len();
+    let size = u32::try_from(size)
+        .map_err(|_| frame::Error::Io(io::Error::new(ErrorKind::InvalidInput, "File too large")))?;
+    let name = path
+        .file_name()
+        .and_then(|name| name.to_str())
+        .unwrap_or_default();
+
+    port.write_all(RECEIVER).map_err(frame::Error::Io)?;
+    // The terminal's own reads poll, the transfer waits
+    let timeout = port.timeout();
+    let mut progress = Progress::new(out, size);
+    let sent = frame::send(
+        &mut Port::new(port),
+        &mut FileReader(file),
+        size,
+        name,
+        dest,
+        &Config::default(),
+        &mut |received| progress.update(received),
+    );
+    progress.finish(sent.as_ref().ok());
+    port.set_timeout(timeout)
+        .map_err(|e| frame::Error::Io(e.into()))?;
+    sent
+}
+
+/// Percentage, bytes and throughput on one line that is rewritten as the upload goes on
+struct Progress<'w> {
+    out: &'w mut dyn Write,
+    size: u32,
+    started: Instant,
+    /// What the receiver had before, not counted for the throughput
+    resumed_from: Option<u32>,
+    received: u32,
+    shown: Option<Instant>,
+}
+
+impl<'w> Progress<'w> {
+    fn new(out: &'w mut dyn Write, size: u32) -> Self {
+        Self {
+            out,
+            size,
+            started: Instant::now(),
+            resumed_from: None,
+            received: 0,
+            shown: None,
+        }
+    }
+
+    fn update(&mut self, received: u32) {
+        let resumed_from = *self.resumed_from.get_or_insert_with(|| {
+            self.started = Instant::now();
+            received
+        });
+        self.received = received;
+        if self
+            .shown
+            .is_some_and(|shown| shown.elapsed() < PROGRESS_INTERVAL)
+            && received < self.size
+        {
+            return;
+        }
+        self.shown = Some(Instant::now());
+
+        let percent = match self.size {
+            0 => 100,
+            size => received as u64 * 100 / size as u64,
+        };
+        let rate = rate(received - resumed_from, self.started.elapsed());
+        let _ = write!(
+            self.out,
+            "\r{percent:3}% {received}/{} bytes, {rate}   ",
+            self.size
+        );
+        let _ = self.out.flush();
+    }
+
+    fn finish(&mut self, sent: Option<&Sent>) {
+        let _ = writeln!(self.out);
+        if let Some(sent) = sent {
+            let elapsed = self.started.elapsed();
+            let _ = write!(
+                self.out,
+                "Sent {} bytes in {:.1} s, {}",
+                sent.bytes,
+                elapsed.as_secs_f64(),
+                rate(sent.bytes, elapsed)
+            );
+            let _ = match sent.resumed_from {
+                0 => writeln!(self.out),
+                n => writeln!(self.out, " (resumed at {n})"),
+            };
+        }
+    }
+}
+
+/// Throughput in binary units
+fn rate(bytes: u32, elapsed: Duration) -> String {
+    let per_second = bytes as f64 / elapsed.as_secs_f64().max(0.001);
+    match per_second {
+        r if r >= 1024.0 * 1024.0 => format!("{:.1} MiB/s", r / (1024.0 * 1024.0)),
+        r if r >= 1024.0 => format!("{:.1} KiB/s", r / 1024.0),
+        r => format!("{r:.0} B/s"),
+    }
+}
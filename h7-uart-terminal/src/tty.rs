@@ -0,0 +1,31 @@
+//! Raw mode for the terminal we run in, so every key press reaches the board as it is typed.
+
+use {
+    nix::sys::termios::{self, OutputFlags, SetArg, Termios},
+    std::{io, os::fd::AsRawFd},
+};
+
+/// Keeps stdin in raw mode while it lives, the previous settings come back when it is dropped
+pub struct RawMode {
+    saved: Termios,
+}
+
+impl RawMode {
+    /// Fails if stdin isn't a terminal
+    pub fn enable() -> io::Result<Self> {
+        let fd = io::stdin().as_raw_fd();
+        let saved = termios::tcgetattr(fd)?;
+        let mut raw = saved.clone();
+        termios::cfmakeraw(&mut raw);
+        // The board and our own messages end lines with "\n" only
+        raw.output_flags |= OutputFlags::OPOST | OutputFlags::ONLCR;
+        termios::tcsetattr(fd, SetArg::TCSANOW, &raw)?;
+        Ok(Self { saved })
+    }
+}
+
+impl Drop for RawMode {
+    fn drop(&mut self) {
+        let _ = termios::tcsetattr(io::stdin().as_raw_fd(), SetArg::TCSAFLUSH, &self.saved);
+    }
+}
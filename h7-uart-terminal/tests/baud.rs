@@ -0,0 +1,81 @@
+#![cfg(all(feature = "linux", not(feature = "rpi")))]
+
+use {
+    h7_uart_terminal::baud::{self, Switch},
+    serialport::{SerialPort, TTYPort},
+    std::{
+        io::{Read, Write},
+        thread,
+        time::Duration,
+    },
+};
+
+const REPLY_TIMEOUT: Duration = Duration::from_millis(300);
+
+/// Read a line the way the board's shell does
+fn read_line(port: &mut TTYPort) -> Vec<u8> {
+    let mut line = Vec::new();
+    let mut byte = [0];
+    while !line.ends_with(b"\n") {
+        port.read_exact(&mut byte).unwrap();
+        line.push(byte[0]);
+    }
+    line
+}
+
+/// Answer `sys uart baud -t` like the board, confirming if `confirm` is set.
+/// Returns the command and the device, closing it would hang up the host end.
+fn board(mut device: TTYPort, confirm: bool) -> thread::JoinHandle<(Vec<u8>, TTYPort)> {
+    device.set_timeout(Duration::from_secs(5)).unwrap();
+    thread::spawn(move || {
+        let command = read_line(&mut device);
+        device
+            .write_all(b"sys uart baud -t 921600\r\nSwitching to 921600 baud, press Enter within 10 s to keep it\r\n")
+            .unwrap();
+        assert_eq!(read_line(&mut device), b"\n");
+        if confirm {
+            device.write_all(b"Keeping 921600 baud\r\n> ").unwrap();
+        }
+        (command, device)
+    })
+}
+
+#[test]
+fn switches_both_ends() {
+    let (mut host, device) = TTYPort::pair().unwrap();
+    let board = board(device, true);
+
+    let switched = baud::switch(&mut host, 921_600, REPLY_TIMEOUT).unwrap();
+    assert_eq!(board.join().unwrap().0, b"sys uart baud -t 921600\n");
+    assert_eq!(switched, Switch::Switched);
+    assert_eq!(host.baud_rate().unwrap(), 921_600);
+}
+
+#[test]
+fn goes_back_without_confirmation() {
+    let (mut host, device) = TTYPort::pair().unwrap();
+    let old = host.baud_rate().unwrap();
+    let board = board(device, false);
+
+    let switched = baud::switch(&mut host, 921_600, REPLY_TIMEOUT);
+    let _device = board.join().unwrap();
+    assert_eq!(switched.unwrap(), Switch::Lost);
+    assert_eq!(host.baud_rate().unwrap(), old);
+}
+
+#[test]
+fn board_without_the_command() {
+    let (mut host, mut device) = TTYPort::pair().unwrap();
+    let old = host.baud_rate().unwrap();
+    device.set_timeout(Duration::from_secs(5)).unwrap();
+    let board = thread::spawn(move || {
+        read_line(&mut device);
+        device.write_all(b"Error: Unknown command\r\n> ").unwrap();
+        device
+    });
+
+    let switched = baud::switch(&mut host, 921_600, REPLY_TIMEOUT);
+    let _device = board.join().unwrap();
+    assert_eq!(switched.unwrap(), Switch::Refused);
+    assert_eq!(host.baud_rate().unwrap(), old);
+}
@@ -0,0 +1,52 @@
+use h7_uart_terminal::keys::{Input, Key, Keys, LineInput, ESCAPE_KEY};
+
+fn keys(bytes: &[u8]) -> Vec<Key> {
+    let mut keys = Keys::default();
+    bytes.iter().filter_map(|&b| keys.push(b)).collect()
+}
+
+fn type_line(bytes: &[u8]) -> (Vec<Input>, String) {
+    let mut input = LineInput::default();
+    let mut echo = Vec::new();
+    let results = bytes
+        .iter()
+        .map(|&b| input.push(b, &mut echo).unwrap())
+        .filter(|result| *result != Input::Pending)
+        .collect();
+    (results, String::from_utf8(echo).unwrap())
+}
+
+#[test]
+fn keys_are_forwarded() {
+    // Ctrl-C, an arrow key and Tab go to the board as they are
+    assert_eq!(
+        keys(b"a\x03\x1b[A\t"),
+        b"a\x03\x1b[A\t".map(Key::Forward).to_vec()
+    );
+}
+
+#[test]
+fn escape_key_commands() {
+    assert_eq!(
+        keys(&[ESCAPE_KEY, b'q', ESCAPE_KEY, b's', ESCAPE_KEY, b'L']),
+        [Key::Quit, Key::Send, Key::Log]
+    );
+    assert_eq!(
+        keys(&[ESCAPE_KEY, ESCAPE_KEY, b'q']),
+        [Key::Forward(ESCAPE_KEY), Key::Forward(b'q')]
+    );
+    assert_eq!(
+        keys(&[ESCAPE_KEY, b'x', b'x']),
+        [Key::Help, Key::Forward(b'x')]
+    );
+}
+
+#[test]
+fn line_input() {
+    let (results, echo) = type_line("app.h7x\x7f sd:/ä\r".as_bytes());
+    assert_eq!(results, [Input::Line("app.h7 sd:/ä".into())]);
+    assert_eq!(echo, "app.h7x\x08 \x08 sd:/ä\n");
+
+    let (results, _) = type_line(b"app\x03");
+    assert_eq!(results, [Input::Cancelled]);
+}
@@ -0,0 +1,57 @@
+use h7_uart_terminal::options::{Options, Parity, StopBits};
+
+fn parse(args: &[&str]) -> Result<Options, String> {
+    Options::parse(args.iter().map(|arg| arg.to_string()))
+}
+
+#[test]
+fn defaults() {
+    let options = parse(&["/dev/ttyUSB0"]).unwrap();
+    assert_eq!(
+        options,
+        Options {
+            device: Some("/dev/ttyUSB0".into()),
+            ..Options::default()
+        }
+    );
+    assert_eq!(options.baud, 115_200);
+}
+
+#[test]
+fn serial_settings() {
+    let options = parse(&[
+        "--baud",
+        "460800",
+        "/dev/ttyUSB0",
+        "--parity",
+        "even",
+        "--stop-bits",
+        "2",
+        "--upload-baud",
+        "0",
+        "--log",
+        "board.log",
+    ])
+    .unwrap();
+    assert_eq!(options.baud, 460_800);
+    assert_eq!(options.parity, Parity::Even);
+    assert_eq!(options.stop_bits, StopBits::Two);
+    assert_eq!(options.upload_baud, 0);
+    assert_eq!(options.log, "board.log");
+}
+
+#[test]
+fn invalid_arguments() {
+    assert_eq!(parse(&["--baud"]).unwrap_err(), "--baud needs a value");
+    assert_eq!(parse(&["--baud", "0"]).unwrap_err(), "Invalid baud rate: 0");
+    assert_eq!(
+        parse(&["--parity", "mark"]).unwrap_err(),
+        "Invalid parity: mark"
+    );
+    assert_eq!(
+        parse(&["--stop-bits", "1.5"]).unwrap_err(),
+        "Invalid stop bits: 1.5"
+    );
+    assert_eq!(parse(&["--flow"]).unwrap_err(), "Unknown option: --flow");
+    assert_eq!(parse(&["a", "b"]).unwrap_err(), "Unexpected argument: b");
+}
@@ -0,0 +1,119 @@
+#![cfg(all(feature = "linux", not(feature = "rpi")))]
+
+use {
+    h7_uart_terminal::send::{self, Port},
+    h7_xfer::frame::{self, Config, Received, Target},
+    serialport::{SerialPort, TTYPort},
+    std::{io, path::PathBuf, thread, time::Duration},
+};
+
+/// The board's end of an upload, in memory
+#[derive(Default)]
+struct Board {
+    data: Vec<u8>,
+    dest: Option<String>,
+}
+
+impl Target for Board {
+    type Error = io::Error;
+
+    fn open(
+        &mut self,
+        _name: &str,
+        dest: Option<&str>,
+        size: u32,
+        truncate: bool,
+    ) -> io::Result<u32> {
+        self.dest = dest.map(str::to_string);
+        if truncate || self.data.len() > size as usize {
+            self.data.clear();
+        }
+        Ok(self.data.len() as u32)
+    }
+
+    fn read_at(&mut self, offset: u32, buf: &mut [u8]) -> io::Result<usize> {
+        let data = &self.data[offset as usize..];
+        let n = buf.len().min(data.len());
+        buf[..n].copy_from_slice(&data[..n]);
+        Ok(n)
+    }
+
+    fn write(&mut self, data: &[u8]) -> io::Result<()> {
+        self.data.extend_from_slice(data);
+        Ok(())
+    }
+
+    fn finish(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// A file with `len` bytes of test data
+fn test_file(name: &str, len: usize) -> (PathBuf, Vec<u8>) {
+    let data: Vec<u8> = (0..len).map(|i| (i * 31 + i / 256) as u8).collect();
+    let path = std::env::temp_dir().join(format!("h7-uart-terminal-{}-{name}", std::process::id()));
+    std::fs::write(&path, &data).unwrap();
+    (path, data)
+}
+
+/// Run the board's shell and receiver on one end of a PTY pair, the upload on the other
+fn upload(
+    path: &PathBuf,
+    dest: Option<&str>,
+    mut board: Board,
+) -> (frame::Sent, Received, Board, String) {
+    let (mut host, mut device) = TTYPort::pair().unwrap();
+    device.set_timeout(Duration::from_secs(5)).unwrap();
+
+    let receiver = thread::spawn(move || {
+        // The shell reads the command line before the receiver starts
+        let mut line = Vec::new();
+        let mut byte = [0];
+        while !line.ends_with(b"\n") {
+            io::Read::read_exact(&mut device, &mut byte).unwrap();
+            line.push(byte[0]);
+        }
+        assert_eq!(line, b"frx\n");
+        // Echo and prompt noise the sender has to skip
+        io::Write::write_all(&mut device, b"frx\r\n").unwrap();
+
+        let received = frame::receive(&mut Port::new(&mut device), &mut board, &Config::default());
+        (received.unwrap(), board)
+    });
+
+    let mut out = Vec::new();
+    let sent = send::send_file(&mut host, path, dest, &mut out).unwrap();
+    let (received, board) = receiver.join().unwrap();
+    std::fs::remove_file(path).unwrap();
+    (sent, received, board, String::from_utf8(out).unwrap())
+}
+
+#[test]
+fn uploads_over_a_pty() {
+    let (path, data) = test_file("app.h7", 5000);
+    let (sent, received, board, out) = upload(&path, Some("sdcard:/app.h7"), Board::default());
+
+    assert_eq!(sent.bytes, 5000);
+    assert_eq!(received.name, path.file_name().unwrap().to_str().unwrap());
+    assert_eq!(received.size, 5000);
+    assert_eq!(board.dest.as_deref(), Some("sdcard:/app.h7"));
+    assert_eq!(board.data, data);
+    assert!(out.contains("100% 5000/5000 bytes"), "{out}");
+    assert!(out.contains("Sent 5000 bytes in"), "{out}");
+}
+
+#[test]
+fn resumes_over_a_pty() {
+    let (path, data) = test_file("resume.bin", 4000);
+    let board = Board {
+        data: data[..1500].to_vec(),
+        dest: None,
+    };
+    let (sent, received, board, out) = upload(&path, None, board);
+
+    assert_eq!(sent.resumed_from, 1500);
+    assert_eq!(sent.bytes, 2500);
+    assert_eq!(received.dest, None);
+    assert_eq!(board.data, data);
+    assert!(out.contains("(resumed at 1500)"), "{out}");
+}
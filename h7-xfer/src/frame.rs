@@ -0,0 +1,653 @@
+//! A compact framed protocol for uploading files and programs from h7-uart-terminal.
+//!
+//! Every frame is `A5 kind seq len payload crc`, with a 16 bit sequence number and length and a
+//! CRC-32 over everything between the magic byte and itself, all little endian. The sender waits
+//! for the receiver's hello, then sends one frame at a time and resends it until the reply with
+//! the same sequence number arrives. Data frames carry their offset, so repeats are harmless and
+//! a NAK names the offset to go on from. An interrupted upload resumes where the receiver's copy
+//! ends, provided the CRC of that part matches the sender's file.
+
+use {
+    crate::{crc::Crc32, Channel},
+    alloc::string::{String, ToString},
+};
+
+/// Data bytes per frame
+pub const MAX_DATA: usize = 1024;
+/// A data frame's offset and data
+const MAX_PAYLOAD: usize = 4 + MAX_DATA;
+const MAGIC: u8 = 0xA5;
+const VERSION: u8 = 1;
+
+/// Receiver is waiting for an upload: version
+const HELLO: u8 = 1;
+/// Upload request: flags, size, file name, NUL, destination (empty for program RAM)
+const OPEN: u8 = 2;
+/// Reply to `OPEN`: offset to start at, CRC of the data before it
+const READY: u8 = 3;
+/// Offset, data
+const DATA: u8 = 4;
+/// Bytes received so far
+const ACK: u8 = 5;
+/// Damaged or unexpected frame: offset to go on from
+const NAK: u8 = 6;
+/// All data sent: CRC of the whole file
+const DONE: u8 = 7;
+/// Either end gives up: reason as text
+const ABORT: u8 = 8;
+
+/// `OPEN` flag to drop whatever an earlier upload left behind
+const FLAG_TRUNCATE: u8 = 1;
+
+#[derive(Debug, Clone, Copy)]
+pub struct Config {
+    /// Failed attempts at one frame before giving up
+    pub retries: u32,
+    /// Wait for a frame or a reply to one
+    pub reply_timeout_ms: u32,
+    /// Wait between the bytes of a frame
+    pub byte_timeout_ms: u32,
+    /// Between hellos while a receiver waits for its sender
+    pub hello_interval_ms: u32,
+    /// Hellos before a receiver gives up on its sender
+    pub hellos: u32,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            retries: 10,
+            reply_timeout_ms: 2_000,
+            byte_timeout_ms: 500,
+            hello_interval_ms: 500,
+            hellos: 60,
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum Error<E> {
+    /// The channel, target or file failed
+    Io(E),
+    /// The other end never started or stopped answering
+    Timeout,
+    /// Too many failed attempts at one frame
+    Retries,
+    /// The other end gave up, with its reason
+    Aborted(String),
+    /// The received data doesn't match the file
+    Checksum,
+    /// A reply that makes no sense at this point
+    Protocol,
+}
+
+impl<E: core::fmt::Display> core::fmt::Display for Error<E> {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        match self {
+            Self::Io(e) => write!(f, "{e}"),
+            Self::Timeout => write!(f, "Timed out"),
+            Self::Retries => write!(f, "Too many errors"),
+            Self::Aborted(reason) => write!(f, "Aborted by the other end: {reason}"),
+            Self::Checksum => write!(f, "Checksum mismatch"),
+            Self::Protocol => write!(f, "Unexpected reply"),
+        }
+    }
+}
+
+/// Where the receiver puts an upload
+pub trait Target {
+    type Error: core::fmt::Display;
+
+    /// Prepare for `size` bytes of the file `name`, going to `dest` or into program RAM if
+    /// `None`. Returns how many bytes an interrupted upload left there to resume after, unless
+    /// `truncate` asks to start over.
+    fn open(
+        &mut self,
+        name: &str,
+        dest: Option<&str>,
+        size: u32,
+        truncate: bool,
+    ) -> Result<u32, Self::Error>;
+
+    /// Read back what is there, to check it before resuming
+    fn read_at(&mut self, offset: u32, buf: &mut [u8]) -> Result<usize, Self::Error>;
+
+    /// Append to the upload
+    fn write(&mut self, data: &[u8]) -> Result<(), Self::Error>;
+
+    /// All data arrived and matched
+    fn finish(&mut self) -> Result<(), Self::Error>;
+}
+
+/// The file a sender uploads
+pub trait Reader {
+    type Error;
+
+    /// Read from `offset` until `buf` is full or the file ends
+    fn read_at(&mut self, offset: u32, buf: &mut [u8]) -> Result<usize, Self::Error>;
+}
+
+/// A finished upload as seen by the receiver
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Received {
+    pub name: String,
+    /// `None` for program RAM
+    pub dest: Option<String>,
+    pub size: u32,
+    /// Bytes kept from an interrupted upload
+    pub resumed_from: u32,
+}
+
+/// A finished upload as seen by the sender
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Sent {
+    /// Bytes sent this time
+    pub bytes: u32,
+    /// Bytes the receiver already had
+    pub resumed_from: u32,
+}
+
+enum Packet {
+    Frame {
+        kind: u8,
+        seq: u16,
+        len: usize,
+    },
+    /// Garbage, a damaged frame or one cut short
+    Bad,
+    Timeout,
+}
+
+/// An upload the receiver is in the middle of
+struct Upload {
+    received: Received,
+    offset: u32,
+    crc: Crc32,
+}
+
+/// Wait for an upload and store it in `target`
+pub fn receive<C, T>(
+    channel: &mut C,
+    target: &mut T,
+    config: &Config,
+) -> Result<Received, Error<C::Error>>
+where
+    C: Channel,
+    C::Error: core::fmt::Display,
+    T: Target<Error = C::Error>,
+{
+    let mut buf = [0; MAX_PAYLOAD];
+    let mut upload: Option<Upload> = None;
+    let mut seq = 0;
+    let mut errors = 0;
+    loop {
+        let timeout = match upload {
+            Some(_) => config.reply_timeout_ms,
+            None => {
+                write_frame(channel, HELLO, 0, &[VERSION])?;
+                config.hello_interval_ms
+            }
+        };
+        let (kind, len) = match read_packet(channel, config, timeout, &mut buf)? {
+            Packet::Frame { kind, seq: s, len } => {
+                seq = s;
+                errors = 0;
+                (kind, len)
+            }
+            packet => {
+                if matches!(packet, Packet::Bad) {
+                    channel.purge(config.byte_timeout_ms).map_err(Error::Io)?;
+                    if let Some(upload) = &upload {
+                        write_frame(channel, NAK, seq, &upload.offset.to_le_bytes())?;
+                    }
+                }
+                errors += 1;
+                let limit = match upload {
+                    Some(_) => config.retries,
+                    None => config.hellos,
+                };
+                if errors > limit {
+                    let error = match packet {
+                        Packet::Bad => Error::Retries,
+                        _ => Error::Timeout,
+                    };
+                    return Err(abort(channel, seq, error));
+                }
+                continue;
+            }
+        };
+        let payload = &buf[..len];
+
+        match kind {
+            OPEN => {
+                let Some((flags, size, name, dest)) = parse_open(payload) else {
+                    return Err(abort(channel, seq, Error::Protocol));
+                };
+                let (name, dest) = (name.to_string(), dest.map(str::to_string));
+                let truncate = flags & FLAG_TRUNCATE != 0;
+                let offset = match target.open(&name, dest.as_deref(), size, truncate) {
+                    Ok(offset) => offset.min(size),
+                    Err(e) => return Err(abort(channel, seq, Error::Io(e))),
+                };
+                let crc = match prefix_crc(offset, &mut buf, |o, b| target.read_at(o, b)) {
+                    Ok(crc) => crc,
+                    Err(e) => return Err(abort(channel, seq, Error::Io(e))),
+                };
+                let mut reply = [0; 8];
+                reply[..4].copy_from_slice(&offset.to_le_bytes());
+                reply[4..].copy_from_slice(&crc.value().to_le_bytes());
+                write_frame(channel, READY, seq, &reply)?;
+                upload = Some(Upload {
+                    received: Received {
+                        name,
+                        dest,
+                        size,
+                        resumed_from: offset,
+                    },
+                    offset,
+                    crc,
+                });
+            }
+            DATA => {
+                let Some(upload) = &mut upload else {
+                    continue;
+                };
+                let Some(offset) = read_u32(payload) else {
+                    write_frame(channel, NAK, seq, &upload.offset.to_le_bytes())?;
+                    continue;
+                };
+                let data = &payload[4..];
+                let end = offset as u64 + data.len() as u64;
+                if end > upload.received.size as u64 {
+                    return Err(abort(channel, seq, Error::Protocol));
+                }
+                if offset == upload.offset {
+                    if let Err(e) = target.write(data) {
+                        return Err(abort(channel, seq, Error::Io(e)));
+                    }
+                    upload.crc.update(data);
+                    upload.offset = end as u32;
+                }
+                // Repeats of data already written are acknowledged, gaps are refused
+                let kind = if end <= upload.offset as u64 {
+                    ACK
+                } else {
+                    NAK
+                };
+                write_frame(channel, kind, seq, &upload.offset.to_le_bytes())?;
+            }
+            DONE => {
+                let Some(upload) = &upload else {
+                    continue;
+                };
+                if upload.offset < upload.received.size {
+                    write_frame(channel, NAK, seq, &upload.offset.to_le_bytes())?;
+                    continue;
+                }
+                if read_u32(payload) != Some(upload.crc.value()) {
+                    return Err(abort(channel, seq, Error::Checksum));
+                }
+                if let Err(e) = target.finish() {
+                    return Err(abort(channel, seq, Error::Io(e)));
+                }
+                write_frame(channel, ACK, seq, &upload.offset.to_le_bytes())?;
+                linger(channel, config, upload.offset, &mut buf)?;
+                return Ok(upload.received.clone());
+            }
+            ABORT => return Err(Error::Aborted(String::from_utf8_lossy(payload).into())),
+            _ => {}
+        }
+    }
+}
+
+/// Upload `size` bytes from `reader` as `name` to `dest`, or into program RAM if `None`.
+/// `progress` gets the number of bytes the receiver has after each frame.
+#[allow(clippy::too_many_arguments)]
+pub fn send<C, R>(
+    channel: &mut C,
+    reader: &mut R,
+    size: u32,
+    name: &str,
+    dest: Option<&str>,
+    config: &Config,
+    progress: &mut dyn FnMut(u32),
+) -> Result<Sent, Error<C::Error>>
+where
+    C: Channel,
+    C::Error: core::fmt::Display,
+    R: Reader<Error = C::Error>,
+{
+    let mut link = Link {
+        channel,
+        config,
+        seq: 0,
+        reply: [0; MAX_PAYLOAD],
+        reply_len: 0,
+    };
+    let result = send_file(&mut link, reader, size, name, dest, progress);
+    match result {
+        Err(e @ (Error::Aborted(_) | Error::Timeout)) => Err(e),
+        Err(e) => Err(abort(link.channel, link.seq, e)),
+        ok => ok,
+    }
+}
+
+fn send_file<C, R>(
+    link: &mut Link<'_, C>,
+    reader: &mut R,
+    size: u32,
+    name: &str,
+    dest: Option<&str>,
+    progress: &mut dyn FnMut(u32),
+) -> Result<Sent, Error<C::Error>>
+where
+    C: Channel,
+    C::Error: core::fmt::Display,
+    R: Reader<Error = C::Error>,
+{
+    link.wait_hello()?;
+
+    let mut out = [0; MAX_PAYLOAD];
+    let mut offset = link.open(&mut out, 0, size, name, dest)?;
+    let mut crc = Crc32::new();
+    if offset > 0 {
+        let expected = link.reply_u32(4);
+        let prefix =
+            prefix_crc(offset, &mut out, |o, b| reader.read_at(o, b)).map_err(Error::Io)?;
+        if prefix.value() == expected {
+            crc = prefix;
+        } else if link.open(&mut out, FLAG_TRUNCATE, size, name, dest)? == 0 {
+            // Something else is there, start over
+            offset = 0;
+        } else {
+            return Err(Error::Protocol);
+        }
+    }
+    let resumed_from = offset;
+    progress(offset);
+
+    let mut errors = 0;
+    while offset < size {
+        let len = (size - offset).min(MAX_DATA as u32) as usize;
+        out[..4].copy_from_slice(&offset.to_le_bytes());
+        let n = fill(reader, offset, &mut out[4..4 + len]).map_err(Error::Io)?;
+        if n < len {
+            // The file shrank since its size was taken
+            return Err(Error::Protocol);
+        }
+        let reply = link.exchange(DATA, &out[..4 + len])?;
+        let acked = link.reply_u32(0);
+        match reply {
+            ACK if acked == offset + len as u32 => {
+                crc.update(&out[4..4 + len]);
+                offset = acked;
+                errors = 0;
+            }
+            // The receiver didn't take it, send it again
+            ACK | NAK if acked == offset => {
+                errors += 1;
+                if errors > link.config.retries {
+                    return Err(Error::Retries);
+                }
+            }
+            _ => return Err(Error::Protocol),
+        }
+        progress(offset);
+    }
+
+    match link.exchange(DONE, &crc.value().to_le_bytes())? {
+        ACK => Ok(Sent {
+            bytes: size - resumed_from,
+            resumed_from,
+        }),
+        _ => Err(Error::Protocol),
+    }
+}
+
+/// The sender's end, keeping the latest reply
+struct Link<'c, C: Channel> {
+    channel: &'c mut C,
+    config: &'c Config,
+    seq: u16,
+    reply: [u8; MAX_PAYLOAD],
+    reply_len: usize,
+}
+
+impl<C: Channel> Link<'_, C> {
+    fn wait_hello(&mut self) -> Result<(), Error<C::Error>> {
+        let mut timeouts = 0;
+        while timeouts <= self.config.retries {
+            let packet = read_packet(
+                self.channel,
+                self.config,
+                self.config.reply_timeout_ms,
+                &mut self.reply,
+            )?;
+            match packet {
+                Packet::Frame { kind: HELLO, .. } => return Ok(()),
+                Packet::Timeout => timeouts += 1,
+                _ => {}
+            }
+        }
+        Err(Error::Timeout)
+    }
+
+    /// Send `OPEN`, returns the offset the receiver starts at
+    fn open(
+        &mut self,
+        out: &mut [u8; MAX_PAYLOAD],
+        flags: u8,
+        size: u32,
+        name: &str,
+        dest: Option<&str>,
+    ) -> Result<u32, Error<C::Error>> {
+        let (name, dest) = (name.as_bytes(), dest.unwrap_or_default().as_bytes());
+        let len = 5 + name.len() + 1 + dest.len();
+        if len > out.len() {
+            return Err(Error::Protocol);
+        }
+        out[0] = flags;
+        out[1..5].copy_from_slice(&size.to_le_bytes());
+        out[5..5 + name.len()].copy_from_slice(name);
+        out[5 + name.len()] = 0;
+        out[6 + name.len()..len].copy_from_slice(dest);
+
+        match self.exchange(OPEN, &out[..len])? {
+            READY if self.reply_len == 8 && self.reply_u32(0) <= size => Ok(self.reply_u32(0)),
+            _ => Err(Error::Protocol),
+        }
+    }
+
+    /// Send a frame until its reply or a NAK arrives, returns the reply's kind
+    fn exchange(&mut self, kind: u8, payload: &[u8]) -> Result<u8, Error<C::Error>> {
+        self.seq = self.seq.wrapping_add(1);
+        for _ in 0..=self.config.retries {
+            write_frame(self.channel, kind, self.seq, payload)?;
+            loop {
+                let packet = read_packet(
+                    self.channel,
+                    self.config,
+                    self.config.reply_timeout_ms,
+                    &mut self.reply,
+                )?;
+                match packet {
+                    Packet::Frame {
+                        kind: ABORT, len, ..
+                    } => {
+                        let reason = String::from_utf8_lossy(&self.reply[..len]);
+                        return Err(Error::Aborted(reason.into()));
+                    }
+                    Packet::Frame { kind, seq, len } if len >= 4 => {
+                        if seq == self.seq || kind == NAK {
+                            self.reply_len = len;
+                            return Ok(kind);
+                        }
+                        // A late reply to an earlier attempt
+                    }
+                    Packet::Frame { .. } => {}
+                    Packet::Bad | Packet::Timeout => break,
+                }
+            }
+        }
+        Err(Error::Retries)
+    }
+
+    fn reply_u32(&self, at: usize) -> u32 {
+        u32::from_le_bytes(self.reply[at..at + 4].try_into().unwrap())
+    }
+}
+
+/// After the last ACK, answer repeated `DONE`s in case it got lost
+fn linger<C: Channel>(
+    channel: &mut C,
+    config: &Config,
+    offset: u32,
+    buf: &mut [u8; MAX_PAYLOAD],
+) -> Result<(), Error<C::Error>> {
+    loop {
+        match read_packet(channel, config, config.hello_interval_ms, buf)? {
+            Packet::Frame {
+                kind: DONE, seq, ..
+            } => write_frame(channel, ACK, seq, &offset.to_le_bytes())?,
+            Packet::Bad => {}
+            _ => return Ok(()),
+        }
+    }
+}
+
+/// Tell the other end why the upload stops, then return `error`
+fn abort<C>(channel: &mut C, seq: u16, error: Error<C::Error>) -> Error<C::Error>
+where
+    C: Channel,
+    C::Error: core::fmt::Display,
+{
+    let reason = error.to_string();
+    let reason = &reason.as_bytes()[..reason.len().min(MAX_DATA)];
+    let _ = write_frame(channel, ABORT, seq, reason);
+    error
+}
+
+fn write_frame<C: Channel>(
+    channel: &mut C,
+    kind: u8,
+    seq: u16,
+    payload: &[u8],
+) -> Result<(), Error<C::Error>> {
+    let [s0, s1] = seq.to_le_bytes();
+    let [l0, l1] = (payload.len() as u16).to_le_bytes();
+    let header = [MAGIC, kind, s0, s1, l0, l1];
+    let mut crc = Crc32::new();
+    crc.update(&header[1..]);
+    crc.update(payload);
+    for part in [&header[..], payload, &crc.value().to_le_bytes()] {
+        channel.write_all(part).map_err(Error::Io)?;
+    }
+    Ok(())
+}
+
+fn read_packet<C: Channel>(
+    channel: &mut C,
+    config: &Config,
+    timeout_ms: u32,
+    buf: &mut [u8; MAX_PAYLOAD],
+) -> Result<Packet, Error<C::Error>> {
+    // Skip anything before the magic byte, like console output
+    loop {
+        match channel.read_byte(timeout_ms).map_err(Error::Io)? {
+            Some(MAGIC) => break,
+            Some(_) => {}
+            None => return Ok(Packet::Timeout),
+        }
+    }
+
+    let mut header = [0; 5];
+    if !read_exact(channel, config, &mut header)? {
+        return Ok(Packet::Bad);
+    }
+    let len = u16::from_le_bytes([header[3], header[4]]) as usize;
+    if len > MAX_PAYLOAD || !read_exact(channel, config, &mut buf[..len])? {
+        return Ok(Packet::Bad);
+    }
+    let mut expected = [0; 4];
+    if !read_exact(channel, config, &mut expected)? {
+        return Ok(Packet::Bad);
+    }
+
+    let mut crc = Crc32::new();
+    crc.update(&header);
+    crc.update(&buf[..len]);
+    Ok(if crc.value() == u32::from_le_bytes(expected) {
+        Packet::Frame {
+            kind: header[0],
+            seq: u16::from_le_bytes([header[1], header[2]]),
+            len,
+        }
+    } else {
+        Packet::Bad
+    })
+}
+
+/// Fill `buf`, `false` if the line went quiet first
+fn read_exact<C: Channel>(
+    channel: &mut C,
+    config: &Config,
+    buf: &mut [u8],
+) -> Result<bool, Error<C::Error>> {
+    for byte in buf {
+        match channel
+            .read_byte(config.byte_timeout_ms)
+            .map_err(Error::Io)?
+        {
+            Some(b) => *byte = b,
+            None => return Ok(false),
+        }
+    }
+    Ok(true)
+}
+
+/// CRC of the first `len` bytes, read through `buf`
+fn prefix_crc<E>(
+    len: u32,
+    buf: &mut [u8],
+    mut read_at: impl FnMut(u32, &mut [u8]) -> Result<usize, E>,
+) -> Result<Crc32, E> {
+    let mut crc = Crc32::new();
+    let mut offset = 0;
+    while offset < len {
+        let chunk = buf.len().min((len - offset) as usize);
+        let n = read_at(offset, &mut buf[..chunk])?;
+        if n == 0 {
+            break;
+        }
+        crc.update(&buf[..n]);
+        offset += n as u32;
+    }
+    Ok(crc)
+}
+
+/// Read until `buf` is full or the file ends
+fn fill<R: Reader>(reader: &mut R, offset: u32, buf: &mut [u8]) -> Result<usize, R::Error> {
+    let mut filled = 0;
+    while filled < buf.len() {
+        match reader.read_at(offset + filled as u32, &mut buf[filled..])? {
+            0 => break,
+            n => filled += n,
+        }
+    }
+    Ok(filled)
+}
+
+fn read_u32(data: &[u8]) -> Option<u32> {
+    Some(u32::from_le_bytes(data.get(..4)?.try_into().ok()?))
+}
+
+/// Flags, size, file name and destination of an `OPEN`
+fn parse_open(payload: &[u8]) -> Option<(u8, u32, &str, Option<&str>)> {
+    let flags = *payload.first()?;
+    let size = read_u32(payload.get(1..)?)?;
+    let rest = payload.get(5..)?;
+    let nul = rest.iter().position(|&b| b == 0)?;
+    let name = core::str::from_utf8(&rest[..nul]).ok()?;
+    let dest = core::str::from_utf8(&rest[nul + 1..]).ok()?;
+    Some((flags, size, name, (!dest.is_empty()).then_some(dest)))
+}
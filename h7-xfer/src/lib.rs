@@ -0,0 +1,58 @@
+#![cfg_attr(target_os = "none", no_std)]
+
+//! File transfers over a serial console, shared by the firmware and the host tools.
+
+extern crate alloc;
+
+pub mod crc;
+pub mod frame;
+pub mod ymodem;
+
+/// A byte stream to the other end of a transfer
+pub trait Channel {
+    type Error;
+
+    /// The next byte, `None` if nothing arrived within `timeout_ms`
+    fn read_byte(&mut self, timeout_ms: u32) -> Result<Option<u8>, Self::Error>;
+
+    fn write_all(&mut self, data: &[u8]) -> Result<(), Self::Error>;
+
+    /// Drop input until the line has been quiet for `idle_ms`
+    fn purge(&mut self, idle_ms: u32) -> Result<(), Self::Error> {
+        while self.read_byte(idle_ms)?.is_some() {}
+        Ok(())
+    }
+}
+
+/// Where received files go
+pub trait Sink {
+    type Error;
+
+    /// A file begins, `name` is empty and `size` unknown when the sender doesn't say
+    fn create(&mut self, name: &str, size: Option<u64>) -> Result<(), Self::Error>;
+
+    fn write(&mut self, data: &[u8]) -> Result<(), Self::Error>;
+
+    /// The file is complete
+    fn finish(&mut self) -> Result<(), Self::Error>;
+}
+
+/// A file to send
+pub trait Source {
+    type Error;
+
+    /// File name without directories
+    fn name(&self) -> &str;
+
+    fn size(&self) -> u64;
+
+    /// Fill `buf` from the current position, `0` at the end of the file
+    fn read(&mut self, buf: &mut [u8]) -> Result<usize, Self::Error>;
+}
+
+/// What a finished transfer moved
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct Transfer {
+    pub files: usize,
+    pub bytes: u64,
+}
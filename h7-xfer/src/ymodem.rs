@@ -0,0 +1,557 @@
+//! YMODEM batch transfers, falling back to XMODEM-1K, XMODEM-CRC and checksum XMODEM.
+//!
+//! The receiver asks for CRC mode and switches to 8 bit checksums when no sender answers. A first
+//! block numbered 1 instead of 0 means the sender speaks XMODEM: a single file without name or
+//! size, whose trailing SUB padding is stripped.
+
+use crate::{crc, Channel, Sink, Source, Transfer};
+
+const SOH: u8 = 0x01;
+const STX: u8 = 0x02;
+const EOT: u8 = 0x04;
+const ACK: u8 = 0x06;
+const NAK: u8 = 0x15;
+const CAN: u8 = 0x18;
+const SUB: u8 = 0x1A;
+/// Start request asking for CRC mode
+const CRC: u8 = b'C';
+/// Sent to abort, two are enough but line noise might eat one
+const CANCEL: [u8; 5] = [CAN; 5];
+
+const SHORT_BLOCK: usize = 128;
+pub const BLOCK_LEN: usize = 1024;
+/// Start requests in CRC mode before the receiver tries checksums
+const CRC_ATTEMPTS: u32 = 3;
+
+/// Protocol the sender speaks, receivers detect it from the first block
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Mode {
+    /// Batches of files with names and sizes
+    Ymodem,
+    /// A single file without a name, padded to a whole block
+    Xmodem,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct Config {
+    /// Failed attempts at one block before giving up
+    pub retries: u32,
+    /// Wait for a block or a reply to one
+    pub block_timeout_ms: u32,
+    /// Wait between the bytes of a block
+    pub byte_timeout_ms: u32,
+    /// Between start requests while a receiver waits for its sender
+    pub start_interval_ms: u32,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            retries: 10,
+            block_timeout_ms: 10_000,
+            byte_timeout_ms: 1_000,
+            start_interval_ms: 3_000,
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum Error<E> {
+    /// The channel, sink or source failed
+    Io(E),
+    /// The other end never started or stopped answering
+    Timeout,
+    /// The other end cancelled
+    Cancelled,
+    /// Too many failed attempts at one block
+    Retries,
+    /// A block arrived out of sequence
+    Sequence,
+    /// A YMODEM header without a valid file name
+    Header,
+    /// XMODEM sends exactly one file
+    Batch,
+}
+
+impl<E: core::fmt::Display> core::fmt::Display for Error<E> {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        match self {
+            Self::Io(e) => write!(f, "{e}"),
+            Self::Timeout => write!(f, "Timed out"),
+            Self::Cancelled => write!(f, "Cancelled by the other end"),
+            Self::Retries => write!(f, "Too many errors"),
+            Self::Sequence => write!(f, "Block out of sequence"),
+            Self::Header => write!(f, "Invalid file header"),
+            Self::Batch => write!(f, "XMODEM sends a single file"),
+        }
+    }
+}
+
+enum Packet {
+    Block {
+        seq: u8,
+        len: usize,
+    },
+    Eot,
+    Cancel,
+    /// Garbage, a damaged block or one cut short
+    Bad,
+    Timeout,
+}
+
+/// Receive files into `sink` until the sender ends the batch, or after one XMODEM file
+pub fn receive<C, S>(
+    channel: &mut C,
+    sink: &mut S,
+    config: &Config,
+) -> Result<Transfer, Error<C::Error>>
+where
+    C: Channel,
+    S: Sink<Error = C::Error>,
+{
+    let mut transfer = Transfer::default();
+    let result = receive_batch(channel, sink, config, &mut transfer);
+    abort_on_error(channel, result.map(|_| transfer))
+}
+
+/// Send `files`, in XMODEM mode there has to be exactly one
+pub fn send<C, S>(
+    channel: &mut C,
+    files: &mut [S],
+    mode: Mode,
+    config: &Config,
+) -> Result<Transfer, Error<C::Error>>
+where
+    C: Channel,
+    S: Source<Error = C::Error>,
+{
+    if mode == Mode::Xmodem && files.len() != 1 {
+        return Err(Error::Batch);
+    }
+    let result = send_batch(channel, files, mode, config);
+    abort_on_error(channel, result)
+}
+
+/// Tell the other end to stop unless it was the one that cancelled
+fn abort_on_error<C: Channel, T>(
+    channel: &mut C,
+    result: Result<T, Error<C::Error>>,
+) -> Result<T, Error<C::Error>> {
+    if matches!(result, Err(ref e) if !matches!(e, Error::Cancelled)) {
+        let _ = channel.write_all(&CANCEL);
+    }
+    result
+}
+
+fn receive_batch<C, S>(
+    channel: &mut C,
+    sink: &mut S,
+    config: &Config,
+    transfer: &mut Transfer,
+) -> Result<(), Error<C::Error>>
+where
+    C: Channel,
+    S: Sink<Error = C::Error>,
+{
+    let mut buf = [0; BLOCK_LEN];
+    let mut crc = true;
+    loop {
+        let first = transfer.files == 0;
+        let mut attempts = 0;
+        let (seq, len) = loop {
+            write(channel, start_request(crc))?;
+            match read_packet(channel, config, crc, config.start_interval_ms, &mut buf)? {
+                Packet::Block { seq, len } => break (seq, len),
+                Packet::Cancel => return Err(Error::Cancelled),
+                // The sender missed the ACK to the previous file's end
+                Packet::Eot => write(channel, ACK)?,
+                Packet::Bad => purge(channel, config)?,
+                Packet::Timeout => {}
+            }
+            attempts += 1;
+            if first && crc && attempts >= CRC_ATTEMPTS {
+                crc = false;
+            }
+            if attempts > config.retries {
+                return Err(Error::Timeout);
+            }
+        };
+
+        match seq {
+            0 => {
+                let (name, size) = parse_header(&buf[..len]).ok_or(Error::Header)?;
+                if name.is_empty() {
+                    return write(channel, ACK);
+                }
+                sink.create(name, size).map_err(Error::Io)?;
+                write(channel, ACK)?;
+                transfer.bytes += receive_file(
+                    channel,
+                    sink,
+                    config,
+                    crc,
+                    Mode::Ymodem,
+                    size,
+                    &mut buf,
+                    None,
+                )?;
+                transfer.files += 1;
+            }
+            1 if first => {
+                sink.create("", None).map_err(Error::Io)?;
+                transfer.bytes += receive_file(
+                    channel,
+                    sink,
+                    config,
+                    crc,
+                    Mode::Xmodem,
+                    None,
+                    &mut buf,
+                    Some(len),
+                )?;
+                transfer.files += 1;
+                return Ok(());
+            }
+            _ => return Err(Error::Sequence),
+        }
+    }
+}
+
+/// Receive data blocks up to the end of file, `first` is the length of block 1 if it is already
+/// in `buf`. Returns the number of bytes written to `sink`.
+#[allow(clippy::too_many_arguments)]
+fn receive_file<C, S>(
+    channel: &mut C,
+    sink: &mut S,
+    config: &Config,
+    crc: bool,
+    mode: Mode,
+    size: Option<u64>,
+    buf: &mut [u8; BLOCK_LEN],
+    first: Option<usize>,
+) -> Result<u64, Error<C::Error>>
+where
+    C: Channel,
+    S: Sink<Error = C::Error>,
+{
+    // XMODEM holds back the latest block, the last one's padding is stripped at the end
+    let mut held = [0; BLOCK_LEN];
+    let mut held_len = 0;
+    let mut remaining = size;
+    let mut written = 0;
+    let mut expected = 1u8;
+    let mut errors = 0;
+    let mut eot_refused = false;
+
+    let mut packet = match first {
+        Some(len) => Packet::Block { seq: 1, len },
+        None => {
+            write(channel, start_request(crc))?;
+            read_packet(channel, config, crc, config.block_timeout_ms, buf)?
+        }
+    };
+    loop {
+        match packet {
+            Packet::Block { seq, len } if seq == expected => {
+                let data = match mode {
+                    Mode::Ymodem => {
+                        let len = remaining.map_or(len, |r| r.min(len as u64) as usize);
+                        remaining = remaining.map(|r| r - len as u64);
+                        &buf[..len]
+                    }
+                    Mode::Xmodem => {
+                        core::mem::swap(&mut held, buf);
+                        &buf[..core::mem::replace(&mut held_len, len)]
+                    }
+                };
+                if !data.is_empty() {
+                    sink.write(data).map_err(Error::Io)?;
+                    written += data.len() as u64;
+                }
+                write(channel, ACK)?;
+                expected = expected.wrapping_add(1);
+                errors = 0;
+            }
+            Packet::Block { seq, .. } if seq == expected.wrapping_sub(1) => {
+                // Our ACK got lost, a repeated YMODEM header also wants the start request again
+                write(channel, ACK)?;
+                if seq == 0 && mode == Mode::Ymodem {
+                    write(channel, start_request(crc))?;
+                }
+            }
+            Packet::Block { .. } => return Err(Error::Sequence),
+            // YMODEM refuses the first EOT to make sure it wasn't line noise
+            Packet::Eot if mode == Mode::Ymodem && !eot_refused => {
+                eot_refused = true;
+                write(channel, NAK)?;
+            }
+            Packet::Eot => {
+                write(channel, ACK)?;
+                break;
+            }
+            Packet::Cancel => return Err(Error::Cancelled),
+            Packet::Bad | Packet::Timeout => {
+                if matches!(packet, Packet::Bad) {
+                    purge(channel, config)?;
+                }
+                errors += 1;
+                if errors > config.retries {
+                    return Err(Error::Retries);
+                }
+                // Nothing arrived yet, the sender may have missed the start request
+                let waiting = expected == 1 && mode == Mode::Ymodem;
+                write(channel, if waiting { start_request(crc) } else { NAK })?;
+            }
+        }
+        packet = read_packet(channel, config, crc, config.block_timeout_ms, buf)?;
+    }
+
+    let padding = held[..held_len]
+        .iter()
+        .rev()
+        .take_while(|&&b| b == SUB)
+        .count();
+    if held_len > padding {
+        sink.write(&held[..held_len - padding]).map_err(Error::Io)?;
+        written += (held_len - padding) as u64;
+    }
+    sink.finish().map_err(Error::Io)?;
+    Ok(written)
+}
+
+fn send_batch<C, S>(
+    channel: &mut C,
+    files: &mut [S],
+    mode: Mode,
+    config: &Config,
+) -> Result<Transfer, Error<C::Error>>
+where
+    C: Channel,
+    S: Source<Error = C::Error>,
+{
+    let mut transfer = Transfer::default();
+    let mut buf = [0; BLOCK_LEN];
+    for file in files.iter_mut() {
+        let mut crc = wait_start(channel, config)?;
+        if mode == Mode::Ymodem {
+            let len = header(&mut buf, file.name(), file.size());
+            send_block(channel, config, crc, 0, &buf[..len])?;
+            crc = wait_start(channel, config)?;
+        }
+
+        // Receivers that only know checksums are unlikely to know 1K blocks
+        let block_len = if crc { BLOCK_LEN } else { SHORT_BLOCK };
+        let mut seq = 1u8;
+        loop {
+            let n = fill(file, &mut buf[..block_len]).map_err(Error::Io)?;
+            if n == 0 {
+                break;
+            }
+            let len = if n <= SHORT_BLOCK {
+                SHORT_BLOCK
+            } else {
+                BLOCK_LEN
+            };
+            buf[n..len].fill(SUB);
+            send_block(channel, config, crc, seq, &buf[..len])?;
+            seq = seq.wrapping_add(1);
+            transfer.bytes += n as u64;
+        }
+        end_of_file(channel, config)?;
+        transfer.files += 1;
+    }
+
+    if mode == Mode::Ymodem {
+        let crc = wait_start(channel, config)?;
+        buf[..SHORT_BLOCK].fill(0);
+        send_block(channel, config, crc, 0, &buf[..SHORT_BLOCK])?;
+    }
+    Ok(transfer)
+}
+
+/// Wait for the receiver's start request, `true` if it asked for CRC mode
+fn wait_start<C: Channel>(channel: &mut C, config: &Config) -> Result<bool, Error<C::Error>> {
+    let mut timeouts = 0;
+    while timeouts < config.retries {
+        match channel
+            .read_byte(config.block_timeout_ms)
+            .map_err(Error::Io)?
+        {
+            Some(CRC) => return Ok(true),
+            Some(NAK) => return Ok(false),
+            Some(CAN) if cancelled(channel, config)? => return Err(Error::Cancelled),
+            // Leftovers like the echo of the command that started the receiver
+            Some(_) => {}
+            None => timeouts += 1,
+        }
+    }
+    Err(Error::Timeout)
+}
+
+fn send_block<C: Channel>(
+    channel: &mut C,
+    config: &Config,
+    crc: bool,
+    seq: u8,
+    data: &[u8],
+) -> Result<(), Error<C::Error>> {
+    let header = [if data.len() == SHORT_BLOCK { SOH } else { STX }, seq, !seq];
+    let crc16 = crc::crc16(data).to_be_bytes();
+    let checksum = [crc::checksum(data)];
+    let trailer: &[u8] = if crc { &crc16 } else { &checksum };
+
+    for _ in 0..=config.retries {
+        for part in [&header[..], data, trailer] {
+            channel.write_all(part).map_err(Error::Io)?;
+        }
+        match channel
+            .read_byte(config.block_timeout_ms)
+            .map_err(Error::Io)?
+        {
+            Some(ACK) => return Ok(()),
+            Some(CAN) if cancelled(channel, config)? => return Err(Error::Cancelled),
+            // NAK, a repeated start request, noise or nothing at all
+            _ => {}
+        }
+    }
+    Err(Error::Retries)
+}
+
+fn end_of_file<C: Channel>(channel: &mut C, config: &Config) -> Result<(), Error<C::Error>> {
+    for _ in 0..=config.retries {
+        write(channel, EOT)?;
+        match channel
+            .read_byte(config.block_timeout_ms)
+            .map_err(Error::Io)?
+        {
+            Some(ACK) => return Ok(()),
+            Some(CAN) if cancelled(channel, config)? => return Err(Error::Cancelled),
+            _ => {}
+        }
+    }
+    Err(Error::Retries)
+}
+
+fn read_packet<C: Channel>(
+    channel: &mut C,
+    config: &Config,
+    crc: bool,
+    timeout_ms: u32,
+    buf: &mut [u8; BLOCK_LEN],
+) -> Result<Packet, Error<C::Error>> {
+    let len = match channel.read_byte(timeout_ms).map_err(Error::Io)? {
+        Some(SOH) => SHORT_BLOCK,
+        Some(STX) => BLOCK_LEN,
+        Some(EOT) => return Ok(Packet::Eot),
+        Some(CAN) if cancelled(channel, config)? => return Ok(Packet::Cancel),
+        Some(_) => return Ok(Packet::Bad),
+        None => return Ok(Packet::Timeout),
+    };
+
+    let mut seq = [0; 2];
+    let mut trailer = [0; 2];
+    let trailer_len = if crc { 2 } else { 1 };
+    let bytes = seq
+        .iter_mut()
+        .chain(&mut buf[..len])
+        .chain(&mut trailer[..trailer_len]);
+    for byte in bytes {
+        match channel
+            .read_byte(config.byte_timeout_ms)
+            .map_err(Error::Io)?
+        {
+            Some(b) => *byte = b,
+            None => return Ok(Packet::Bad),
+        }
+    }
+
+    let data = &buf[..len];
+    let valid = if crc {
+        crc::crc16(data).to_be_bytes() == trailer
+    } else {
+        crc::checksum(data) == trailer[0]
+    };
+    Ok(if valid && seq[0] == !seq[1] {
+        Packet::Block { seq: seq[0], len }
+    } else {
+        Packet::Bad
+    })
+}
+
+/// A CAN was read, it takes a second one to cancel
+fn cancelled<C: Channel>(channel: &mut C, config: &Config) -> Result<bool, Error<C::Error>> {
+    Ok(channel
+        .read_byte(config.byte_timeout_ms)
+        .map_err(Error::Io)?
+        == Some(CAN))
+}
+
+fn purge<C: Channel>(channel: &mut C, config: &Config) -> Result<(), Error<C::Error>> {
+    channel.purge(config.byte_timeout_ms).map_err(Error::Io)
+}
+
+fn write<C: Channel>(channel: &mut C, byte: u8) -> Result<(), Error<C::Error>> {
+    channel.write_all(&[byte]).map_err(Error::Io)
+}
+
+fn start_request(crc: bool) -> u8 {
+    if crc {
+        CRC
+    } else {
+        NAK
+    }
+}
+
+/// Read until `buf` is full or the file ends
+fn fill<S: Source>(file: &mut S, buf: &mut [u8]) -> Result<usize, S::Error> {
+    let mut filled = 0;
+    while filled < buf.len() {
+        match file.read(&mut buf[filled..])? {
+            0 => break,
+            n => filled += n,
+        }
+    }
+    Ok(filled)
+}
+
+/// Block 0: the file name, a NUL and the size in decimal. Returns the block length.
+fn header(buf: &mut [u8; BLOCK_LEN], name: &str, size: u64) -> usize {
+    buf.fill(0);
+    // Room for the NUL, the size and its terminator
+    let name = &name.as_bytes()[..name.len().min(BLOCK_LEN - 22)];
+    buf[..name.len()].copy_from_slice(name);
+
+    let mut digits = [0; 20];
+    let mut n = size;
+    let mut count = 0;
+    loop {
+        digits[count] = b'0' + (n % 10) as u8;
+        count += 1;
+        n /= 10;
+        if n == 0 {
+            break;
+        }
+    }
+    let start = name.len() + 1;
+    for (dst, src) in buf[start..].iter_mut().zip(digits[..count].iter().rev()) {
+        *dst = *src;
+    }
+
+    if start + count < SHORT_BLOCK {
+        SHORT_BLOCK
+    } else {
+        BLOCK_LEN
+    }
+}
+
+/// File name and size from block 0, an empty name ends the batch
+fn parse_header(data: &[u8]) -> Option<(&str, Option<u64>)> {
+    let end = data.iter().position(|&b| b == 0)?;
+    let name = core::str::from_utf8(&data[..end]).ok()?;
+    let size = data[end + 1..]
+        .split(|&b| b == b' ' || b == 0)
+        .next()
+        .and_then(|s| core::str::from_utf8(s).ok())
+        .and_then(|s| s.parse().ok());
+    Some((name, size))
+}
@@ -0,0 +1,61 @@
+use {
+    h7_xfer::Channel,
+    std::{
+        io,
+        sync::mpsc::{self, Receiver, RecvTimeoutError, Sender},
+        time::Duration,
+    },
+};
+
+/// One end of an in-memory serial line
+pub struct Pipe {
+    rx: Receiver<u8>,
+    tx: Sender<u8>,
+    /// Called with the index and value of every written byte, `None` drops it
+    pub fault: Box<dyn FnMut(usize, u8) -> Option<u8> + Send>,
+    written: usize,
+    /// Incoming bytes to ignore
+    pub deaf_to: Option<u8>,
+}
+
+pub fn pipe() -> (Pipe, Pipe) {
+    let (a_tx, b_rx) = mpsc::channel();
+    let (b_tx, a_rx) = mpsc::channel();
+    let end = |rx, tx| Pipe {
+        rx,
+        tx,
+        fault: Box::new(|_, b| Some(b)),
+        written: 0,
+        deaf_to: None,
+    };
+    (end(a_rx, a_tx), end(b_rx, b_tx))
+}
+
+impl Channel for Pipe {
+    type Error = io::Error;
+
+    fn read_byte(&mut self, timeout_ms: u32) -> Result<Option<u8>, io::Error> {
+        loop {
+            match self
+                .rx
+                .recv_timeout(Duration::from_millis(timeout_ms.into()))
+            {
+                Ok(b) if Some(b) == self.deaf_to => {}
+                Ok(b) => return Ok(Some(b)),
+                Err(RecvTimeoutError::Timeout) => return Ok(None),
+                Err(RecvTimeoutError::Disconnected) => return Err(io::ErrorKind::BrokenPipe.into()),
+            }
+        }
+    }
+
+    fn write_all(&mut self, data: &[u8]) -> Result<(), io::Error> {
+        for &b in data {
+            if let Some(b) = (self.fault)(self.written, b) {
+                // The other end may be gone after cancelling
+                let _ = self.tx.send(b);
+            }
+            self.written += 1;
+        }
+        Ok(())
+    }
+}
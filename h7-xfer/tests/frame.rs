@@ -0,0 +1,287 @@
+mod common;
+
+use {
+    common::{pipe, Pipe},
+    h7_xfer::{
+        frame::{self, Config, Error, Reader, Received, Sent, Target},
+        Channel,
+    },
+    std::{collections::HashMap, io, thread},
+};
+
+const CONFIG: Config = Config {
+    retries: 10,
+    reply_timeout_ms: 300,
+    byte_timeout_ms: 50,
+    hello_interval_ms: 50,
+    hellos: 20,
+};
+
+/// Uploads by destination, `None` being program RAM
+#[derive(Default)]
+struct Memory {
+    files: HashMap<Option<String>, Vec<u8>>,
+    current: Option<String>,
+    finished: bool,
+    /// Fail writes past this many bytes
+    limit: Option<usize>,
+}
+
+impl Memory {
+    fn file(&mut self) -> &mut Vec<u8> {
+        self.files.entry(self.current.clone()).or_default()
+    }
+}
+
+impl Target for Memory {
+    type Error = io::Error;
+
+    fn open(
+        &mut self,
+        _name: &str,
+        dest: Option<&str>,
+        size: u32,
+        truncate: bool,
+    ) -> Result<u32, io::Error> {
+        self.current = dest.map(str::to_string);
+        let file = self.file();
+        if truncate || file.len() > size as usize {
+            file.clear();
+        }
+        Ok(file.len() as u32)
+    }
+
+    fn read_at(&mut self, offset: u32, buf: &mut [u8]) -> Result<usize, io::Error> {
+        let file = &self.file()[offset as usize..];
+        let n = buf.len().min(file.len());
+        buf[..n].copy_from_slice(&file[..n]);
+        Ok(n)
+    }
+
+    fn write(&mut self, data: &[u8]) -> Result<(), io::Error> {
+        let limit = self.limit;
+        let file = self.file();
+        if limit.is_some_and(|limit| file.len() + data.len() > limit) {
+            return Err(io::Error::other("disk full"));
+        }
+        file.extend_from_slice(data);
+        Ok(())
+    }
+
+    fn finish(&mut self) -> Result<(), io::Error> {
+        self.finished = true;
+        Ok(())
+    }
+}
+
+struct File(Vec<u8>);
+
+impl Reader for File {
+    type Error = io::Error;
+
+    fn read_at(&mut self, offset: u32, buf: &mut [u8]) -> Result<usize, io::Error> {
+        let data = &self.0[offset as usize..];
+        // Short reads, the sender has to fill its frames itself
+        let n = buf.len().min(data.len()).min(700);
+        buf[..n].copy_from_slice(&data[..n]);
+        Ok(n)
+    }
+}
+
+fn data(len: usize) -> Vec<u8> {
+    (0..len).map(|i| (i * 13 + i / 256) as u8).collect()
+}
+
+/// What each end returned and where the upload went
+type Outcome = (
+    Result<Sent, Error<io::Error>>,
+    Result<Received, Error<io::Error>>,
+    Memory,
+    Vec<u32>,
+);
+
+fn upload(
+    mut sender: Pipe,
+    mut receiver: Pipe,
+    file: Vec<u8>,
+    dest: Option<&'static str>,
+    mut target: Memory,
+) -> Outcome {
+    let received = thread::spawn(move || {
+        // The shell echoes the command that started the receiver
+        receiver.write_all(b"frx\r\n").unwrap();
+        let received = frame::receive(&mut receiver, &mut target, &CONFIG);
+        (received, target)
+    });
+    let mut progress = Vec::new();
+    let size = file.len() as u32;
+    let sent = frame::send(
+        &mut sender,
+        &mut File(file),
+        size,
+        "app.h7",
+        dest,
+        &CONFIG,
+        &mut |n| progress.push(n),
+    );
+    let (received, target) = received.join().unwrap();
+    (sent, received, target, progress)
+}
+
+#[test]
+fn uploads_a_file() {
+    let (sender, receiver) = pipe();
+    let file = data(5000);
+    let (sent, received, mut target, progress) = upload(
+        sender,
+        receiver,
+        file.clone(),
+        Some("sdcard:/apps/app.h7"),
+        Memory::default(),
+    );
+
+    assert_eq!(
+        sent.unwrap(),
+        Sent {
+            bytes: 5000,
+            resumed_from: 0
+        }
+    );
+    assert_eq!(
+        received.unwrap(),
+        Received {
+            name: "app.h7".into(),
+            dest: Some("sdcard:/apps/app.h7".into()),
+            size: 5000,
+            resumed_from: 0,
+        }
+    );
+    assert!(target.finished);
+    assert_eq!(target.file(), &file);
+    assert_eq!(progress, [0, 1024, 2048, 3072, 4096, 5000]);
+}
+
+#[test]
+fn uploads_a_program() {
+    let (sender, receiver) = pipe();
+    let file = data(1500);
+    let (sent, received, target, _) =
+        upload(sender, receiver, file.clone(), None, Memory::default());
+
+    assert_eq!(sent.unwrap().bytes, 1500);
+    assert_eq!(received.unwrap().dest, None);
+    assert_eq!(target.files[&None], file);
+}
+
+#[test]
+fn uploads_an_empty_file() {
+    let (sender, receiver) = pipe();
+    let (sent, received, target, _) = upload(
+        sender,
+        receiver,
+        Vec::new(),
+        Some("empty"),
+        Memory::default(),
+    );
+
+    assert_eq!(sent.unwrap().bytes, 0);
+    assert_eq!(received.unwrap().size, 0);
+    assert!(target.finished);
+}
+
+#[test]
+fn resumes_an_interrupted_upload() {
+    let (sender, receiver) = pipe();
+    let file = data(6000);
+    let mut target = Memory::default();
+    target
+        .files
+        .insert(Some("app.h7".into()), file[..2500].to_vec());
+    let (sent, received, mut target, progress) =
+        upload(sender, receiver, file.clone(), Some("app.h7"), target);
+
+    assert_eq!(
+        sent.unwrap(),
+        Sent {
+            bytes: 3500,
+            resumed_from: 2500
+        }
+    );
+    assert_eq!(received.unwrap().resumed_from, 2500);
+    assert_eq!(target.file(), &file);
+    assert_eq!(progress[0], 2500);
+}
+
+#[test]
+fn starts_over_when_the_start_differs() {
+    let (sender, receiver) = pipe();
+    let file = data(3000);
+    let mut target = Memory::default();
+    target.files.insert(Some("app.h7".into()), vec![0xFF; 1000]);
+    let (sent, received, mut target, _) =
+        upload(sender, receiver, file.clone(), Some("app.h7"), target);
+
+    assert_eq!(sent.unwrap().resumed_from, 0);
+    assert_eq!(received.unwrap().resumed_from, 0);
+    assert_eq!(target.file(), &file);
+}
+
+#[test]
+fn recovers_from_line_errors() {
+    let (mut sender, mut receiver) = pipe();
+    sender.fault = Box::new(|i, b| match i {
+        // Inside the open request, the first data frame and a later one's header
+        12 | 400 | 2100 => Some(b ^ 0x01),
+        // Cut a frame short
+        3500 => None,
+        _ => Some(b),
+    });
+    // Lose the reply to the first data frame
+    receiver.fault = Box::new(|i, b| (!(40..45).contains(&i)).then_some(b));
+    let file = data(6000);
+    let (sent, received, mut target, _) = upload(
+        sender,
+        receiver,
+        file.clone(),
+        Some("app.h7"),
+        Memory::default(),
+    );
+
+    assert_eq!(sent.unwrap().bytes, 6000);
+    assert_eq!(received.unwrap().size, 6000);
+    assert_eq!(target.file(), &file);
+}
+
+#[test]
+fn target_errors_abort_the_sender() {
+    let (sender, receiver) = pipe();
+    let target = Memory {
+        limit: Some(2048),
+        ..Default::default()
+    };
+    let (sent, received, target, _) = upload(sender, receiver, data(8000), Some("big.bin"), target);
+
+    assert!(matches!(sent, Err(Error::Aborted(reason)) if reason == "disk full"));
+    assert!(matches!(received, Err(Error::Io(_))));
+    assert!(!target.finished);
+}
+
+#[test]
+fn sender_times_out_without_receiver() {
+    let (mut sender, _receiver) = pipe();
+    let config = Config {
+        retries: 2,
+        reply_timeout_ms: 10,
+        ..CONFIG
+    };
+    let result = frame::send(
+        &mut sender,
+        &mut File(data(10)),
+        10,
+        "a",
+        None,
+        &config,
+        &mut |_| {},
+    );
+    assert!(matches!(result, Err(Error::Timeout)));
+}
@@ -0,0 +1,222 @@
+mod common;
+
+use {
+    common::{pipe, Pipe},
+    h7_xfer::{
+        crc,
+        ymodem::{self, Config, Error, Mode},
+        Sink, Source, Transfer,
+    },
+    std::{io, thread},
+};
+
+const CONFIG: Config = Config {
+    retries: 10,
+    block_timeout_ms: 500,
+    byte_timeout_ms: 50,
+    start_interval_ms: 100,
+};
+
+#[derive(Default)]
+struct Files {
+    files: Vec<(String, Option<u64>, Vec<u8>)>,
+    finished: usize,
+    /// Fail writes once this many bytes were received
+    limit: Option<usize>,
+}
+
+impl Sink for Files {
+    type Error = io::Error;
+
+    fn create(&mut self, name: &str, size: Option<u64>) -> Result<(), io::Error> {
+        self.files.push((name.into(), size, Vec::new()));
+        Ok(())
+    }
+
+    fn write(&mut self, data: &[u8]) -> Result<(), io::Error> {
+        let file = &mut self.files.last_mut().unwrap().2;
+        if self
+            .limit
+            .is_some_and(|limit| file.len() + data.len() > limit)
+        {
+            return Err(io::ErrorKind::StorageFull.into());
+        }
+        file.extend_from_slice(data);
+        Ok(())
+    }
+
+    fn finish(&mut self) -> Result<(), io::Error> {
+        self.finished += 1;
+        Ok(())
+    }
+}
+
+struct File {
+    name: &'static str,
+    data: Vec<u8>,
+    pos: usize,
+}
+
+impl File {
+    fn new(name: &'static str, len: usize) -> Self {
+        let data = (0..len).map(|i| (i * 7 + i / 251) as u8).collect();
+        Self { name, data, pos: 0 }
+    }
+}
+
+impl Source for File {
+    type Error = io::Error;
+
+    fn name(&self) -> &str {
+        self.name
+    }
+
+    fn size(&self) -> u64 {
+        self.data.len() as u64
+    }
+
+    fn read(&mut self, buf: &mut [u8]) -> Result<usize, io::Error> {
+        // Short reads, the sender has to fill its blocks itself
+        let n = buf.len().min(self.data.len() - self.pos).min(300);
+        buf[..n].copy_from_slice(&self.data[self.pos..self.pos + n]);
+        self.pos += n;
+        Ok(n)
+    }
+}
+
+/// What each end returned
+type Outcome = Result<Transfer, Error<io::Error>>;
+
+fn transfer(
+    mut sender: Pipe,
+    mut receiver: Pipe,
+    mut files: Vec<File>,
+    mode: Mode,
+    mut sink: Files,
+) -> (Outcome, Outcome, Files) {
+    let sent = thread::spawn(move || ymodem::send(&mut sender, &mut files, mode, &CONFIG));
+    let received = ymodem::receive(&mut receiver, &mut sink, &CONFIG);
+    (sent.join().unwrap(), received, sink)
+}
+
+#[test]
+fn crc16_check_value() {
+    assert_eq!(crc::crc16(b"123456789"), 0x31C3);
+    assert_eq!(crc::checksum(&[0xFF, 0x02]), 0x01);
+}
+
+#[test]
+fn ymodem_batch() {
+    let (sender, receiver) = pipe();
+    let files = vec![
+        File::new("app.bin", 3000),
+        File::new("empty.txt", 0),
+        File::new("small.txt", 100),
+    ];
+    let expected: Vec<_> = files.iter().map(|f| (f.name, f.data.clone())).collect();
+
+    let (sent, received, sink) = transfer(sender, receiver, files, Mode::Ymodem, Files::default());
+    let stats = Transfer {
+        files: 3,
+        bytes: 3100,
+    };
+    assert_eq!(sent.unwrap(), stats);
+    assert_eq!(received.unwrap(), stats);
+    assert_eq!(sink.finished, 3);
+    for ((name, size, data), (expected_name, expected_data)) in sink.files.iter().zip(&expected) {
+        assert_eq!(name, expected_name);
+        assert_eq!(*size, Some(expected_data.len() as u64));
+        assert_eq!(data, expected_data);
+    }
+}
+
+#[test]
+fn xmodem_1k_fallback() {
+    let (sender, receiver) = pipe();
+    let file = File::new("ignored", 2500);
+    let expected = file.data.clone();
+
+    let (sent, received, sink) =
+        transfer(sender, receiver, vec![file], Mode::Xmodem, Files::default());
+    assert_eq!(sent.unwrap().bytes, 2500);
+    assert_eq!(received.unwrap().files, 1);
+    assert_eq!(sink.files, [(String::new(), None, expected)]);
+}
+
+#[test]
+fn checksum_fallback() {
+    let (mut sender, receiver) = pipe();
+    // A sender that doesn't know CRC mode never sees the receiver's requests for it
+    sender.deaf_to = Some(b'C');
+    let file = File::new("ignored", 300);
+    let expected = file.data.clone();
+
+    let (sent, received, sink) =
+        transfer(sender, receiver, vec![file], Mode::Xmodem, Files::default());
+    assert_eq!(sent.unwrap().bytes, 300);
+    assert_eq!(received.unwrap().bytes, 300);
+    assert_eq!(sink.files[0].2, expected);
+}
+
+#[test]
+fn recovers_from_line_errors() {
+    let (mut sender, mut receiver) = pipe();
+    sender.fault = Box::new(|i, b| match i {
+        // Inside the header block and the first data block
+        40 | 600 => Some(b ^ 0x10),
+        // Cut the second data block short
+        1500 => None,
+        _ => Some(b),
+    });
+    // Lose an ACK
+    receiver.fault = Box::new(|i, b| (i != 3).then_some(b));
+    let file = File::new("noisy.bin", 4000);
+    let expected = file.data.clone();
+
+    let (sent, received, sink) =
+        transfer(sender, receiver, vec![file], Mode::Ymodem, Files::default());
+    assert_eq!(sent.unwrap().bytes, 4000);
+    assert_eq!(received.unwrap().bytes, 4000);
+    assert_eq!(sink.files[0].2, expected);
+}
+
+#[test]
+fn sink_errors_cancel_the_sender() {
+    let (sender, receiver) = pipe();
+    let sink = Files {
+        limit: Some(2048),
+        ..Default::default()
+    };
+
+    let (sent, received, _) = transfer(
+        sender,
+        receiver,
+        vec![File::new("big.bin", 8192)],
+        Mode::Ymodem,
+        sink,
+    );
+    assert!(matches!(sent, Err(Error::Cancelled)));
+    assert!(matches!(received, Err(Error::Io(e)) if e.kind() == io::ErrorKind::StorageFull));
+}
+
+#[test]
+fn xmodem_sends_one_file() {
+    let (mut sender, _receiver) = pipe();
+    let mut files = [File::new("a", 1), File::new("b", 1)];
+    assert!(matches!(
+        ymodem::send(&mut sender, &mut files, Mode::Xmodem, &CONFIG),
+        Err(Error::Batch)
+    ));
+}
+
+#[test]
+fn receiver_times_out_without_sender() {
+    let (_sender, mut receiver) = pipe();
+    let config = Config {
+        retries: 2,
+        start_interval_ms: 10,
+        ..CONFIG
+    };
+    let result = ymodem::receive(&mut receiver, &mut Files::default(), &config);
+    assert!(matches!(result, Err(Error::Timeout)));
+}